@@ -0,0 +1,69 @@
+/*
+ * C API of soundcloud-dl, built with `cargo build --release --features capi`
+ *
+ * A handle owns a runtime downloading in the background. URLs are queued with
+ * scdl_enqueue_url, and progress is read as JSON events with scdl_poll_event:
+ *
+ *   {"event": "track_start", "track_id": 1, "title": "..."}
+ *   {"event": "bytes", "track_id": 1, "received": 1024, "total": 4096}
+ *   {"event": "track_done", "track_id": 1, "path": "..."}
+ *   {"event": "track_error", "track_id": 1, "message": "..."}
+ *   {"event": "finished", "url": "...", "downloaded": 1, "failed": 0, "skipped": 0}
+ *   {"event": "failed", "url": "...", "message": "..."}
+ *
+ * All strings are NUL-terminated UTF-8.
+ */
+
+#ifndef SOUNDCLOUD_DL_H
+#define SOUNDCLOUD_DL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct ScdlHandle ScdlHandle;
+
+/*
+ * Creates a downloader saving to output_dir, signed in with oauth_token.
+ *
+ * Returns the handle, to be freed with scdl_free, or NULL if an argument is
+ * NULL or the downloader could not be set up.
+ */
+ScdlHandle *scdl_init(const char *oauth_token, const char *output_dir);
+
+/*
+ * Starts downloading the track or playlist at url in the background.
+ *
+ * Returns 0 once queued, -1 if an argument is NULL or url is not UTF-8.
+ */
+int scdl_enqueue_url(const ScdlHandle *handle, const char *url);
+
+/*
+ * Takes the oldest progress event.
+ *
+ * Returns the event as a JSON string, to be freed with scdl_string_free, or
+ * NULL if no event is waiting.
+ */
+char *scdl_poll_event(const ScdlHandle *handle);
+
+/* Frees a string returned by scdl_poll_event. NULL is ignored. */
+void scdl_string_free(char *s);
+
+/*
+ * Stops the downloads in progress, deleting their partial files. URLs queued
+ * afterwards are downloaded as usual.
+ */
+void scdl_cancel(const ScdlHandle *handle);
+
+/*
+ * Frees a handle, cancelling its downloads. Blocks until the downloads in
+ * progress have stopped. NULL is ignored, and the handle must not be used
+ * afterwards.
+ */
+void scdl_free(ScdlHandle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SOUNDCLOUD_DL_H */
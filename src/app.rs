@@ -0,0 +1,978 @@
+use chrono::Utc;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{
+    archive, audit, cli, clipboard, config, doctor, downloader, error, ffmpeg, following, library,
+    lock, mirror, notify, queue, search, snapshot, soundcloud, storage, sync, temp, template, tui,
+    update, upgrade, util,
+};
+
+use archive::DownloadArchive;
+use cli::CacheAction;
+use cli::Cli;
+use cli::Commands;
+use cli::FfmpegAction;
+use cli::FollowingAction;
+use cli::Input;
+use cli::QueueAction;
+use cli::SearchKind;
+use cli::SnapshotAction;
+use clipboard::ClipboardWatcher;
+use config::Config;
+use downloader::{download_playlists, BatchSummary, DownloadOptions, DownloadRequest, Downloader};
+use error::{AppError, Result};
+use ffmpeg::FFmpeg;
+use library::Library;
+use mirror::Mirror;
+use queue::JobQueue;
+use snapshot::{Snapshot, SnapshotStore};
+use soundcloud::model::Playlist;
+use soundcloud::SoundcloudClient;
+use storage::Storage;
+use sync::Syncer;
+use upgrade::Upgrader;
+
+/// Entry point of the `soundcloud-dl` binary
+#[tokio::main]
+pub async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+async fn run() -> Result<()> {
+    let cli = Cli::parse();
+    config::set_portable(cli.portable);
+    lock::set_mode(cli.lock_mode());
+
+    let mut config = config::Config::new()?;
+
+    let _log_guard = cli.init_logging(&config)?;
+    let _temp_guard = temp::init(cli.temp_dir.as_deref())?;
+
+    if cli.command.is_none() && cli.config_init(&mut config)? {
+        return Ok(());
+    }
+
+    // Maintenance commands need neither a token nor an existing FFmpeg
+    match &cli.command {
+        Some(Commands::Ffmpeg { action }) => {
+            let client = cli.build_client(&config, String::new())?;
+            return ffmpeg_command(&cli, &client, *action).await;
+        }
+        Some(Commands::Doctor { .. }) => {
+            return doctor::run(&cli, &config, &cli.resolve_output_dir(&config)?).await;
+        }
+        Some(Commands::SelfUpdate { check }) => {
+            let client = cli.build_client(&config, String::new())?;
+            update::self_update(&client, *check).await?;
+            return Ok(());
+        }
+        Some(Commands::Queue {
+            action: QueueAction::List,
+        }) => {
+            for job in JobQueue::open(None)?.list()? {
+                println!(
+                    "{:<8} {:>12}  attempts: {}  {}{}",
+                    job.state,
+                    job.track_id,
+                    job.attempts,
+                    job.output_dir.display(),
+                    job.last_error
+                        .map(|e| format!("  ({})", e))
+                        .unwrap_or_default()
+                );
+            }
+            return Ok(());
+        }
+        Some(Commands::Gone) => {
+            let gone = Library::open(cli.library.clone())?.gone(None);
+            for (id, entry) in &gone {
+                println!(
+                    "{}  {:>12}  {}  {}",
+                    entry
+                        .gone_since
+                        .map(|since| since.format("%Y-%m-%d").to_string())
+                        .unwrap_or_default(),
+                    id,
+                    entry.permalink_url,
+                    entry.path.display()
+                );
+            }
+            tracing::info!(
+                "{} downloaded track(s) are gone from SoundCloud",
+                gone.len()
+            );
+            return Ok(());
+        }
+        Some(Commands::ExportArchive { file }) => {
+            let library = Library::open(cli.library.clone())?;
+            let added = DownloadArchive::open(file)?
+                .extend(library.entries().into_iter().map(|(id, _)| id))
+                .await?;
+            tracing::info!("Added {} track(s) to {}", added, file.display());
+            return Ok(());
+        }
+        Some(Commands::Verify { dir, repair }) => {
+            return verify_command(&cli, dir, *repair).await;
+        }
+        Some(Commands::Cache {
+            action: CacheAction::Clear,
+        }) => {
+            let removed = cli.metadata_cache()?.clear()?;
+            tracing::info!("Removed {} cached object(s)", removed);
+            return Ok(());
+        }
+        Some(Commands::Queue {
+            action: QueueAction::Clear { all },
+        }) => {
+            let removed = JobQueue::open(None)?.clear(*all)?;
+            tracing::info!("Removed {} job(s) from the queue", removed);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Each account gets a client of its own, so rate limits of one never slow another
+    let accounts = cli.resolve_accounts(&config)?;
+    let clients = accounts
+        .iter()
+        .map(|account| cli.build_account_client(&config, account))
+        .collect::<Result<Vec<_>>>()?;
+
+    let ffmpeg = cli.resolve_ffmpeg_path(&clients[0]).await?;
+
+    let library = Arc::new(Library::open(cli.library.clone())?);
+
+    let mut options = cli.download_options();
+    options.tag_mappings = config.tag_mappings()?;
+    if let Some(archive) = &cli.download_archive {
+        options.archive = Some(Arc::new(DownloadArchive::open(archive)?));
+    }
+    if cli.queue || matches!(cli.command, Some(Commands::Queue { .. })) {
+        options.queue = Some(Arc::new(JobQueue::open(None)?));
+    }
+
+    let mut runs = Vec::with_capacity(accounts.len());
+    for (account, client) in accounts.into_iter().zip(clients) {
+        // Placeholders in the output directory are filled in per track, like the file name
+        let (output, output_template) = template::split_output(&account.output);
+        let mut options = options.clone();
+        if let Some(dirs) = &output_template {
+            options.filename_template = options.filename_template.nested_in(dirs)?;
+        }
+        if let Some(storage) = config.storage() {
+            options.storage = Storage::from_config(storage, &output)?;
+        }
+        // Profiles share the library, but each has its own output directory
+        if account.profile.is_some() {
+            options.library_scope = Some(output.clone());
+        }
+        runs.push(AccountRun {
+            profile: account.profile,
+            output,
+            client,
+            options,
+        });
+    }
+
+    let started = Utc::now();
+    let timer = Instant::now();
+    let rate_limits = runs
+        .iter()
+        .map(|run| run.client.rate_limits())
+        .collect::<Vec<_>>();
+
+    let cancel = options.cancel.clone();
+    let command = run_accounts(&cli, &config, runs, ffmpeg, library.clone());
+    let mut command = std::pin::pin!(command);
+
+    let mut summary = tokio::select! {
+        result = &mut command => result?,
+        _ = tokio::signal::ctrl_c() => {
+            tracing::warn!("Interrupted, stopping downloads");
+            cancel.cancel();
+            // Downloads delete their partial files as they stop, a second Ctrl+C drops
+            // the command right away, which still kills any running FFmpeg processes
+            tokio::select! {
+                _ = &mut command => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            return Err(AppError::Cancelled);
+        }
+    };
+
+    if cli.command.as_ref().is_some_and(Commands::is_batch) {
+        summary.elapsed = Some(timer.elapsed());
+        summary.rate_limited = Some(
+            rate_limits
+                .iter()
+                .fold((0, Duration::ZERO), |(waits, waited), stats| {
+                    (waits + stats.waits(), waited + stats.waited())
+                }),
+        );
+        if cli.verbosity() >= 0 {
+            print!("\n{}", summary);
+        }
+        if cli.notify {
+            notify::batch_complete(&summary);
+        }
+    }
+
+    let gone = library.gone(Some(started));
+    if !gone.is_empty() && cli.verbosity() >= 0 {
+        println!(
+            "\n{} downloaded track(s) are gone from SoundCloud, your copies may be the last ones:",
+            gone.len()
+        );
+        for (_, entry) in &gone {
+            println!("  {}  {}", entry.permalink_url, entry.path.display());
+        }
+    }
+
+    if summary.failed > 0 {
+        return Err(AppError::PartialFailure {
+            failed: summary.failed,
+            total: summary.total,
+        });
+    }
+
+    Ok(())
+}
+
+/// A command run for one account
+struct AccountRun {
+    /// Name of the profile, `None` for the stored token
+    profile: Option<String>,
+    output: PathBuf,
+    client: SoundcloudClient,
+    options: DownloadOptions,
+}
+
+/// Runs the command for each account in turn, going on with the others when one fails
+///
+/// # Returns
+/// Result containing the counts across all accounts, or the last failure
+async fn run_accounts(
+    cli: &Cli,
+    config: &Config,
+    runs: Vec<AccountRun>,
+    ffmpeg: Option<FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+) -> Result<BatchSummary> {
+    let mut summary = BatchSummary::default();
+    let mut failure = None;
+
+    for run in runs {
+        if let Some(profile) = &run.profile {
+            tracing::info!(
+                "Running for profile {} into {}",
+                profile,
+                run.output.display()
+            );
+        }
+
+        let result = async {
+            if !cli.skip_auth_check {
+                check_token(&run.client).await?;
+            }
+            handle_command(
+                cli,
+                config,
+                run.output,
+                run.client,
+                ffmpeg.clone(),
+                library.clone(),
+                run.options,
+            )
+            .await
+        }
+        .await;
+        match (result, &run.profile) {
+            (Ok(account_summary), _) => summary.merge(&account_summary),
+            (Err(AppError::Cancelled), _) => return Err(AppError::Cancelled),
+            (Err(e), Some(profile)) => {
+                tracing::error!("Profile {} failed: {}", profile, e);
+                failure = Some(e);
+            }
+            (Err(e), None) => failure = Some(e),
+        }
+    }
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(summary),
+    }
+}
+
+async fn handle_command(
+    cli: &Cli,
+    config: &Config,
+    output: PathBuf,
+    client: SoundcloudClient,
+    ffmpeg: Option<FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+    options: DownloadOptions,
+) -> Result<BatchSummary> {
+    let mut summary = BatchSummary::default();
+
+    match &cli.command {
+        Some(Commands::Track { url, .. }) => {
+            let request = match Input::track(url) {
+                Input::Url(url) => DownloadRequest::TrackUrl(url),
+                Input::Track { id, secret_token } => DownloadRequest::TrackId { id, secret_token },
+                Input::Playlist { .. } => {
+                    return Err(AppError::Configuration(
+                        "Input is a playlist, use the playlist command instead".into(),
+                    ))
+                }
+            };
+
+            let downloader = Downloader::new(client, &output, ffmpeg, library, options.clone())?;
+            summary = downloader.download(request).await?;
+            tracing::info!("Track download completed successfully!");
+        }
+        Some(Commands::Likes {
+            skip,
+            limit,
+            chunk_size,
+            interactive,
+            include_playlists,
+            playlist_limit,
+            liked_after,
+            user,
+            ..
+        }) => {
+            let user = client.resolve_user(user.clone()).await?;
+            let playlist_likes = if *include_playlists {
+                tracing::info!("Fetching playlists liked by: {}", user.username);
+                client
+                    .get_playlist_likes(user.id, *playlist_limit, *liked_after)
+                    .await?
+            } else {
+                Vec::new()
+            };
+
+            let request = DownloadRequest::Likes {
+                user: Box::new(user),
+                skip: *skip,
+                limit: *limit,
+                chunk_size: *chunk_size,
+            };
+
+            let downloader = Downloader::new(
+                client.clone(),
+                &output,
+                ffmpeg.clone(),
+                library.clone(),
+                options.clone(),
+            )?;
+            summary = if *interactive {
+                let tracks = downloader.fetch(&request).await?;
+                downloader
+                    .download_tracks(tui::select_tracks(tracks)?)
+                    .await?
+            } else {
+                downloader.download(request).await?
+            };
+
+            if !playlist_likes.is_empty() {
+                tracing::info!("Found {} liked playlist(s)", playlist_likes.len());
+                let playlists_summary = download_playlists(
+                    &options.for_liked_playlists(),
+                    &output,
+                    client,
+                    ffmpeg,
+                    library,
+                    &playlist_likes,
+                    *interactive,
+                )
+                .await?;
+                summary.merge(&playlists_summary);
+            }
+            tracing::info!("Likes download completed successfully!");
+        }
+        Some(Commands::Playlist {
+            url, interactive, ..
+        }) => {
+            let playlist = fetch_playlist(&client, url).await?;
+
+            let output = if options.filename_template.has_collection() {
+                output.clone()
+            } else {
+                util::playlist_dir(&output, &playlist)
+            };
+
+            let request = DownloadRequest::Playlist(Box::new(playlist));
+
+            let downloader = Downloader::new(client, &output, ffmpeg, library, options.clone())?;
+            summary = if *interactive {
+                let tracks = downloader.fetch(&request).await?;
+                downloader
+                    .download_tracks(tui::select_tracks(tracks)?)
+                    .await?
+            } else {
+                downloader.download(request).await?
+            };
+
+            tracing::info!("Playlist download completed successfully!");
+        }
+        Some(Commands::Playlists {
+            created,
+            liked,
+            all,
+            limit,
+            user,
+            ..
+        }) => {
+            let user = client.resolve_user(user.clone()).await?;
+            let limit = limit.unwrap_or(u32::MAX);
+
+            let mut playlists = Vec::new();
+            if *created || *all || !*liked {
+                tracing::info!("Fetching playlists created by: {}", user.username);
+                playlists.extend(client.get_user_playlists(user.id, limit).await?);
+            }
+            if *liked || *all {
+                tracing::info!("Fetching playlists liked by: {}", user.username);
+                playlists.extend(client.get_playlist_likes(user.id, limit, None).await?);
+            }
+
+            let mut seen = HashSet::new();
+            playlists.retain(|playlist| seen.insert(playlist.id));
+            tracing::info!("Found {} playlist(s)", playlists.len());
+
+            summary = download_playlists(
+                &options, &output, client, ffmpeg, library, &playlists, false,
+            )
+            .await?;
+            tracing::info!("Playlists download completed successfully!");
+        }
+        Some(Commands::Feed { since, limit, .. }) => {
+            tracing::info!("Fetching activity stream");
+            let items = client.get_stream(*limit, *since).await?;
+
+            // The same upload shows up once per follower who reposted it
+            let mut seen = HashSet::new();
+            let mut tracks = Vec::new();
+            let mut playlists = Vec::new();
+            for item in items {
+                if let Some(track) = item.track {
+                    if seen.insert(("track", track.id)) {
+                        tracks.push(track);
+                    }
+                } else if let Some(playlist) = item.playlist {
+                    if seen.insert(("playlist", playlist.id)) {
+                        playlists.push(playlist);
+                    }
+                }
+            }
+
+            let downloader = Downloader::new(
+                client.clone(),
+                &output,
+                ffmpeg.clone(),
+                library.clone(),
+                options.clone(),
+            )?;
+            downloader.set_collection("Feed");
+            let tracks = downloader.select_items(tracks).await;
+            summary = downloader.download_tracks(tracks).await?;
+
+            summary.merge(
+                &download_playlists(
+                    &options, &output, client, ffmpeg, library, &playlists, false,
+                )
+                .await?,
+            );
+            tracing::info!("Feed download completed successfully!");
+        }
+        Some(Commands::Charts {
+            genre, kind, limit, ..
+        }) => {
+            tracing::info!("Fetching {} {} chart", kind.as_str(), genre);
+            let tracks = client.get_charts(kind.as_str(), genre, *limit).await?;
+
+            let chart = format!(
+                "{} {} {}",
+                genre,
+                kind.as_str(),
+                chrono::Local::now().format("%Y-%m-%d")
+            );
+            let output = if options.filename_template.has_collection() {
+                output.clone()
+            } else {
+                output.join(util::sanitize(&chart))
+            };
+
+            let downloader = Downloader::new(client, &output, ffmpeg, library, options.clone())?;
+            downloader.set_collection(&chart);
+            let tracks = downloader.select_items(tracks).await;
+            summary = downloader.download_tracks(tracks).await?;
+            tracing::info!("Chart download completed successfully!");
+        }
+        Some(Commands::Related { url, limit, .. }) => {
+            let track_id = match Input::track(url) {
+                Input::Url(url) => client.track_from_url(&url).await?.id,
+                Input::Track { id, .. } => id,
+                Input::Playlist { .. } => {
+                    return Err(AppError::Configuration(
+                        "Input is a playlist, related tracks need a track".into(),
+                    ))
+                }
+            };
+
+            tracing::info!("Fetching tracks related to track {}", track_id);
+            let tracks = client.get_related_tracks(track_id, *limit).await?;
+
+            let downloader = Downloader::new(client, &output, ffmpeg, library, options.clone())?;
+            downloader.set_collection("Related");
+            let tracks = downloader.select_items(tracks).await;
+            summary = downloader.download_tracks(tracks).await?;
+            tracing::info!("Related tracks download completed successfully!");
+        }
+        Some(Commands::Search {
+            kind,
+            limit,
+            download,
+            download_all,
+            query,
+            ..
+        }) => match kind {
+            SearchKind::Tracks => {
+                let tracks = client.search_tracks(query, *limit).await?;
+                search::print_tracks(&tracks);
+
+                let tracks = search::select(tracks.len(), download, *download_all)
+                    .into_iter()
+                    .map(|i| tracks[i].clone())
+                    .collect::<Vec<_>>();
+
+                if !tracks.is_empty() {
+                    let downloader =
+                        Downloader::new(client, &output, ffmpeg, library, options.clone())?;
+                    summary = downloader.download_tracks(tracks).await?;
+                }
+            }
+            SearchKind::Playlists => {
+                let playlists = client.search_playlists(query, *limit).await?;
+                search::print_playlists(&playlists);
+
+                let playlists = search::select(playlists.len(), download, *download_all)
+                    .into_iter()
+                    .map(|i| playlists[i].clone())
+                    .collect::<Vec<_>>();
+
+                summary = download_playlists(
+                    &options, &output, client, ffmpeg, library, &playlists, false,
+                )
+                .await?;
+            }
+            SearchKind::Users => {
+                let users = client.search_users(query, *limit).await?;
+                search::print_users(&users);
+            }
+        },
+        Some(Commands::Mirror { user, limit, .. }) => {
+            let user = client.resolve_user(user.clone()).await?;
+
+            summary = Mirror::new(
+                client,
+                ffmpeg,
+                library,
+                options.clone(),
+                &output,
+                user,
+                limit.unwrap_or(u32::MAX),
+            )
+            .run()
+            .await?;
+
+            tracing::info!("Mirror completed successfully!");
+        }
+        Some(Commands::Following {
+            export,
+            limit,
+            user,
+            action,
+        }) => {
+            let user = client.resolve_user(user.clone()).await?;
+            tracing::info!("Fetching users followed by: {}", user.username);
+            let followings = client
+                .get_followings(user.id, limit.unwrap_or(u32::MAX))
+                .await?;
+
+            match action {
+                Some(FollowingAction::Download {
+                    tracks_per_artist, ..
+                }) => {
+                    tracing::info!("Found {} followed user(s)", followings.len());
+                    summary = following::download(
+                        &options,
+                        &output,
+                        client,
+                        ffmpeg,
+                        library,
+                        &followings,
+                        *tracks_per_artist,
+                    )
+                    .await?;
+                    tracing::info!("Following download completed successfully!");
+                }
+                None => match export {
+                    Some(format) => print!("{}", following::export(&followings, *format)?),
+                    None => search::print_users(&followings),
+                },
+            }
+        }
+        Some(Commands::Snapshot { url, action }) => {
+            let (url, save) = match (action, url) {
+                (Some(SnapshotAction::Diff { url }), _) => (url, false),
+                (None, Some(url)) => (url, true),
+                (None, None) => {
+                    return Err(AppError::Configuration(
+                        "No playlist specified, pass its URL or ID".into(),
+                    ))
+                }
+            };
+
+            let playlist = fetch_playlist(&client, url).await?;
+            let store = SnapshotStore::open(playlist.id)?;
+            let previous = store.latest()?;
+            let current = Snapshot::take(&client, &playlist).await?;
+
+            match &previous {
+                Some(previous) => {
+                    println!(
+                        "{}: {} track(s), changes since {}:",
+                        current.title,
+                        current.tracks.len(),
+                        previous.taken_at.format("%Y-%m-%d %H:%M:%S UTC")
+                    );
+                    current.diff(previous).print();
+                }
+                None if save => println!(
+                    "{}: {} track(s), first snapshot",
+                    current.title,
+                    current.tracks.len()
+                ),
+                None => {
+                    return Err(AppError::Configuration(format!(
+                        "No snapshot of {} yet, take one with: soundcloud-dl snapshot {}",
+                        current.title, url
+                    )))
+                }
+            }
+
+            if save {
+                store.push(current).await?;
+            }
+        }
+        Some(Commands::Sync { names, .. }) => {
+            let definitions = config.syncs(names)?;
+            summary = Syncer::new(
+                client,
+                ffmpeg,
+                library,
+                options,
+                &output,
+                config.storage().cloned(),
+            )
+            .run(&definitions)
+            .await?;
+            tracing::info!("Sync completed successfully!");
+        }
+        Some(Commands::Clipboard { interval, .. }) => {
+            ClipboardWatcher::new(
+                client,
+                ffmpeg,
+                library,
+                options,
+                &output,
+                Duration::from_millis(*interval),
+            )
+            .run()
+            .await?;
+        }
+        Some(Commands::Import { file, column, .. }) => {
+            let downloader = Downloader::new(client, &output, ffmpeg, library, options.clone())?;
+            let import = downloader.import(file, column).await?;
+
+            tracing::info!(
+                "Resolved {} tracks from {} rows, skipped {} rows",
+                import.tracks.len(),
+                import.rows,
+                import.skipped.len()
+            );
+            for (row, reason) in &import.skipped {
+                tracing::warn!(status = "Skipped", "row {}: {}", row, reason);
+            }
+
+            let tracks = downloader.select_items(import.tracks).await;
+            summary = downloader.download_tracks(tracks).await?;
+            tracing::info!("Import completed successfully!");
+        }
+        Some(Commands::Retag { dir }) => {
+            let retagged = Downloader::new(client, dir, ffmpeg, library, options.clone())?
+                .retag(dir)
+                .await?;
+
+            tracing::info!(
+                "Retagged {} of {} file(s), {} skipped, {} failed",
+                retagged.retagged,
+                retagged.total,
+                retagged.skipped,
+                retagged.failed
+            );
+            summary.total = retagged.total;
+            summary.failed = retagged.failed;
+        }
+        Some(Commands::Organize {
+            dir,
+            filename_template,
+            dry_run,
+        }) => {
+            let mut options = options.clone();
+            if let Some(template) = filename_template {
+                options.filename_template = template.clone();
+            }
+
+            let organized = Downloader::new(client, dir, ffmpeg, library, options)?
+                .organize(dir, *dry_run)
+                .await?;
+
+            tracing::info!(
+                "{} {} of {} file(s), {} already organized, {} skipped, {} failed",
+                if *dry_run { "Would move" } else { "Moved" },
+                organized.moved,
+                organized.total,
+                organized.unchanged,
+                organized.skipped,
+                organized.failed
+            );
+            summary.total = organized.total;
+            summary.failed = organized.failed;
+        }
+        Some(Commands::Upgrade { keep_backup }) => {
+            summary = Upgrader::new(client, ffmpeg, library, options.clone(), *keep_backup)
+                .run()
+                .await?;
+            tracing::info!("Upgrade completed");
+        }
+        Some(Commands::Queue { .. }) => {
+            let queue = options
+                .queue
+                .clone()
+                .expect("queue is opened for queue commands");
+
+            for dir in queue.pending_dirs()? {
+                tracing::info!("Resuming queued downloads into {}", dir.display());
+                let downloader = Downloader::new(
+                    client.clone(),
+                    &dir,
+                    ffmpeg.clone(),
+                    library.clone(),
+                    options.clone(),
+                )?;
+                summary.merge(&downloader.run_queue(&queue, Vec::new()).await?);
+            }
+            tracing::info!("Queue completed");
+        }
+        Some(
+            Commands::Ffmpeg { .. }
+            | Commands::SelfUpdate { .. }
+            | Commands::Gone
+            | Commands::Cache { .. }
+            | Commands::Doctor { .. }
+            | Commands::ExportArchive { .. }
+            | Commands::Verify { .. },
+        ) => {
+            unreachable!("handled before authentication")
+        }
+        None => {
+            return Err(AppError::Configuration(
+                "No command specified. Use --help to see available commands.".into(),
+            ))
+        }
+    };
+
+    Ok(summary)
+}
+
+/// Fetches the playlist a `playlist` or `snapshot` argument points to
+async fn fetch_playlist(client: &SoundcloudClient, url: &str) -> Result<Playlist> {
+    match Input::playlist(url) {
+        Input::Url(url) => client.playlist_from_url(&url).await,
+        Input::Playlist { id, secret_token } => {
+            client.fetch_playlist(id, secret_token.as_deref()).await
+        }
+        Input::Track { .. } => Err(AppError::Configuration(
+            "Input is a track, use the track command instead".into(),
+        )),
+    }
+}
+
+/// Makes sure SoundCloud accepts the OAuth token, and the tokens it rotates to, before
+/// any work is started for its account, so an expired token does not fail every track
+/// of a long batch
+async fn check_token(client: &SoundcloudClient) -> Result<()> {
+    let me = match client.get_me().await {
+        Ok(user) => user,
+        Err(e) => {
+            return token_check_failed(
+                e,
+                "the OAuth token",
+                "Log in on soundcloud.com, copy the Authorization header of a request to \
+                 api-v2.soundcloud.com from the browser's developer tools (see \
+                 \"Getting Your OAuth Token\" in the README) and save it with:\n  \
+                 soundcloud-dl --auth \"OAuth <token>\" --save-token",
+            )
+        }
+    };
+    tracing::debug!("Logged in as {}", me.username);
+
+    let mut others = Vec::new();
+    for (i, token) in client.rotation_tokens().iter().enumerate() {
+        match client.get_me_as(token).await {
+            Ok(user) if user.id != me.id => others.push(user.username),
+            Ok(_) => {}
+            Err(e) => token_check_failed(
+                e,
+                &format!("rotation token {}", i + 1),
+                "Replace it in rotation_tokens in the config file.",
+            )?,
+        }
+    }
+    if !others.is_empty() {
+        tracing::info!(
+            "API calls switch to the accounts {} while {} is rate limited, private tracks \
+             only shared with {} are not found then",
+            others.join(", "),
+            me.username,
+            me.username
+        );
+    }
+
+    Ok(())
+}
+
+/// Turns a failed token check into an error asking for a new token if SoundCloud
+/// rejected it, anything else only logs a warning
+///
+/// # Arguments
+/// * `token` - Which token was checked, e.g. `the OAuth token`
+/// * `fix` - How to replace the token
+fn token_check_failed(e: AppError, token: &str, fix: &str) -> Result<()> {
+    match e {
+        AppError::Unauthorized(e) => {
+            tracing::debug!("Token check failed: {}", e);
+            Err(AppError::Auth(format!(
+                "SoundCloud rejected {}, it has probably expired.\n{}\n\
+                 Use --skip-auth-check to run without checking the token.",
+                token, fix
+            )))
+        }
+        // Anything else, e.g. a flaky connection, fails on its own later if it persists
+        e => {
+            tracing::warn!("Could not check {}: {}", token, e);
+            Ok(())
+        }
+    }
+}
+
+/// Runs the `verify` subcommand, printing every track with a problem
+async fn verify_command(cli: &Cli, dir: &Path, repair: bool) -> Result<()> {
+    let library = Library::open(cli.library.clone())?;
+    let ffmpeg = cli.find_ffmpeg().ok();
+    if ffmpeg.is_none() {
+        tracing::warn!("FFmpeg not found, only checking that files exist and are unchanged");
+    }
+
+    let findings = audit::audit(&library, ffmpeg.as_ref(), dir).await;
+    let broken = findings
+        .iter()
+        .filter(|finding| !finding.problems.is_empty())
+        .collect::<Vec<_>>();
+
+    for finding in &broken {
+        let problems = finding
+            .problems
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        tracing::warn!(
+            status = "Broken",
+            "{}: {}",
+            finding.entry.path.display(),
+            problems.join(", ")
+        );
+    }
+    tracing::info!(
+        "Checked {} file(s), {} with problems",
+        findings.len(),
+        broken.len()
+    );
+
+    if repair && !broken.is_empty() {
+        let queued = audit::queue_repairs(&JobQueue::open(None)?, &findings)?;
+        tracing::info!(
+            "Queued {} track(s) for download, run `soundcloud-dl queue run` to repair them",
+            queued
+        );
+    }
+
+    if !broken.is_empty() {
+        return Err(AppError::PartialFailure {
+            failed: broken.len(),
+            total: findings.len(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Runs an `ffmpeg` maintenance subcommand
+async fn ffmpeg_command(cli: &Cli, client: &SoundcloudClient, action: FfmpegAction) -> Result<()> {
+    let install_dir = cli.ffmpeg_path.as_ref();
+
+    match action {
+        FfmpegAction::Status => match cli.find_ffmpeg() {
+            Ok(ffmpeg) => {
+                println!("FFmpeg:  {}", ffmpeg.path().display());
+                println!("Version: {}", ffmpeg.version().await?);
+                match ffmpeg.probe_path() {
+                    Ok(path) => println!("ffprobe: {}", path.display()),
+                    Err(_) => println!("ffprobe: not found, --verify is unavailable"),
+                }
+                if let Some(build) = ffmpeg::installed_build(install_dir) {
+                    println!("Build:   {}", build);
+                }
+            }
+            Err(_) => println!("FFmpeg is not installed, run `soundcloud-dl ffmpeg install`"),
+        },
+        FfmpegAction::Install => {
+            let path = ffmpeg::download_ffmpeg(client, install_dir).await?;
+            println!("Installed FFmpeg into {}", path.display());
+        }
+        FfmpegAction::Update => {
+            let latest = ffmpeg::latest_build().await?;
+            if ffmpeg::installed_build(install_dir).as_ref() == Some(&latest) {
+                println!("FFmpeg is up to date (build {})", latest);
+            } else {
+                let path = ffmpeg::download_ffmpeg(client, install_dir).await?;
+                println!("Updated FFmpeg in {} to build {}", path.display(), latest);
+            }
+        }
+        FfmpegAction::Path => println!("{}", cli.find_ffmpeg()?.path().display()),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::Result;
+use crate::lock;
+
+/// Extractor name yt-dlp uses for SoundCloud tracks
+const EXTRACTOR: &str = "soundcloud";
+
+/// Download archive in yt-dlp's `--download-archive` format, one `soundcloud <id>`
+/// line per downloaded track
+///
+/// Sharing the archive with yt-dlp or scdl keeps the tools from downloading the same
+/// tracks twice. Lines of other extractors are left untouched.
+#[derive(Debug)]
+pub struct DownloadArchive {
+    path: PathBuf,
+    ids: Mutex<HashSet<u64>>,
+}
+
+impl DownloadArchive {
+    /// Reads the archive at `path`, which is created on the first download if missing
+    pub fn open(path: &Path) -> Result<Self> {
+        let ids = if path.exists() {
+            parse(&fs::read_to_string(path)?)
+        } else {
+            HashSet::new()
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            ids: Mutex::new(ids),
+        })
+    }
+
+    /// Whether a track is listed, as of the last time the archive was read
+    pub fn contains(&self, id: u64) -> bool {
+        self.ids.lock().unwrap().contains(&id)
+    }
+
+    /// Picks up the tracks other instances added since the archive was read
+    pub async fn reload(&self) -> Result<()> {
+        let _lock = lock::lock(&self.path).await?;
+        self.read_saved()
+    }
+
+    /// Appends a downloaded track to the archive unless it is already listed
+    ///
+    /// Tracks other instances added since the archive was read are picked up first,
+    /// so none is listed twice.
+    pub async fn record(&self, id: u64) -> Result<()> {
+        if self.contains(id) {
+            return Ok(());
+        }
+
+        let _lock = lock::lock(&self.path).await?;
+        self.read_saved()?;
+        if !self.ids.lock().unwrap().insert(id) {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{} {}", EXTRACTOR, id)?;
+
+        Ok(())
+    }
+
+    /// Adds tracks to the archive, skipping those already listed
+    ///
+    /// # Returns
+    /// Result containing the number of added tracks
+    pub async fn extend(&self, ids: impl IntoIterator<Item = u64>) -> Result<usize> {
+        let mut added = 0;
+        for id in ids {
+            if !self.contains(id) {
+                self.record(id).await?;
+                added += 1;
+            }
+        }
+
+        Ok(added)
+    }
+
+    /// Adds the tracks listed in the archive file to the ones read before, to be called
+    /// with the lock held
+    fn read_saved(&self) -> Result<()> {
+        if self.path.exists() {
+            let saved = parse(&fs::read_to_string(&self.path)?);
+            self.ids.lock().unwrap().extend(saved);
+        }
+        Ok(())
+    }
+}
+
+/// Collects the SoundCloud track IDs of an archive, ignoring other extractors
+fn parse(archive: &str) -> HashSet<u64> {
+    archive
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let extractor = parts.next()?;
+            let id = parts.next()?;
+            extractor
+                .eq_ignore_ascii_case(EXTRACTOR)
+                .then(|| id.parse().ok())
+                .flatten()
+        })
+        .collect()
+}
@@ -0,0 +1,104 @@
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::io::Cursor;
+
+use crate::error::{AppError, Result};
+use crate::soundcloud::DownloadedFile;
+
+/// Quality of re-encoded JPEG artwork
+const JPEG_QUALITY: u8 = 90;
+
+/// Image format embedded artwork is converted to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ArtworkFormat {
+    Jpeg,
+    Png,
+}
+
+impl ArtworkFormat {
+    fn from_ext(ext: &str) -> Option<Self> {
+        match ext {
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+
+    fn ext(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+        }
+    }
+}
+
+/// How cover art is processed before it is embedded
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ArtworkOptions {
+    /// Longest side in pixels, larger artwork is scaled down
+    pub max_size: Option<u32>,
+    /// Format to convert artwork to, the original format is kept when `None`
+    pub format: Option<ArtworkFormat>,
+}
+
+impl ArtworkOptions {
+    /// Scales down and converts cover art as configured
+    ///
+    /// Artwork that already fits is returned untouched, without re-encoding it.
+    ///
+    /// # Arguments
+    /// * `artwork` - The downloaded cover art
+    ///
+    /// # Returns
+    /// Result containing the processed cover art
+    pub async fn apply(self, artwork: DownloadedFile) -> Result<DownloadedFile> {
+        if self.max_size.is_none() && self.format.is_none() {
+            return Ok(artwork);
+        }
+
+        // Decoding and resizing a large image takes a while, keep it off the runtime
+        tokio::task::spawn_blocking(move || self.apply_blocking(artwork))
+            .await
+            .map_err(|e| AppError::Audio(format!("Artwork processing failed: {}", e)))?
+    }
+
+    fn apply_blocking(self, artwork: DownloadedFile) -> Result<DownloadedFile> {
+        let image = image::load_from_memory(&artwork.data)?;
+        let original = ArtworkFormat::from_ext(&artwork.file_ext);
+
+        let too_large = self
+            .max_size
+            .is_some_and(|max| image.width() > max || image.height() > max);
+        let format = self.format.or(original).unwrap_or(ArtworkFormat::Jpeg);
+
+        if !too_large && original == Some(format) {
+            return Ok(artwork);
+        }
+
+        let image = match self.max_size {
+            Some(max) if too_large => image.resize(max, max, FilterType::Lanczos3),
+            _ => image,
+        };
+
+        Ok(DownloadedFile {
+            data: encode(&image, format)?.into(),
+            file_ext: format.ext().to_string(),
+        })
+    }
+}
+
+fn encode(image: &DynamicImage, format: ArtworkFormat) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+
+    match format {
+        // JPEG has no alpha channel
+        ArtworkFormat::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY))?,
+        ArtworkFormat::Png => image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)?,
+    }
+
+    Ok(data)
+}
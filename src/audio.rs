@@ -1,22 +1,42 @@
 use bytes::Bytes;
-use id3::frame::{Picture, PictureType};
-use id3::{TagLike, Version};
+use id3::frame::{Comment, Picture, PictureType};
+use id3::{Frame, TagLike};
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Seek, Write};
+use std::ops::Range;
 use std::path::Path;
+use std::time::Duration;
 
+use crate::cue::TracklistEntry;
 use crate::downloader::Downloader;
 use crate::error::{AppError, Result};
+use crate::soundcloud::model::Track;
 use crate::soundcloud::DownloadedFile;
+use crate::tags::{Id3Version, TagField, Tags};
+use crate::util;
+
+/// Audio quieter than this counts as silence when splitting mixes
+const SILENCE_NOISE: &str = "-50dB";
+/// Padding reserved after a new ID3 tag, so retagging rewrites the tag in place
+/// instead of moving the audio behind it
+const ID3_PADDING: usize = 4096;
+/// Minimum length of a silent gap between two songs of a mix
+const SILENCE_MIN_DURATION: Duration = Duration::from_secs(2);
+/// Gaps this close to the start of a mix are part of the intro, not song boundaries
+const MIN_SEGMENT_LENGTH: Duration = Duration::from_secs(30);
 
 impl Downloader {
     /// Processes and saves an MP3 file with optional thumbnail metadata
     ///
+    /// The stream is written to disk first, without the tags it was served with, so
+    /// it is not held in memory while the artwork is encoded. The ID3 tag is then
+    /// written ahead of it in a single pass, with padding left for later retags.
+    ///
     /// # Arguments
     /// * `path` - Output path for the file
     /// * `audio` - Audio file bytes
-    /// * `thumbnail` - Thumbnail image bytes
-    /// * `thumbnail_ext` - Thumbnail image file extension
+    /// * `thumbnail` - Thumbnail image bytes, moved into the tag without a copy
+    /// * `tags` - Text tags to write
     ///
     /// # Returns
     /// Result indicating success or failure
@@ -25,15 +45,126 @@ impl Downloader {
         path: P,
         audio: Bytes,
         thumbnail: Option<DownloadedFile>,
+        tags: &Tags,
     ) -> Result<()> {
-        let file = File::create(path.as_ref())?;
-        let mut writer = BufWriter::new(file);
-        writer.write_all(&audio)?;
+        let path = path.as_ref();
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+
+        // Removed when dropped, also when tagging fails
+        let mut stream = tempfile::Builder::new()
+            .prefix(".")
+            .suffix(".stream")
+            .tempfile_in(dir)?;
+        stream.write_all(&audio[untagged_range(&audio)])?;
+        drop(audio);
+        stream.rewind()?;
+
+        let tag = self.id3_tag(id3::Tag::new(), thumbnail, tags);
+        let mut writer = BufWriter::new(File::create(path)?);
+        if tag.frames().next().is_some() {
+            id3::Encoder::new()
+                .version(self.options.id3_version.into())
+                .padding(ID3_PADDING)
+                .encode(&tag, &mut writer)?;
+        }
+        drop(tag);
+        std::io::copy(stream.as_file_mut(), &mut writer)?;
+        if self.options.id3v1 {
+            writer.write_all(&id3v1_tag(tags))?;
+        }
         writer.flush()?;
 
-        if let Some(thumbnail) = thumbnail {
-            let mut tag = id3::Tag::new();
+        Ok(())
+    }
+
+    /// Adds text tags and cover art to an ID3 tag and writes it to an MP3 file,
+    /// followed by an ID3v1 tag if enabled
+    ///
+    /// Frames of `tag` that are not replaced are kept, so an existing tag can be
+    /// updated in place.
+    ///
+    /// # Arguments
+    /// * `path` - The MP3 file
+    /// * `tag` - Tag to add the frames to
+    /// * `thumbnail` - Thumbnail image bytes
+    /// * `tags` - Text tags to write
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    pub fn write_id3(
+        &self,
+        path: &Path,
+        tag: id3::Tag,
+        thumbnail: Option<DownloadedFile>,
+        tags: &Tags,
+    ) -> Result<()> {
+        let tag = self.id3_tag(tag, thumbnail, tags);
 
+        if tag.frames().next().is_some() {
+            tag.write_to_path(path, self.options.id3_version.into())?;
+        }
+
+        if self.options.id3v1 {
+            // Never stack a second ID3v1 tag onto a file that is tagged again
+            id3::v1::Tag::remove_from_path(path)?;
+            File::options()
+                .append(true)
+                .open(path)?
+                .write_all(&id3v1_tag(tags))?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds text tags and cover art to an ID3 tag
+    ///
+    /// # Arguments
+    /// * `tag` - Tag to add the frames to
+    /// * `thumbnail` - Thumbnail image bytes, moved into the picture frame
+    /// * `tags` - Text tags to write
+    ///
+    /// # Returns
+    /// The tag with the frames added
+    fn id3_tag(
+        &self,
+        mut tag: id3::Tag,
+        thumbnail: Option<DownloadedFile>,
+        tags: &Tags,
+    ) -> id3::Tag {
+        let version = self.options.id3_version;
+
+        for (field, value) in tags.iter() {
+            match field {
+                TagField::Comment => {
+                    tag.add_frame(Comment {
+                        lang: "eng".to_string(),
+                        description: String::new(),
+                        text: value.to_string(),
+                    });
+                }
+                // ID3v2.3 has no TDRC, the date is split into year and day/month frames
+                TagField::Date if matches!(version, Id3Version::V23) => {
+                    if let Some((year, month, day)) = split_date(value) {
+                        tag.set_text("TYER", year);
+                        tag.set_text("TDAT", format!("{}{}", day, month));
+                    }
+                }
+                _ => tag.set_text(field.id3_frame(), value),
+            }
+        }
+
+        for (frame, value) in tags.id3_custom() {
+            if frame.starts_with('W') {
+                tag.add_frame(Frame::link(frame, value));
+            } else {
+                tag.set_text(frame, value);
+            }
+        }
+
+        if let Some(thumbnail) = thumbnail {
             // Use more specific mime type and ensure proper formatting
             let mime_type = match thumbnail.file_ext.as_str() {
                 "jpg" | "jpeg" => "image/jpeg",
@@ -45,24 +176,26 @@ impl Downloader {
                 mime_type: mime_type.to_string(),
                 picture_type: PictureType::CoverFront,
                 description: "Front Cover".to_string(),
-                data: thumbnail.data.to_vec(),
+                // Takes over the buffer when nothing else shares it
+                data: Vec::from(thumbnail.data),
             };
             tag.add_frame(picture);
-
-            // Write with ID3v2.4 which has better support for large artwork
-            tag.write_to_path(&path.as_ref(), Version::Id3v24)?;
         }
 
-        Ok(())
+        tag
     }
 
     /// Processes and saves an M4A file with optional thumbnail metadata and duration
     ///
+    /// Without FFmpeg the file is saved as is and the tags are written to it directly,
+    /// leaving out chapters and mapped tags.
+    ///
     /// # Arguments
     /// * `path` - Output path for the file
     /// * `audio` - Audio file bytes
     /// * `thumbnail` - Thumbnail image bytes
-    /// * `thumbnail_ext` - Thumbnail image file extension
+    /// * `tags` - Text tags to write
+    /// * `chapters` - FFmpeg metadata file defining chapters
     ///
     /// # Returns
     /// Result indicating success or failure
@@ -71,9 +204,23 @@ impl Downloader {
         path: P,
         audio: Bytes,
         thumbnail: Option<DownloadedFile>,
+        tags: &Tags,
+        chapters: Option<&str>,
     ) -> Result<()> {
-        self.ffmpeg
-            .reformat_m4a(audio, thumbnail, path.as_ref().to_path_buf())
+        let Some(ffmpeg) = &self.ffmpeg else {
+            write_file(path.as_ref(), &audio)?;
+            return write_mp4_tags(path.as_ref(), thumbnail, tags);
+        };
+
+        ffmpeg
+            .reformat_m4a(
+                audio,
+                thumbnail,
+                tags,
+                chapters,
+                path.as_ref().to_path_buf(),
+            )
+            .await
     }
 
     /// Processes and saves an OGG file, currently without any additional metadata
@@ -93,12 +240,7 @@ impl Downloader {
         audio: Bytes,
         _thumbnail: Option<DownloadedFile>,
     ) -> Result<()> {
-        let file = File::create(path.as_ref())?;
-        let mut writer = BufWriter::new(file);
-        writer.write_all(&audio)?;
-        writer.flush()?;
-
-        Ok(())
+        write_file(path.as_ref(), &audio)
     }
 
     pub async fn process_m3u8<P: AsRef<Path>>(
@@ -106,13 +248,19 @@ impl Downloader {
         path: P,
         playlist_data: Bytes,
         thumbnail: Option<DownloadedFile>,
+        tags: &Tags,
+        chapters: Option<&str>,
     ) -> Result<()> {
         // Use FFmpeg to convert the concatenated segments to m4a
-        self.ffmpeg.process_m3u8(
-            Bytes::from(playlist_data),
-            thumbnail,
-            path.as_ref().to_path_buf(),
-        )?;
+        self.require_ffmpeg("HLS streams")?
+            .process_m3u8(
+                playlist_data,
+                thumbnail,
+                tags,
+                chapters,
+                path.as_ref().to_path_buf(),
+            )
+            .await?;
 
         Ok(())
     }
@@ -124,7 +272,8 @@ impl Downloader {
     /// * `audio` - Audio file bytes
     /// * `audio_ext` - Audio file extension
     /// * `thumbnail` - Thumbnail image bytes
-    /// * `thumbnail_ext` - Thumbnail image file extension
+    /// * `tags` - Text tags to write
+    /// * `chapters` - FFmpeg metadata file defining chapters, only embedded in M4A files
     ///
     /// # Returns
     /// Result indicating success or failure
@@ -134,14 +283,21 @@ impl Downloader {
         audio: DownloadedFile,
         audio_ext: &str,
         thumbnail: Option<DownloadedFile>,
+        tags: &Tags,
+        chapters: Option<&str>,
     ) -> Result<()> {
         if audio.file_ext == "m3u8" {
-            return self.process_m3u8(path, audio.data, thumbnail).await;
+            return self
+                .process_m3u8(path, audio.data, thumbnail, tags, chapters)
+                .await;
         }
 
         match audio_ext {
-            "mp3" => self.process_mp3(path, audio.data, thumbnail).await,
-            "m4a" => self.process_m4a(path, audio.data, thumbnail).await,
+            "mp3" => self.process_mp3(path, audio.data, thumbnail, tags).await,
+            "m4a" => {
+                self.process_m4a(path, audio.data, thumbnail, tags, chapters)
+                    .await
+            }
             "ogg" => self.process_ogg(path, audio.data, thumbnail).await,
             _ => Err(AppError::Audio(format!(
                 "Unsupported audio format: {}",
@@ -149,4 +305,307 @@ impl Downloader {
             ))),
         }
     }
+
+    /// Cuts a downloaded mix into one tagged file per song, in a folder named after the mix
+    ///
+    /// Cuts at the tracklist's start times when there is one, otherwise at silent gaps.
+    ///
+    /// # Arguments
+    /// * `track` - The mix
+    /// * `path` - Path of the downloaded mix
+    /// * `tracklist` - Songs within the mix, may be empty
+    ///
+    /// # Returns
+    /// Result containing the number of files written
+    pub async fn split_mix(
+        &self,
+        track: &Track,
+        path: &Path,
+        tracklist: &[TracklistEntry],
+    ) -> Result<usize> {
+        let mut segments = if tracklist.is_empty() {
+            self.require_ffmpeg("Splitting mixes")?
+                .detect_silence(path, SILENCE_NOISE, SILENCE_MIN_DURATION)
+                .await?
+                .into_iter()
+                .filter(|gap| *gap >= MIN_SEGMENT_LENGTH)
+                .map(|gap| (gap, None, None))
+                .collect::<Vec<_>>()
+        } else {
+            tracklist
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.start,
+                        Some(entry.title.clone()),
+                        entry.performer.clone(),
+                    )
+                })
+                .collect()
+        };
+
+        // Silence detection only finds the gaps between songs, the first song starts at 0
+        if segments.first().is_none_or(|(start, ..)| !start.is_zero()) {
+            segments.insert(0, (Duration::ZERO, None, None));
+        }
+
+        if segments.len() < 2 {
+            return Ok(0);
+        }
+
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("m4a");
+        let dir = path.with_extension("");
+        std::fs::create_dir_all(&dir)?;
+
+        let total = segments.len();
+        for (i, (start, title, artist)) in segments.iter().enumerate() {
+            let number = i + 1;
+            let end = segments.get(i + 1).map(|(next, ..)| *next);
+            let title = title
+                .clone()
+                .unwrap_or_else(|| format!("{} (Part {})", track.title, number));
+            let artist = artist
+                .clone()
+                .unwrap_or_else(|| track.user.username.clone());
+
+            let mut tags = self.tags(track);
+            tags.push(TagField::Title, title.clone());
+            tags.push(TagField::Artist, artist.clone());
+            tags.push(TagField::Album, track.title.clone());
+            tags.push(TagField::TrackNumber, format!("{}/{}", number, total));
+
+            let file_name =
+                util::sanitize(&format!("{:02} - {} - {}.{}", number, artist, title, ext));
+            let segment_path = dir.join(file_name);
+            let part = util::part_path(&segment_path);
+            let extract = self.require_ffmpeg("Splitting mixes")?.extract_segment(
+                path,
+                *start,
+                end,
+                &tags,
+                part.clone(),
+            );
+            if let Err(e) = self.cancellable(extract).await {
+                let _ = std::fs::remove_file(&part);
+                return Err(e);
+            }
+            std::fs::rename(&part, &segment_path)?;
+        }
+
+        Ok(total)
+    }
+}
+
+/// Writes downloaded audio to disk as is
+fn write_file(path: &Path, audio: &[u8]) -> Result<()> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(audio)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Returns the part of an MP3 stream between its leading ID3v2 tags and a trailing
+/// ID3v1 tag, so the tags written in their place are the only ones in the file
+fn untagged_range(audio: &[u8]) -> Range<usize> {
+    let mut start = 0;
+    while let Some(header) = audio
+        .get(start..start + 10)
+        .filter(|header| header.starts_with(b"ID3"))
+    {
+        // Syncsafe size of the tag without its header, plus a footer if flagged
+        let size = header[6..10]
+            .iter()
+            .fold(0, |size, byte| size << 7 | usize::from(byte & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        start = (start + 10 + size + footer).min(audio.len());
+    }
+
+    let mut end = audio.len();
+    if end - start >= 128 && audio[end - 128..].starts_with(b"TAG") {
+        end -= 128;
+    }
+
+    start..end
+}
+
+/// Splits a `YYYY-MM-DD` date into its parts
+fn split_date(date: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = date.splitn(3, '-');
+    Some((parts.next()?, parts.next()?, parts.next()?))
+}
+
+/// Adds text tags and cover art to the metadata of an M4A file
+///
+/// Atoms the file was served with are kept unless a tag replaces them.
+///
+/// # Arguments
+/// * `path` - The M4A file
+/// * `thumbnail` - Thumbnail image bytes
+/// * `tags` - Text tags to write
+fn write_mp4_tags(path: &Path, thumbnail: Option<DownloadedFile>, tags: &Tags) -> Result<()> {
+    let mut tag = mp4ameta::Tag::read_from_path(path)?;
+
+    for (field, value) in tags.iter() {
+        match field {
+            TagField::Title => tag.set_title(value),
+            TagField::Artist => tag.set_artist(value),
+            TagField::Album => tag.set_album(value),
+            TagField::Genre => tag.set_genre(value),
+            TagField::Copyright => tag.set_copyright(value),
+            TagField::Comment => tag.set_comment(value),
+            TagField::Date => tag.set_year(value),
+            TagField::TrackNumber => {
+                let (number, total) = value.split_once('/').unwrap_or((value, ""));
+                if let Ok(number) = number.parse() {
+                    tag.set_track_number(number);
+                }
+                if let Ok(total) = total.parse() {
+                    tag.set_total_tracks(total);
+                }
+            }
+        }
+    }
+
+    if let Some(thumbnail) = thumbnail {
+        let data = Vec::from(thumbnail.data);
+        match thumbnail.file_ext.as_str() {
+            "png" => tag.set_artwork(mp4ameta::Img::png(data)),
+            _ => tag.set_artwork(mp4ameta::Img::jpeg(data)),
+        }
+    }
+
+    tag.write_to_path(path)?;
+    Ok(())
+}
+
+/// Builds a 128 byte ID3v1.1 tag for players that cannot read ID3v2, appended after
+/// the audio
+///
+/// Fields are cut to their fixed lengths and characters outside Latin-1 are replaced.
+fn id3v1_tag(tags: &Tags) -> Vec<u8> {
+    fn field(value: Option<&str>, len: usize) -> Vec<u8> {
+        let mut bytes = value
+            .unwrap_or_default()
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+            .take(len)
+            .collect::<Vec<_>>();
+        bytes.resize(len, 0);
+        bytes
+    }
+
+    let track = tags
+        .get(TagField::TrackNumber)
+        .and_then(|number| number.split('/').next()?.parse::<u8>().ok())
+        .unwrap_or(0);
+
+    let mut tag = b"TAG".to_vec();
+    tag.extend(field(tags.get(TagField::Title), 30));
+    tag.extend(field(tags.get(TagField::Artist), 30));
+    tag.extend(field(tags.get(TagField::Album), 30));
+    tag.extend(field(tags.get(TagField::Date), 4));
+    tag.extend(field(tags.get(TagField::Comment), 28));
+    tag.extend([0, track, u8::MAX]); // ID3v1.1 track number, unknown genre
+    tag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id3v2_header(size: usize, flags: u8) -> Vec<u8> {
+        let mut header = b"ID3\x04\x00".to_vec();
+        header.push(flags);
+        header.extend((0..4).rev().map(|i| ((size >> (7 * i)) & 0x7f) as u8));
+        header
+    }
+
+    fn atom(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut atom = ((8 + content.len()) as u32).to_be_bytes().to_vec();
+        atom.extend(kind);
+        atom.extend(content);
+        atom
+    }
+
+    /// An M4A file with a movie header, no tracks and a few bytes of media data
+    fn m4a() -> Vec<u8> {
+        let mut mvhd = vec![0; 100];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes()); // timescale
+        mvhd[20..24].copy_from_slice(&0x0001_0000u32.to_be_bytes()); // rate 1.0
+
+        let mut file = atom(b"ftyp", b"M4A \0\0\0\0M4A isom");
+        file.extend(atom(b"moov", &atom(b"mvhd", &mvhd)));
+        file.extend(atom(b"mdat", &[1, 2, 3, 4]));
+        file
+    }
+
+    #[test]
+    fn writes_mp4_tags() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("track.m4a");
+        std::fs::write(&path, m4a()).unwrap();
+
+        let mut tags = Tags::default();
+        tags.push(TagField::Title, "Title".into());
+        tags.push(TagField::Artist, "Artist".into());
+        tags.push(TagField::Album, "Album".into());
+        tags.push(TagField::Genre, "House".into());
+        tags.push(TagField::Date, "2024-05-06".into());
+        tags.push(TagField::TrackNumber, "2/7".into());
+        let cover = DownloadedFile {
+            data: Bytes::from_static(b"\x89PNG"),
+            file_ext: "png".into(),
+        };
+        write_mp4_tags(&path, Some(cover), &tags).unwrap();
+
+        let tag = mp4ameta::Tag::read_from_path(&path).unwrap();
+        assert_eq!(tag.title(), Some("Title"));
+        assert_eq!(tag.artist(), Some("Artist"));
+        assert_eq!(tag.album(), Some("Album"));
+        assert_eq!(tag.genre(), Some("House"));
+        assert_eq!(tag.year(), Some("2024-05-06"));
+        assert_eq!(tag.track(), (Some(2), Some(7)));
+        let artwork = tag.artwork().unwrap();
+        assert_eq!(artwork.fmt, mp4ameta::ImgFmt::Png);
+        assert_eq!(artwork.data, b"\x89PNG");
+    }
+
+    #[test]
+    fn untagged_range_keeps_untagged_audio() {
+        let audio = [0xff, 0xfb, 1, 2, 3];
+        assert_eq!(untagged_range(&audio), 0..5);
+    }
+
+    #[test]
+    fn untagged_range_skips_id3v2_tags() {
+        let mut audio = id3v2_header(300, 0);
+        audio.resize(10 + 300, 0);
+        audio.extend(id3v2_header(5, 0x10));
+        audio.resize(audio.len() + 5 + 10, 0);
+        let start = audio.len();
+        audio.extend([0xff, 0xfb, 1, 2, 3]);
+
+        assert_eq!(untagged_range(&audio), start..start + 5);
+    }
+
+    #[test]
+    fn untagged_range_drops_id3v1_tag() {
+        let mut audio = vec![0xff; 200];
+        audio.extend(id3v1_tag(&Tags::default()));
+
+        assert_eq!(untagged_range(&audio), 0..200);
+    }
+
+    #[test]
+    fn untagged_range_clamps_oversized_tag() {
+        let mut audio = id3v2_header(1000, 0);
+        audio.extend([0xff, 0xfb]);
+
+        assert_eq!(untagged_range(&audio), 12..12);
+    }
 }
@@ -0,0 +1,201 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::ffmpeg::FFmpeg;
+use crate::library::{Library, LibraryEntry};
+use crate::queue::{JobQueue, Priority};
+use crate::util;
+
+/// Allowed difference between the measured length and the one recorded by ffprobe
+/// at download time
+const MEASURED_DURATION_TOLERANCE: Duration = Duration::from_secs(1);
+/// Allowed difference between the measured length and the API's, for files
+/// downloaded before lengths were measured
+const API_DURATION_TOLERANCE: Duration = Duration::from_secs(2);
+/// Tags FFmpeg writes to every M4A file, which do not count as the file being tagged
+const CONTAINER_TAGS: &[&str] = &[
+    "major_brand",
+    "minor_version",
+    "compatible_brands",
+    "encoder",
+];
+
+/// Something wrong with a downloaded file
+#[derive(Clone, Debug)]
+pub enum Problem {
+    Missing,
+    /// The contents changed since the file was saved
+    ChecksumMismatch,
+    NoAudio,
+    DurationMismatch {
+        expected: Duration,
+        actual: Duration,
+    },
+    NoTags,
+    /// The file could not be read or probed
+    Unreadable(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "file is missing"),
+            Self::ChecksumMismatch => write!(f, "checksum does not match"),
+            Self::NoAudio => write!(f, "no audio stream"),
+            Self::DurationMismatch { expected, actual } => write!(
+                f,
+                "duration is {:.1}s, expected {:.1}s",
+                actual.as_secs_f64(),
+                expected.as_secs_f64()
+            ),
+            Self::NoTags => write!(f, "tags are missing"),
+            Self::Unreadable(e) => write!(f, "cannot be read: {}", e),
+        }
+    }
+}
+
+/// Outcome of checking one library track
+pub struct Finding {
+    pub id: u64,
+    pub entry: LibraryEntry,
+    /// Empty when the file is intact
+    pub problems: Vec<Problem>,
+}
+
+/// Checks the file of every library track within a directory
+///
+/// Files must exist and match the checksum recorded when they were saved. With
+/// FFmpeg, they must also contain audio of the recorded length and be tagged.
+///
+/// # Arguments
+/// * `library` - Library listing the tracks to check
+/// * `ffmpeg` - Used to probe the files, the checks needing it are skipped without it
+/// * `dir` - Only tracks saved within this directory are checked
+///
+/// # Returns
+/// A finding for every checked track
+pub async fn audit(
+    library: &Library,
+    ffmpeg: Option<&FFmpeg<PathBuf>>,
+    dir: &Path,
+) -> Vec<Finding> {
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let mut findings = Vec::new();
+
+    for (id, entry) in library.entries() {
+        let in_dir = std::path::absolute(&entry.path).is_ok_and(|path| path.starts_with(&dir));
+        if !in_dir {
+            continue;
+        }
+
+        let problems = check(&entry, ffmpeg).await;
+        findings.push(Finding {
+            id,
+            entry,
+            problems,
+        });
+    }
+
+    findings
+}
+
+async fn check(entry: &LibraryEntry, ffmpeg: Option<&FFmpeg<PathBuf>>) -> Vec<Problem> {
+    let path = &entry.path;
+    if !path.is_file() {
+        // Only the storage backend has a copy, which cannot be checked from here
+        if let Some(location) = &entry.stored_at {
+            tracing::debug!("{} is only kept at {}", path.display(), location);
+            return Vec::new();
+        }
+        return vec![Problem::Missing];
+    }
+
+    let mut problems = Vec::new();
+
+    if let Some(info) = &entry.file {
+        let hashed = path.clone();
+        let sha256 = tokio::task::spawn_blocking(move || util::file_sha256(&hashed)).await;
+        match sha256 {
+            Ok(Ok(sha256)) if sha256 == info.sha256 => {}
+            Ok(Ok(_)) => problems.push(Problem::ChecksumMismatch),
+            Ok(Err(e)) => return vec![Problem::Unreadable(e.to_string())],
+            Err(e) => return vec![Problem::Unreadable(e.to_string())],
+        }
+    }
+
+    let Some(ffmpeg) = ffmpeg else {
+        return problems;
+    };
+
+    let probe = match ffmpeg.probe(path).await {
+        Ok(probe) => probe,
+        Err(e) => {
+            problems.push(Problem::Unreadable(e.to_string()));
+            return problems;
+        }
+    };
+
+    if probe.audio_streams == 0 {
+        problems.push(Problem::NoAudio);
+    }
+
+    let expected = match entry.file.as_ref().and_then(|info| info.duration) {
+        Some(ms) => Some((Duration::from_millis(ms), MEASURED_DURATION_TOLERANCE)),
+        None => entry
+            .duration
+            .filter(|ms| *ms > 0)
+            .map(|ms| (Duration::from_millis(ms), API_DURATION_TOLERANCE)),
+    };
+    if let (Some((expected, tolerance)), Some(actual)) = (expected, probe.duration) {
+        if actual.abs_diff(expected) > tolerance {
+            problems.push(Problem::DurationMismatch { expected, actual });
+        }
+    }
+
+    let tagged = match util::audio_ext(path) {
+        Some("mp3") => id3::Tag::read_from_path(path).is_ok(),
+        Some("m4a") => probe
+            .tags
+            .keys()
+            .any(|key| !CONTAINER_TAGS.contains(&key.as_str())),
+        // Other formats are saved without tags
+        _ => true,
+    };
+    if !tagged {
+        problems.push(Problem::NoTags);
+    }
+
+    problems
+}
+
+/// Queues a download of every track with a problem into the directory of its file
+///
+/// # Returns
+/// Result containing the number of queued tracks
+pub fn queue_repairs(queue: &JobQueue, findings: &[Finding]) -> Result<usize> {
+    let mut queued = 0;
+
+    for finding in findings
+        .iter()
+        .filter(|finding| !finding.problems.is_empty())
+    {
+        let dir = finding
+            .entry
+            .path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+
+        queue.requeue(
+            finding.id,
+            util::secret_token(&finding.entry.permalink_url).as_deref(),
+            dir,
+            Priority::Bulk,
+        )?;
+        queued += 1;
+    }
+
+    Ok(queued)
+}
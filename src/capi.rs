@@ -0,0 +1,326 @@
+//! C ABI for embedding the downloader in desktop GUIs without shelling out
+//!
+//! Build it as a shared library with `cargo build --release --features capi`, which
+//! writes `libsoundcloud_dl.so`, `libsoundcloud_dl.dylib` or `soundcloud_dl.dll` to
+//! `target/release`. The declarations are in `include/soundcloud_dl.h`.
+//!
+//! A handle owns a runtime downloading in the background. URLs are queued with
+//! [`scdl_enqueue_url`], and progress is read as JSON events with [`scdl_poll_event`]:
+//!
+//! - `{"event": "track_start", "track_id": 1, "title": "..."}`
+//! - `{"event": "bytes", "track_id": 1, "received": 1024, "total": 4096}`
+//! - `{"event": "track_done", "track_id": 1, "path": "..."}`
+//! - `{"event": "track_error", "track_id": 1, "message": "..."}`
+//! - `{"event": "finished", "url": "...", "downloaded": 1, "failed": 0, "skipped": 0}`
+//! - `{"event": "failed", "url": "...", "message": "..."}`
+
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
+
+use crate::downloader::{BatchSummary, DownloadOptions, DownloadRequest, Downloader};
+use crate::error::{AppError, Result};
+use crate::ffmpeg::FFmpeg;
+use crate::library::Library;
+use crate::progress::ProgressReporter;
+use crate::soundcloud::model::{Resource, Track};
+use crate::soundcloud::SoundcloudClient;
+use crate::util;
+
+/// A downloader with its own runtime, created by [`scdl_init`]
+pub struct ScdlHandle {
+    runtime: Runtime,
+    client: SoundcloudClient,
+    ffmpeg: Option<FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+    output: PathBuf,
+    events: Arc<EventQueue>,
+    /// Options of the next downloads, whose token is replaced once cancelled
+    options: Mutex<DownloadOptions>,
+    /// Threads driving the queued URLs, joined before the runtime is shut down
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Events waiting to be polled
+#[derive(Debug, Default)]
+struct EventQueue(Mutex<VecDeque<Value>>);
+
+impl EventQueue {
+    fn push(&self, event: Value) {
+        self.0.lock().unwrap().push_back(event);
+    }
+}
+
+impl ProgressReporter for EventQueue {
+    fn on_track_start(&self, track: &Track) {
+        self.push(json!({
+            "event": "track_start",
+            "track_id": track.id,
+            "title": track.title,
+        }));
+    }
+
+    fn on_bytes(&self, track_id: u64, received: u64, total: Option<u64>) {
+        let event = json!({
+            "event": "bytes",
+            "track_id": track_id,
+            "received": received,
+            "total": total,
+        });
+
+        // Only the latest count of a track is kept until it is polled
+        let mut events = self.0.lock().unwrap();
+        match events.back_mut() {
+            Some(last) if last["event"] == "bytes" && last["track_id"] == track_id => *last = event,
+            _ => events.push_back(event),
+        }
+    }
+
+    fn on_track_done(&self, track: &Track, path: &Path) {
+        self.push(json!({
+            "event": "track_done",
+            "track_id": track.id,
+            "path": path.display().to_string(),
+        }));
+    }
+
+    fn on_error(&self, track: &Track, error: &AppError) {
+        self.push(json!({
+            "event": "track_error",
+            "track_id": track.id,
+            "message": error.to_string(),
+        }));
+    }
+}
+
+impl ScdlHandle {
+    fn new(oauth_token: String, output: PathBuf) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let client = SoundcloudClient::builder(oauth_token).build()?;
+        let events = Arc::new(EventQueue::default());
+        let options = DownloadOptions {
+            progress: Some(events.clone()),
+            ..DownloadOptions::default()
+        };
+
+        Ok(Self {
+            runtime,
+            client,
+            ffmpeg: FFmpeg::default().ok(),
+            library: Arc::new(Library::open(None)?),
+            output,
+            events,
+            options: Mutex::new(options),
+            workers: Mutex::default(),
+        })
+    }
+
+    fn enqueue(&self, url: String) {
+        let client = self.client.clone();
+        let ffmpeg = self.ffmpeg.clone();
+        let library = self.library.clone();
+        let output = self.output.clone();
+        let options = self.options.lock().unwrap().clone();
+        let events = self.events.clone();
+        let runtime = self.runtime.handle().clone();
+
+        // Driven by a thread of its own, as the download future is not `Send`
+        let worker = std::thread::spawn(move || {
+            let download = download_url(&url, client, &output, ffmpeg, library, options);
+            let event = match runtime.block_on(download) {
+                Ok(summary) => json!({
+                    "event": "finished",
+                    "url": url,
+                    "downloaded": summary.downloaded,
+                    "failed": summary.failed,
+                    "skipped": summary.skipped(),
+                }),
+                Err(e) => json!({
+                    "event": "failed",
+                    "url": url,
+                    "message": e.to_string(),
+                }),
+            };
+            events.push(event);
+        });
+
+        let mut workers = self.workers.lock().unwrap();
+        workers.retain(|worker| !worker.is_finished());
+        workers.push(worker);
+    }
+
+    fn cancel(&self) {
+        let mut options = self.options.lock().unwrap();
+        options.cancel.cancel();
+        options.cancel = CancellationToken::new();
+    }
+
+    /// Cancels the downloads in progress and waits for their threads to finish, so none
+    /// is left polling the runtime once it is shut down
+    fn shutdown(self) {
+        self.options.lock().unwrap().cancel.cancel();
+        for worker in self.workers.into_inner().unwrap() {
+            if worker.join().is_err() {
+                tracing::error!("A download thread panicked");
+            }
+        }
+        self.runtime.shutdown_background();
+    }
+}
+
+/// Downloads the track or playlist a URL points to
+async fn download_url(
+    url: &str,
+    client: SoundcloudClient,
+    output: &Path,
+    ffmpeg: Option<FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+    options: DownloadOptions,
+) -> Result<BatchSummary> {
+    let (dir, request) = match client.resolve(url).await? {
+        Resource::Track(track) => (
+            output.to_path_buf(),
+            DownloadRequest::TrackId {
+                id: track.id,
+                secret_token: track.secret_token,
+            },
+        ),
+        Resource::Playlist(playlist) => (
+            util::playlist_dir(output, &playlist),
+            DownloadRequest::Playlist(Box::new(playlist)),
+        ),
+        Resource::User(user) => {
+            return Err(AppError::Configuration(format!(
+                "{} is a profile, only tracks and playlists can be downloaded",
+                user.username
+            )))
+        }
+    };
+
+    Downloader::new(client, &dir, ffmpeg, library, options)?
+        .download(request)
+        .await
+}
+
+/// Reads a C string argument, `None` if it is null or not UTF-8
+///
+/// # Safety
+/// `s` must be null or point to a NUL-terminated string
+unsafe fn read_str(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok().map(str::to_string)
+}
+
+/// Creates a downloader saving to `output_dir`, signed in with `oauth_token`
+///
+/// FFmpeg is used when found on PATH or in the default install location, otherwise
+/// only progressive streams are downloaded.
+///
+/// # Returns
+/// The handle, to be freed with [`scdl_free`], or null if the arguments are invalid or
+/// the downloader could not be set up
+///
+/// # Safety
+/// Both arguments must be null or point to NUL-terminated UTF-8 strings
+#[no_mangle]
+pub unsafe extern "C" fn scdl_init(
+    oauth_token: *const c_char,
+    output_dir: *const c_char,
+) -> *mut ScdlHandle {
+    let (Some(oauth_token), Some(output_dir)) = (read_str(oauth_token), read_str(output_dir))
+    else {
+        return std::ptr::null_mut();
+    };
+
+    match ScdlHandle::new(oauth_token, PathBuf::from(output_dir)) {
+        Ok(handle) => Box::into_raw(Box::new(handle)),
+        Err(e) => {
+            tracing::error!("Could not set up the downloader: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Starts downloading the track or playlist at `url` in the background
+///
+/// # Returns
+/// 0 once queued, -1 if an argument is null or `url` is not UTF-8
+///
+/// # Safety
+/// `handle` must come from [`scdl_init`] and `url` must point to a NUL-terminated
+/// string
+#[no_mangle]
+pub unsafe extern "C" fn scdl_enqueue_url(handle: *const ScdlHandle, url: *const c_char) -> c_int {
+    let (Some(handle), Some(url)) = (handle.as_ref(), read_str(url)) else {
+        return -1;
+    };
+
+    handle.enqueue(url);
+    0
+}
+
+/// Takes the oldest progress event
+///
+/// # Returns
+/// The event as a JSON string, to be freed with [`scdl_string_free`], or null if no
+/// event is waiting
+///
+/// # Safety
+/// `handle` must come from [`scdl_init`]
+#[no_mangle]
+pub unsafe extern "C" fn scdl_poll_event(handle: *const ScdlHandle) -> *mut c_char {
+    let Some(event) = handle
+        .as_ref()
+        .and_then(|handle| handle.events.0.lock().unwrap().pop_front())
+    else {
+        return std::ptr::null_mut();
+    };
+
+    CString::new(event.to_string()).map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+/// Frees a string returned by [`scdl_poll_event`]
+///
+/// # Safety
+/// `s` must be null or come from [`scdl_poll_event`], and not be freed before
+#[no_mangle]
+pub unsafe extern "C" fn scdl_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Stops the downloads in progress, deleting their partial files
+///
+/// URLs queued afterwards are downloaded as usual.
+///
+/// # Safety
+/// `handle` must come from [`scdl_init`]
+#[no_mangle]
+pub unsafe extern "C" fn scdl_cancel(handle: *const ScdlHandle) {
+    if let Some(handle) = handle.as_ref() {
+        handle.cancel();
+    }
+}
+
+/// Frees a handle, cancelling its downloads
+///
+/// Blocks until the downloads in progress have stopped and removed their partial files.
+///
+/// # Safety
+/// `handle` must be null or come from [`scdl_init`], and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn scdl_free(handle: *mut ScdlHandle) {
+    if !handle.is_null() {
+        Box::from_raw(handle).shutdown();
+    }
+}
@@ -1,11 +1,30 @@
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use regex::Regex;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing_appender::non_blocking::WorkerGuard;
 
 use crate::{
-    config::Config,
+    artwork::{ArtworkFormat, ArtworkOptions},
+    config::{self, Config},
+    downloader::{DownloadOptions, DEFAULT_MIN_FREE_SPACE},
     error::{AppError, Result},
     ffmpeg::{self, FFmpeg},
-    util,
+    following::ExportFormat,
+    hooks::Hooks,
+    lock::LockMode,
+    logging::{self, LogRotation},
+    progress::{ProgressBars, ProgressReporter},
+    soundcloud::{
+        HttpTrace, MetadataCache, SoundcloudClient, SoundcloudClientBuilder, TranscodingSelector,
+        DEFAULT_TRANSCODING_ORDER,
+    },
+    tags::Id3Version,
+    template::{FilenameTemplate, DEFAULT_FILENAME_TEMPLATE},
+    util::{self, ItemRanges},
 };
 
 #[derive(Parser)]
@@ -19,18 +38,272 @@ pub struct Cli {
     #[arg(long)]
     pub config: Option<String>,
 
+    /// Keep the config, token, FFmpeg and library next to the executable instead of in
+    /// the user's profile, also turned on by a portable.flag file next to it
+    #[arg(long)]
+    pub portable: bool,
+
+    /// Directory for temporary files, e.g. on the same drive as the output (default:
+    /// the system temp directory)
+    #[arg(long)]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Run the command for this profile of the config file, given once per account,
+    /// e.g. `--profile personal --profile label`
+    #[arg(long = "profile", value_name = "NAME")]
+    pub profiles: Vec<String>,
+
+    /// Run the command for every profile of the config file
+    #[arg(long, conflicts_with = "profiles")]
+    pub all_profiles: bool,
+
+    /// Library file recording downloaded tracks (default: library.json in the data directory)
+    #[arg(long)]
+    pub library: Option<PathBuf>,
+
+    /// Skip tracks listed in this yt-dlp style archive and add downloaded ones to it
+    #[arg(long)]
+    pub download_archive: Option<PathBuf>,
+
+    /// Wait for as long as another soundcloud-dl writes the archive or library,
+    /// instead of giving up after 30 seconds
+    #[arg(long)]
+    pub wait_for_lock: bool,
+
+    /// Don't lock the archive and library while writing them, e.g. on network drives
+    /// without lock support
+    #[arg(long, conflicts_with = "wait_for_lock")]
+    pub no_lock: bool,
+
     /// Clear the stored OAuth token
     #[arg(long)]
     pub clear_token: bool,
 
+    /// Client ID sent with API calls, looked up from the SoundCloud web player when
+    /// running without a token if not given
+    #[arg(long, env = "SOUNDCLOUD_DL_CLIENT_ID")]
+    pub client_id: Option<String>,
+
+    /// Don't check the OAuth token before starting, and run without one if none is
+    /// stored, which only works for public tracks
+    #[arg(long)]
+    pub skip_auth_check: bool,
+
     /// FFmpeg binary path (if not provided, will use `ffmpeg` from PATH or download it)
     #[arg(long)]
     pub ffmpeg_path: Option<String>,
 
+    /// Kill FFmpeg runs taking longer than this many seconds [default: 3600]
+    #[arg(long)]
+    pub ffmpeg_timeout: Option<u64>,
+
+    /// Download progressive MP3/M4A streams without FFmpeg, failing for HLS-only tracks
+    /// (M4A files are tagged without chapters or mapped tags)
+    #[arg(long, conflicts_with = "ffmpeg_path")]
+    pub no_ffmpeg: bool,
+
     /// Save the provided OAuth token for future use
     #[arg(short = 't', long)]
     pub save_token: bool,
 
+    /// Connect/read timeout for requests in seconds [default: 30]
+    #[arg(long)]
+    pub timeout: Option<u64>,
+
+    /// Maximum number of retries when rate limited [default: 5]
+    #[arg(long)]
+    pub max_retries: Option<u32>,
+
+    /// Always ask the API instead of using track, playlist and user data cached by earlier runs
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Hours cached track, playlist and user data is used for before it is fetched again
+    #[arg(long, default_value = "24")]
+    pub cache_ttl: u64,
+
+    /// Send API calls to this base URL instead of https://api-v2.soundcloud.com/, e.g. a
+    /// caching proxy or a local test server. The OAuth token is only sent to it over
+    /// HTTPS or on this machine
+    #[arg(long, env = "SOUNDCLOUD_DL_API_BASE")]
+    pub api_base: Option<reqwest::Url>,
+
+    /// Maximum number of concurrent downloads, lowered automatically while rate limited
+    #[arg(long, default_value = "3")]
+    pub concurrency: usize,
+
+    /// Maximum number of concurrent API calls, lowered automatically while rate limited
+    #[arg(long, default_value = "4")]
+    pub api_concurrency: usize,
+
+    /// Skip tracks where only a preview is available [default: true for likes and playlists]
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub skip_previews: Option<bool>,
+
+    /// Only download tracks uploaded on or after this date (YYYY-MM-DD)
+    #[arg(long, value_parser = util::parse_date)]
+    pub uploaded_after: Option<DateTime<Utc>>,
+
+    /// Only download tracks uploaded before this date (YYYY-MM-DD)
+    #[arg(long, value_parser = util::parse_date)]
+    pub uploaded_before: Option<DateTime<Utc>>,
+
+    /// Only download tracks at least this long (e.g. 30m, 1:30:00)
+    #[arg(long, value_parser = util::parse_duration)]
+    pub min_duration: Option<Duration>,
+
+    /// Only download tracks at most this long (e.g. 2h, 10:00)
+    #[arg(long, value_parser = util::parse_duration)]
+    pub max_duration: Option<Duration>,
+
+    /// Skip tracks whose audio file is larger than this (e.g. 200M)
+    #[arg(long, value_parser = util::parse_size)]
+    pub max_filesize: Option<u64>,
+
+    /// Space to leave free on the output volume, batches that would need more are not
+    /// started (e.g. 2G, 0 to only check for the estimated size) [default: 500M]
+    #[arg(long, value_parser = util::parse_size)]
+    pub min_free_space: Option<u64>,
+
+    /// Only download tracks whose title or artist matches this regex (case-insensitive)
+    #[arg(long = "match", value_parser = util::parse_regex)]
+    pub match_filter: Option<Regex>,
+
+    /// Skip tracks whose title or artist matches this regex (case-insensitive)
+    #[arg(long = "reject", value_parser = util::parse_regex)]
+    pub reject_filter: Option<Regex>,
+
+    /// Only download tracks with one of these licenses (e.g. cc-by,cc-by-sa)
+    #[arg(long = "license", value_delimiter = ',')]
+    pub licenses: Vec<String>,
+
+    /// Save each track's comments to a .comments.json file next to it
+    #[arg(long)]
+    pub write_comments: bool,
+
+    /// Save each track's waveform (JSON or PNG) next to it
+    #[arg(long)]
+    pub write_waveform: bool,
+
+    /// Write a .cue sheet for mixes with a timestamped tracklist in their description or comments
+    #[arg(long)]
+    pub write_cue: bool,
+
+    /// Embed the tracklist of mixes as chapters in M4A files
+    #[arg(long)]
+    pub embed_chapters: bool,
+
+    /// Also cut mixes into one tagged file per song, at tracklist times or silent gaps
+    #[arg(long)]
+    pub split_tracks: bool,
+
+    /// Cut everything before this position from each track (e.g. 0:30)
+    #[arg(long, value_parser = util::parse_duration)]
+    pub trim_start: Option<Duration>,
+
+    /// Cut everything after this position from each track (e.g. 59:00)
+    #[arg(long, value_parser = util::parse_duration)]
+    pub trim_end: Option<Duration>,
+
+    /// Remove silence at the start and end of each track (re-encodes the audio)
+    #[arg(long)]
+    pub remove_silence_edges: bool,
+
+    /// Set the modification time of downloaded files to the track's release date (default)
+    #[arg(long, overrides_with = "no_mtime")]
+    pub mtime: bool,
+
+    /// Keep the download time as modification time of downloaded files
+    #[arg(long, overrides_with = "mtime")]
+    pub no_mtime: bool,
+
+    /// ID3v2 version of MP3 tags, 2.3 for older players and car stereos
+    #[arg(long, value_enum, default_value = "2.4")]
+    pub id3_version: Id3Version,
+
+    /// Also write an ID3v1 tag to MP3 files for players that only read ID3v1
+    #[arg(long)]
+    pub id3v1: bool,
+
+    /// File names of downloaded tracks, `/` creates subdirectories (placeholders: {artist},
+    /// {title}, {id}, {user}, {genre}, {label}, {date}, {year}, {collection})
+    #[arg(long, value_parser = FilenameTemplate::parse, default_value = DEFAULT_FILENAME_TEMPLATE)]
+    pub filename_template: FilenameTemplate,
+
+    /// Order the streams of a track are tried in, as protocol:quality pairs (protocols:
+    /// progressive, hls; qualities: hq, sq), streams needing FFmpeg are skipped without it
+    #[arg(long, value_parser = TranscodingSelector::parse, default_value = DEFAULT_TRANSCODING_ORDER)]
+    pub transcoding_order: TranscodingSelector,
+
+    /// Append the track ID to file names, e.g. "Artist - Title [123456789].mp3"
+    /// (by default only added when another track already has the same name)
+    #[arg(long)]
+    pub add_id: bool,
+
+    /// Don't record the track URL in a comment tag and the `user.xdg.origin.url` attribute
+    #[arg(long)]
+    pub no_source_url: bool,
+
+    /// Scale down embedded artwork whose longer side exceeds this many pixels
+    #[arg(long)]
+    pub artwork_max_size: Option<u32>,
+
+    /// Convert embedded artwork to this format
+    #[arg(long, value_enum)]
+    pub artwork_format: Option<ArtworkFormat>,
+
+    /// Don't embed artwork
+    #[arg(long, conflicts_with_all = ["artwork_max_size", "artwork_format"])]
+    pub no_artwork: bool,
+
+    /// Check each downloaded file with ffprobe and download it again if it is broken
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Run a shell command after each download, e.g. "beet import {path}"
+    /// (placeholders: {path}, {id}, {title}, {artist}, {url}, which stand for the
+    /// quoted environment variables SCDL_PATH, SCDL_ID, SCDL_TITLE, SCDL_ARTIST, SCDL_URL)
+    #[arg(long)]
+    pub exec: Option<String>,
+
+    /// POST a JSON event to this URL after each download
+    #[arg(long)]
+    pub on_complete_webhook: Option<reqwest::Url>,
+
+    /// Download through a persistent queue, so interrupted batches can be resumed with
+    /// `queue run` and failed tracks are retried with backoff
+    #[arg(long)]
+    pub queue: bool,
+
+    /// Show a desktop notification with the results when a batch download finishes
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Show more output, -v for debug messages and -vv for everything
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Show less output, -q for warnings and errors only and -qq for errors only
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "verbose")]
+    pub quiet: u8,
+
+    /// Also write JSON logs to this file, including debug events hidden from the console
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// How often the log file is rotated [default: daily]
+    #[arg(long, value_enum)]
+    pub log_rotation: Option<LogRotation>,
+
+    /// Log the method, URL, status and duration of every HTTP request to the log file,
+    /// or to the console with -v, with the OAuth token redacted
+    #[arg(long)]
+    pub debug_http: bool,
+
+    /// With --debug-http, also log the first 2000 characters of API response bodies
+    #[arg(long, requires = "debug_http")]
+    pub debug_http_bodies: bool,
+
     /// Assume yes to all prompts
     #[arg(short = 'y')]
     pub yes: bool,
@@ -44,17 +317,19 @@ pub struct Cli {
 pub enum Commands {
     /// Download a single track
     Track {
-        /// Output directory for downloaded files
-        #[arg(short, long, default_value = ".")]
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
         output: Option<PathBuf>,
 
-        /// URL of the track to download
+        /// URL, API URL or ID of the track to download
         url: String,
     },
     /// Download liked tracks
     Likes {
-        /// Output directory for downloaded files
-        #[arg(short, long, default_value = ".")]
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
         output: Option<PathBuf>,
 
         /// Number of likes to skip
@@ -69,26 +344,537 @@ pub enum Commands {
         #[arg(long, default_value = "50")]
         chunk_size: u32,
 
+        /// Pick which likes to download in an interactive list
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Positions of the likes to download, e.g. "1-10,15,20-"
+        #[arg(long, value_parser = ItemRanges::parse)]
+        items: Option<ItemRanges>,
+
+        /// Download likes oldest-first instead of newest-first
+        #[arg(long)]
+        reverse: bool,
+
+        /// Stop at the first like that is already in the library, for fast incremental syncs
+        #[arg(long)]
+        new_only: bool,
+
+        /// Only download tracks liked on or after this date (YYYY-MM-DD)
+        #[arg(long, value_parser = util::parse_date)]
+        liked_after: Option<DateTime<Utc>>,
+
+        /// Also download liked playlists and albums, each into its own folder. The
+        /// like filters apply to them as well
+        #[arg(long)]
+        include_playlists: bool,
+
+        /// Maximum number of liked playlists to download with --include-playlists
+        #[arg(long, default_value = "10", requires = "include_playlists")]
+        playlist_limit: u32,
+
         /// Soundcloud username to download likes from
         user: Option<String>,
     },
     /// Download a playlist
     Playlist {
-        /// Output directory for downloaded files
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Pick which tracks to download in an interactive list
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Positions of the tracks to download, e.g. "1-10,15,20-"
+        #[arg(long, value_parser = ItemRanges::parse)]
+        items: Option<ItemRanges>,
+
+        /// Download the playlist from the last track to the first
+        #[arg(long)]
+        reverse: bool,
+
+        /// URL, API URL or ID of the playlist to download
+        url: String,
+    },
+    /// Download the playlists created or liked by a user, one directory per playlist
+    Playlists {
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Download playlists the user created (the default)
+        #[arg(long)]
+        created: bool,
+
+        /// Download playlists the user liked
+        #[arg(long)]
+        liked: bool,
+
+        /// Download both created and liked playlists
+        #[arg(long, conflicts_with_all = ["created", "liked"])]
+        all: bool,
+
+        /// Maximum number of playlists of each kind [default: all]
+        #[arg(short, long)]
+        limit: Option<u32>,
+
+        /// Soundcloud username to download playlists from
+        user: Option<String>,
+    },
+    /// Download new uploads and reposts from the users you follow
+    Feed {
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Only download items posted on or after this date (YYYY-MM-DD)
+        #[arg(long, value_parser = util::parse_date)]
+        since: Option<DateTime<Utc>>,
+
+        /// Maximum number of stream items to fetch
+        #[arg(short, long, default_value = "100")]
+        limit: u32,
+    },
+    /// Download the tracks of a SoundCloud chart into a dated folder
+    Charts {
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Genre of the chart, e.g. electronic, hiphoprap, techno
+        #[arg(short, long, default_value = "all-music")]
+        genre: String,
+
+        /// Which chart to download
+        #[arg(short, long, value_enum, default_value = "top")]
+        kind: ChartKind,
+
+        /// Maximum number of tracks
+        #[arg(short, long, default_value = "50")]
+        limit: u32,
+    },
+    /// Download the tracks related to a track, like its station
+    Related {
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
         #[arg(short, long)]
         output: Option<PathBuf>,
 
-        /// URL of the playlist to download
+        /// Maximum number of related tracks
+        #[arg(short, long, default_value = "25")]
+        limit: u32,
+
+        /// URL, API URL or ID of the seed track
         url: String,
     },
+    /// Search SoundCloud and download results
+    Search {
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Kind of results to search for
+        #[arg(short, long, value_enum, default_value = "tracks")]
+        kind: SearchKind,
+
+        /// Maximum number of results
+        #[arg(short, long, default_value = "20")]
+        limit: u32,
+
+        /// Download the results with these numbers (comma separated)
+        #[arg(short, long, value_delimiter = ',')]
+        download: Vec<usize>,
+
+        /// Download every result
+        #[arg(long, conflicts_with = "download")]
+        download_all: bool,
+
+        /// Search query
+        query: String,
+    },
+    /// Download a user's uploads, likes, reposts and playlists into one directory tree
+    Mirror {
+        /// Output directory, the mirror is created in a subdirectory named after the user
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Maximum number of items to fetch from each collection [default: all]
+        #[arg(short, long)]
+        limit: Option<u32>,
+
+        /// Soundcloud username to mirror (defaults to the logged in user)
+        user: Option<String>,
+    },
+    /// List, export or download from the users someone follows
+    Following {
+        /// Print the follow list in this format instead of a readable list
+        #[arg(long, value_enum)]
+        export: Option<ExportFormat>,
+
+        /// Maximum number of followed users [default: all]
+        #[arg(short, long)]
+        limit: Option<u32>,
+
+        /// Soundcloud username whose follow list to use (defaults to the logged in user)
+        user: Option<String>,
+
+        #[command(subcommand)]
+        action: Option<FollowingAction>,
+    },
+    /// Save a playlist's track list, to find tracks removed from it later
+    ///
+    /// Snapshots are kept in the data directory, each new one is compared with the
+    /// previous snapshot of the playlist.
+    Snapshot {
+        /// URL, API URL or ID of the playlist
+        url: Option<String>,
+
+        #[command(subcommand)]
+        action: Option<SnapshotAction>,
+    },
+    /// Download the sources of the `[[sync]]` entries in the config file, e.g. from a
+    /// single cron job
+    ///
+    /// Each entry names a source, an output directory and optionally filters and
+    /// formats, which otherwise come from the command line.
+    Sync {
+        /// Run every entry
+        #[arg(long, conflicts_with = "names", required_unless_present = "names")]
+        all: bool,
+
+        /// Names of the entries to run, or their sources if they have no name
+        names: Vec<String>,
+    },
+    /// Watch the clipboard and download every SoundCloud URL copied to it
+    Clipboard {
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// How often to check the clipboard, in milliseconds
+        #[arg(long, default_value = "1000")]
+        interval: u64,
+    },
+    /// Download the tracks and playlists listed in a CSV or JSON export
+    ///
+    /// CSV files need a header line, JSON files hold an array of objects or URLs.
+    /// Rows without a SoundCloud URL or that cannot be resolved are reported and skipped.
+    Import {
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Column or JSON key holding the URLs
+        #[arg(short, long, default_value = "url")]
+        column: String,
+
+        /// CSV or JSON file to import
+        file: PathBuf,
+    },
+    /// Refetch the metadata of downloaded files and rewrite their tags and artwork
+    ///
+    /// Files are matched to tracks through the library, `.info.json` sidecars or
+    /// SoundCloud URLs in their tags.
+    Retag {
+        /// Directory to retag, including its subdirectories
+        dir: PathBuf,
+    },
+    /// Rename and move downloaded files to match a filename template
+    ///
+    /// Files are matched to tracks like in `retag`, sidecars move along with them and
+    /// the library is updated with the new paths.
+    Organize {
+        /// Directory to organize, the template is relative to it
+        dir: PathBuf,
+
+        /// Template to apply, see the global --filename-template [default: the global template]
+        #[arg(long, value_parser = FilenameTemplate::parse)]
+        filename_template: Option<FilenameTemplate>,
+
+        /// Only show what would be moved
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Download library tracks again whose audio was replaced or improved on SoundCloud
+    ///
+    /// Picks up tracks whose length changed, previews whose full track became
+    /// available and tracks that are now offered in higher quality.
+    Upgrade {
+        /// Keep each replaced file as <name>.bak
+        #[arg(long)]
+        keep_backup: bool,
+    },
+    /// List downloaded tracks that were deleted or made private on SoundCloud
+    Gone,
+    /// Add every track in the library to a yt-dlp style download archive
+    ExportArchive {
+        /// Archive file, created if missing, tracks already listed are kept
+        file: PathBuf,
+    },
+    /// Check the downloaded files of library tracks for missing, changed or broken files
+    ///
+    /// Files must exist and match the checksum recorded when they were saved. With
+    /// FFmpeg, they must also contain audio of the expected length and be tagged.
+    Verify {
+        /// Only check tracks saved within this directory
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Queue a new download of every track with a problem, run with `queue run`
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Check the configuration, token, FFmpeg, network access and output directory
+    Doctor {
+        /// Output directory to check for free space and write access
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Update soundcloud-dl to the latest release
+    SelfUpdate {
+        /// Only check whether a newer release is available
+        #[arg(long)]
+        check: bool,
+    },
+    /// Resume, list or clear queued downloads
+    Queue {
+        #[command(subcommand)]
+        action: QueueAction,
+    },
+    /// Manage the cache of track, playlist and user data
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Show, install or update the FFmpeg used for processing
+    Ffmpeg {
+        #[command(subcommand)]
+        action: FfmpegAction,
+    },
+}
+
+/// A SoundCloud account a command runs for
+pub struct Account {
+    /// Name of the profile in the config file, `None` for the stored token
+    pub profile: Option<String>,
+    pub oauth_token: String,
+    /// Tokens API calls switch to while rate limited
+    pub rotation_tokens: Vec<String>,
+    /// Output directory of the account
+    pub output: PathBuf,
+}
+
+#[derive(Clone, Copy, Subcommand)]
+pub enum QueueAction {
+    /// Download every pending job, e.g. after an interrupted run
+    Run,
+    /// List jobs that have not completed
+    List,
+    /// Remove completed and failed jobs
+    Clear {
+        /// Also remove pending jobs
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum FollowingAction {
+    /// Download the latest uploads of each followed user into a folder named after them
+    Download {
+        /// Output directory for downloaded files, @music for a SoundCloud folder in your
+        /// Music directory (default: `output` from the config file, or the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Number of latest uploads to download from each followed user
+        #[arg(long, default_value = "5")]
+        tracks_per_artist: u32,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Show the tracks added, removed and reordered since the last snapshot, without
+    /// saving a new one
+    Diff {
+        /// URL, API URL or ID of the playlist
+        url: String,
+    },
+}
+
+#[derive(Clone, Copy, Subcommand)]
+pub enum CacheAction {
+    /// Remove all cached data
+    Clear,
+}
+
+#[derive(Clone, Copy, Subcommand)]
+pub enum FfmpegAction {
+    /// Show the detected FFmpeg binary and its version
+    Status,
+    /// Download and install the latest build, replacing any installed one
+    Install,
+    /// Install the latest build if it differs from the installed one
+    Update,
+    /// Print the path of the detected FFmpeg binary
+    Path,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SearchKind {
+    Tracks,
+    Playlists,
+    Users,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ChartKind {
+    /// Most played tracks
+    Top,
+    /// New and hot tracks
+    Trending,
+}
+
+impl ChartKind {
+    /// Returns the `kind` parameter of the `/charts` endpoint
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Top => "top",
+            Self::Trending => "trending",
+        }
+    }
+}
+
+/// A track or playlist reference given on the command line
+pub enum Input {
+    /// A SoundCloud page URL which needs to be resolved
+    Url(String),
+    /// A track ID, taken from a bare ID, an API URL or a `soundcloud:tracks:` URN
+    Track {
+        id: u64,
+        secret_token: Option<String>,
+    },
+    /// A playlist ID, taken from a bare ID, an API URL or a `soundcloud:playlists:` URN
+    Playlist {
+        id: u64,
+        secret_token: Option<String>,
+    },
+}
+
+impl Input {
+    /// Classifies a `track` argument, treating bare IDs as track IDs
+    pub fn track(input: &str) -> Self {
+        Self::classify(input, |id| Self::Track {
+            id,
+            secret_token: None,
+        })
+    }
+
+    /// Classifies a `playlist` argument, treating bare IDs as playlist IDs
+    pub fn playlist(input: &str) -> Self {
+        Self::classify(input, |id| Self::Playlist {
+            id,
+            secret_token: None,
+        })
+    }
+
+    fn classify(input: &str, bare_id: fn(u64) -> Self) -> Self {
+        let input = input.trim();
+
+        if let Ok(id) = input.parse::<u64>() {
+            return bare_id(id);
+        }
+
+        let (kind, id, secret_token) = if let Some(urn) = input.strip_prefix("soundcloud:") {
+            match urn.split_once(':') {
+                Some((kind, id)) => (kind.to_string(), id.to_string(), None),
+                None => return Self::Url(input.to_string()),
+            }
+        } else {
+            match reqwest::Url::parse(input) {
+                Ok(url)
+                    if matches!(
+                        url.host_str(),
+                        Some("api-v2.soundcloud.com" | "api.soundcloud.com")
+                    ) =>
+                {
+                    let mut segments = url
+                        .path_segments()
+                        .map(|segments| segments.filter(|s| !s.is_empty()).collect::<Vec<_>>())
+                        .unwrap_or_default();
+
+                    match (segments.pop(), segments.pop()) {
+                        (Some(id), Some(kind)) => {
+                            (kind.to_string(), id.to_string(), util::secret_token(input))
+                        }
+                        _ => return Self::Url(input.to_string()),
+                    }
+                }
+                _ => return Self::Url(input.to_string()),
+            }
+        };
+
+        match (kind.as_str(), id.parse::<u64>()) {
+            ("tracks", Ok(id)) => Self::Track { id, secret_token },
+            ("playlists", Ok(id)) => Self::Playlist { id, secret_token },
+            _ => Self::Url(input.to_string()),
+        }
+    }
 }
 
 impl Commands {
+    /// Whether the command downloads many tracks in one run
+    pub fn is_batch(&self) -> bool {
+        !matches!(
+            self,
+            Self::Track { .. }
+                | Self::Retag { .. }
+                | Self::Organize { .. }
+                | Self::Following { action: None, .. }
+                | Self::Snapshot { .. }
+        )
+    }
+
     pub fn output_dir(&self) -> Option<&PathBuf> {
         match self {
             Self::Track { output, .. } => output.as_ref(),
             Self::Likes { output, .. } => output.as_ref(),
             Self::Playlist { output, .. } => output.as_ref(),
+            Self::Playlists { output, .. } => output.as_ref(),
+            Self::Feed { output, .. } => output.as_ref(),
+            Self::Charts { output, .. } => output.as_ref(),
+            Self::Related { output, .. } => output.as_ref(),
+            Self::Search { output, .. } => output.as_ref(),
+            Self::Mirror { output, .. } => output.as_ref(),
+            Self::Following {
+                action: Some(FollowingAction::Download { output, .. }),
+                ..
+            } => output.as_ref(),
+            Self::Clipboard { output, .. } => output.as_ref(),
+            Self::Import { output, .. } => output.as_ref(),
+            Self::Doctor { output } => output.as_ref(),
+            Self::Retag { .. }
+            | Self::Organize { .. }
+            | Self::Upgrade { .. }
+            | Self::Following { action: None, .. }
+            | Self::Snapshot { .. }
+            | Self::Sync { .. }
+            | Self::Gone
+            | Self::ExportArchive { .. }
+            | Self::Verify { .. }
+            | Self::Queue { .. }
+            | Self::Cache { .. }
+            | Self::SelfUpdate { .. }
+            | Self::Ffmpeg { .. } => None,
         }
     }
 }
@@ -105,45 +891,289 @@ impl Cli {
             .map_or_else(|| config.get_oauth_token(), |token| Ok(Some(token.clone())))
         {
             Ok(Some(token)) => Ok(token),
-            _ => Err(AppError::Configuration(
+            _ if self.skip_auth_check => {
+                tracing::info!("No OAuth token, only public tracks can be downloaded");
+                Ok(String::new())
+            }
+            _ => Err(AppError::Auth(
                 "OAuth token is required to run this program. Exiting.".into(),
             )),
         }
     }
 
-    pub async fn resolve_ffmpeg_path(&self) -> Result<FFmpeg<PathBuf>> {
-        let ffmpeg = match self.ffmpeg_path.as_ref() {
+    pub fn build_client(&self, config: &Config, oauth_token: String) -> Result<SoundcloudClient> {
+        self.client_builder(config, oauth_token)?.build()
+    }
+
+    /// Builds the client of an account, which rotates between its tokens when any are
+    /// configured
+    pub fn build_account_client(
+        &self,
+        config: &Config,
+        account: &Account,
+    ) -> Result<SoundcloudClient> {
+        self.client_builder(config, account.oauth_token.clone())?
+            .rotation_tokens(account.rotation_tokens.clone())
+            .build()
+    }
+
+    fn client_builder(
+        &self,
+        config: &Config,
+        oauth_token: String,
+    ) -> Result<SoundcloudClientBuilder> {
+        let mut builder = SoundcloudClient::builder(oauth_token)
+            .max_concurrency(self.concurrency)
+            .max_api_concurrency(self.api_concurrency)
+            .client_id(self.client_id.clone().or_else(|| config.client_id()))
+            .rotation_client_ids(config.rotation_client_ids().to_vec());
+
+        if let Some(api_base) = &self.api_base {
+            builder = builder.api_base(api_base.clone());
+        }
+
+        builder = builder.http_trace(match (self.debug_http, self.debug_http_bodies) {
+            (_, true) => HttpTrace::Bodies,
+            (true, false) => HttpTrace::Requests,
+            (false, false) => HttpTrace::Off,
+        });
+
+        if let Some(timeout) = self.timeout.or(config.request_timeout()) {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+
+        if let Some(max_retries) = self.max_retries.or(config.max_retries()) {
+            builder = builder.max_retries(max_retries);
+        }
+
+        // Upgrades look for audio replaced since the last download, which cached data hides
+        if !self.no_cache && !matches!(self.command, Some(Commands::Upgrade { .. })) {
+            builder = builder.cache(Arc::new(self.metadata_cache()?));
+        }
+
+        Ok(builder)
+    }
+
+    /// Opens the metadata cache with the configured TTL
+    pub fn metadata_cache(&self) -> Result<MetadataCache> {
+        MetadataCache::open(None, Duration::from_secs(self.cache_ttl * 60 * 60))
+    }
+
+    /// Finds FFmpeg at `--ffmpeg-path`, on PATH or in the default install directory
+    pub fn find_ffmpeg(&self) -> Result<FFmpeg<PathBuf>> {
+        match self.ffmpeg_path.as_ref() {
             Some(path) => ffmpeg::FFmpeg::new(PathBuf::from(path)),
             None => ffmpeg::FFmpeg::default(),
-        };
+        }
+    }
 
-        match ffmpeg {
-            Ok(ffmpeg) => Ok(ffmpeg),
+    /// Finds or installs FFmpeg, `None` in `--no-ffmpeg` mode
+    ///
+    /// # Arguments
+    /// * `client` - Client used to download FFmpeg when it is missing
+    pub async fn resolve_ffmpeg_path(
+        &self,
+        client: &SoundcloudClient,
+    ) -> Result<Option<FFmpeg<PathBuf>>> {
+        if self.no_ffmpeg {
+            return Ok(None);
+        }
+
+        let ffmpeg = match self.find_ffmpeg() {
+            Ok(ffmpeg) => ffmpeg,
             Err(_)
                 if self.yes
                     || util::prompt("FFmpeg is not installed. Do you want to install it?") =>
             {
-                let path = ffmpeg::download_ffmpeg(self.ffmpeg_path.as_ref()).await?;
-                Ok(ffmpeg::FFmpeg::new(path)?)
+                let path = ffmpeg::download_ffmpeg(client, self.ffmpeg_path.as_ref()).await?;
+                ffmpeg::FFmpeg::new(path)?
             }
-            Err(_) => Err(AppError::FFmpeg(
-                "FFmpeg is required to run this program. Exiting.".into(),
-            )),
+            Err(_) => {
+                return Err(AppError::FFmpeg(
+                    "FFmpeg is required to run this program, install it with `soundcloud-dl ffmpeg install` or use --no-ffmpeg. Exiting.".into(),
+                ))
+            }
+        };
+
+        Ok(Some(match self.ffmpeg_timeout {
+            Some(timeout) => ffmpeg.timeout(Duration::from_secs(timeout)),
+            None => ffmpeg,
+        }))
+    }
+
+    pub fn download_options(&self) -> DownloadOptions {
+        let is_batch = self.command.as_ref().is_some_and(Commands::is_batch);
+
+        let (items, reverse) = match &self.command {
+            Some(Commands::Likes { items, reverse, .. })
+            | Some(Commands::Playlist { items, reverse, .. }) => (items.clone(), *reverse),
+            _ => (None, false),
+        };
+
+        let (liked_after, new_only) = match &self.command {
+            Some(Commands::Likes {
+                liked_after,
+                new_only,
+                ..
+            }) => (*liked_after, *new_only),
+            _ => (None, false),
+        };
+
+        DownloadOptions {
+            skip_previews: self.skip_previews.unwrap_or(is_batch),
+            items,
+            reverse,
+            liked_after,
+            uploaded_after: self.uploaded_after,
+            uploaded_before: self.uploaded_before,
+            min_duration: self.min_duration,
+            max_duration: self.max_duration,
+            max_filesize: self.max_filesize,
+            min_free_space: self.min_free_space.unwrap_or(DEFAULT_MIN_FREE_SPACE),
+            match_filter: self.match_filter.clone(),
+            reject_filter: self.reject_filter.clone(),
+            licenses: self.licenses.clone(),
+            new_only,
+            skip_downloaded: matches!(self.command, Some(Commands::Feed { .. })),
+            library_scope: None,
+            write_comments: self.write_comments,
+            write_waveform: self.write_waveform,
+            write_cue: self.write_cue,
+            embed_chapters: self.embed_chapters,
+            split_tracks: self.split_tracks,
+            verify: self.verify,
+            no_artwork: self.no_artwork,
+            artwork: ArtworkOptions {
+                max_size: self.artwork_max_size,
+                format: self.artwork_format,
+            },
+            id3_version: self.id3_version,
+            id3v1: self.id3v1,
+            filename_template: self.filename_template.clone(),
+            transcodings: self.transcoding_order.clone(),
+            add_id: self.add_id,
+            set_mtime: !self.no_mtime,
+            source_url: !self.no_source_url,
+            hooks: Hooks {
+                exec: self.exec.clone(),
+                webhook: self.on_complete_webhook.clone(),
+            },
+            // Configured in the config file
+            storage: None,
+            tag_mappings: Vec::new(),
+            // Opened in main
+            archive: None,
+            queue: None,
+            cancel: CancellationToken::new(),
+            progress: (self.verbosity() >= 0)
+                .then(|| Arc::new(ProgressBars::new()) as Arc<dyn ProgressReporter>),
+            trim: ffmpeg::Trim {
+                start: self.trim_start,
+                end: self.trim_end,
+                silence_edges: self.remove_silence_edges,
+            },
         }
     }
 
-    pub fn resolve_output_dir(&self) -> Option<PathBuf> {
-        self.command
-            .as_ref()
-            .and_then(|c| c.output_dir())
-            .map(|p| p.clone())
+    /// Returns the output directory given on the command line or in the config file,
+    /// the current directory if neither names one
+    pub fn resolve_output_dir(&self, config: &Config) -> Result<PathBuf> {
+        let output = match self.command.as_ref().and_then(|c| c.output_dir()) {
+            Some(output) => output.clone(),
+            None => config.output().unwrap_or_else(|| PathBuf::from(".")),
+        };
+
+        config::expand_output(&output)
+    }
+
+    /// Resolves the accounts the command runs for, one per selected profile
+    ///
+    /// Profiles without an output directory of their own download into a folder named
+    /// after them in the output directory. Without `--profile` or `--all-profiles`
+    /// there is a single account using the stored token.
+    pub fn resolve_accounts(&self, config: &Config) -> Result<Vec<Account>> {
+        let names = if self.all_profiles {
+            let names = config.profile_names();
+            if names.is_empty() {
+                return Err(AppError::Configuration(format!(
+                    "--all-profiles needs [profiles.<name>] sections in {}",
+                    config.path().display()
+                )));
+            }
+            names
+        } else {
+            self.profiles.clone()
+        };
+
+        if names.is_empty() {
+            return Ok(vec![Account {
+                profile: None,
+                oauth_token: self.resolve_auth_token(config)?,
+                rotation_tokens: config.rotation_tokens().to_vec(),
+                output: self.resolve_output_dir(config)?,
+            }]);
+        }
+
+        names
+            .into_iter()
+            .map(|name| {
+                let profile = config.profile(&name)?;
+                let oauth_token = match &profile.oauth_token {
+                    Some(token) => token.clone(),
+                    None => self.resolve_auth_token(config)?,
+                };
+                let output = match &profile.output {
+                    Some(output) => config::expand_output(output)?,
+                    None => self.resolve_output_dir(config)?.join(util::sanitize(&name)),
+                };
+
+                Ok(Account {
+                    profile: Some(name),
+                    oauth_token,
+                    rotation_tokens: profile.rotation_tokens.clone(),
+                    output,
+                })
+            })
+            .collect()
+    }
+
+    /// How the archive and library are locked against other instances
+    pub fn lock_mode(&self) -> LockMode {
+        if self.no_lock {
+            LockMode::Off
+        } else if self.wait_for_lock {
+            LockMode::Wait
+        } else {
+            LockMode::Timeout
+        }
+    }
+
+    /// Console verbosity, positive for `-v` and negative for `-q`
+    pub fn verbosity(&self) -> i8 {
+        self.verbose.min(2) as i8 - self.quiet.min(2) as i8
+    }
+
+    /// Installs logging, with the log file options of the command line taking
+    /// precedence over the config file
+    ///
+    /// # Returns
+    /// Result containing a guard that must be kept alive until exit
+    pub fn init_logging(&self, config: &Config) -> Result<Option<WorkerGuard>> {
+        let log = config.log().cloned().unwrap_or_default();
+
+        logging::init(
+            self.verbosity(),
+            self.log_file.as_deref().or(log.file.as_deref()),
+            self.log_rotation.or(log.rotation).unwrap_or_default(),
+            log.max_files,
+        )
     }
 
     pub fn config_init(&self, config: &mut Config) -> Result<bool> {
         let mut action_performed = false;
         if let Some(auth) = &self.auth {
             if self.save_token {
-                config.save_oauth_token(&auth)?;
+                config.save_oauth_token(auth)?;
                 tracing::info!("OAuth token saved successfully!");
 
                 action_performed = true;
@@ -0,0 +1,158 @@
+use arboard::Clipboard;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::downloader::{DownloadOptions, DownloadRequest, Downloader};
+use crate::error::{AppError, Result};
+use crate::ffmpeg::FFmpeg;
+use crate::library::Library;
+use crate::notify;
+use crate::soundcloud::model::Resource;
+use crate::soundcloud::SoundcloudClient;
+use crate::util;
+
+/// Hosts whose URLs are picked up from the clipboard
+const SOUNDCLOUD_HOSTS: &[&str] = &["soundcloud.com", "www.soundcloud.com", "m.soundcloud.com"];
+
+/// Watches the system clipboard and downloads every SoundCloud URL copied to it
+pub struct ClipboardWatcher {
+    client: SoundcloudClient,
+    ffmpeg: Option<FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+    options: DownloadOptions,
+    output: PathBuf,
+    interval: Duration,
+}
+
+impl ClipboardWatcher {
+    pub fn new(
+        client: SoundcloudClient,
+        ffmpeg: Option<FFmpeg<PathBuf>>,
+        library: Arc<Library>,
+        options: DownloadOptions,
+        output: &Path,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            client,
+            ffmpeg,
+            library,
+            options,
+            output: output.to_path_buf(),
+            interval,
+        }
+    }
+
+    /// Polls the clipboard until interrupted, downloading copied tracks and playlists
+    /// in the background
+    ///
+    /// Whatever is on the clipboard when watching starts is ignored, and each URL is
+    /// only downloaded once per run.
+    ///
+    /// # Returns
+    /// Result indicating failure to access the clipboard
+    pub async fn run(self) -> Result<()> {
+        let mut clipboard = Clipboard::new()?;
+        let mut last_text = clipboard.get_text().ok();
+        let mut seen = HashSet::new();
+        let mut downloads = FuturesUnordered::new();
+        let mut ticker = tokio::time::interval(self.interval);
+
+        tracing::info!("Watching the clipboard for SoundCloud URLs, press Ctrl+C to stop");
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    // Non-text content such as images is reported as an error
+                    let Ok(text) = clipboard.get_text() else {
+                        continue;
+                    };
+                    if last_text.as_ref() == Some(&text) {
+                        continue;
+                    }
+
+                    for url in soundcloud_urls(&text) {
+                        if seen.insert(url.clone()) {
+                            tracing::info!("Queued {} from the clipboard", url);
+                            notify::show("Queued for download", &url);
+                            downloads.push(self.download(url));
+                        }
+                    }
+                    last_text = Some(text);
+                }
+                Some((url, result)) = downloads.next() => match result {
+                    Ok(title) => notify::show("Download finished", &title),
+                    Err(e) => {
+                        tracing::error!("Failed to download {}: {}", url, e);
+                        notify::show("Download failed", &format!("{}: {}", url, e));
+                    }
+                },
+            }
+        }
+    }
+
+    /// Downloads a copied track or playlist
+    ///
+    /// # Returns
+    /// The URL along with a result containing the title of what was downloaded
+    async fn download(&self, url: String) -> (String, Result<String>) {
+        let result = async {
+            match self.client.resolve(&url).await? {
+                Resource::Track(track) => {
+                    self.downloader(&self.output)?
+                        .download(DownloadRequest::TrackId {
+                            id: track.id,
+                            secret_token: track.secret_token.clone(),
+                        })
+                        .await?;
+                    Ok(track.title)
+                }
+                Resource::Playlist(playlist) => {
+                    let title = playlist.title.clone();
+                    let summary = self
+                        .downloader(&util::playlist_dir(&self.output, &playlist))?
+                        .download(DownloadRequest::Playlist(Box::new(playlist)))
+                        .await?;
+                    Ok(format!(
+                        "{} ({} of {} tracks)",
+                        title, summary.downloaded, summary.total
+                    ))
+                }
+                Resource::User(user) => Err(AppError::Configuration(format!(
+                    "{} is a profile, only tracks and playlists are downloaded from the clipboard",
+                    user.username
+                ))),
+            }
+        }
+        .await;
+
+        (url, result)
+    }
+
+    fn downloader(&self, output: &Path) -> Result<Downloader> {
+        Downloader::new(
+            self.client.clone(),
+            output,
+            self.ffmpeg.clone(),
+            self.library.clone(),
+            self.options.clone(),
+        )
+    }
+}
+
+/// Extracts the SoundCloud page and share links from copied text
+fn soundcloud_urls(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter_map(|word| reqwest::Url::parse(word).ok())
+        .filter(|url| {
+            util::is_short_link(url)
+                || url
+                    .host_str()
+                    .is_some_and(|host| SOUNDCLOUD_HOSTS.contains(&host))
+        })
+        .map(|url| url.to_string())
+        .collect()
+}
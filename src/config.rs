@@ -1,16 +1,95 @@
 use crate::error::{AppError, Result};
-use directories::ProjectDirs;
+use crate::logging::LogConfig;
+use crate::storage::StorageConfig;
+use crate::sync::SyncConfig;
+use crate::tags::{TagConfig, TagMapping};
+use directories::{ProjectDirs, UserDirs};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 const APP_NAME: &str = "soundcloud-dl";
 const ORGANIZATION: &str = "damaredayo";
+/// Output directory standing for the platform's Music directory
+const MUSIC_DIR_ALIAS: &str = "@music";
+/// Folder created in the Music directory for `@music`
+const MUSIC_SUBDIR: &str = "SoundCloud";
+/// File next to the executable that turns on portable mode
+const PORTABLE_FLAG: &str = "portable.flag";
+
+/// Whether files are kept next to the executable, decided once per run
+static PORTABLE: OnceLock<bool> = OnceLock::new();
 
 #[derive(Default, Deserialize, Serialize)]
 struct ConfigFile {
     #[serde(skip_serializing_if = "Option::is_none")]
     oauth_token: Option<String>,
+
+    /// Connect/read timeout for requests, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_timeout: Option<u64>,
+
+    /// Maximum number of retries for rate limited requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_retries: Option<u32>,
+
+    /// Where finished downloads are stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    storage: Option<StorageConfig>,
+
+    /// Log file options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log: Option<LogConfig>,
+
+    /// Extra tags mapped from track fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<TagConfig>,
+
+    /// Output directory used when none is given on the command line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<PathBuf>,
+
+    /// OAuth tokens of other accounts API calls switch to while the token in use is
+    /// rate limited, for very large archival jobs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rotation_tokens: Vec<String>,
+
+    /// `client_id` sent with API calls, looked up from the web player for calls made
+    /// without a token if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+
+    /// `client_id`s API calls switch to while the one in use is rate limited
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rotation_client_ids: Vec<String>,
+
+    /// Accounts selected with `--profile`, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    profiles: BTreeMap<String, ProfileConfig>,
+
+    /// Sources run by `sync`, one `[[sync]]` entry each
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sync: Vec<SyncConfig>,
+}
+
+/// A `[profiles.<name>]` section of the config file, another SoundCloud account
+/// synced with `--profile <name>`
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ProfileConfig {
+    /// OAuth token of the account, the stored token is used if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth_token: Option<String>,
+
+    /// Output directory of the account (default: a folder named after the profile in
+    /// the output directory)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+
+    /// Tokens the account's API calls switch to while rate limited
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rotation_tokens: Vec<String>,
 }
 
 pub struct Config {
@@ -18,11 +97,102 @@ pub struct Config {
     config: ConfigFile,
 }
 
+/// Directories the application keeps its config, data and cache in
+pub struct AppDirs {
+    config_dir: PathBuf,
+    data_dir: PathBuf,
+    cache_dir: PathBuf,
+}
+
+impl AppDirs {
+    pub fn config_dir(&self) -> &Path {
+        &self.config_dir
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+}
+
+/// Returns the config and data directories of the application, next to the executable
+/// in portable mode and in the platform specific locations otherwise
+pub fn project_dirs() -> Result<AppDirs> {
+    if let Some(dir) = portable_dir() {
+        return Ok(AppDirs {
+            config_dir: dir.clone(),
+            data_dir: dir.join("data"),
+            cache_dir: dir.join("cache"),
+        });
+    }
+
+    let dirs = ProjectDirs::from("com", ORGANIZATION, APP_NAME)
+        .ok_or_else(|| AppError::Configuration("Could not determine config directory".into()))?;
+
+    Ok(AppDirs {
+        config_dir: dirs.config_dir().to_path_buf(),
+        data_dir: dirs.data_dir().to_path_buf(),
+        cache_dir: dirs.cache_dir().to_path_buf(),
+    })
+}
+
+/// Resolves an output directory starting with `@music` to the `SoundCloud` folder in
+/// the platform's Music directory, e.g. `$XDG_MUSIC_DIR/SoundCloud` on Linux
+pub fn expand_output(output: &Path) -> Result<PathBuf> {
+    let mut components = output.components();
+    if components
+        .next()
+        .is_none_or(|first| first.as_os_str() != MUSIC_DIR_ALIAS)
+    {
+        return Ok(output.to_path_buf());
+    }
+
+    // Linux systems without XDG user directories have no Music directory configured
+    let music_dir = UserDirs::new()
+        .map(|dirs| {
+            dirs.audio_dir()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| dirs.home_dir().join("Music"))
+        })
+        .ok_or_else(|| {
+            AppError::Configuration("Could not determine your Music directory".into())
+        })?;
+
+    Ok(music_dir.join(MUSIC_SUBDIR).join(components.as_path()))
+}
+
+/// Turns on portable mode, which keeps the config, token, FFmpeg and library next to
+/// the executable, e.g. on a USB stick
+///
+/// A `portable.flag` file next to the executable turns it on as well. Has no effect
+/// once any directory was looked up.
+pub fn set_portable(portable: bool) {
+    let _ = PORTABLE.set(portable || has_portable_flag());
+}
+
+/// Returns the directory of the executable if running in portable mode
+pub fn portable_dir() -> Option<PathBuf> {
+    let portable = *PORTABLE.get_or_init(has_portable_flag);
+    portable.then(exe_dir).flatten()
+}
+
+fn has_portable_flag() -> bool {
+    exe_dir().is_some_and(|dir| dir.join(PORTABLE_FLAG).exists())
+}
+
+fn exe_dir() -> Option<PathBuf> {
+    std::env::current_exe()
+        .ok()?
+        .parent()
+        .map(Path::to_path_buf)
+}
+
 impl Config {
     pub fn new() -> Result<Self> {
-        let proj_dirs = ProjectDirs::from("com", ORGANIZATION, APP_NAME).ok_or_else(|| {
-            AppError::Configuration("Could not determine config directory".into())
-        })?;
+        let proj_dirs = project_dirs()?;
 
         // Ensure config directory exists
         fs::create_dir_all(proj_dirs.config_dir())?;
@@ -41,13 +211,143 @@ impl Config {
         })
     }
 
+    pub fn path(&self) -> &Path {
+        &self.config_path
+    }
+
+    /// Reads the config file again, reporting syntax errors that are otherwise ignored
+    /// in favour of the defaults
+    ///
+    /// # Returns
+    /// Result indicating whether the file is missing or valid
+    pub fn validate(&self) -> Result<()> {
+        if !self.config_path.exists() {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(&self.config_path)?;
+        toml::from_str::<ConfigFile>(&content).map_err(|e| {
+            AppError::Configuration(format!("{}: {}", self.config_path.display(), e))
+        })?;
+
+        Ok(())
+    }
+
     pub fn get_oauth_token(&self) -> Result<Option<String>> {
         Ok(self.config.oauth_token.clone())
     }
 
+    pub fn output(&self) -> Option<PathBuf> {
+        self.config.output.clone()
+    }
+
+    pub fn client_id(&self) -> Option<String> {
+        self.config.client_id.clone()
+    }
+
+    pub fn rotation_tokens(&self) -> &[String] {
+        &self.config.rotation_tokens
+    }
+
+    pub fn rotation_client_ids(&self) -> &[String] {
+        &self.config.rotation_client_ids
+    }
+
+    /// Returns a profile of the config file
+    pub fn profile(&self, name: &str) -> Result<&ProfileConfig> {
+        self.config.profiles.get(name).ok_or_else(|| {
+            AppError::Configuration(format!(
+                "No profile named '{}' in {}",
+                name,
+                self.config_path.display()
+            ))
+        })
+    }
+
+    /// Returns the names of all profiles, in alphabetical order
+    pub fn profile_names(&self) -> Vec<String> {
+        self.config.profiles.keys().cloned().collect()
+    }
+
+    /// Returns the `[[sync]]` definitions to run, all of them if no names are given
+    ///
+    /// # Arguments
+    /// * `names` - Names of the definitions to run
+    ///
+    /// # Returns
+    /// Result containing the definitions in the order of the config file, or an error
+    /// naming a definition that does not exist
+    pub fn syncs(&self, names: &[String]) -> Result<Vec<SyncConfig>> {
+        if self.config.sync.is_empty() {
+            return Err(AppError::Configuration(format!(
+                "sync needs [[sync]] entries in {}",
+                self.config_path.display()
+            )));
+        }
+
+        if let Some(missing) = names
+            .iter()
+            .find(|name| !self.config.sync.iter().any(|sync| sync.name() == *name))
+        {
+            return Err(AppError::Configuration(format!(
+                "No sync named '{}' in {}",
+                missing,
+                self.config_path.display()
+            )));
+        }
+
+        Ok(self
+            .config
+            .sync
+            .iter()
+            .filter(|sync| names.is_empty() || names.iter().any(|name| name == sync.name()))
+            .cloned()
+            .collect())
+    }
+
+    pub fn request_timeout(&self) -> Option<u64> {
+        self.config.request_timeout
+    }
+
+    pub fn max_retries(&self) -> Option<u32> {
+        self.config.max_retries
+    }
+
+    pub fn storage(&self) -> Option<&StorageConfig> {
+        self.config.storage.as_ref()
+    }
+
+    pub fn log(&self) -> Option<&LogConfig> {
+        self.config.log.as_ref()
+    }
+
+    /// Returns the configured tag mappings, after checking that they are valid
+    pub fn tag_mappings(&self) -> Result<Vec<TagMapping>> {
+        let mappings = self
+            .config
+            .tags
+            .as_ref()
+            .map(|tags| tags.mapping.clone())
+            .unwrap_or_default();
+
+        for mapping in &mappings {
+            mapping.validate()?;
+        }
+
+        Ok(mappings)
+    }
+
     pub fn save_oauth_token(&mut self, token: &str) -> Result<()> {
         self.config.oauth_token = Some(token.to_string());
+        self.save()
+    }
 
+    pub fn clear_oauth_token(&mut self) -> Result<()> {
+        self.config.oauth_token = None;
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
         let toml = toml::to_string_pretty(&self.config)
             .map_err(|e| AppError::Configuration(format!("Failed to serialize config: {}", e)))?;
 
@@ -62,12 +362,4 @@ impl Config {
 
         Ok(())
     }
-
-    pub fn clear_oauth_token(&self) -> Result<()> {
-        let config = ConfigFile::default();
-        let toml = toml::to_string_pretty(&config)
-            .map_err(|e| AppError::Configuration(format!("Failed to serialize config: {}", e)))?;
-        fs::write(&self.config_path, toml)?;
-        Ok(())
-    }
 }
@@ -0,0 +1,249 @@
+use regex::Regex;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::soundcloud::model::{Comment, Track};
+use crate::util;
+
+/// Matches `[n.] [(]h:mm:ss[)] [-] rest`, the most common tracklist layout
+static LEADING_TIMESTAMP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*(?:\d{1,3}[.)]\s*)?[\[(]?((?:\d{1,2}:)?\d{1,2}:\d{2})[\])]?\s*[-–—|:.]?\s*(.+?)\s*$",
+    )
+    .unwrap()
+});
+
+/// Matches `[n.] rest [(]h:mm:ss[)]`, with the timestamp at the end of the line
+static TRAILING_TIMESTAMP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*(?:\d{1,3}[.)]\s*)?(.+?)\s*[-–—|]?\s*[\[(]?((?:\d{1,2}:)?\d{1,2}:\d{2})[\])]?\s*$",
+    )
+    .unwrap()
+});
+
+/// Separates the artist from the title in `Artist - Title`
+static ARTIST_SEPARATOR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+[-–—]\s+").unwrap());
+
+/// CUE sheet positions are counted in frames of 1/75 second
+const CUE_FRAMES_PER_SECOND: u64 = 75;
+
+/// Minimum number of entries for a tracklist to be worth writing out
+const MIN_ENTRIES: usize = 2;
+
+/// A song within a mix, starting at a point in the audio
+#[derive(Clone, Debug, PartialEq)]
+pub struct TracklistEntry {
+    pub start: Duration,
+    pub performer: Option<String>,
+    pub title: String,
+}
+
+impl TracklistEntry {
+    /// Builds an entry from `Artist - Title` text
+    fn new(start: Duration, text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+
+        let (performer, title) = match ARTIST_SEPARATOR.split(text).collect::<Vec<_>>()[..] {
+            [performer, ref title @ ..] if !title.is_empty() => {
+                (Some(performer.to_string()), title.join(" - "))
+            }
+            _ => (None, text.to_string()),
+        };
+
+        Some(Self {
+            start,
+            performer,
+            title,
+        })
+    }
+}
+
+/// Parses timestamped lines such as `1. 00:00 Artist – Title` out of free text like a
+/// track description
+///
+/// # Returns
+/// The entries sorted by start time, empty if fewer than two timestamped lines were found
+pub fn parse_tracklist(text: &str) -> Vec<TracklistEntry> {
+    let entries = text
+        .lines()
+        .filter_map(|line| {
+            let (timestamp, rest) = if let Some(captures) = LEADING_TIMESTAMP.captures(line) {
+                (captures.get(1)?, captures.get(2)?)
+            } else {
+                let captures = TRAILING_TIMESTAMP.captures(line)?;
+                (captures.get(2)?, captures.get(1)?)
+            };
+
+            let start = util::parse_duration(timestamp.as_str()).ok()?;
+            TracklistEntry::new(start, rest.as_str())
+        })
+        .collect();
+
+    finish(entries)
+}
+
+/// Builds a tracklist from timed comments naming a song, e.g. `Artist - Title` left at
+/// the point it starts playing
+pub fn tracklist_from_comments(comments: &[Comment]) -> Vec<TracklistEntry> {
+    let entries = comments
+        .iter()
+        .filter(|comment| ARTIST_SEPARATOR.is_match(&comment.body))
+        .filter_map(|comment| {
+            let start = Duration::from_millis(comment.timestamp?);
+            TracklistEntry::new(start, comment.body.lines().next()?)
+        })
+        .collect();
+
+    finish(entries)
+}
+
+/// Sorts entries by start time, keeping the first entry for each start
+fn finish(mut entries: Vec<TracklistEntry>) -> Vec<TracklistEntry> {
+    entries.sort_by_key(|entry| entry.start);
+    entries.dedup_by_key(|entry| entry.start);
+
+    if entries.len() < MIN_ENTRIES {
+        entries.clear();
+    }
+
+    entries
+}
+
+/// Writes a CUE sheet for a downloaded mix
+///
+/// # Arguments
+/// * `path` - Output path of the `.cue` file
+/// * `audio` - Path of the audio file the sheet refers to
+/// * `track` - The mix the tracklist belongs to
+/// * `entries` - Songs within the mix
+///
+/// # Returns
+/// Result indicating success or failure
+pub fn write_cue(
+    path: &Path,
+    audio: &Path,
+    track: &Track,
+    entries: &[TracklistEntry],
+) -> Result<()> {
+    let file_name = audio
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_type = match audio.extension().and_then(|ext| ext.to_str()) {
+        Some("mp3") => "MP3",
+        _ => "WAVE",
+    };
+
+    let mut cue = String::new();
+    let _ = writeln!(cue, "PERFORMER {}", quote(&track.user.username));
+    let _ = writeln!(cue, "TITLE {}", quote(&track.title));
+    let _ = writeln!(cue, "FILE {} {}", quote(&file_name), file_type);
+
+    for (i, entry) in entries.iter().enumerate() {
+        let _ = writeln!(cue, "  TRACK {:02} AUDIO", i + 1);
+        let _ = writeln!(cue, "    TITLE {}", quote(&entry.title));
+        if let Some(performer) = &entry.performer {
+            let _ = writeln!(cue, "    PERFORMER {}", quote(performer));
+        }
+        let _ = writeln!(cue, "    INDEX 01 {}", cue_time(entry.start));
+    }
+
+    fs::write(path, cue)?;
+
+    Ok(())
+}
+
+/// Renders a tracklist as an FFmpeg metadata file with one chapter per entry
+///
+/// # Arguments
+/// * `entries` - Songs within the mix
+/// * `total` - Length of the mix, where the last chapter ends
+pub fn ffmetadata_chapters(entries: &[TracklistEntry], total: Duration) -> String {
+    let mut metadata = String::from(";FFMETADATA1\n");
+
+    for (i, entry) in entries.iter().enumerate() {
+        let end = entries
+            .get(i + 1)
+            .map_or(total, |next| next.start)
+            .max(entry.start);
+        let title = match &entry.performer {
+            Some(performer) => format!("{} - {}", performer, entry.title),
+            None => entry.title.clone(),
+        };
+
+        let _ = writeln!(metadata, "[CHAPTER]");
+        let _ = writeln!(metadata, "TIMEBASE=1/1000");
+        let _ = writeln!(metadata, "START={}", entry.start.as_millis());
+        let _ = writeln!(metadata, "END={}", end.as_millis());
+        let _ = writeln!(metadata, "title={}", escape_ffmetadata(&title));
+    }
+
+    metadata
+}
+
+/// Escapes the characters that are special in FFmpeg metadata files
+fn escape_ffmetadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Formats a position as CUE `mm:ss:ff`
+fn cue_time(position: Duration) -> String {
+    let frames = position.as_millis() as u64 * CUE_FRAMES_PER_SECOND / 1000;
+    let secs = frames / CUE_FRAMES_PER_SECOND;
+
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 60,
+        secs % 60,
+        frames % CUE_FRAMES_PER_SECOND
+    )
+}
+
+/// Points the `FILE` line of a cue sheet at a renamed audio file
+///
+/// # Arguments
+/// * `path` - The cue sheet
+/// * `audio` - New path of the audio file it describes
+///
+/// # Returns
+/// Result indicating success or failure
+pub fn retarget_cue(path: &Path, audio: &Path) -> Result<()> {
+    let file_name = audio
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let cue = fs::read_to_string(path)?
+        .lines()
+        .map(|line| match line.strip_prefix("FILE ") {
+            Some(rest) => {
+                let file_type = rest.rsplit(' ').next().unwrap_or("WAVE");
+                format!("FILE {} {}\n", quote(&file_name), file_type)
+            }
+            None => format!("{}\n", line),
+        })
+        .collect::<String>();
+
+    fs::write(path, cue)?;
+
+    Ok(())
+}
+
+/// CUE strings are double quoted and cannot contain double quotes themselves
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "'"))
+}
@@ -0,0 +1,215 @@
+use indicatif::HumanBytes;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::cli::Cli;
+use crate::config::{self, Config};
+use crate::downloader::DEFAULT_MIN_FREE_SPACE;
+use crate::error::{AppError, Result};
+use crate::soundcloud::SoundcloudClient;
+use crate::util;
+
+/// URL requested to check that media can be downloaded
+const CDN_URL: &str = "https://cf-media.sndcdn.com/";
+/// File created to check that a directory is writable
+const PROBE_FILE: &str = ".soundcloud-dl-doctor";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Self::Pass => "PASS",
+            Self::Warn => "WARN",
+            Self::Fail => "FAIL",
+        };
+        write!(f, "[{}]", label)
+    }
+}
+
+/// Outcome of one diagnostic check
+struct Check {
+    name: String,
+    status: Status,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Checks the configuration, token, FFmpeg, network and output directory, printing
+/// a report with one line per check
+///
+/// # Arguments
+/// * `cli` - Parsed command line, for the token, FFmpeg path and client options
+/// * `config` - The loaded config file
+/// * `output` - Directory downloads would be saved to
+///
+/// # Returns
+/// Result indicating whether every check passed, warnings do not count as failures
+pub async fn run(cli: &Cli, config: &Config, output: &Path) -> Result<()> {
+    let mut checks = vec![check_config(config)];
+
+    let token = cli.resolve_auth_token(config).ok();
+    let client = cli.build_client(config, token.clone().unwrap_or_default())?;
+
+    checks.push(check_token(&client, token.as_deref()).await);
+    checks.push(check_ffmpeg(cli).await);
+    checks.push(check_host(&client, "SoundCloud API", client.api_base().as_str()).await);
+    checks.push(check_host(&client, "SoundCloud CDN", CDN_URL).await);
+    checks.push(check_space(output));
+    checks.push(check_writable("Output directory", output));
+    if let Ok(dirs) = config::project_dirs() {
+        checks.push(check_writable("Data directory", dirs.data_dir()));
+    }
+
+    for check in &checks {
+        println!("{} {:<18} {}", check.status, check.name, check.detail);
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        return Err(AppError::Verification(format!(
+            "{} of {} checks failed",
+            failed,
+            checks.len()
+        )));
+    }
+
+    Ok(())
+}
+
+fn check_config(config: &Config) -> Check {
+    let path = config.path().display().to_string();
+
+    match config.validate() {
+        Ok(()) if config.path().exists() => Check::new("Config file", Status::Pass, path),
+        Ok(()) => Check::new(
+            "Config file",
+            Status::Pass,
+            format!("{} does not exist, using defaults", path),
+        ),
+        Err(e) => Check::new("Config file", Status::Fail, e.to_string()),
+    }
+}
+
+async fn check_token(client: &SoundcloudClient, token: Option<&str>) -> Check {
+    if token.is_none() {
+        return Check::new(
+            "OAuth token",
+            Status::Fail,
+            "no token given or stored, save one with --auth <TOKEN> --save-token",
+        );
+    }
+
+    match client.get_me().await {
+        Ok(user) => Check::new(
+            "OAuth token",
+            Status::Pass,
+            format!("logged in as {}", user.username),
+        ),
+        Err(e @ AppError::Unauthorized(_)) => {
+            Check::new("OAuth token", Status::Fail, e.to_string())
+        }
+        Err(e) => Check::new(
+            "OAuth token",
+            Status::Warn,
+            format!("could not be checked: {}", e),
+        ),
+    }
+}
+
+async fn check_ffmpeg(cli: &Cli) -> Check {
+    if cli.no_ffmpeg {
+        return Check::new("FFmpeg", Status::Warn, "disabled with --no-ffmpeg");
+    }
+
+    let ffmpeg = match cli.find_ffmpeg() {
+        Ok(ffmpeg) => ffmpeg,
+        Err(_) => {
+            return Check::new(
+                "FFmpeg",
+                Status::Warn,
+                "not found, it is installed on the next download or with `soundcloud-dl ffmpeg install`",
+            )
+        }
+    };
+
+    match ffmpeg.version().await {
+        Ok(version) => Check::new(
+            "FFmpeg",
+            Status::Pass,
+            format!("{} at {}", version, ffmpeg.path().display()),
+        ),
+        Err(e) => Check::new(
+            "FFmpeg",
+            Status::Fail,
+            format!("{} cannot be run: {}", ffmpeg.path().display(), e),
+        ),
+    }
+}
+
+async fn check_host(client: &SoundcloudClient, name: &str, url: &str) -> Check {
+    // Any response, even an error status, shows the host can be reached
+    match client.ping(url).await {
+        Ok(status) => Check::new(name, Status::Pass, format!("{} answered {}", url, status)),
+        Err(e) => Check::new(name, Status::Fail, format!("{} is unreachable: {}", url, e)),
+    }
+}
+
+fn check_space(dir: &Path) -> Check {
+    match util::available_space(dir) {
+        Some(free) if free < DEFAULT_MIN_FREE_SPACE => Check::new(
+            "Disk space",
+            Status::Warn,
+            format!(
+                "only {} free in {}, downloads stop below {}",
+                HumanBytes(free),
+                dir.display(),
+                HumanBytes(DEFAULT_MIN_FREE_SPACE)
+            ),
+        ),
+        Some(free) => Check::new(
+            "Disk space",
+            Status::Pass,
+            format!("{} free in {}", HumanBytes(free), dir.display()),
+        ),
+        None => Check::new(
+            "Disk space",
+            Status::Warn,
+            format!("could not determine free space of {}", dir.display()),
+        ),
+    }
+}
+
+fn check_writable(name: &str, dir: &Path) -> Check {
+    let probe = dir.join(PROBE_FILE);
+    let result = fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&probe, b""))
+        .and_then(|_| fs::remove_file(&probe));
+
+    match result {
+        Ok(()) => Check::new(name, Status::Pass, format!("{} is writable", dir.display())),
+        Err(e) => Check::new(
+            name,
+            Status::Fail,
+            format!("cannot write to {}: {}", dir.display(), e),
+        ),
+    }
+}
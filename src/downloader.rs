@@ -1,185 +1,1500 @@
-use crate::error::Result;
-use crate::soundcloud::model::{Format, User};
-use crate::soundcloud::{model::Track, SoundcloudClient};
-use crate::{ffmpeg, util};
-use futures::stream::{FuturesUnordered, StreamExt};
-use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::sync::Semaphore;
+use crate::archive::DownloadArchive;
+use crate::artwork::ArtworkOptions;
+use crate::cue::{self, TracklistEntry};
+use crate::error::{AppError, Recovery, Result};
+use crate::ffmpeg;
+use crate::hooks::Hooks;
+use crate::library::{FileInfo, Library};
+use crate::progress::{NoProgress, ProgressReporter};
+use crate::queue::{Job, JobQueue, Priority};
+use crate::soundcloud::model::{Format, Playlist, Protocol, Quality, Transcoding, User};
+use crate::soundcloud::{
+    model::Track, AdaptiveLimiter, DownloadedFile, ResolvedStream, SoundcloudClient,
+    TranscodingSelector,
+};
+use crate::storage::Storage;
+use crate::tags::{Id3Version, TagField, TagMapping, Tags};
+use crate::template::FilenameTemplate;
+use crate::tui;
+use crate::util::{self, ItemRanges};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, FuturesUnordered, StreamExt};
+use indicatif::HumanBytes;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
-const MAX_CONCURRENT_DOWNLOADS: usize = 3;
+/// Number of times a track is downloaded before giving up when verification fails
+const VERIFY_ATTEMPTS: u32 = 2;
+/// Number of times a track is downloaded before giving up on transient errors
+const TRACK_ATTEMPTS: u32 = 3;
+/// Wait before the first retry of a track, doubled for each further one
+const TRACK_RETRY_DELAY: Duration = Duration::from_secs(10);
+/// Allowed difference between the probed and the API's duration
+const VERIFY_DURATION_TOLERANCE: Duration = Duration::from_secs(2);
+/// Size of a second of audio at 256 kbps, the highest bitrate SoundCloud serves
+const ESTIMATED_BYTES_PER_SEC: u64 = 256_000 / 8;
+/// Tracks whose streams are resolved at once by the metadata stage of a batch
+const METADATA_CONCURRENCY: usize = 4;
+/// Tracks the metadata stage may get ahead of the download workers, which bounds how
+/// long resolved media URLs wait before they are used
+const PREFETCH_AHEAD: usize = 8;
+/// Space left free on the output volume unless `--min-free-space` says otherwise
+pub const DEFAULT_MIN_FREE_SPACE: u64 = 500 * 1024 * 1024;
 
 pub struct Downloader {
     pub client: SoundcloudClient,
-    pub ffmpeg: ffmpeg::FFmpeg<PathBuf>,
+    /// `None` in `--no-ffmpeg` mode
+    pub ffmpeg: Option<ffmpeg::FFmpeg<PathBuf>>,
     output_dir: PathBuf,
-    semaphore: Arc<Semaphore>,
+    limiter: Arc<AdaptiveLimiter>,
+    pub library: Arc<Library>,
+    pub options: DownloadOptions,
+    /// Track each file name was given to in this batch, to tell apart tracks that
+    /// share an artist and title
+    file_owners: Mutex<HashMap<PathBuf, u64>>,
+    /// Title of the playlist being downloaded, for tag mappings
+    playlist_title: OnceLock<String>,
+    /// Playlist title or collection name such as `Likes`, for `{collection}` in paths
+    collection: OnceLock<String>,
+    /// Streams resolved by the metadata stage of a batch, taken by the download workers
+    prefetched: Mutex<HashMap<u64, ResolvedStream>>,
+}
+
+/// What a [`Downloader`] fetches or downloads, so entry points resolve their input once
+/// and hand over the result
+#[derive(Clone, Debug)]
+pub enum DownloadRequest {
+    /// A track page URL
+    TrackUrl(String),
+    /// A track by ID, with the secret token of a private track
+    TrackId {
+        id: u64,
+        secret_token: Option<String>,
+    },
+    /// An already fetched playlist, which is not fetched again
+    Playlist(Box<Playlist>),
+    /// A playlist by ID, e.g. one from a listing which leaves out its tracks
+    PlaylistId {
+        id: u64,
+        secret_token: Option<String>,
+    },
+    /// The liked tracks of a user
+    Likes {
+        user: Box<User>,
+        /// Likes skipped from the most recent one
+        skip: usize,
+        limit: u32,
+        chunk_size: u32,
+    },
+    /// The latest uploads of a user
+    Uploads { user_id: u64, limit: u32 },
+}
+
+/// Options controlling which tracks are downloaded and how
+#[derive(Clone, Debug, Default)]
+pub struct DownloadOptions {
+    /// Skip tracks where only a preview snippet is available instead of saving a truncated file
+    pub skip_previews: bool,
+    /// Positions within a playlist or likes to download, all when `None`
+    pub items: Option<ItemRanges>,
+    /// Process playlists and likes in reverse order
+    pub reverse: bool,
+    /// Only download likes made on or after this time
+    pub liked_after: Option<DateTime<Utc>>,
+    /// Only download tracks uploaded on or after this time
+    pub uploaded_after: Option<DateTime<Utc>>,
+    /// Only download tracks uploaded before this time
+    pub uploaded_before: Option<DateTime<Utc>>,
+    /// Only download tracks at least this long
+    pub min_duration: Option<Duration>,
+    /// Only download tracks at most this long
+    pub max_duration: Option<Duration>,
+    /// Skip tracks whose audio file is larger than this many bytes
+    pub max_filesize: Option<u64>,
+    /// Bytes to leave free on the output volume when starting a batch
+    pub min_free_space: u64,
+    /// Only download tracks whose title or artist matches
+    pub match_filter: Option<Regex>,
+    /// Skip tracks whose title or artist matches
+    pub reject_filter: Option<Regex>,
+    /// Only download tracks with one of these license codes, e.g. `cc-by`
+    pub licenses: Vec<String>,
+    /// Stop fetching likes at the first track already in the library
+    pub new_only: bool,
+    /// Skip tracks that are already in the library
+    pub skip_downloaded: bool,
+    /// Only library tracks saved in this directory count for `new_only` and
+    /// `skip_downloaded`, so accounts sharing the library don't skip each other's tracks
+    pub library_scope: Option<PathBuf>,
+    /// Save each track's comments to a `.comments.json` file next to it
+    pub write_comments: bool,
+    /// Save each track's waveform to a `.waveform.json` or `.waveform.png` file next to it
+    pub write_waveform: bool,
+    /// Write a `.cue` sheet for mixes with a tracklist in their description or comments
+    pub write_cue: bool,
+    /// Embed the tracklist of mixes as chapters in M4A files
+    pub embed_chapters: bool,
+    /// Also cut mixes into one file per song, at tracklist times or silent gaps
+    pub split_tracks: bool,
+    /// Parts of each track to cut away
+    pub trim: ffmpeg::Trim,
+    /// Check each saved file with ffprobe and download it again if it is broken
+    pub verify: bool,
+    /// Don't embed cover art
+    pub no_artwork: bool,
+    /// How cover art is resized and converted before it is embedded
+    pub artwork: ArtworkOptions,
+    /// Track fields written to extra tags
+    pub tag_mappings: Vec<TagMapping>,
+    /// ID3v2 version of MP3 tags
+    pub id3_version: Id3Version,
+    /// Also write an ID3v1 tag to MP3 files
+    pub id3v1: bool,
+    /// File names of downloaded tracks
+    pub filename_template: FilenameTemplate,
+    /// Protocols and qualities downloads prefer, in order
+    pub transcodings: TranscodingSelector,
+    /// Always append the track ID to file names
+    pub add_id: bool,
+    /// Set the modification time of saved files to the track's release date
+    pub set_mtime: bool,
+    /// Record the track's URL in a comment tag and, on Unix, an extended attribute
+    pub source_url: bool,
+    /// Commands and webhooks run after each download
+    pub hooks: Hooks,
+    /// Where finished downloads are stored, `None` to only keep them in the output directory
+    pub storage: Option<Storage>,
+    /// yt-dlp style archive of downloaded tracks, whose tracks are skipped
+    pub archive: Option<Arc<DownloadArchive>>,
+    /// Persistent queue downloads go through, so they can be resumed after a restart
+    pub queue: Option<Arc<JobQueue>>,
+    /// Stops downloads in progress once cancelled, deleting their partial files
+    pub cancel: CancellationToken,
+    /// Told about the progress of each track, `None` to report nothing
+    pub progress: Option<Arc<dyn ProgressReporter>>,
+}
+
+impl DownloadOptions {
+    /// Returns the options for the playlists liked along with the likes, without the
+    /// `items` positions and `reverse` order, which pick from the likes themselves
+    pub fn for_liked_playlists(&self) -> Self {
+        Self {
+            items: None,
+            reverse: false,
+            ..self.clone()
+        }
+    }
+}
+
+/// A track comment as saved in the `.comments.json` sidecar
+#[derive(Serialize)]
+struct CommentRecord {
+    author: String,
+    /// Position in the track in milliseconds
+    timestamp: Option<u64>,
+    body: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+/// Outcome counts for a batch of track downloads
+#[derive(Clone, Debug, Default)]
+pub struct BatchSummary {
+    pub total: usize,
+    processed: usize,
+    pub downloaded: usize,
+    pub failed: usize,
+    pub geo_blocked: usize,
+    pub previews: usize,
+    pub too_large: usize,
+    /// Tracks whose streams are all encrypted or otherwise unplayable
+    pub unplayable: usize,
+    /// Deleted or private tracks
+    pub not_found: usize,
+    /// Bytes downloaded for the saved tracks
+    pub bytes: u64,
+    /// Duration of the run, set once it finished
+    pub elapsed: Option<Duration>,
+    /// Times requests were rate limited and how long was waited in total, set once the
+    /// run finished
+    pub rate_limited: Option<(u64, Duration)>,
 }
 
 impl Downloader {
     pub fn new(
         client: SoundcloudClient,
-        output: &PathBuf,
-        ffmpeg: ffmpeg::FFmpeg<PathBuf>,
+        output: &Path,
+        ffmpeg: Option<ffmpeg::FFmpeg<PathBuf>>,
+        library: Arc<Library>,
+        options: DownloadOptions,
     ) -> Result<Self> {
+        // Deep playlist trees can exceed the Windows path limit on their own
+        let output = util::long_path(output.to_path_buf());
         std::fs::create_dir_all(&output)?;
         tracing::info!("Using output directory: {:?}", output);
 
         Ok(Self {
+            limiter: client.limiter(),
             client,
-            output_dir: output.clone(),
-            semaphore: Arc::new(Semaphore::new(MAX_CONCURRENT_DOWNLOADS)),
+            output_dir: output,
             ffmpeg,
+            library,
+            options,
+            file_owners: Mutex::new(HashMap::new()),
+            playlist_title: OnceLock::new(),
+            collection: OnceLock::new(),
+            prefetched: Mutex::new(HashMap::new()),
         })
     }
 
-    pub async fn download_track(&self, url: &str) -> Result<()> {
-        tracing::info!("Fetching track from: {}", url);
-        let mut track = self.client.track_from_url(url).await?;
+    /// Downloads what a request names
+    ///
+    /// Single tracks are saved on their own, failures are returned rather than
+    /// counted. Playlists and likes are downloaded as a batch.
+    ///
+    /// # Returns
+    /// Result containing the outcome counts
+    pub async fn download(&self, request: DownloadRequest) -> Result<BatchSummary> {
+        match request {
+            DownloadRequest::TrackUrl(_) | DownloadRequest::TrackId { .. } => {
+                let track = self.fetch(&request).await?.remove(0);
+                self.save_track(&track).await
+            }
+            request => {
+                let tracks = self.fetch(&request).await?;
+                self.download_tracks(tracks).await
+            }
+        }
+    }
 
-        if track
-            .media
-            .transcodings
-            .iter()
-            .find(|t| t.format.protocol == "progressive" && t.quality == "hq")
-            .or_else(|| {
-                track
-                    .media
-                    .transcodings
-                    .iter()
-                    .find(|t| t.format.protocol == "hls" && t.quality == "hq")
-            })
-            .is_none()
-        {
-            track = self.client.fetch_track(track.id).await?;
+    /// Fetches the full metadata of the tracks a request names, with the `items`
+    /// positions and track filters applied to playlists and likes
+    pub async fn fetch(&self, request: &DownloadRequest) -> Result<Vec<Track>> {
+        match request {
+            DownloadRequest::TrackUrl(url) => {
+                tracing::info!("Fetching track from: {}", url);
+                let mut track = self.client.track_from_url(url).await?;
+
+                let has_hq = track.media.transcodings.iter().any(|t| {
+                    t.quality == Quality::Hq
+                        && matches!(t.format.protocol, Protocol::Progressive | Protocol::Hls)
+                });
+                if !has_hq {
+                    track = self
+                        .client
+                        .fetch_track(track.id, track.secret_token.as_deref())
+                        .await?;
+                }
+
+                Ok(vec![track])
+            }
+            DownloadRequest::TrackId { id, secret_token } => {
+                tracing::info!("Fetching track with ID: {}", id);
+                Ok(vec![self.fetch_track(*id, secret_token.as_deref()).await?])
+            }
+            DownloadRequest::Playlist(playlist) => {
+                self.playlist_tracks(playlist.as_ref().clone()).await
+            }
+            DownloadRequest::PlaylistId { id, secret_token } => {
+                let playlist = self
+                    .client
+                    .fetch_playlist(*id, secret_token.as_deref())
+                    .await?;
+                self.playlist_tracks(playlist).await
+            }
+            DownloadRequest::Likes {
+                user,
+                skip,
+                limit,
+                chunk_size,
+            } => self.fetch_likes(user, *skip, *limit, *chunk_size).await,
+            DownloadRequest::Uploads { user_id, limit } => {
+                tracing::info!("Fetching uploads of user with ID: {}", user_id);
+                let tracks = self.client.get_user_tracks(*user_id, *limit).await?;
+                Ok(self.select_items(tracks).await)
+            }
         }
+    }
 
-        let path = self.process_track(&track).await?;
-        tracing::info!(
-            "Downloaded track {} to: {}",
-            track.permalink_url,
-            path.display()
-        );
+    /// Fetches a track, putting a tombstone on library tracks that are gone from
+    /// SoundCloud
+    ///
+    /// Cached metadata is not used, as it would hide tracks deleted since.
+    pub async fn fetch_track(&self, id: u64, secret_token: Option<&str>) -> Result<Track> {
+        let result = self.client.fetch_track_fresh(id, secret_token).await;
+        self.library.update_availability(id, &result).await?;
 
-        Ok(())
+        result
     }
 
-    pub async fn download_playlist(&self, id: u64) -> Result<()> {
-        let playlist = self.client.fetch_playlist(id).await?;
+    async fn save_track(&self, track: &Track) -> Result<BatchSummary> {
+        let mut summary = BatchSummary::new(1);
 
-        tracing::info!("Fetching playlist from: {}", playlist.permalink_url);
+        if self.in_archive(track).await {
+            tracing::info!(
+                "{} is in the download archive, skipping it",
+                track.permalink_url
+            );
+            return Ok(summary);
+        }
 
-        let tracks_len = playlist.tracks.len();
+        if let Some(queue) = &self.options.queue {
+            let queued = queue.enqueue(
+                track.id,
+                track.secret_token.as_deref(),
+                &self.output_dir,
+                Priority::Single,
+            )?;
+            if !queued {
+                tracing::info!(
+                    "{} was already downloaded by the queue, skipping it",
+                    track.permalink_url
+                );
+                return Ok(summary);
+            }
+            // Other queued jobs of the directory are left to `queue run`
+            return self
+                .run_jobs(queue, vec![track.clone()], Some(track.id))
+                .await;
+        }
 
-        let mut futures = FuturesUnordered::new();
+        let (path, bytes) = self.process_track(track).await?;
+        summary.record(Ok((track.clone(), path, bytes)));
 
-        for (i, track) in playlist.tracks.into_iter().enumerate() {
-            let permit = self.semaphore.clone().acquire_owned().await.unwrap();
-            let progress = i + 1;
+        Ok(summary)
+    }
 
-            futures.push(tokio::spawn(async move {
-                let _permit = permit; // Keep permit alive for scope of task
-                (track, progress)
-            }));
-        }
+    /// Fetches the full metadata of all tracks of a playlist
+    async fn playlist_tracks(&self, mut playlist: Playlist) -> Result<Vec<Track>> {
+        tracing::info!("Fetching playlist from: {}", playlist.permalink_url);
+        let _ = self.playlist_title.set(playlist.title.clone());
+        self.set_collection(&playlist.title);
 
-        while let Some(result) = futures.next().await {
-            let (track, progress) = result.unwrap();
+        let tracks = std::mem::take(&mut playlist.tracks)
+            .into_iter()
+            .map(|track| {
+                let id = track.id;
+                (id, track.into_track())
+            })
+            .collect::<Vec<_>>();
 
-            let track_id = track.id;
+        // Large playlists only include full metadata for the first few tracks,
+        // the rest are bare stubs which are hydrated in bulk
+        let stub_ids = tracks
+            .iter()
+            .filter(|(_, track)| track.is_none())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
 
-            let track = match track.into_track() {
+        let mut hydrated = if stub_ids.is_empty() {
+            HashMap::new()
+        } else {
+            tracing::info!("Fetching metadata for {} tracks", stub_ids.len());
+            self.client
+                .fetch_tracks(&stub_ids, Some(&playlist))
+                .await?
+                .into_iter()
+                .map(|track| (track.id, track))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let mut hydrated_tracks = Vec::with_capacity(tracks.len());
+        for (track_id, track) in tracks {
+            let track = match track.or_else(|| hydrated.remove(&track_id)) {
                 Some(track) => track,
-                None => match self.client.fetch_track(track_id).await {
+                None => match self.fetch_track(track_id, None).await {
                     Ok(track) => track,
                     Err(e) => {
-                        tracing::error!("Failed to fetch track: {}", e);
+                        tracing::error!("Failed to fetch track {}: {}", track_id, e);
                         continue;
                     }
                 },
             };
 
-            match self.process_track(&track).await {
-                Ok(path) => {
-                    tracing::info!(
-                        "Downloaded track {} to: {} | ({}/{})",
-                        track.permalink_url,
-                        path.display(),
-                        progress,
-                        tracks_len,
-                    );
-                }
-                Err(e) => {
-                    tracing::error!("Failed to download track: {}", e);
-                }
-            }
+            hydrated_tracks.push(track);
         }
 
-        Ok(())
+        Ok(self.select_items(hydrated_tracks).await)
     }
 
-    pub async fn download_likes(
+    /// Fetches the liked tracks of a user
+    async fn fetch_likes(
         &self,
         user: &User,
         skip: usize,
         limit: u32,
         chunk_size: u32,
-    ) -> Result<()> {
+    ) -> Result<Vec<Track>> {
         tracing::info!("Fetching likes for user: {}", user.username);
+        self.set_collection("Likes");
 
-        let likes = self.client.get_likes(user.id, limit, chunk_size).await?;
+        let likes = if self.options.new_only {
+            self.reload_records().await;
+            let likes = self
+                .client
+                .get_likes_until(user.id, limit, chunk_size, |like| {
+                    self.is_downloaded(like.track.id)
+                })
+                .await?;
+            tracing::info!("Found {} new like(s) since the last sync", likes.len());
+            likes
+        } else {
+            self.client.get_likes(user.id, limit, chunk_size).await?
+        };
         let total = likes.len().min(limit as usize);
 
-        let mut futures = FuturesUnordered::new();
+        let tracks = likes
+            .into_iter()
+            .skip(skip)
+            .take(total)
+            .filter(|like| match (self.options.liked_after, like.created_at) {
+                (Some(after), Some(liked)) => liked >= after,
+                _ => true,
+            })
+            .map(|like| like.track)
+            .collect();
 
-        for (i, like) in likes.into_iter().skip(skip).enumerate() {
-            if i >= total {
-                break;
+        Ok(self.select_items(tracks).await)
+    }
+
+    /// Sets the name filled in for `{collection}` in output paths, the first name set
+    /// is kept
+    pub fn set_collection(&self, name: &str) {
+        let _ = self.collection.set(name.to_string());
+    }
+
+    /// Applies the `items` positions, track filters and `reverse` order to a collection's tracks
+    pub async fn select_items(&self, tracks: Vec<Track>) -> Vec<Track> {
+        let count = tracks.len();
+        self.reload_records().await;
+
+        let mut tracks = tracks
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| {
+                self.options
+                    .items
+                    .as_ref()
+                    .is_none_or(|items| items.contains(i + 1))
+            })
+            .map(|(_, track)| track)
+            .filter(|track| !(self.options.skip_downloaded && self.is_downloaded(track.id)))
+            .filter(|track| {
+                !self
+                    .options
+                    .archive
+                    .as_ref()
+                    .is_some_and(|archive| archive.contains(track.id))
+            })
+            .filter(|track| self.matches_filters(track))
+            .collect::<Vec<_>>();
+
+        if tracks.len() < count {
+            tracing::info!(
+                "Selected {} of {} track(s) after filtering",
+                tracks.len(),
+                count
+            );
+        }
+
+        if self.options.reverse {
+            tracks.reverse();
+        }
+
+        tracks
+    }
+
+    /// Downloads already fetched tracks concurrently, logging a summary at the end
+    ///
+    /// Streams are resolved by a metadata stage limited by the API's rate limiter, which
+    /// feeds the download workers limited by the CDN's.
+    pub async fn download_tracks(&self, tracks: Vec<Track>) -> Result<BatchSummary> {
+        self.check_free_space(&tracks)?;
+
+        if let Some(queue) = &self.options.queue {
+            for track in &tracks {
+                let queued = queue.enqueue(
+                    track.id,
+                    track.secret_token.as_deref(),
+                    &self.output_dir,
+                    Priority::Bulk,
+                )?;
+                if !queued {
+                    tracing::debug!(
+                        "{} was already downloaded by the queue",
+                        track.permalink_url
+                    );
+                }
             }
+            return self.run_queue(queue, tracks).await;
+        }
+
+        let total = tracks.len();
+        let (sender, receiver) = mpsc::channel::<Track>(PREFETCH_AHEAD);
+
+        // Metadata stage, resolving streams through the API's own rate limiter
+        let prefetch = async move {
+            let mut prepared = stream::iter(tracks)
+                .map(|track| async move {
+                    self.prefetch_stream(&track).await;
+                    track
+                })
+                .buffered(METADATA_CONCURRENCY);
 
-            let permit = self.semaphore.clone().acquire_owned().await.unwrap();
-            let track = like.track;
-            let progress = i + 1 + skip;
+            while let Some(track) = prepared.next().await {
+                // The download stage stopped early
+                if self.options.cancel.is_cancelled() || sender.send(track).await.is_err() {
+                    break;
+                }
+            }
+        };
 
-            futures.push(tokio::spawn(async move {
-                let _permit = permit; // Keep permit alive for scope of task
-                (track, progress)
-            }));
+        // Download stage, taking a download slot before the next track so the metadata
+        // stage only gets a few tracks ahead
+        let download = async move {
+            let downloads = stream::unfold(receiver, |mut receiver| async move {
+                // The metadata stage stops as well, which ends the channel
+                let permit = tokio::select! {
+                    biased;
+                    _ = self.options.cancel.cancelled() => return None,
+                    permit = self.limiter.acquire() => permit,
+                };
+                let track = receiver.recv().await?;
+                Some(((permit, track), receiver))
+            })
+            .map(|(permit, track)| async move {
+                let _permit = permit;
+                let (path, bytes) = self.process_track_retrying(&track).await?;
+                Ok::<_, AppError>((track, path, bytes))
+            })
+            .buffer_unordered(usize::MAX);
+            let mut downloads = std::pin::pin!(downloads);
+
+            let mut summary = BatchSummary::new(total);
+            while let Some(result) = downloads.next().await {
+                match result {
+                    Err(AppError::Cancelled) => {
+                        // Lets the other downloads delete their partial files
+                        while downloads.next().await.is_some() {}
+                        return Err(AppError::Cancelled);
+                    }
+                    Err(e) if e.recovery() == Recovery::Abort => {
+                        tracing::error!("Stopping the batch: {}", e);
+                        return Err(e);
+                    }
+                    result => summary.record(result),
+                }
+            }
+            if self.options.cancel.is_cancelled() {
+                return Err(AppError::Cancelled);
+            }
+            summary.log();
+
+            Ok(summary)
+        };
+
+        let ((), summary) = tokio::join!(prefetch, download);
+        self.prefetched.lock().unwrap().clear();
+
+        summary
+    }
+
+    /// Resolves the stream of a track ahead of its download
+    ///
+    /// Failures are left to the download, which resolves the stream again and reports
+    /// the error.
+    async fn prefetch_stream(&self, track: &Track) {
+        if track.is_geo_blocked() || (track.is_preview() && self.options.skip_previews) {
+            return;
         }
+        let Some(transcoding) = self
+            .transcoding_selector()
+            .select(&track.media.transcodings)
+        else {
+            return;
+        };
+
+        match self.client.resolve_stream(track, transcoding).await {
+            Ok(stream) => {
+                self.prefetched.lock().unwrap().insert(track.id, stream);
+            }
+            Err(e) => tracing::debug!(
+                "Could not resolve the stream of {} ahead of time: {}",
+                track.permalink_url,
+                e
+            ),
+        }
+    }
+
+    /// Downloads a track, trying again after transient errors such as server errors
+    /// or dropped connections
+    async fn process_track_retrying(&self, track: &Track) -> Result<(PathBuf, u64)> {
+        let mut delay = TRACK_RETRY_DELAY;
+        let mut attempt = 1;
 
-        while let Some(result) = futures.next().await {
-            let (track, progress) = result.unwrap();
-            match self.process_track(&track).await {
-                Ok(path) => {
-                    tracing::info!(
-                        "Downloaded track {} to: {} | ({}/{})",
+        loop {
+            match self.process_track(track).await {
+                Err(e) if e.recovery() == Recovery::Retry && attempt < TRACK_ATTEMPTS => {
+                    tracing::warn!(
+                        "Failed to download {}, retrying in {:?}: {}",
                         track.permalink_url,
-                        path.display(),
-                        progress,
-                        total
+                        delay,
+                        e
                     );
+                    self.sleep(delay).await?;
+                    delay *= 2;
+                    attempt += 1;
                 }
+                result => return result,
+            }
+        }
+    }
+
+    /// Fails before a batch is started when its estimated size would not leave
+    /// `min_free_space` bytes free on the output volume
+    ///
+    /// The estimate assumes every track is downloaded at the highest bitrate SoundCloud
+    /// serves, so it errs on the side of caution.
+    fn check_free_space(&self, tracks: &[Track]) -> Result<()> {
+        let Some(available) = util::available_space(&self.output_dir) else {
+            tracing::debug!(
+                "Could not determine free space of {}",
+                self.output_dir.display()
+            );
+            return Ok(());
+        };
+
+        let estimate = tracks
+            .iter()
+            .map(|track| track.full_duration.max(track.duration) / 1000 * ESTIMATED_BYTES_PER_SEC)
+            .sum::<u64>();
+        let required = estimate.saturating_add(self.options.min_free_space);
+
+        if required > available {
+            return Err(AppError::DiskSpace(format!(
+                "{} track(s) need about {} and {} should stay free, but only {} is available in {}",
+                tracks.len(),
+                HumanBytes(estimate),
+                HumanBytes(self.options.min_free_space),
+                HumanBytes(available),
+                self.output_dir.display()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Works through the queued jobs of the output directory until none are left, waiting
+    /// out the backoff of failed jobs
+    ///
+    /// # Arguments
+    /// * `queue` - The job queue
+    /// * `tracks` - Metadata of tracks that were just queued, other tracks are fetched again
+    ///
+    /// # Returns
+    /// Result containing the counts of jobs that completed or were given up on
+    pub async fn run_queue(&self, queue: &JobQueue, tracks: Vec<Track>) -> Result<BatchSummary> {
+        self.run_jobs(queue, tracks, None).await
+    }
+
+    /// Works through the queued jobs of the output directory like
+    /// [`run_queue`](Self::run_queue), only the job of `track_id` if given
+    async fn run_jobs(
+        &self,
+        queue: &JobQueue,
+        tracks: Vec<Track>,
+        track_id: Option<u64>,
+    ) -> Result<BatchSummary> {
+        let known = tracks
+            .into_iter()
+            .map(|track| (track.id, track))
+            .collect::<HashMap<_, _>>();
+        let mut seen = HashSet::new();
+        let mut summary = BatchSummary::default();
+        let mut workers = FuturesUnordered::new();
+
+        loop {
+            if self.options.cancel.is_cancelled() {
+                return Err(AppError::Cancelled);
+            }
+
+            while let Some(job) = queue.claim(Some(&self.output_dir), track_id)? {
+                if seen.insert(job.id) {
+                    summary.total += 1;
+                }
+
+                let track = known.get(&job.track_id).cloned();
+                workers.push(self.run_job(queue, job, track));
+            }
+
+            if let Some(outcome) = workers.next().await {
+                match &outcome {
+                    // Lets the other workers put their jobs back
+                    Err(AppError::Cancelled) => while workers.next().await.is_some() {},
+                    // The other downloads are dropped, their jobs are run again later
+                    Err(_) => {
+                        workers.clear();
+                        queue.release_claimed()?;
+                    }
+                    Ok(_) => {}
+                }
+                if let Some(result) = outcome? {
+                    summary.record(result);
+                }
+                continue;
+            }
+
+            match queue.next_ready_in(Some(&self.output_dir), track_id)? {
+                Some(wait) => {
+                    tracing::info!("Waiting {:?} before retrying failed downloads", wait);
+                    self.sleep(wait).await?;
+                }
+                None => break,
+            }
+        }
+
+        summary.log();
+
+        Ok(summary)
+    }
+
+    /// Downloads the track of a claimed job and records the outcome in the queue
+    ///
+    /// # Returns
+    /// Result containing the outcome, `None` if the job was put back to be retried
+    async fn run_job(
+        &self,
+        queue: &JobQueue,
+        job: Job,
+        track: Option<Track>,
+    ) -> Result<Option<Result<(Track, PathBuf, u64)>>> {
+        // The download itself stops on its own, deleting its partial file
+        let claimed = self
+            .cancellable(async {
+                let permit = self.limiter.acquire().await;
+
+                let track = match track {
+                    Some(track) => track,
+                    None => {
+                        self.fetch_track(job.track_id, job.secret_token.as_deref())
+                            .await?
+                    }
+                };
+                Ok((permit, track))
+            })
+            .await;
+        let result = match claimed {
+            Ok((_permit, track)) => self
+                .process_track(&track)
+                .await
+                .map(|(path, bytes)| (track, path, bytes)),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Err(AppError::Cancelled) => {
+                queue.release(&job)?;
+                Err(AppError::Cancelled)
+            }
+            Ok(done) => {
+                queue.complete(&job)?;
+                Ok(Some(Ok(done)))
+            }
+            Err(e) => {
+                // Retrying cannot fix tracks that are unavailable or filtered out
+                let recovery = e.recovery();
+                let retry = recovery != Recovery::Skip;
+
+                if recovery == Recovery::Abort {
+                    queue.fail(&job, &e.to_string(), retry)?;
+                    tracing::error!("Stopping the queue: {}", e);
+                    return Err(e);
+                }
+
+                if queue.fail(&job, &e.to_string(), retry)? {
+                    tracing::warn!(
+                        "Failed to download track {}, retrying later: {}",
+                        job.track_id,
+                        e
+                    );
+                    Ok(None)
+                } else {
+                    Ok(Some(Err(e)))
+                }
+            }
+        }
+    }
+
+    /// Whether the track is listed in the download archive, read again first so tracks
+    /// another instance downloaded in the meantime are not downloaded twice
+    async fn in_archive(&self, track: &Track) -> bool {
+        let Some(archive) = &self.options.archive else {
+            return false;
+        };
+        if let Err(e) = archive.reload().await {
+            tracing::warn!("Failed to read the download archive: {}", e);
+        }
+        archive.contains(track.id)
+    }
+
+    /// Whether a track is in the library, saved in the directory it is scoped to if any
+    fn is_downloaded(&self, id: u64) -> bool {
+        match &self.options.library_scope {
+            Some(scope) => self
+                .library
+                .get(id)
+                .is_some_and(|entry| entry.path.starts_with(scope)),
+            None => self.library.contains(id),
+        }
+    }
+
+    /// Whether every track of a playlist is in the library, so `new_only` stops at it
+    async fn is_synced(&self, playlist: &Playlist) -> bool {
+        self.reload_records().await;
+        !playlist.tracks.is_empty()
+            && playlist
+                .tracks
+                .iter()
+                .all(|track| self.is_downloaded(track.id))
+    }
+
+    /// Reads the download archive, and the library when tracks in it are skipped, again
+    /// to pick up tracks other instances downloaded since they were read
+    async fn reload_records(&self) {
+        if let Some(archive) = &self.options.archive {
+            if let Err(e) = archive.reload().await {
+                tracing::warn!("Failed to read the download archive: {}", e);
+            }
+        }
+        if self.options.skip_downloaded || self.options.new_only {
+            if let Err(e) = self.library.reload().await {
+                tracing::warn!("Failed to read the library: {}", e);
+            }
+        }
+    }
+
+    /// Whether the track passes the upload date, title/artist, license and duration filters
+    fn matches_filters(&self, track: &Track) -> bool {
+        let options = &self.options;
+
+        let title_or_artist =
+            |re: &Regex| re.is_match(&track.title) || re.is_match(&track.user.username);
+
+        if options
+            .match_filter
+            .as_ref()
+            .is_some_and(|re| !title_or_artist(re))
+            || options.reject_filter.as_ref().is_some_and(title_or_artist)
+        {
+            return false;
+        }
+
+        if let Some(uploaded) = track.created_at {
+            if options.uploaded_after.is_some_and(|after| uploaded < after)
+                || options
+                    .uploaded_before
+                    .is_some_and(|before| uploaded >= before)
+            {
+                return false;
+            }
+        }
+
+        if !options.licenses.is_empty()
+            && !track
+                .license
+                .as_ref()
+                .is_some_and(|license| options.licenses.contains(license))
+        {
+            return false;
+        }
+
+        let duration = Duration::from_millis(track.full_duration.max(track.duration));
+        if duration > Duration::ZERO
+            && (options.min_duration.is_some_and(|min| duration < min)
+                || options.max_duration.is_some_and(|max| duration > max))
+        {
+            return false;
+        }
+
+        true
+    }
+
+    /// Downloads a track, reporting its progress
+    ///
+    /// # Returns
+    /// Result containing the path of the saved file and the bytes downloaded for it
+    async fn process_track(&self, track: &Track) -> Result<(PathBuf, u64)> {
+        self.progress().on_track_start(track);
+        let result = self.save_track_file(track).await;
+        match &result {
+            Ok((path, _)) => self.progress().on_track_done(track, path),
+            Err(e) => self.progress().on_error(track, e),
+        }
+
+        result
+    }
+
+    async fn save_track_file(&self, track: &Track) -> Result<(PathBuf, u64)> {
+        if track.is_preview() {
+            if self.options.skip_previews {
+                return Err(AppError::PreviewOnly(track.title.clone()));
+            }
+
+            tracing::warn!(
+                "Only a {}s preview of {}{} is available, the saved file will be truncated",
+                track.duration / 1000,
+                track.permalink_url,
+                if track.is_go_plus() { " (Go+)" } else { "" },
+            );
+        }
+
+        let tracklist =
+            if self.options.write_cue || self.options.embed_chapters || self.options.split_tracks {
+                self.fetch_tracklist(track).await
+            } else {
+                Vec::new()
+            };
+
+        let mut attempt = 1;
+        let (path, quality, bytes) = loop {
+            let (path, quality, bytes) = self.save_audio(track, &tracklist).await?;
+            if !self.options.verify {
+                break (path, quality, bytes);
+            }
+
+            match self.verify(track, &path).await {
+                Ok(()) => break (path, quality, bytes),
+                Err(e) => {
+                    let _ = std::fs::remove_file(&path);
+                    if attempt >= VERIFY_ATTEMPTS {
+                        return Err(e);
+                    }
+
+                    tracing::warn!("{}, downloading {} again", e, track.permalink_url);
+                    attempt += 1;
+                }
+            }
+        };
+
+        // Files written next to the audio, stored along with it
+        let mut sidecars = Vec::new();
+
+        if self.options.write_comments {
+            match self.write_comments(track, &path).await {
+                Ok(comments) => sidecars.push(comments),
                 Err(e) => {
-                    tracing::error!("Failed to download track: {}", e);
+                    tracing::warn!("Failed to save comments of {}: {}", track.permalink_url, e)
                 }
             }
         }
 
-        Ok(())
+        if self.options.write_waveform {
+            match self.write_waveform(track, &path).await {
+                Ok(waveform) => sidecars.extend(waveform),
+                Err(e) => {
+                    tracing::warn!("Failed to save waveform of {}: {}", track.permalink_url, e)
+                }
+            }
+        }
+
+        if self.options.write_cue && !tracklist.is_empty() {
+            let cue_path = path.with_extension("cue");
+            match cue::write_cue(&cue_path, &path, track, &tracklist) {
+                Ok(()) => sidecars.push(cue_path),
+                Err(e) => tracing::warn!(
+                    "Failed to write CUE sheet of {}: {}",
+                    track.permalink_url,
+                    e
+                ),
+            }
+        }
+
+        if self.options.split_tracks {
+            match self.split_mix(track, &path, &tracklist).await {
+                Ok(0) => tracing::info!("No song boundaries found in {}", track.permalink_url),
+                Ok(count) => tracing::info!("Split {} into {} files", track.permalink_url, count),
+                Err(e) => tracing::warn!("Failed to split {}: {}", track.permalink_url, e),
+            }
+        }
+
+        let stored_at = match &self.options.storage {
+            Some(storage) => {
+                let stored_at = storage.store(&path).await?;
+                for sidecar in &sidecars {
+                    if let Err(e) = storage.store(sidecar).await {
+                        tracing::warn!("Failed to store {}: {}", sidecar.display(), e);
+                    }
+                }
+                Some(stored_at)
+            }
+            None => None,
+        };
+
+        if let Err(e) = self.library.record(track, &path, Some(&quality)).await {
+            tracing::warn!("Failed to record track in library: {}", e);
+        }
+        if let Some(stored_at) = stored_at {
+            if let Err(e) = self.library.set_stored_at(track.id, stored_at).await {
+                tracing::warn!("Failed to record storage location in library: {}", e);
+            }
+        }
+        if let Some(archive) = &self.options.archive {
+            if let Err(e) = archive.record(track.id).await {
+                tracing::warn!("Failed to record track in download archive: {}", e);
+            }
+        }
+        match self.file_info(&path).await {
+            Ok(info) => {
+                if let Err(e) = self.library.set_file_info(track.id, info).await {
+                    tracing::warn!("Failed to record file info in library: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to checksum {}: {}", path.display(), e),
+        }
+
+        self.options.hooks.run(track, &path).await;
+
+        if self
+            .options
+            .storage
+            .as_ref()
+            .is_some_and(|storage| !storage.keep_local())
+        {
+            for sidecar in &sidecars {
+                let _ = std::fs::remove_file(sidecar);
+            }
+            std::fs::remove_file(&path)?;
+        }
+
+        Ok((path, bytes))
     }
 
-    async fn process_track(&self, track: &Track) -> Result<PathBuf> {
-        let (transcoding, audio) = self.client.download_track(track).await?;
-        let thumbnail = self.client.download_cover(track).await?;
+    /// Downloads, tags and post-processes the audio of a track
+    ///
+    /// # Returns
+    /// Result containing the path of the saved file, the quality of the stream and the
+    /// bytes downloaded for it
+    async fn save_audio(
+        &self,
+        track: &Track,
+        tracklist: &[TracklistEntry],
+    ) -> Result<(PathBuf, String, u64)> {
+        let (transcoding, audio) = self.cancellable(self.download_audio(track)).await?;
+        // FFmpeg fetches the segments of HLS streams, whose size is only known once saved
+        let streamed =
+            (transcoding.format.protocol != Protocol::Hls).then_some(audio.data.len() as u64);
+        let thumbnail = self.artwork(track).await?;
 
         let audio_ext = Self::mime_type_to_ext(&transcoding.format);
 
         let path = self.prepare_file_path(track, &audio_ext);
+        let part = util::part_path(&path);
+        // Filename templates can place tracks in subdirectories
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let chapters = (self.options.embed_chapters && !tracklist.is_empty()).then(|| {
+            cue::ffmetadata_chapters(
+                tracklist,
+                Duration::from_millis(track.full_duration.max(track.duration)),
+            )
+        });
+
+        let result = self
+            .cancellable(async {
+                self.process_audio(
+                    &part,
+                    audio,
+                    &audio_ext,
+                    thumbnail,
+                    &self.tags(track),
+                    chapters.as_deref(),
+                )
+                .await?;
+
+                if !self.options.trim.is_empty() {
+                    self.require_ffmpeg("Trimming")?
+                        .trim(&part, &self.options.trim)
+                        .await?;
+                }
+
+                Ok(())
+            })
+            .await;
+
+        // Partial files of failed and cancelled downloads alike are deleted
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&part);
+            return Err(e);
+        }
+        let bytes = match streamed {
+            Some(bytes) => bytes,
+            None => std::fs::metadata(&part)?.len(),
+        };
+        std::fs::rename(&part, &path)?;
+
+        self.set_file_attributes(track, &path);
 
-        self.process_audio(&path, audio, &audio_ext, thumbnail)
-            .await?;
+        Ok((path, transcoding.quality.to_string(), bytes))
+    }
 
-        Ok(path)
+    /// Runs a step of a download until the run is cancelled, dropping the step then,
+    /// which also kills the FFmpeg processes it started
+    ///
+    /// # Returns
+    /// Result of the step, [`AppError::Cancelled`] if it was cancelled
+    pub async fn cancellable<T>(&self, step: impl Future<Output = Result<T>>) -> Result<T> {
+        tokio::select! {
+            biased;
+            _ = self.options.cancel.cancelled() => Err(AppError::Cancelled),
+            result = step => result,
+        }
+    }
+
+    /// Waits before trying something again, failing once the run is cancelled
+    async fn sleep(&self, duration: Duration) -> Result<()> {
+        self.cancellable(async {
+            tokio::time::sleep(duration).await;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Downloads the most preferred playable transcoding of a track
+    ///
+    /// Streams that cannot be resolved or fetched are passed over for the next one, as
+    /// long as the error is not one every stream would run into. With the queue, a stream
+    /// resolved by an interrupted attempt is reused until its URL expires.
+    ///
+    /// # Returns
+    /// Result containing the transcoding and its media, an HLS playlist for HLS streams
+    async fn download_audio<'t>(
+        &self,
+        track: &'t Track,
+    ) -> Result<(&'t Transcoding, DownloadedFile)> {
+        if track.is_geo_blocked() {
+            return Err(AppError::GeoBlocked(track.title.clone()));
+        }
+
+        let prefetched = self
+            .prefetched
+            .lock()
+            .unwrap()
+            .remove(&track.id)
+            .filter(|stream| !stream.is_expired());
+        let resumed = match (prefetched, &self.options.queue) {
+            (Some(stream), _) => Some(stream),
+            (None, Some(queue)) => queue.stream(track.id)?,
+            (None, None) => None,
+        };
+
+        let mut last_error = None;
+        for transcoding in self
+            .transcoding_selector()
+            .candidates(&track.media.transcodings)
+        {
+            let audio = match self
+                .fetch_transcoding(track, transcoding, resumed.as_ref())
+                .await
+            {
+                Ok(audio) => audio,
+                Err(e) if matches!(e.recovery(), Recovery::Skip | Recovery::Retry) => {
+                    tracing::warn!(
+                        "Could not download the {} {} stream of {}, trying the next one: {}",
+                        transcoding.format.protocol,
+                        transcoding.quality,
+                        track.permalink_url,
+                        e
+                    );
+                    last_error = Some(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            // Some streams only reveal their encryption in the playlist
+            if transcoding.format.protocol == Protocol::Hls && audio.is_encrypted_playlist() {
+                tracing::debug!(
+                    "The {} {} stream of {} is encrypted, trying the next one",
+                    transcoding.format.protocol,
+                    transcoding.quality,
+                    track.permalink_url
+                );
+                continue;
+            }
+
+            return Ok((transcoding, audio));
+        }
+
+        if let Some(e) = last_error {
+            return Err(e);
+        }
+        let offered = track
+            .media
+            .transcodings
+            .iter()
+            .map(|t| format!("{} {}", t.format.protocol, t.quality))
+            .collect::<Vec<_>>();
+        Err(AppError::NoPlayableStream(format!(
+            "{}, offered streams: {}",
+            track.title,
+            match offered.is_empty() {
+                true => "none".to_string(),
+                false => offered.join(", "),
+            }
+        )))
+    }
+
+    /// Downloads the media of a transcoding, resolving its URL unless a resumed stream
+    /// of the same transcoding is still valid
+    async fn fetch_transcoding(
+        &self,
+        track: &Track,
+        transcoding: &Transcoding,
+        resumed: Option<&ResolvedStream>,
+    ) -> Result<DownloadedFile> {
+        let max_size = self.options.max_filesize;
+
+        if let Some(stream) = resumed.filter(|stream| stream.transcoding_url == transcoding.url) {
+            match self
+                .client
+                .fetch_stream(track, stream, max_size, self.progress())
+                .await
+            {
+                // The CDN rejects URLs whose signature expired early
+                Err(AppError::NotFound(_)) => {}
+                Err(AppError::Network(e)) if e.status().is_some_and(|s| s.is_client_error()) => {}
+                result => return result,
+            }
+            tracing::debug!(
+                "Stream of {} expired, resolving it again",
+                track.permalink_url
+            );
+        }
+
+        let stream = self.client.resolve_stream(track, transcoding).await?;
+        if let Some(queue) = &self.options.queue {
+            queue.save_stream(track.id, &stream)?;
+        }
+
+        self.client
+            .fetch_stream(track, &stream, max_size, self.progress())
+            .await
+    }
+
+    /// Returns where the progress of tracks is reported
+    pub fn progress(&self) -> &dyn ProgressReporter {
+        self.options.progress.as_deref().unwrap_or(&NoProgress)
+    }
+
+    /// Returns the configured transcoding order, leaving out protocols that need FFmpeg
+    /// when it is not available
+    pub fn transcoding_selector(&self) -> TranscodingSelector {
+        match self.ffmpeg {
+            Some(_) => self.options.transcodings.clone(),
+            None => self.options.transcodings.without_ffmpeg(),
+        }
+    }
+
+    /// Computes the checksum of a saved file and, with FFmpeg, measures its length and
+    /// bitrate
+    pub async fn file_info(&self, path: &Path) -> Result<FileInfo> {
+        let hashed = path.to_path_buf();
+        let sha256 = tokio::task::spawn_blocking(move || util::file_sha256(&hashed))
+            .await
+            .map_err(|e| AppError::Audio(format!("Checksum failed: {}", e)))??;
+
+        let probe = match &self.ffmpeg {
+            Some(ffmpeg) => ffmpeg.probe(path).await.ok(),
+            None => None,
+        };
+
+        Ok(FileInfo {
+            size: std::fs::metadata(path)?.len(),
+            sha256,
+            duration: probe
+                .as_ref()
+                .and_then(|probe| probe.duration)
+                .map(|duration| duration.as_millis() as u64),
+            bitrate: probe.and_then(|probe| probe.bit_rate),
+        })
+    }
+
+    /// Records the origin URL and sets the modification time of a saved file as enabled
+    pub fn set_file_attributes(&self, track: &Track, path: &Path) {
+        if self.options.source_url {
+            record_origin(path, &track.permalink_url);
+        }
+
+        if let Some(date) = track.release_date().filter(|_| self.options.set_mtime) {
+            if let Err(e) = set_modified(path, date) {
+                tracing::warn!(
+                    "Failed to set modification time of {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Downloads the cover art to embed, processed as configured
+    ///
+    /// Artwork that cannot be processed is embedded as downloaded.
+    pub async fn artwork(&self, track: &Track) -> Result<Option<DownloadedFile>> {
+        if self.options.no_artwork {
+            return Ok(None);
+        }
+
+        let Some(artwork) = self.client.download_cover(track).await? else {
+            return Ok(None);
+        };
+
+        match self.options.artwork.apply(artwork.clone()).await {
+            Ok(artwork) => Ok(Some(artwork)),
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to process artwork of {}: {}",
+                    track.permalink_url,
+                    e
+                );
+                Ok(Some(artwork))
+            }
+        }
+    }
+
+    /// Text tags written to the files of a track
+    pub fn tags(&self, track: &Track) -> Tags {
+        let mut tags = Tags::from_track(track);
+        if self.options.source_url {
+            tags.push(TagField::Comment, track.permalink_url.clone());
+        }
+        tags.push_mapped(
+            &self.options.tag_mappings,
+            track,
+            self.playlist_title.get().map(String::as_str),
+        );
+
+        tags
+    }
+
+    /// Checks with ffprobe that a saved file has audio, the expected duration and artwork
+    async fn verify(&self, track: &Track, path: &Path) -> Result<()> {
+        let probe = self.require_ffmpeg("Verifying")?.probe(path).await?;
+        let fail =
+            |reason: String| AppError::Verification(format!("{}: {}", path.display(), reason));
+
+        if probe.audio_streams == 0 {
+            return Err(fail("no audio stream".into()));
+        }
+
+        // Trimming changes the length, so only untrimmed files can be compared
+        let expected = Duration::from_millis(track.duration);
+        if let (Some(actual), true) = (
+            probe.duration,
+            !expected.is_zero() && self.options.trim.is_empty(),
+        ) {
+            if actual.abs_diff(expected) > VERIFY_DURATION_TOLERANCE {
+                return Err(fail(format!(
+                    "duration is {:.1}s, expected {:.1}s",
+                    actual.as_secs_f64(),
+                    expected.as_secs_f64()
+                )));
+            }
+        }
+
+        let supports_artwork = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("mp3" | "m4a")
+        );
+        if track.artwork_url.is_some()
+            && !self.options.no_artwork
+            && supports_artwork
+            && !probe.has_artwork
+        {
+            return Err(fail("artwork is missing".into()));
+        }
+
+        Ok(())
+    }
+
+    /// Finds the tracklist of a mix in its description, falling back to timed comments
+    async fn fetch_tracklist(&self, track: &Track) -> Vec<TracklistEntry> {
+        let tracklist = track
+            .description
+            .as_deref()
+            .map(cue::parse_tracklist)
+            .unwrap_or_default();
+        if !tracklist.is_empty() {
+            return tracklist;
+        }
+
+        match self.client.get_comments(track.id).await {
+            Ok(comments) => cue::tracklist_from_comments(&comments),
+            Err(e) => {
+                tracing::warn!("Failed to fetch comments of {}: {}", track.permalink_url, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Fetches the comments of a track and writes them next to its audio file
+    async fn write_comments(&self, track: &Track, path: &Path) -> Result<PathBuf> {
+        let comments = self
+            .client
+            .get_comments(track.id)
+            .await?
+            .into_iter()
+            .map(|comment| CommentRecord {
+                author: comment.user.username,
+                timestamp: comment.timestamp,
+                body: comment.body,
+                created_at: comment.created_at,
+            })
+            .collect::<Vec<_>>();
+
+        let comments_path = path.with_extension("comments.json");
+        std::fs::write(&comments_path, serde_json::to_string_pretty(&comments)?)?;
+
+        Ok(comments_path)
+    }
+
+    /// Downloads the waveform of a track and writes it next to its audio file
+    ///
+    /// # Returns
+    /// Result containing the path of the waveform, `None` if the track has none
+    async fn write_waveform(&self, track: &Track, path: &Path) -> Result<Option<PathBuf>> {
+        let Some(waveform) = self.client.download_waveform(track).await? else {
+            tracing::warn!("No waveform available for {}", track.permalink_url);
+            return Ok(None);
+        };
+
+        let ext = if waveform.file_ext.is_empty() {
+            "json"
+        } else {
+            &waveform.file_ext
+        };
+        let waveform_path = path.with_extension(format!("waveform.{}", ext));
+        std::fs::write(&waveform_path, waveform.data)?;
+
+        Ok(Some(waveform_path))
+    }
+
+    /// Returns FFmpeg, or an error naming the feature that needs it in `--no-ffmpeg` mode
+    pub fn require_ffmpeg(&self, feature: &str) -> Result<&ffmpeg::FFmpeg<PathBuf>> {
+        self.ffmpeg.as_ref().ok_or_else(|| {
+            AppError::FFmpeg(format!(
+                "{} requires FFmpeg, run without --no-ffmpeg",
+                feature
+            ))
+        })
     }
 
     fn mime_type_to_ext(format: &Format) -> String {
@@ -192,22 +1507,354 @@ impl Downloader {
         .to_string()
     }
 
+    /// Links a track saved earlier in the run into this downloader's output instead of
+    /// downloading it again
+    ///
+    /// The hard link shares the file's data and tags with the first copy. Where hard
+    /// links are not supported the existing file is referenced instead.
+    ///
+    /// # Arguments
+    /// * `track` - The track
+    /// * `existing` - Where it was saved
+    ///
+    /// # Returns
+    /// Result containing where the track is found in this output
+    pub fn link_track(&self, track: &Track, existing: &Path) -> Result<PathBuf> {
+        let ext = util::audio_ext(existing).unwrap_or("mp3").to_string();
+        let path = self.prepare_file_path(track, &ext);
+        if path.exists() {
+            return Ok(path);
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        match std::fs::hard_link(existing, &path) {
+            Ok(()) => Ok(path),
+            Err(e) => {
+                tracing::debug!("Could not link {}: {}", existing.display(), e);
+                Ok(existing.to_path_buf())
+            }
+        }
+    }
+
     fn prepare_file_path(&self, track: &Track, ext: &str) -> PathBuf {
-        let username = util::sanitize(&track.user.username);
-        let artist = if util::is_empty(&username) {
-            track.user.permalink.clone()
-        } else {
-            track.user.username.clone()
+        let template = &self.options.filename_template;
+        let collection = self.collection.get().map(String::as_str);
+        let with_id = |track: &Track| {
+            let suffix = format!(" [{}]", track.id);
+            template.file_path(&self.output_dir, track, collection, &suffix, ext)
         };
 
-        let title = if util::is_empty(&track.title) {
-            track.permalink.clone()
-        } else {
-            track.title.clone()
+        if self.options.add_id {
+            return with_id(track);
+        }
+
+        let path = template.file_path(&self.output_dir, track, collection, "", ext);
+        let mut owners = self.file_owners.lock().unwrap();
+        let owner = owners
+            .get(&path)
+            .copied()
+            .or_else(|| self.library.owner_of(&path));
+
+        match owner {
+            Some(id) if id != track.id => {
+                tracing::info!(
+                    "{} is taken by track {}, adding the ID of {} to its name",
+                    path.display(),
+                    id,
+                    track.permalink_url
+                );
+                with_id(track)
+            }
+            _ => {
+                owners.insert(path.clone(), track.id);
+                path
+            }
+        }
+    }
+}
+
+impl BatchSummary {
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            ..Self::default()
+        }
+    }
+
+    /// Adds the counts of another batch, e.g. of another playlist in the same run
+    pub fn merge(&mut self, other: &BatchSummary) {
+        self.total += other.total;
+        self.processed += other.processed;
+        self.downloaded += other.downloaded;
+        self.failed += other.failed;
+        self.geo_blocked += other.geo_blocked;
+        self.previews += other.previews;
+        self.too_large += other.too_large;
+        self.unplayable += other.unplayable;
+        self.not_found += other.not_found;
+        self.bytes += other.bytes;
+    }
+
+    /// Logs the outcome of a single track and updates the counts
+    fn record(&mut self, result: Result<(Track, PathBuf, u64)>) {
+        self.processed += 1;
+
+        match result {
+            Ok((track, path, bytes)) => {
+                self.downloaded += 1;
+                self.bytes += bytes;
+                tracing::info!(
+                    status = "Downloaded",
+                    "{} to {} ({}/{})",
+                    track.permalink_url,
+                    path.display(),
+                    self.processed,
+                    self.total,
+                );
+            }
+            Err(e @ AppError::GeoBlocked(_)) => {
+                self.geo_blocked += 1;
+                tracing::warn!(status = "Skipped", "{}", e);
+            }
+            Err(e @ AppError::PreviewOnly(_)) => {
+                self.previews += 1;
+                tracing::warn!(status = "Skipped", "{}", e);
+            }
+            Err(e @ AppError::TooLarge { .. }) => {
+                self.too_large += 1;
+                tracing::warn!(status = "Skipped", "{}", e);
+            }
+            Err(e @ AppError::NoPlayableStream(_)) => {
+                self.unplayable += 1;
+                tracing::warn!(status = "Skipped", "{}", e);
+            }
+            Err(e @ AppError::NotFound(_)) => {
+                self.not_found += 1;
+                tracing::warn!(status = "Skipped", "{}", e);
+            }
+            Err(e) => {
+                self.failed += 1;
+                tracing::error!(status = "Failed", "{}", e);
+            }
+        }
+    }
+
+    fn log(&self) {
+        tracing::debug!(
+            "Batch finished: {} downloaded, {} failed, {} skipped of {} track(s)",
+            self.downloaded,
+            self.failed,
+            self.skipped(),
+            self.total
+        );
+    }
+
+    /// Tracks that were not attempted because they cannot be downloaded in full
+    pub fn skipped(&self) -> usize {
+        self.geo_blocked + self.previews + self.too_large + self.unplayable + self.not_found
+    }
+}
+
+impl fmt::Display for BatchSummary {
+    /// Renders the counts as a small table, leaving out skip reasons that did not occur
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = [
+            ("Downloaded", self.downloaded, true),
+            ("Failed", self.failed, true),
+            ("Not in region", self.geo_blocked, self.geo_blocked > 0),
+            ("Preview only", self.previews, self.previews > 0),
+            ("Too large", self.too_large, self.too_large > 0),
+            ("No stream", self.unplayable, self.unplayable > 0),
+            ("Not found", self.not_found, self.not_found > 0),
+            ("Total", self.total, true),
+        ];
+
+        for (label, count, _) in rows.iter().filter(|(.., shown)| *shown) {
+            writeln!(f, "{:<14}{:>6}", label, count)?;
+        }
+
+        // Timing is only known for whole runs
+        if let Some(elapsed) = self.elapsed {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "{:<14}{:>10}",
+                "Elapsed",
+                util::format_duration(elapsed.as_millis() as u64)
+            )?;
+
+            if self.bytes > 0 {
+                let speed = self.bytes as f64 / elapsed.as_secs_f64().max(1.0);
+                writeln!(
+                    f,
+                    "{:<14}{:>10}",
+                    "Size",
+                    HumanBytes(self.bytes).to_string()
+                )?;
+                writeln!(
+                    f,
+                    "{:<14}{:>10}",
+                    "Speed",
+                    format!("{}/s", HumanBytes(speed as u64))
+                )?;
+            }
+        }
+        if let Some((waits, waited)) = self.rate_limited.filter(|(waits, _)| *waits > 0) {
+            writeln!(
+                f,
+                "{:<14}{:>10}",
+                "Rate limited",
+                format!(
+                    "{}x, {}",
+                    waits,
+                    util::format_duration(waited.as_millis() as u64)
+                )
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Downloads each playlist into its own subdirectory of `output`, continuing past failures
+///
+/// Playlists sharing a title are told apart by their ID, see [`util::playlist_dirs`].
+/// With `{collection}` in the file name template the template names the directories
+/// instead. With `new_only`, the playlists are taken to be newest first and the
+/// download stops at the first one whose tracks were all downloaded before.
+///
+/// # Arguments
+/// * `interactive` - Pick which tracks of each playlist to download in a list
+pub async fn download_playlists(
+    options: &DownloadOptions,
+    output: &Path,
+    client: SoundcloudClient,
+    ffmpeg: Option<ffmpeg::FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+    playlists: &[Playlist],
+    interactive: bool,
+) -> Result<BatchSummary> {
+    let mut summary = BatchSummary::default();
+    let nested = !options.filename_template.has_collection();
+
+    for (playlist, dir) in playlists.iter().zip(util::playlist_dirs(output, playlists)) {
+        let downloader = Downloader::new(
+            client.clone(),
+            if nested { &dir } else { output },
+            ffmpeg.clone(),
+            library.clone(),
+            options.clone(),
+        )?;
+        if let Some(name) = dir.file_name() {
+            downloader.set_collection(&name.to_string_lossy());
+        }
+
+        let result = async {
+            let playlist = client
+                .fetch_playlist(playlist.id, playlist.secret_token.as_deref())
+                .await?;
+            if options.new_only && downloader.is_synced(&playlist).await {
+                return Ok(None);
+            }
+
+            let tracks = downloader
+                .fetch(&DownloadRequest::Playlist(Box::new(playlist)))
+                .await?;
+            let tracks = match interactive && !tracks.is_empty() {
+                true => tui::select_tracks(tracks)?,
+                false => tracks,
+            };
+            downloader.download_tracks(tracks).await.map(Some)
+        }
+        .await;
+
+        match result {
+            Ok(Some(playlist_summary)) => summary.merge(&playlist_summary),
+            Ok(None) => {
+                tracing::info!(
+                    "{} was downloaded before, stopping at it",
+                    playlist.permalink_url
+                );
+                break;
+            }
+            // Cancelling the run, a rejected token or a full disk fail every playlist
+            Err(e) if e.recovery() == Recovery::Abort => return Err(e),
+            Err(e) => {
+                tracing::error!(
+                    "Failed to download playlist {}: {}",
+                    playlist.permalink_url,
+                    e
+                );
+                summary.total += 1;
+                summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Sets the modification time of a file
+fn set_modified(path: &Path, time: DateTime<Utc>) -> std::io::Result<()> {
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(time.into())
+}
+
+/// Records where a file was downloaded from in the `user.xdg.origin.url` extended
+/// attribute, which file managers show as the file's origin
+#[cfg(unix)]
+fn record_origin(path: &Path, url: &str) {
+    if let Err(e) = xattr::set(path, "user.xdg.origin.url", url.as_bytes()) {
+        tracing::debug!("Failed to set origin of {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(not(unix))]
+fn record_origin(_path: &Path, _url: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: u64) -> Track {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "permalink": format!("track-{}", id),
+            "permalink_url": format!("https://soundcloud.com/user/track-{}", id),
+            "title": format!("Track {}", id),
+            "media": { "transcodings": [] },
+            "user": { "id": 1, "permalink": "user" },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn liked_playlists_ignore_like_positions() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = Arc::new(Library::open(Some(dir.path().join("library.json"))).unwrap());
+        let likes = DownloadOptions {
+            items: Some(ItemRanges::parse("1-2").unwrap()),
+            reverse: true,
+            ..DownloadOptions::default()
         };
 
-        let filename = format!("{} - {}.{}", artist, title, ext);
-        let safe_filename = util::sanitize(&filename);
-        self.output_dir.join(safe_filename)
+        let downloader = Downloader::new(
+            SoundcloudClient::builder(String::new()).build().unwrap(),
+            dir.path(),
+            None,
+            library,
+            likes.for_liked_playlists(),
+        )
+        .unwrap();
+        let tracks = downloader.select_items((1..=5).map(track).collect()).await;
+
+        assert_eq!(
+            tracks.iter().map(|track| track.id).collect::<Vec<_>>(),
+            [1, 2, 3, 4, 5]
+        );
     }
 }
@@ -16,15 +16,150 @@ pub enum AppError {
     #[error("Audio processing error: {0}")]
     Audio(String),
 
+    #[error("Not found on SoundCloud: {0}")]
+    NotFound(String),
+
+    #[error("SoundCloud rejected the OAuth token: {0}")]
+    Unauthorized(String),
+
+    #[error("{endpoint} returned {status}{body}")]
+    Api {
+        endpoint: String,
+        status: reqwest::StatusCode,
+        /// Start of the response body
+        body: String,
+    },
+
+    #[error("Server error {status} from {host}")]
+    ServerError {
+        status: reqwest::StatusCode,
+        host: String,
+    },
+
+    #[error("Track is not available in your region: {0}")]
+    GeoBlocked(String),
+
+    #[error("Only a preview is available for track: {0}")]
+    PreviewOnly(String),
+
+    #[error("No playable stream for track: {0}")]
+    NoPlayableStream(String),
+
+    #[error("File size of {size} bytes exceeds the maximum of {max} bytes")]
+    TooLarge { size: u64, max: u64 },
+
+    #[error("Queue error: {0}")]
+    Queue(#[from] rusqlite::Error),
+
+    #[error("Clipboard error: {0}")]
+    Clipboard(#[from] arboard::Error),
+
+    #[error("Hook error: {0}")]
+    Hook(String),
+
+    #[error("Update error: {0}")]
+    Update(String),
+
+    #[error("Not enough disk space: {0}")]
+    DiskSpace(String),
+
+    #[error("Verification failed: {0}")]
+    Verification(String),
+
+    #[error("Interrupted by the user")]
+    Cancelled,
+
+    #[error("Authentication error: {0}")]
+    Auth(String),
+
+    #[error("{failed} of {total} track(s) failed to download")]
+    PartialFailure { failed: usize, total: usize },
+
     #[error("Rate limited by SoundCloud API")]
     RateLimited,
 
+    #[error("{0} is locked by another soundcloud-dl, pass --wait-for-lock to wait for it")]
+    Locked(String),
+
     #[error("Configuration error: {0}")]
     Configuration(String),
 
     #[error("Parse error: {0}")]
     Parse(#[from] serde_json::Error),
 
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
+
     #[error("ID3 tag error: {0}")]
     Id3(#[from] id3::Error),
+
+    #[error("MP4 tag error: {0}")]
+    Mp4Tag(#[from] mp4ameta::Error),
+}
+
+/// What a batch does with a track that failed with an error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    /// The error is transient, the track may succeed when tried again
+    Retry,
+    /// The track cannot be downloaded, it is counted as skipped
+    Skip,
+    /// The track failed, the rest of the batch goes on
+    Fail,
+    /// Every other track would fail the same way, so the batch stops
+    Abort,
+}
+
+impl AppError {
+    /// Classifies the error for batches, which retry, skip or give up on a track, or
+    /// stop altogether
+    pub fn recovery(&self) -> Recovery {
+        match self {
+            Self::RateLimited | Self::ServerError { .. } => Recovery::Retry,
+            Self::Network(e) => match e.status() {
+                Some(status) if status.is_server_error() => Recovery::Retry,
+                Some(status) if is_auth_status(status) => Recovery::Abort,
+                Some(_) => Recovery::Fail,
+                // Timeouts, refused connections and interrupted transfers
+                None => Recovery::Retry,
+            },
+            Self::NotFound(_)
+            | Self::GeoBlocked(_)
+            | Self::PreviewOnly(_)
+            | Self::NoPlayableStream(_)
+            | Self::TooLarge { .. } => Recovery::Skip,
+            Self::Unauthorized(_)
+            | Self::Auth(_)
+            | Self::DiskSpace(_)
+            | Self::Locked(_)
+            | Self::Cancelled => Recovery::Abort,
+            _ => Recovery::Fail,
+        }
+    }
+
+    /// Process exit code for the error, so scripts can tell failures apart
+    ///
+    /// * 1 - any other error
+    /// * 2 - missing or rejected OAuth token
+    /// * 3 - network error
+    /// * 4 - rate limited by SoundCloud
+    /// * 5 - FFmpeg error
+    /// * 6 - some tracks of a batch failed
+    /// * 130 - interrupted with Ctrl+C
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Auth(_) | Self::Unauthorized(_) => 2,
+            Self::Network(e) if e.status().is_some_and(is_auth_status) => 2,
+            Self::Network(_) | Self::ServerError { .. } => 3,
+            Self::RateLimited => 4,
+            Self::FFmpeg(_) => 5,
+            Self::PartialFailure { .. } => 6,
+            Self::Cancelled => 130,
+            _ => 1,
+        }
+    }
+}
+
+fn is_auth_status(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN
 }
@@ -1,47 +1,226 @@
+use bytes::Bytes;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fs::{self, File};
+use std::io::{Cursor, Read};
 use std::path::{Path, PathBuf};
+use tar::Archive;
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
 
-use crate::error::Result;
+use crate::error::{AppError, Result};
+use crate::soundcloud::SoundcloudClient;
+use crate::util;
+
+/// Windows and Linux builds, with one `checksums.sha256` listing every archive
+const BTBN_RELEASE_URL: &str = "https://github.com/BtbN/FFmpeg-Builds/releases/download/latest";
+/// macOS builds, with a `.sha256` file next to each archive
+const MACOS_RELEASE_URL: &str = "https://ffmpeg.martin-riedl.de/redirect/latest/macos";
+
+const PROGRESS_TEMPLATE: &str = "{bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})";
+
+/// A build archive and the file its SHA-256 checksum is published in
+struct Artifact {
+    url: String,
+    checksums_url: String,
+}
+
+impl Artifact {
+    fn btbn(platform: &str, ext: &str) -> Self {
+        Self {
+            url: format!(
+                "{}/ffmpeg-master-latest-{}-lgpl.{}",
+                BTBN_RELEASE_URL, platform, ext
+            ),
+            checksums_url: format!("{}/checksums.sha256", BTBN_RELEASE_URL),
+        }
+    }
+
+    fn macos(arch: &str, binary: &str) -> Self {
+        let url = format!("{}/{}/release/{}.zip", MACOS_RELEASE_URL, arch, binary);
+        Self {
+            checksums_url: format!("{}.sha256", url),
+            url,
+        }
+    }
+
+    /// Whether the archive is a zip rather than a `.tar.xz`
+    fn is_zip(&self) -> bool {
+        self.url.ends_with(".zip")
+    }
+
+    /// Name of the archive as listed in the checksums file
+    fn file_name(&self) -> &str {
+        self.url.rsplit('/').next().unwrap_or(&self.url)
+    }
+}
+
+/// Picks the archives to install for the platform and architecture this binary was built for
+fn artifacts() -> Result<Vec<Artifact>> {
+    let (os, arch) = (std::env::consts::OS, std::env::consts::ARCH);
+
+    match (os, arch) {
+        ("windows", "x86_64") => Ok(vec![Artifact::btbn("win64", "zip")]),
+        ("windows", "aarch64") => Ok(vec![Artifact::btbn("winarm64", "zip")]),
+        ("linux", "x86_64") => Ok(vec![Artifact::btbn("linux64", "tar.xz")]),
+        ("linux", "aarch64") => Ok(vec![Artifact::btbn("linuxarm64", "tar.xz")]),
+        ("macos", "x86_64" | "aarch64") => {
+            let arch = if arch == "x86_64" { "amd64" } else { "arm64" };
+            Ok(vec![
+                Artifact::macos(arch, "ffmpeg"),
+                Artifact::macos(arch, "ffprobe"),
+            ])
+        }
+        _ => Err(AppError::FFmpeg(format!(
+            "No FFmpeg build is available for {}-{}, install FFmpeg manually and pass --ffmpeg-path",
+            os, arch
+        ))),
+    }
+}
+
+/// Downloads an archive with a progress bar and checks it against its published SHA-256
+/// checksum
+///
+/// The archive is kept as a `.part` file in `dir` until it is verified, so an interrupted
+/// download is resumed by the next attempt.
+///
+/// # Returns
+/// Result containing the archive and its checksum
+async fn fetch_verified(
+    client: &SoundcloudClient,
+    artifact: &Artifact,
+    dir: &Path,
+) -> Result<(Bytes, String)> {
+    let expected = fetch_checksum(artifact).await?;
+
+    tracing::info!("Downloading FFmpeg from {}", artifact.url);
+    let part = dir.join(format!("{}.part", artifact.file_name()));
+    let progress = ProgressBar::no_length().with_style(
+        ProgressStyle::with_template(PROGRESS_TEMPLATE)
+            .unwrap_or_else(|_| ProgressStyle::default_bar()),
+    );
+    let result = client.download_file(&artifact.url, &part, &progress).await;
+    progress.finish_and_clear();
+    result?;
+
+    let data = Bytes::from(fs::read(&part)?);
+    let actual = util::sha256_hex(&data);
+
+    // A corrupt partial file must not be resumed again
+    fs::remove_file(&part)?;
+
+    if !actual.eq_ignore_ascii_case(&expected) {
+        return Err(AppError::FFmpeg(format!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            artifact.file_name(),
+            expected,
+            actual
+        )));
+    }
+
+    Ok((data, actual))
+}
+
+/// Fetches the published SHA-256 checksum of an archive
+async fn fetch_checksum(artifact: &Artifact) -> Result<String> {
+    let checksums = reqwest::get(&artifact.checksums_url)
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    util::find_checksum(&checksums, artifact.file_name()).ok_or_else(|| {
+        AppError::FFmpeg(format!(
+            "No checksum published for {}",
+            artifact.file_name()
+        ))
+    })
+}
 
 #[cfg(target_os = "windows")]
-const FFMPEG_URL: &str = "https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/ffmpeg-master-latest-win64-lgpl.zip";
-#[cfg(target_os = "linux")]
-const FFMPEG_URL: &str = "https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/ffmpeg-master-latest-linux64-lgpl.tar.xz";
-#[cfg(target_os = "macos")]
-const FFMPEG_URL: &str = "https://evermeet.cx/ffmpeg/getrelease/zip";
+const BINARIES: [&str; 2] = ["ffmpeg.exe", "ffprobe.exe"];
+#[cfg(not(target_os = "windows"))]
+const BINARIES: [&str; 2] = ["ffmpeg", "ffprobe"];
+
+/// Subdirectory of the install directory holding one directory per build
+const VERSIONS_DIR: &str = "versions";
+/// File in the versions directory naming the installed build
+const CURRENT_FILE: &str = "current";
+/// Number of checksum characters used to name a build's directory
+const VERSION_LENGTH: usize = 12;
+
+/// Returns the file name of an archive entry if it is one of the binaries to install
+fn binary_name(entry: &str) -> Option<&'static str> {
+    let name = entry.rsplit(['/', '\\']).next()?;
+    BINARIES.into_iter().find(|binary| *binary == name)
+}
+
+/// Extracts the FFmpeg binaries from a build archive into `dir`, ignoring its directory layout
+fn extract(artifact: &Artifact, data: Bytes, dir: &Path) -> Result<()> {
+    if artifact.is_zip() {
+        let mut archive =
+            ZipArchive::new(Cursor::new(data)).map_err(|e| AppError::FFmpeg(e.to_string()))?;
+
+        for i in 0..archive.len() {
+            let file = archive
+                .by_index(i)
+                .map_err(|e| AppError::FFmpeg(e.to_string()))?;
+            if let Some(name) = binary_name(file.name()).filter(|_| file.is_file()) {
+                write_binary(file, &dir.join(name))?;
+            }
+        }
+    } else {
+        let mut archive = Archive::new(XzDecoder::new(Cursor::new(data)));
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let name = binary_name(&entry.path()?.to_string_lossy());
+            if let Some(name) = name.filter(|_| entry.header().entry_type().is_file()) {
+                write_binary(entry, &dir.join(name))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_binary(mut reader: impl Read, path: &Path) -> Result<()> {
+    let mut out = File::create(path)?;
+    std::io::copy(&mut reader, &mut out)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
 
 #[cfg(target_os = "windows")]
 mod windows {
-    use bytes::Bytes;
     use std::{
-        fs::File,
+        fs,
         path::{Path, PathBuf},
     };
-    use zip::ZipArchive;
 
-    use crate::error::{AppError, Result};
+    use crate::config;
+    use crate::error::Result;
 
     pub(crate) fn get_default_ffmpeg_path() -> PathBuf {
+        if let Some(dir) = config::portable_dir() {
+            return dir.join("ffmpeg");
+        }
+
         directories::BaseDirs::new()
             .map(|dirs| dirs.data_local_dir().join("ffmpeg"))
             .unwrap_or_else(|| PathBuf::from(r"C:\Program Files\ffmpeg"))
     }
 
-    pub(crate) async fn platform_specific_install(target_dir: &Path, data: Bytes) -> Result<()> {
-        let cursor = std::io::Cursor::new(data);
-        let mut archive = ZipArchive::new(cursor).map_err(|e| AppError::FFmpeg(e.to_string()))?;
-
-        let target_path = target_dir.join("ffmpeg.exe");
-
-        for i in 0..archive.len() {
-            let mut file = archive
-                .by_index(i)
-                .map_err(|e| AppError::FFmpeg(e.to_string()))?;
-            if file.name().contains("ffmpeg.exe") {
-                let mut out = File::create(&target_path)?;
-                std::io::copy(&mut file, &mut out)?;
-                break;
-            }
-        }
+    /// Copies `binary` over `link`, symlinks need elevated privileges on Windows
+    pub(crate) fn swap_link(dir: &Path, binary: &Path, link: &Path) -> Result<()> {
+        let tmp = link.with_extension("tmp");
+        fs::copy(dir.join(binary), &tmp)?;
+        fs::rename(&tmp, link)?;
 
         Ok(())
     }
@@ -52,38 +231,30 @@ pub(crate) use windows::*;
 
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 mod unix {
-    use bytes::Bytes;
-    use flate2::read::GzDecoder;
     use std::{
-        fs::File,
+        fs,
         path::{Path, PathBuf},
     };
-    use tar::Archive;
 
+    use crate::config;
     use crate::error::Result;
 
     pub(crate) fn get_default_ffmpeg_path() -> PathBuf {
-        PathBuf::from("/usr/local/bin")
-    }
-
-    pub(crate) async fn platform_specific_install(target_dir: &Path, data: Bytes) -> Result<()> {
-        let gz = GzDecoder::new(std::io::Cursor::new(data));
-        let mut archive = Archive::new(gz);
-        let target_path = target_dir.join("ffmpeg");
-
-        for entry in archive.entries()? {
-            let mut entry = entry?;
-            if entry.path()?.to_string_lossy().contains("ffmpeg") {
-                let mut out = File::create(&target_path)?;
-                std::io::copy(&mut entry, &mut out)?;
-                break;
-            }
+        if let Some(dir) = config::portable_dir() {
+            return dir.join("ffmpeg");
         }
 
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = std::fs::metadata(&target_path)?.permissions();
-        perms.set_mode(0o755);
-        std::fs::set_permissions(&target_path, perms)?;
+        directories::BaseDirs::new()
+            .map(|dirs| dirs.data_local_dir().join("ffmpeg"))
+            .unwrap_or_else(|| PathBuf::from("/usr/local/bin"))
+    }
+
+    /// Points `link` at `binary` (relative to `dir`), replacing any previous link in one rename
+    pub(crate) fn swap_link(_dir: &Path, binary: &Path, link: &Path) -> Result<()> {
+        let tmp = link.with_extension("tmp");
+        let _ = fs::remove_file(&tmp);
+        std::os::unix::fs::symlink(binary, &tmp)?;
+        fs::rename(&tmp, link)?;
 
         Ok(())
     }
@@ -92,21 +263,120 @@ mod unix {
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 pub(crate) use unix::*;
 
-pub async fn download_ffmpeg<P: AsRef<Path>>(path: Option<P>) -> Result<PathBuf> {
-    #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
-    return Err(AppError::FFmpeg("Unsupported platform".to_string()));
+/// Downloads the latest FFmpeg build and installs it
+///
+/// Each build is extracted into `versions/<checksum>` and `ffmpeg`/`ffprobe` in the install
+/// directory are then switched over to it, so a failed install leaves the previous one working.
+///
+/// # Arguments
+/// * `client` - Client whose retry settings are used for the download
+/// * `path` - Install directory, the platform default if `None`
+///
+/// # Returns
+/// Result containing the install directory
+pub async fn download_ffmpeg<P: AsRef<Path>>(
+    client: &SoundcloudClient,
+    path: Option<P>,
+) -> Result<PathBuf> {
+    let target_dir = path
+        .map(|p| p.as_ref().to_path_buf())
+        .unwrap_or_else(get_default_ffmpeg_path);
+    let versions_dir = target_dir.join(VERSIONS_DIR);
 
-    let (url, target_dir) = (
-        FFMPEG_URL,
-        path.map(|p| p.as_ref().to_path_buf())
-            .unwrap_or_else(get_default_ffmpeg_path),
-    );
+    fs::create_dir_all(&versions_dir)?;
 
-    let response = reqwest::get(url).await?;
-    let data = response.bytes().await?;
+    let mut downloads = Vec::new();
+    for artifact in artifacts()? {
+        let (data, checksum) = fetch_verified(client, &artifact, &versions_dir).await?;
+        downloads.push((artifact, data, checksum));
+    }
 
-    std::fs::create_dir_all(&target_dir)?;
-    platform_specific_install(&target_dir, data).await?;
+    let version = downloads
+        .first()
+        .map(|(_, _, checksum)| checksum[..VERSION_LENGTH].to_string())
+        .unwrap_or_default();
+    let version_dir = versions_dir.join(&version);
+    let staging_dir = versions_dir.join(format!(".{}.partial", version));
+
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)?;
+    }
+    fs::create_dir_all(&staging_dir)?;
+
+    for (artifact, data, _) in downloads {
+        extract(&artifact, data, &staging_dir)?;
+    }
+
+    if let Some(missing) = BINARIES
+        .iter()
+        .find(|binary| !staging_dir.join(binary).is_file())
+    {
+        return Err(AppError::FFmpeg(format!(
+            "The FFmpeg archive does not contain {}",
+            missing
+        )));
+    }
+
+    if version_dir.exists() {
+        fs::remove_dir_all(&version_dir)?;
+    }
+    fs::rename(&staging_dir, &version_dir)?;
+
+    for binary in BINARIES {
+        let relative = Path::new(VERSIONS_DIR).join(&version).join(binary);
+        swap_link(&target_dir, &relative, &target_dir.join(binary))?;
+    }
+
+    fs::write(versions_dir.join(CURRENT_FILE), &version)?;
+
+    tracing::info!(
+        "Installed FFmpeg build {} into {}",
+        version,
+        target_dir.display()
+    );
+    remove_old_versions(&versions_dir, &version);
 
     Ok(target_dir)
 }
+
+/// Returns the build installed by [`download_ffmpeg`] in `path`, `None` if FFmpeg was
+/// installed some other way
+pub fn installed_build<P: AsRef<Path>>(path: Option<P>) -> Option<String> {
+    let target_dir = path
+        .map(|p| p.as_ref().to_path_buf())
+        .unwrap_or_else(get_default_ffmpeg_path);
+
+    fs::read_to_string(target_dir.join(VERSIONS_DIR).join(CURRENT_FILE))
+        .ok()
+        .map(|version| version.trim().to_string())
+}
+
+/// Returns the name [`download_ffmpeg`] would give the latest published build
+pub async fn latest_build() -> Result<String> {
+    let artifact = artifacts()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::FFmpeg("No FFmpeg build available".to_string()))?;
+    let checksum = fetch_checksum(&artifact).await?;
+
+    Ok(checksum[..VERSION_LENGTH.min(checksum.len())].to_lowercase())
+}
+
+/// Deletes builds other than `current`, logging failures since the install already succeeded
+fn remove_old_versions(versions_dir: &Path, current: &str) {
+    let Ok(entries) = fs::read_dir(versions_dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        if entry.file_name() != current && entry.path().is_dir() {
+            if let Err(e) = fs::remove_dir_all(entry.path()) {
+                tracing::warn!(
+                    "Failed to remove old FFmpeg build {}: {}",
+                    entry.path().display(),
+                    e
+                );
+            }
+        }
+    }
+}
@@ -1,30 +1,143 @@
 use bytes::Bytes;
-use std::fs::File;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use tempfile::NamedTempFile;
+use std::process::{ExitStatus, Output, Stdio};
+use std::time::Duration;
+use tempfile::TempPath;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
 
 use super::download::get_default_ffmpeg_path;
 use crate::error::{AppError, Result};
 use crate::soundcloud::DownloadedFile;
+use crate::tags::Tags;
+use crate::temp;
+use crate::util;
 
 #[cfg(target_os = "windows")]
 const BINARY_NAME: &str = "ffmpeg.exe";
 #[cfg(not(target_os = "windows"))]
 const BINARY_NAME: &str = "ffmpeg";
 
+#[cfg(target_os = "windows")]
+const PROBE_BINARY_NAME: &str = "ffprobe.exe";
+#[cfg(not(target_os = "windows"))]
+const PROBE_BINARY_NAME: &str = "ffprobe";
+
+/// Default for the maximum time a single FFmpeg run may take
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Number of lines of FFmpeg output included in error messages
+const STDERR_TAIL_LINES: usize = 5;
+
+/// Audio quieter than this counts as silence when trimming the edges
+const TRIM_SILENCE_NOISE: &str = "-50dB";
+
+/// Parts of the audio to cut away after downloading
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Trim {
+    /// Position the trimmed audio starts at
+    pub start: Option<Duration>,
+    /// Position the trimmed audio ends at
+    pub end: Option<Duration>,
+    /// Remove silence at the very start and end, which requires re-encoding
+    pub silence_edges: bool,
+}
+
+impl Trim {
+    pub fn is_empty(&self) -> bool {
+        self.start.is_none() && self.end.is_none() && !self.silence_edges
+    }
+
+    /// Builds the audio filter chain used when re-encoding
+    fn filter(&self) -> String {
+        let mut filters = Vec::new();
+
+        if self.start.is_some() || self.end.is_some() {
+            let mut atrim = Vec::new();
+            if let Some(start) = self.start {
+                atrim.push(format!("start={:.3}", start.as_secs_f64()));
+            }
+            if let Some(end) = self.end {
+                atrim.push(format!("end={:.3}", end.as_secs_f64()));
+            }
+            filters.push(format!("atrim={}", atrim.join(":")));
+            filters.push("asetpts=PTS-STARTPTS".to_string());
+        }
+
+        if self.silence_edges {
+            // silenceremove only trims the start reliably, so run it on the reversed audio too
+            let leading = format!(
+                "silenceremove=start_periods=1:start_threshold={}",
+                TRIM_SILENCE_NOISE
+            );
+            filters.push(leading.clone());
+            filters.push("areverse".to_string());
+            filters.push(leading);
+            filters.push("areverse".to_string());
+        }
+
+        filters.join(",")
+    }
+}
+
+/// Properties of a media file as reported by ffprobe
+#[derive(Clone, Debug)]
+pub struct Probe {
+    pub duration: Option<Duration>,
+    /// Average bitrate in bits per second
+    pub bit_rate: Option<u64>,
+    pub audio_streams: usize,
+    /// Whether the file has an embedded cover image
+    pub has_artwork: bool,
+    /// Container level tags, keyed by lowercase name
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    #[serde(default)]
+    disposition: HashMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    /// Seconds as a decimal string
+    duration: Option<String>,
+    /// Bits per second as a decimal string
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
 /// FFmpeg wrapper for audio processing operations
-pub struct FFmpeg<P>(P)
+#[derive(Clone)]
+pub struct FFmpeg<P>
 where
-    P: AsRef<Path>;
+    P: AsRef<Path>,
+{
+    path: P,
+    /// Maximum time a single FFmpeg run may take before it is killed
+    timeout: Duration,
+}
 
 impl FFmpeg<PathBuf> {
     /// Creates a new FFmpeg instance using the default installation path
     /// First checks PATH, then the default install location
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self> {
-        which::which("ffmpeg").map(Self).or_else(|_| {
-            let default = Self(get_default_ffmpeg_path().join(BINARY_NAME));
+        which::which("ffmpeg").map(Self::at).or_else(|_| {
+            let default = Self::at(get_default_ffmpeg_path().join(BINARY_NAME));
             if default.is_installed() {
                 Ok(default)
             } else {
@@ -39,7 +152,7 @@ impl FFmpeg<PathBuf> {
             path.push(BINARY_NAME);
         }
 
-        let ffmpeg = Self(path);
+        let ffmpeg = Self::at(path);
         if !ffmpeg.is_installed() {
             return Err(AppError::FFmpeg(format!(
                 "FFmpeg not found at path: {}",
@@ -51,92 +164,371 @@ impl FFmpeg<PathBuf> {
 }
 
 impl<P: AsRef<Path>> FFmpeg<P> {
+    fn at(path: P) -> Self {
+        Self {
+            path,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets the maximum time a single FFmpeg run may take
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     /// Returns reference to the FFmpeg binary path
     pub fn path(&self) -> &P {
-        &self.0
+        &self.path
     }
 
     /// Checks if FFmpeg is installed and callable
     pub fn is_installed(&self) -> bool {
-        Command::new(self.path().as_ref())
+        std::process::Command::new(self.path().as_ref())
             .arg("-version")
             .output()
             .is_ok()
     }
 
-    /// Reformats M4A audio file with optional thumbnail
-    pub fn reformat_m4a(
+    /// Returns the first line of `ffmpeg -version`, e.g. `ffmpeg version 7.1 Copyright ...`
+    pub async fn version(&self) -> Result<String> {
+        let mut cmd = self.command();
+        cmd.arg("-version");
+
+        let output = self.output(cmd, None).await?;
+        if !output.status.success() {
+            return Err(failure(
+                "FFmpeg failed",
+                output.status,
+                &String::from_utf8_lossy(&output.stderr),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string())
+    }
+
+    /// Reformats M4A audio file with optional thumbnail and chapters
+    ///
+    /// `chapters` is the contents of an FFmpeg metadata file defining the chapters
+    pub async fn reformat_m4a(
         &self,
         m4a: Bytes,
         thumbnail: Option<DownloadedFile>,
+        tags: &Tags,
+        chapters: Option<&str>,
         output_path: P,
     ) -> Result<()> {
-        let tmp_audio = NamedTempFile::with_suffix(".m4a")?;
-        File::create(&tmp_audio)?.write_all(&m4a)?;
+        // The audio is piped to stdin instead of going through a temporary file
+        let mut cmd = self.command();
+        cmd.args(["-y", "-i", "pipe:0"]).args(["-threads", "0"]); // Use all available CPU threads
 
-        let mut cmd = Command::new(self.path().as_ref());
-        cmd.args(&["-y", "-i", tmp_audio.path().to_str().unwrap()])
-            .args(&["-threads", "0"]); // Use all available CPU threads
+        let _tmp_inputs = self.add_inputs(&mut cmd, thumbnail, chapters)?;
 
-        if let Some(thumb) = thumbnail {
-            self.add_thumbnail_args(&mut cmd, &thumb)?;
-        } else {
-            cmd.args(&["-c", "copy"]);
-        }
+        Self::add_metadata_args(&mut cmd, tags);
 
-        self.run_command(cmd, output_path)
+        self.run_command_with_input(cmd, output_path, Some(m4a))
+            .await
     }
 
-    /// Processes M3U8 playlist data with optional thumbnail
-    pub fn process_m3u8(
+    /// Processes M3U8 playlist data with optional thumbnail and chapters
+    ///
+    /// `chapters` is the contents of an FFmpeg metadata file defining the chapters
+    pub async fn process_m3u8(
         &self,
         m3u8: Bytes,
         thumbnail: Option<DownloadedFile>,
+        tags: &Tags,
+        chapters: Option<&str>,
         output_path: P,
     ) -> Result<()> {
-        let tmp_playlist = NamedTempFile::with_suffix(".m3u8")?;
-        File::create(&tmp_playlist)?.write_all(&m3u8)?;
+        let tmp_playlist = temp_file(".m3u8", &m3u8)?;
 
-        let mut cmd = Command::new(self.path().as_ref());
+        let mut cmd = self.command();
         cmd.arg("-y")
-            .args(&["-protocol_whitelist", "file,http,https,tcp,tls"])
-            .args(&["-threads", "0"])
-            .args(&["-i", tmp_playlist.path().to_str().unwrap()]);
+            .args(["-protocol_whitelist", "file,http,https,tcp,tls"])
+            .args(["-threads", "0"])
+            .args(["-i", tmp_playlist.to_str().unwrap()]);
+
+        let _tmp_inputs = self.add_inputs(&mut cmd, thumbnail, chapters)?;
+
+        Self::add_metadata_args(&mut cmd, tags);
+
+        self.run_command(cmd, output_path).await
+    }
+
+    /// Returns the ffprobe binary installed next to FFmpeg, or the one on PATH
+    pub fn probe_path(&self) -> Result<PathBuf> {
+        let sibling = self.path().as_ref().with_file_name(PROBE_BINARY_NAME);
+        if sibling.is_file() {
+            return Ok(sibling);
+        }
+
+        which::which("ffprobe").map_err(|_| AppError::FFmpeg("ffprobe not found".to_string()))
+    }
+
+    /// Reads the streams, duration, bitrate and tags of a media file with ffprobe
+    ///
+    /// # Arguments
+    /// * `input` - File to probe
+    ///
+    /// # Returns
+    /// Result containing the [`Probe`] or an error if the file cannot be read
+    pub async fn probe(&self, input: &Path) -> Result<Probe> {
+        let mut cmd = Command::new(self.probe_path()?);
+        cmd.kill_on_drop(true)
+            .args([
+                "-v",
+                "error",
+                "-print_format",
+                "json",
+                "-show_format",
+                "-show_streams",
+            ])
+            .arg(input);
+
+        let output = self.output(cmd, None).await?;
+        if !output.status.success() {
+            return Err(failure(
+                "ffprobe failed",
+                output.status,
+                &String::from_utf8_lossy(&output.stderr),
+            ));
+        }
+
+        let probe: ProbeOutput = serde_json::from_slice(&output.stdout)?;
+        let (duration, bit_rate, tags) = probe
+            .format
+            .map(|format| (format.duration, format.bit_rate, format.tags))
+            .unwrap_or_default();
+
+        Ok(Probe {
+            duration: duration
+                .and_then(|secs| secs.parse::<f64>().ok())
+                .map(Duration::from_secs_f64),
+            bit_rate: bit_rate.and_then(|rate| rate.parse().ok()),
+            audio_streams: probe
+                .streams
+                .iter()
+                .filter(|stream| stream.codec_type.as_deref() == Some("audio"))
+                .count(),
+            has_artwork: probe
+                .streams
+                .iter()
+                .any(|stream| stream.disposition.get("attached_pic") == Some(&1)),
+            tags: tags
+                .into_iter()
+                .map(|(key, value)| (key.to_lowercase(), value))
+                .collect(),
+        })
+    }
+
+    /// Finds the silent gaps in an audio file with the `silencedetect` filter
+    ///
+    /// # Arguments
+    /// * `input` - Audio file to analyse
+    /// * `noise` - Level below which audio counts as silence, e.g. `-50dB`
+    /// * `min_duration` - Minimum length of a gap
+    ///
+    /// # Returns
+    /// Result containing the middle of each gap, in order
+    pub async fn detect_silence(
+        &self,
+        input: &Path,
+        noise: &str,
+        min_duration: Duration,
+    ) -> Result<Vec<Duration>> {
+        let mut cmd = self.command();
+        cmd.args(["-hide_banner", "-nostats", "-i"])
+            .arg(input)
+            .arg("-af")
+            .arg(format!(
+                "silencedetect=noise={}:d={}",
+                noise,
+                min_duration.as_secs_f64()
+            ))
+            .args(["-f", "null", "-"]);
+        let output = self.output(cmd, None).await?;
+
+        if !output.status.success() {
+            return Err(failure(
+                "Silence detection failed",
+                output.status,
+                &String::from_utf8_lossy(&output.stderr),
+            ));
+        }
+
+        // The filter logs `silence_start: 12.3` and `silence_end: 14.5 | silence_duration: 2.2`
+        let value = |line: &str, key: &str| {
+            line.split_once(key)
+                .and_then(|(_, rest)| rest.split_whitespace().next())
+                .and_then(|secs| secs.parse::<f64>().ok())
+        };
+
+        let mut gaps = Vec::new();
+        let mut start = None;
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            if let Some(secs) = value(line, "silence_start:") {
+                start = Some(secs.max(0.0));
+            } else if let (Some(begin), Some(end)) = (start.take(), value(line, "silence_end:")) {
+                gaps.push(Duration::from_secs_f64((begin + end) / 2.0));
+            }
+        }
+
+        Ok(gaps)
+    }
+
+    /// Copies a section of an audio file into a new file with its own tags
+    ///
+    /// # Arguments
+    /// * `input` - Audio file to cut from
+    /// * `start` - Start of the section
+    /// * `end` - End of the section, the end of the file if `None`
+    /// * `tags` - Text tags to write
+    /// * `output_path` - Path of the new file
+    pub async fn extract_segment(
+        &self,
+        input: &Path,
+        start: Duration,
+        end: Option<Duration>,
+        tags: &Tags,
+        output_path: P,
+    ) -> Result<()> {
+        let mut cmd = self.command();
+        cmd.args(["-y", "-i"])
+            .arg(input)
+            .arg("-ss")
+            .arg(format!("{:.3}", start.as_secs_f64()));
+
+        if let Some(end) = end {
+            cmd.arg("-to").arg(format!("{:.3}", end.as_secs_f64()));
+        }
+
+        cmd.args(["-map", "0:a", "-c", "copy", "-map_metadata", "-1"]);
+
+        Self::add_metadata_args(&mut cmd, tags);
+
+        self.run_command(cmd, output_path).await
+    }
+
+    /// Trims an audio file in place, keeping its tags and artwork
+    ///
+    /// Plain start/end trims copy the audio stream, removing silent edges forces an encode.
+    ///
+    /// # Arguments
+    /// * `path` - Audio file to trim
+    /// * `trim` - Parts of the audio to cut away
+    pub async fn trim(&self, path: &Path, trim: &Trim) -> Result<()> {
+        let ext = util::audio_ext(path).unwrap_or_default().to_string();
+        let tmp_path = path.with_extension(format!("trim.{}", ext));
+
+        let mut cmd = self.command();
+        cmd.args(["-y", "-i"])
+            .arg(path)
+            .args(["-map", "0", "-map_metadata", "0"]);
+
+        if trim.silence_edges {
+            let encoder: &[&str] = match ext.as_str() {
+                "mp3" => &["libmp3lame", "-q:a", "2"],
+                "ogg" | "opus" => &["libopus", "-b:a", "160k"],
+                _ => &["aac", "-b:a", "256k"],
+            };
+
+            cmd.arg("-af")
+                .arg(trim.filter())
+                .args(["-c:v", "copy", "-c:a"])
+                .args(encoder);
+        } else {
+            if let Some(start) = trim.start {
+                cmd.arg("-ss").arg(format!("{:.3}", start.as_secs_f64()));
+            }
+            if let Some(end) = trim.end {
+                cmd.arg("-to").arg(format!("{:.3}", end.as_secs_f64()));
+            }
+            cmd.args(["-c", "copy"]);
+        }
+
+        if let Err(e) = self.run_command(cmd, &tmp_path).await {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Adds the thumbnail and chapter inputs following the audio input, along with the
+    /// stream mapping and codec options
+    ///
+    /// Returns guards for the temporary input files, which delete them when dropped and
+    /// so must be held until the command has exited
+    fn add_inputs(
+        &self,
+        cmd: &mut Command,
+        thumbnail: Option<DownloadedFile>,
+        chapters: Option<&str>,
+    ) -> Result<Vec<TempPath>> {
+        let mut tmp_inputs = Vec::new();
+
+        // All inputs have to come before the output options
+        if let Some(chapters) = chapters {
+            let tmp_chapters = temp_file(".txt", chapters.as_bytes())?;
+            cmd.args(["-i", tmp_chapters.to_str().unwrap()]);
+            tmp_inputs.push(tmp_chapters);
+        }
+        let has_chapters = !tmp_inputs.is_empty();
 
         if let Some(thumb) = thumbnail {
-            self.add_thumbnail_args(&mut cmd, &thumb)?;
+            let input = if has_chapters { 2 } else { 1 };
+            tmp_inputs.push(self.add_thumbnail_args(cmd, &thumb, input)?);
         } else {
-            cmd.args(&["-c", "copy"]);
+            cmd.args(["-c", "copy"]);
+        }
+
+        if has_chapters {
+            cmd.args(["-map_metadata", "1", "-map_chapters", "1"]);
         }
 
-        self.run_command(cmd, output_path)
+        Ok(tmp_inputs)
     }
 
-    /// Adds thumbnail metadata to FFmpeg command
-    fn add_thumbnail_args(&self, cmd: &mut Command, thumb: &DownloadedFile) -> Result<()> {
-        let tmp_thumb = NamedTempFile::new()?
-            .into_temp_path()
-            .with_extension(&thumb.file_ext);
+    /// Adds text tags to FFmpeg command
+    fn add_metadata_args(cmd: &mut Command, tags: &Tags) {
+        for (key, value) in tags.ffmpeg_metadata() {
+            cmd.arg("-metadata").arg(format!("{}={}", key, value));
+        }
+    }
 
-        File::create(&tmp_thumb)?.write_all(&thumb.data)?;
+    /// Adds thumbnail metadata to FFmpeg command, the thumbnail being input number `input`
+    ///
+    /// Returns the guard of the temporary thumbnail file
+    fn add_thumbnail_args(
+        &self,
+        cmd: &mut Command,
+        thumb: &DownloadedFile,
+        input: usize,
+    ) -> Result<TempPath> {
+        let tmp_thumb = temp_file(&format!(".{}", thumb.file_ext), &thumb.data)?;
 
         // Add thumbnail input
-        cmd.args(&["-i", tmp_thumb.to_str().unwrap()]);
+        cmd.args(["-i", tmp_thumb.to_str().unwrap()]);
 
         // Specify which streams to include
-        cmd.args(&[
-            "-map", "0:a", // Audio from first input
-            "-map", "1:v", // Video from second input
-        ]);
+        cmd.args(["-map", "0:a"]); // Audio from first input
+        cmd.arg("-map").arg(format!("{}:v", input)); // Video from the thumbnail input
 
         // Set codec options
-        cmd.args(&[
+        cmd.args([
             "-c:a", "copy", // Copy audio stream without re-encoding
             "-c:v", "copy", // Copy video stream without re-encoding
         ]);
 
         // Set metadata for the thumbnail
-        cmd.args(&[
+        cmd.args([
             "-metadata:s:v",
             "title=Album cover",
             "-metadata:s:v",
@@ -145,30 +537,148 @@ impl<P: AsRef<Path>> FFmpeg<P> {
             "attached_pic",
         ]);
 
-        Ok(())
+        Ok(tmp_thumb)
     }
 
     /// Runs FFmpeg command with common output arguments
-    fn run_command(&self, mut cmd: Command, output_path: P) -> Result<()> {
-        cmd.args(&[
-            "-movflags",
-            "+faststart",
-            "-loglevel",
-            "error",
-            output_path.as_ref().to_str().unwrap(),
-        ])
-        .stdout(Stdio::null())
-        .stderr(Stdio::inherit());
-
-        let status = cmd.status()?;
-
-        if !status.success() {
-            return Err(AppError::FFmpeg(format!(
-                "FFmpeg failed with exit code: {}",
-                status.code().unwrap_or(1)
-            )));
+    /// Creates an FFmpeg command which is killed if its future is dropped, e.g. on Ctrl+C
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(self.path().as_ref());
+        cmd.kill_on_drop(true);
+        cmd
+    }
+
+    /// Runs a command to completion, feeding `stdin` to it and collecting its stderr
+    ///
+    /// The child is killed if it runs longer than the timeout.
+    async fn output(&self, mut cmd: Command, stdin: Option<Bytes>) -> Result<Output> {
+        cmd.stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+        let mut child = cmd.spawn()?;
+        let pipe = child.stdin.take();
+
+        let run = async move {
+            // Feed stdin while waiting, so a full pipe can never block FFmpeg
+            let write = async move {
+                if let (Some(data), Some(mut pipe)) = (stdin, pipe) {
+                    match pipe.write_all(&data).await {
+                        // FFmpeg stops reading early when it fails, which its exit code reports
+                        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+                        result => result?,
+                    }
+                }
+                Ok::<_, std::io::Error>(())
+            };
+
+            let (written, output) = tokio::join!(write, child.wait_with_output());
+            written?;
+            output
+        };
+
+        tokio::time::timeout(self.timeout, run)
+            .await
+            .map_err(|_| {
+                AppError::FFmpeg(format!(
+                    "FFmpeg timed out after {}s",
+                    self.timeout.as_secs()
+                ))
+            })?
+            .map_err(Into::into)
+    }
+
+    async fn run_command(&self, cmd: Command, output_path: impl AsRef<Path>) -> Result<()> {
+        self.run_command_with_input(cmd, output_path, None).await
+    }
+
+    /// Runs FFmpeg command with common output arguments, writing `stdin` to its standard input
+    async fn run_command_with_input(
+        &self,
+        mut cmd: Command,
+        output_path: impl AsRef<Path>,
+        stdin: Option<Bytes>,
+    ) -> Result<()> {
+        cmd.args(["-movflags", "+faststart", "-loglevel", "error"]);
+
+        // FFmpeg picks the muxer from the extension, which `.part` files hide
+        if output_path
+            .as_ref()
+            .extension()
+            .is_some_and(|ext| ext == "part")
+        {
+            if let Some(ext) = util::audio_ext(output_path.as_ref()) {
+                cmd.args(["-f", muxer(ext)]);
+            }
+        }
+
+        cmd.arg(output_path.as_ref()).stdout(Stdio::null());
+
+        let output = self.output(cmd, stdin).await?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if !output.status.success() {
+            tracing::debug!(
+                "FFmpeg output for {}:\n{}",
+                output_path.as_ref().display(),
+                stderr
+            );
+            return Err(failure("FFmpeg failed", output.status, &stderr));
+        }
+
+        if !stderr.trim().is_empty() {
+            tracing::warn!(
+                "FFmpeg reported errors for {}: {}",
+                output_path.as_ref().display(),
+                stderr_tail(&stderr)
+            );
         }
 
         Ok(())
     }
 }
+
+/// Returns the FFmpeg muxer for an audio file extension
+fn muxer(ext: &str) -> &str {
+    match ext {
+        "m4a" => "ipod",
+        ext => ext,
+    }
+}
+
+/// Builds the error for a failed FFmpeg run, including the end of its output
+fn failure(what: &str, status: ExitStatus, stderr: &str) -> AppError {
+    let code = status.code().unwrap_or(1);
+    let tail = stderr_tail(stderr);
+
+    if tail.is_empty() {
+        AppError::FFmpeg(format!("{} with exit code: {}", what, code))
+    } else {
+        AppError::FFmpeg(format!("{} with exit code: {}: {}", what, code, tail))
+    }
+}
+
+/// Returns the last few non-empty lines of FFmpeg's output, joined on one line
+fn stderr_tail(stderr: &str) -> String {
+    let lines = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join(" | ")
+}
+
+/// Writes `data` to a new temporary file, deleted when the returned guard is dropped
+fn temp_file(suffix: &str, data: &[u8]) -> Result<TempPath> {
+    let mut file = tempfile::Builder::new()
+        .suffix(suffix)
+        .tempfile_in(temp::dir())?;
+    file.write_all(data)?;
+
+    Ok(file.into_temp_path())
+}
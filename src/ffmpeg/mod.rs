@@ -1,5 +1,6 @@
 mod download;
+#[allow(clippy::module_inception)]
 mod ffmpeg;
 
-pub use download::download_ffmpeg;
+pub use download::{download_ffmpeg, installed_build, latest_build};
 pub use ffmpeg::*;
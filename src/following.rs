@@ -0,0 +1,140 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::downloader::{BatchSummary, DownloadOptions, DownloadRequest, Downloader};
+use crate::error::Result;
+use crate::ffmpeg::FFmpeg;
+use crate::library::Library;
+use crate::soundcloud::model::User;
+use crate::soundcloud::SoundcloudClient;
+use crate::util;
+
+/// Format of an exported follow list
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ExportFormat {
+    /// A JSON array of users
+    Json,
+    /// One user per line, with a header row
+    Csv,
+}
+
+/// A followed user as exported
+#[derive(Serialize)]
+struct FollowedUser<'a> {
+    id: u64,
+    username: &'a str,
+    permalink_url: String,
+    followers_count: Option<u64>,
+    track_count: Option<u64>,
+}
+
+impl<'a> From<&'a User> for FollowedUser<'a> {
+    fn from(user: &'a User) -> Self {
+        Self {
+            id: user.id,
+            username: &user.username,
+            permalink_url: user
+                .permalink_url
+                .clone()
+                .unwrap_or_else(|| format!("https://soundcloud.com/{}", user.permalink)),
+            followers_count: user.followers_count,
+            track_count: user.track_count,
+        }
+    }
+}
+
+/// Formats a follow list for export, e.g. to back it up or move it to another account
+///
+/// # Arguments
+/// * `users` - The followed users
+/// * `format` - Format to export them in
+///
+/// # Returns
+/// Result containing the exported list
+pub fn export(users: &[User], format: ExportFormat) -> Result<String> {
+    let users = users.iter().map(FollowedUser::from).collect::<Vec<_>>();
+
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(&users)?),
+        ExportFormat::Csv => {
+            let mut csv = String::from("id,username,permalink_url,followers_count,track_count\n");
+            for user in &users {
+                csv.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    user.id,
+                    csv_field(user.username),
+                    csv_field(&user.permalink_url),
+                    user.followers_count
+                        .map(|n| n.to_string())
+                        .unwrap_or_default(),
+                    user.track_count.map(|n| n.to_string()).unwrap_or_default()
+                ));
+            }
+            Ok(csv)
+        }
+    }
+}
+
+/// Downloads the latest uploads of each followed user into a folder named after them,
+/// continuing past failures
+///
+/// # Arguments
+/// * `users` - The followed users
+/// * `tracks_per_artist` - Number of uploads to download from each of them
+///
+/// # Returns
+/// Result containing the download counts across all users
+pub async fn download(
+    options: &DownloadOptions,
+    output: &Path,
+    client: SoundcloudClient,
+    ffmpeg: Option<FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+    users: &[User],
+    tracks_per_artist: u32,
+) -> Result<BatchSummary> {
+    let mut summary = BatchSummary::default();
+    let nested = !options.filename_template.has_collection();
+
+    for user in users {
+        let name = if util::is_empty(&user.username) {
+            &user.permalink
+        } else {
+            &user.username
+        };
+        let dir = output.join(util::sanitize(name));
+
+        let downloader = Downloader::new(
+            client.clone(),
+            if nested { &dir } else { output },
+            ffmpeg.clone(),
+            library.clone(),
+            options.clone(),
+        )?;
+        downloader.set_collection(name);
+
+        match downloader
+            .download(DownloadRequest::Uploads {
+                user_id: user.id,
+                limit: tracks_per_artist,
+            })
+            .await
+        {
+            Ok(user_summary) => summary.merge(&user_summary),
+            Err(e) => tracing::error!("Failed to download uploads of {}: {}", user.username, e),
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
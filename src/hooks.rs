@@ -0,0 +1,140 @@
+use serde::Serialize;
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::error::{AppError, Result};
+use crate::soundcloud::model::Track;
+
+/// Commands and webhooks run after each successful download
+#[derive(Clone, Debug, Default)]
+pub struct Hooks {
+    /// Shell command with `{path}`, `{id}`, `{title}`, `{artist}` and `{url}` placeholders
+    pub exec: Option<String>,
+    /// URL a [`DownloadEvent`] is POSTed to
+    pub webhook: Option<reqwest::Url>,
+}
+
+/// JSON payload sent to the webhook
+#[derive(Serialize)]
+struct DownloadEvent<'a> {
+    event: &'static str,
+    id: u64,
+    title: &'a str,
+    artist: &'a str,
+    permalink_url: &'a str,
+    path: &'a Path,
+}
+
+impl Hooks {
+    /// Runs every configured hook for a downloaded track
+    ///
+    /// Hooks are isolated from the download, failures are only logged.
+    ///
+    /// # Arguments
+    /// * `track` - The downloaded track
+    /// * `path` - Where the track was saved
+    pub async fn run(&self, track: &Track, path: &Path) {
+        if let Some(command) = &self.exec {
+            if let Err(e) = exec(command, track, path).await {
+                tracing::warn!("Hook for {} failed: {}", track.permalink_url, e);
+            }
+        }
+
+        if let Some(url) = &self.webhook {
+            if let Err(e) = post(url, track, path).await {
+                tracing::warn!("Webhook for {} failed: {}", track.permalink_url, e);
+            }
+        }
+    }
+}
+
+/// Placeholders of hook commands and the environment variables holding their values
+const PLACEHOLDERS: &[(&str, &str)] = &[
+    ("{path}", "SCDL_PATH"),
+    ("{id}", "SCDL_ID"),
+    ("{title}", "SCDL_TITLE"),
+    ("{artist}", "SCDL_ARTIST"),
+    ("{url}", "SCDL_URL"),
+];
+
+/// Runs `command` through the shell
+///
+/// Track values are only passed as environment variables, the placeholders are
+/// replaced by quoted references to them, so the shell never parses titles or other
+/// values taken from SoundCloud as part of the command.
+async fn exec(command: &str, track: &Track, path: &Path) -> Result<()> {
+    let command = PLACEHOLDERS
+        .iter()
+        .fold(command.to_string(), |command, (placeholder, var)| {
+            command.replace(placeholder, &reference(var))
+        });
+
+    let status = shell(&command)
+        .env("SCDL_PATH", path)
+        .env("SCDL_ID", track.id.to_string())
+        .env("SCDL_TITLE", &track.title)
+        .env("SCDL_ARTIST", &track.user.username)
+        .env("SCDL_URL", &track.permalink_url)
+        .kill_on_drop(true)
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(AppError::Hook(format!(
+            "`{}` exited with {}",
+            command, status
+        )));
+    }
+
+    Ok(())
+}
+
+async fn post(url: &reqwest::Url, track: &Track, path: &Path) -> Result<()> {
+    let event = DownloadEvent {
+        event: "download_complete",
+        id: track.id,
+        title: &track.title,
+        artist: &track.user.username,
+        permalink_url: &track.permalink_url,
+        path,
+    };
+
+    reqwest::Client::new()
+        .post(url.clone())
+        .json(&event)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+/// cmd.exe does not follow the argument quoting of other programs, so the command is
+/// passed as is, with delayed expansion turned on for the `!VAR!` references
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.raw_arg(format!("/D /V:ON /S /C \"{}\"", command));
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+/// Returns a quoted reference to an environment variable, passed as a single argument
+///
+/// Delayed `!VAR!` expansion happens after cmd.exe parsed the command, so characters
+/// like `&` or `%` in the value are never interpreted, unlike with `%VAR%`.
+#[cfg(windows)]
+fn reference(var: &str) -> String {
+    format!("\"!{}!\"", var)
+}
+
+/// Returns a quoted reference to an environment variable, passed as a single argument
+#[cfg(not(windows))]
+fn reference(var: &str) -> String {
+    format!("\"${}\"", var)
+}
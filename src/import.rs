@@ -0,0 +1,280 @@
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::downloader::{DownloadRequest, Downloader};
+use crate::error::{AppError, Result};
+use crate::soundcloud::model::{Resource, Track};
+use crate::util;
+
+/// A row of an imported file, by lowercase column name
+struct Row {
+    /// 1-based line of a CSV file or position in a JSON array
+    number: usize,
+    columns: HashMap<String, String>,
+}
+
+/// Tracks resolved from an import file
+pub struct Import {
+    pub tracks: Vec<Track>,
+    pub rows: usize,
+    /// Rows that could not be resolved, with the reason
+    pub skipped: Vec<(usize, String)>,
+}
+
+impl Downloader {
+    /// Reads a CSV or JSON export and resolves the track or playlist URL in each row
+    ///
+    /// CSV files need a header line. JSON files hold an array of objects, or of plain
+    /// URL strings which are read as the `url` column. Rows are skipped when the column
+    /// is empty, holds no SoundCloud URL or cannot be resolved.
+    ///
+    /// # Arguments
+    /// * `path` - The export, read as JSON if it has a `.json` extension
+    /// * `column` - Column holding the URLs, matched case-insensitively
+    ///
+    /// # Returns
+    /// Result containing the resolved tracks, without duplicates
+    pub async fn import(&self, path: &Path, column: &str) -> Result<Import> {
+        let contents = fs::read_to_string(path)?;
+        let rows = if path.extension().is_some_and(|ext| ext == "json") {
+            json_rows(&contents)?
+        } else {
+            csv_rows(&contents)
+        };
+
+        let column = column.to_lowercase();
+        if !rows.is_empty() && rows.iter().all(|row| !row.columns.contains_key(&column)) {
+            let mut columns = rows[0].columns.keys().cloned().collect::<Vec<_>>();
+            columns.sort();
+            return Err(AppError::Configuration(format!(
+                "{} has no '{}' column, found: {}",
+                path.display(),
+                column,
+                columns.join(", ")
+            )));
+        }
+
+        let mut import = Import {
+            tracks: Vec::new(),
+            rows: rows.len(),
+            skipped: Vec::new(),
+        };
+        let mut seen = HashSet::new();
+
+        for row in rows {
+            let value = row
+                .columns
+                .get(&column)
+                .map(|value| value.trim())
+                .unwrap_or_default();
+
+            match self.resolve_row(value).await {
+                Ok(tracks) => import
+                    .tracks
+                    .extend(tracks.into_iter().filter(|track| seen.insert(track.id))),
+                Err(reason) => import.skipped.push((row.number, reason)),
+            }
+        }
+
+        Ok(import)
+    }
+
+    /// Resolves the URL of a row to its tracks
+    ///
+    /// # Returns
+    /// The track, the tracks of a playlist, or why the row is skipped
+    async fn resolve_row(&self, value: &str) -> std::result::Result<Vec<Track>, String> {
+        if value.is_empty() {
+            return Err("no URL".into());
+        }
+
+        let is_soundcloud = reqwest::Url::parse(value).is_ok_and(|url| {
+            util::is_short_link(&url)
+                || url
+                    .host_str()
+                    .is_some_and(|host| host.ends_with("soundcloud.com"))
+        });
+        if !is_soundcloud {
+            return Err(format!("not a SoundCloud URL: {}", value));
+        }
+
+        match self.client.resolve(value).await {
+            Ok(Resource::Track(track)) => Ok(vec![*track]),
+            Ok(Resource::Playlist(playlist)) => self
+                .fetch(&DownloadRequest::Playlist(Box::new(playlist)))
+                .await
+                .map_err(|e| format!("{}: {}", value, e)),
+            Ok(Resource::User(_)) => Err(format!("{} is a profile", value)),
+            Err(e) => Err(format!("{}: {}", value, e)),
+        }
+    }
+}
+
+/// Parses a CSV file with a header line, allowing quoted fields with embedded
+/// separators, quotes and line breaks
+fn csv_rows(contents: &str) -> Vec<Row> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = contents.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => record.push(std::mem::take(&mut field)),
+            '\n' if !in_quotes => {
+                record.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut record)));
+                line += 1;
+                record_line = line;
+            }
+            '\r' if !in_quotes => {}
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+
+    let mut records = records
+        .into_iter()
+        .filter(|(_, record)| record.iter().any(|field| !field.trim().is_empty()));
+    let Some((_, header)) = records.next() else {
+        return Vec::new();
+    };
+    let header = header
+        .iter()
+        .map(|name| name.trim().to_lowercase())
+        .collect::<Vec<_>>();
+
+    records
+        .map(|(number, record)| Row {
+            number,
+            columns: header.iter().cloned().zip(record).collect(),
+        })
+        .collect()
+}
+
+/// Parses a JSON array of objects or URL strings
+fn json_rows(contents: &str) -> Result<Vec<Row>> {
+    let Value::Array(items) = serde_json::from_str(contents)? else {
+        return Err(AppError::Configuration(
+            "JSON imports must hold an array of objects or URLs".into(),
+        ));
+    };
+
+    let rows = items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let columns = match item {
+                Value::String(url) => HashMap::from([("url".to_string(), url)]),
+                Value::Object(object) => object
+                    .into_iter()
+                    .filter_map(|(name, value)| {
+                        let value = match value {
+                            Value::String(value) => value,
+                            Value::Number(value) => value.to_string(),
+                            _ => return None,
+                        };
+                        Some((name.to_lowercase(), value))
+                    })
+                    .collect(),
+                _ => HashMap::new(),
+            };
+
+            Row {
+                number: i + 1,
+                columns,
+            }
+        })
+        .collect();
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rows as line number and the values of the given columns
+    fn values(rows: &[Row], columns: &[&str]) -> Vec<(usize, Vec<String>)> {
+        rows.iter()
+            .map(|row| {
+                let values = columns
+                    .iter()
+                    .map(|column| row.columns.get(*column).cloned().unwrap_or_default())
+                    .collect();
+                (row.number, values)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_quoted_fields() {
+        let rows = csv_rows("Title,URL\n\"Hello, world\",a\n\"Say \"\"hi\"\"\",b\n");
+        assert_eq!(
+            values(&rows, &["title", "url"]),
+            [
+                (2, vec!["Hello, world".into(), "a".into()]),
+                (3, vec!["Say \"hi\"".into(), "b".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_lines_of_multiline_fields() {
+        let rows = csv_rows("title,url\n\"two\nlines\",a\n\nlast,b");
+        assert_eq!(
+            values(&rows, &["title", "url"]),
+            [
+                (2, vec!["two\nlines".into(), "a".into()]),
+                (5, vec!["last".into(), "b".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn strips_bom_and_crlf() {
+        let rows = csv_rows("\u{feff}url,title\r\nhttps://soundcloud.com/a/b,x\r\n");
+        assert_eq!(
+            values(&rows, &["url", "title"]),
+            [(2, vec!["https://soundcloud.com/a/b".into(), "x".into()])]
+        );
+    }
+
+    #[test]
+    fn reads_header_only_and_empty_files() {
+        assert!(csv_rows("").is_empty());
+        assert!(csv_rows("url\n").is_empty());
+    }
+
+    #[test]
+    fn reads_json_objects_and_strings() {
+        let rows = json_rows(r#"[{"URL": "a", "id": 1}, "b", null]"#).unwrap();
+        assert_eq!(
+            values(&rows, &["url", "id"]),
+            [
+                (1, vec!["a".into(), "1".into()]),
+                (2, vec!["b".into(), String::new()]),
+                (3, vec![String::new(), String::new()]),
+            ]
+        );
+        assert!(json_rows(r#"{"url": "a"}"#).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+mod app;
+mod archive;
+mod artwork;
+mod audio;
+mod audit;
+#[cfg(feature = "capi")]
+pub mod capi;
+mod cli;
+mod clipboard;
+mod config;
+mod cue;
+mod doctor;
+mod downloader;
+mod error;
+mod ffmpeg;
+mod following;
+mod hooks;
+mod import;
+mod library;
+mod lock;
+mod logging;
+mod matcher;
+mod mirror;
+mod notify;
+mod organize;
+mod progress;
+mod queue;
+mod retag;
+mod search;
+mod snapshot;
+mod soundcloud;
+mod storage;
+mod sync;
+mod tags;
+mod temp;
+mod template;
+mod tui;
+mod update;
+mod upgrade;
+mod util;
+
+pub use app::main;
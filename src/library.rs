@@ -0,0 +1,283 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config;
+use crate::error::{AppError, Result};
+use crate::lock;
+use crate::soundcloud::model::Track;
+
+const LIBRARY_FILE: &str = "library.json";
+
+/// A track recorded in the library after it was downloaded
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub permalink_url: String,
+    pub downloaded_at: DateTime<Utc>,
+    /// Length of the streamed audio in milliseconds when it was downloaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    /// Quality of the downloaded stream, `hq` or `sq`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    /// Whether only a preview snippet could be downloaded
+    #[serde(default)]
+    pub preview: bool,
+    /// Tombstone set when the track was first found deleted or made private, after
+    /// which the downloaded file may be the last copy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gone_since: Option<DateTime<Utc>>,
+    /// Checksum and properties of the saved file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileInfo>,
+    /// Location the storage backend put the file at, e.g. its URL, which is all that
+    /// is left of it when the local copy was not kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_at: Option<String>,
+}
+
+/// Properties of a saved file, recorded to check its integrity later
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileInfo {
+    pub size: u64,
+    /// Lowercase hex SHA-256 of the file contents
+    pub sha256: String,
+    /// Length of the audio in milliseconds as measured by ffprobe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    /// Average bitrate in bits per second as measured by ffprobe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u64>,
+}
+
+/// Persistent record of every downloaded track, keyed by track ID
+pub struct Library {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<u64, LibraryEntry>>,
+}
+
+impl Library {
+    /// Opens the library at the given path, or at the default location in the
+    /// application's data directory
+    pub fn open(path: Option<PathBuf>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                let data_dir = config::project_dirs()?.data_dir().to_path_buf();
+                fs::create_dir_all(&data_dir)?;
+                data_dir.join(LIBRARY_FILE)
+            }
+        };
+
+        let entries = if path.exists() {
+            read_entries(&path)?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Whether a track was downloaded, as of the last time the library was read
+    pub fn contains(&self, id: u64) -> bool {
+        self.entries.lock().unwrap().contains_key(&id)
+    }
+
+    pub fn get(&self, id: u64) -> Option<LibraryEntry> {
+        self.entries.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Returns every recorded track along with its ID
+    pub fn entries(&self) -> Vec<(u64, LibraryEntry)> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| (*id, entry.clone()))
+            .collect()
+    }
+
+    /// Returns the ID of the track that was downloaded to `path`
+    pub fn owner_of(&self, path: &Path) -> Option<u64> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, entry)| entry.path == path)
+            .map(|(id, _)| *id)
+    }
+
+    /// Records a downloaded track and saves the library
+    ///
+    /// # Arguments
+    /// * `track` - The downloaded track
+    /// * `path` - Where it was saved
+    /// * `quality` - Quality of the downloaded stream, if known
+    pub async fn record(&self, track: &Track, path: &Path, quality: Option<&str>) -> Result<()> {
+        let entry = LibraryEntry {
+            path: path.to_path_buf(),
+            permalink_url: track.permalink_url.clone(),
+            downloaded_at: Utc::now(),
+            duration: Some(track.duration),
+            quality: quality.map(str::to_string),
+            preview: track.is_preview(),
+            gone_since: None,
+            file: None,
+            stored_at: None,
+        };
+
+        self.update(|entries| {
+            entries.insert(track.id, entry);
+        })
+        .await
+    }
+
+    /// Updates the path of a recorded track after its file was moved and saves the library
+    pub async fn relocate(&self, id: u64, path: &Path) -> Result<()> {
+        self.update(|entries| {
+            if let Some(entry) = entries.get_mut(&id) {
+                entry.path = path.to_path_buf();
+            }
+        })
+        .await
+    }
+
+    /// Records the checksum and properties of a track's file and saves the library
+    pub async fn set_file_info(&self, id: u64, info: FileInfo) -> Result<()> {
+        self.update(|entries| {
+            if let Some(entry) = entries.get_mut(&id) {
+                entry.file = Some(info);
+            }
+        })
+        .await
+    }
+
+    /// Records where the storage backend put a track's file and saves the library
+    pub async fn set_stored_at(&self, id: u64, location: String) -> Result<()> {
+        self.update(|entries| {
+            if let Some(entry) = entries.get_mut(&id) {
+                entry.stored_at = Some(location);
+            }
+        })
+        .await
+    }
+
+    /// Puts a tombstone on a downloaded track that could not be fetched because it is
+    /// gone, or removes it when the track could be fetched again
+    ///
+    /// # Arguments
+    /// * `id` - ID of the fetched track
+    /// * `result` - Outcome of fetching it
+    pub async fn update_availability(&self, id: u64, result: &Result<Track>) -> Result<()> {
+        match result {
+            Ok(_) => self.mark_available(id).await,
+            Err(AppError::NotFound(_)) => {
+                if let Some(entry) = self.mark_gone(id).await? {
+                    tracing::warn!(
+                        status = "Gone",
+                        "{} was deleted or made private, {} may be the last copy",
+                        entry.permalink_url,
+                        entry.path.display()
+                    );
+                }
+                Ok(())
+            }
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Records that a downloaded track is no longer available on SoundCloud
+    ///
+    /// # Returns
+    /// Result containing the track's entry if it was not known to be gone before
+    async fn mark_gone(&self, id: u64) -> Result<Option<LibraryEntry>> {
+        if self.get(id).is_none_or(|entry| entry.gone_since.is_some()) {
+            return Ok(None);
+        }
+
+        self.update(|entries| {
+            let entry = entries
+                .get_mut(&id)
+                .filter(|entry| entry.gone_since.is_none())?;
+            entry.gone_since = Some(Utc::now());
+            Some(entry.clone())
+        })
+        .await
+    }
+
+    /// Removes the tombstone of a track that is available again
+    pub async fn mark_available(&self, id: u64) -> Result<()> {
+        if self.get(id).is_none_or(|entry| entry.gone_since.is_none()) {
+            return Ok(());
+        }
+
+        self.update(|entries| {
+            if let Some(entry) = entries.get_mut(&id) {
+                entry.gone_since = None;
+            }
+        })
+        .await
+    }
+
+    /// Returns the downloaded tracks that are gone from SoundCloud, optionally only
+    /// those found gone since a point in time
+    pub fn gone(&self, since: Option<DateTime<Utc>>) -> Vec<(u64, LibraryEntry)> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| {
+                entry
+                    .gone_since
+                    .is_some_and(|gone| since.is_none_or(|since| gone >= since))
+            })
+            .map(|(id, entry)| (*id, entry.clone()))
+            .collect()
+    }
+
+    /// Picks up the tracks other instances recorded or changed since the library was read
+    pub async fn reload(&self) -> Result<()> {
+        let _lock = lock::lock(&self.path).await?;
+        if self.path.exists() {
+            *self.entries.lock().unwrap() = read_entries(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Applies a change to the library and saves it, writing to a temporary file first
+    /// so a crash never leaves it truncated
+    ///
+    /// The change is applied to the library as saved by other instances, read again
+    /// under the lock, so the latest change to an entry wins and saving never drops
+    /// theirs.
+    async fn update<R>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<u64, LibraryEntry>) -> R,
+    ) -> Result<R> {
+        let _lock = lock::lock(&self.path).await?;
+        let mut entries = self.entries.lock().unwrap();
+        if self.path.exists() {
+            *entries = read_entries(&self.path)?;
+        }
+        let result = change(&mut entries);
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&*entries)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(result)
+    }
+}
+
+fn read_entries(path: &Path) -> Result<BTreeMap<u64, LibraryEntry>> {
+    serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| {
+        AppError::Configuration(format!("Could not read library {}: {}", path.display(), e))
+    })
+}
@@ -0,0 +1,98 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::error::{AppError, Result};
+
+/// How long a write waits for another instance before giving up, unless waiting is
+/// turned on
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interval between attempts to take a lock held by another instance
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How shared files are locked, decided once per run
+static MODE: OnceLock<LockMode> = OnceLock::new();
+
+/// How the archive and library are locked while they are written, so instances
+/// sharing them, e.g. a cron `watch` job and a manual run, never lose each other's
+/// records
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockMode {
+    /// Wait a while for another instance, then fail
+    #[default]
+    Timeout,
+    /// Wait for as long as another instance holds the lock
+    Wait,
+    /// Don't lock, for file systems without lock support
+    Off,
+}
+
+/// Sets how shared files are locked for the rest of the run
+///
+/// Has no effect once any file was locked.
+pub fn set_mode(mode: LockMode) {
+    let _ = MODE.set(mode);
+}
+
+/// Advisory lock on a shared file, released when dropped
+#[derive(Debug)]
+pub struct FileLock {
+    _file: File,
+}
+
+/// Locks a shared file against other instances by locking a `.lock` file next to it
+///
+/// Waiting for the lock happens on the blocking thread pool, so other downloads keep
+/// going in the meantime.
+///
+/// # Arguments
+/// * `path` - The shared file
+///
+/// # Returns
+/// The lock, `None` if locking is turned off
+pub async fn lock(path: &Path) -> Result<Option<FileLock>> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || lock_blocking(&path))
+        .await
+        .map_err(std::io::Error::from)?
+}
+
+fn lock_blocking(path: &Path) -> Result<Option<FileLock>> {
+    let mode = *MODE.get_or_init(LockMode::default);
+    if mode == LockMode::Off {
+        return Ok(None);
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path(path))?;
+
+    if mode == LockMode::Wait {
+        file.lock()?;
+        return Ok(Some(FileLock { _file: file }));
+    }
+
+    let started = Instant::now();
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(Some(FileLock { _file: file })),
+            Err(TryLockError::WouldBlock) if started.elapsed() < LOCK_TIMEOUT => {
+                std::thread::sleep(LOCK_POLL_INTERVAL);
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(AppError::Locked(path.display().to_string()))
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".lock");
+    PathBuf::from(name)
+}
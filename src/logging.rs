@@ -0,0 +1,241 @@
+use clap::ValueEnum;
+use ratatui::crossterm::style::{Color, Stylize};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io::{IsTerminal, Write as _};
+use std::path::{Path, PathBuf};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::prelude::*;
+
+use crate::error::{AppError, Result};
+use crate::progress;
+
+/// Rotated log files kept by default
+const DEFAULT_MAX_FILES: usize = 7;
+/// Width of the right aligned status column, e.g. `  Downloaded`
+const STATUS_WIDTH: usize = 12;
+
+/// `[log]` section of the config file
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct LogConfig {
+    /// File to write JSON logs to, rotated files get a date suffix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<LogRotation>,
+
+    /// Number of rotated files to keep, older ones are deleted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<usize>,
+}
+
+/// How often the log file is rotated
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    /// Keep appending to a single file
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+/// Installs the global tracing subscriber
+///
+/// Events are printed to the console by [`ConsoleReporter`] at the given verbosity. With
+/// a log file, every event of this crate down to debug level is additionally written to
+/// it as JSON lines, whatever the console shows.
+///
+/// # Arguments
+/// * `verbosity` - 0 by default, raised by `-v` and lowered by `-q`
+/// * `file` - File to write JSON logs to
+/// * `rotation` - How often the file is rotated
+/// * `max_files` - Number of rotated files to keep
+///
+/// # Returns
+/// Result containing a guard that flushes the log file when dropped
+pub fn init(
+    verbosity: i8,
+    file: Option<&Path>,
+    rotation: LogRotation,
+    max_files: Option<usize>,
+) -> Result<Option<WorkerGuard>> {
+    let console = ConsoleReporter::new().with_filter(console_targets(verbosity));
+
+    let Some(file) = file else {
+        tracing_subscriber::registry().with(console).init();
+        return Ok(None);
+    };
+
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    let file_name = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| AppError::Configuration(format!("Invalid log file: {}", file.display())))?;
+
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation.into())
+        .filename_prefix(file_name)
+        .max_log_files(max_files.unwrap_or(DEFAULT_MAX_FILES))
+        .build(dir)
+        .map_err(|e| {
+            AppError::Configuration(format!("Failed to open log file {}: {}", file.display(), e))
+        })?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+
+    let targets = Targets::new()
+        .with_default(LevelFilter::INFO)
+        .with_target(env!("CARGO_CRATE_NAME"), LevelFilter::DEBUG);
+    let json = tracing_subscriber::fmt::layer()
+        .json()
+        .with_writer(writer)
+        .with_filter(targets);
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(json)
+        .init();
+
+    Ok(Some(guard))
+}
+
+/// Levels shown on the console for this crate and for its dependencies
+fn console_targets(verbosity: i8) -> Targets {
+    let (own, dependencies) = match verbosity {
+        i8::MIN..=-2 => (LevelFilter::ERROR, LevelFilter::ERROR),
+        -1 => (LevelFilter::WARN, LevelFilter::WARN),
+        0 => (LevelFilter::INFO, LevelFilter::WARN),
+        1 => (LevelFilter::DEBUG, LevelFilter::INFO),
+        _ => (LevelFilter::TRACE, LevelFilter::DEBUG),
+    };
+
+    Targets::new()
+        .with_default(dependencies)
+        .with_target(env!("CARGO_CRATE_NAME"), own)
+}
+
+/// Prints events as short, colored lines instead of raw tracing output
+///
+/// Events with a `status` field, such as the outcome of a track, are printed with the
+/// status right aligned in front, colored by level. Other warnings and errors get a
+/// `warning:` or `error:` prefix, debug events show their target. Warnings and errors
+/// go to stderr, so they stay out of output that is piped elsewhere.
+struct ConsoleReporter {
+    /// Whether stdout and stderr are colored
+    ansi: (bool, bool),
+}
+
+impl ConsoleReporter {
+    fn new() -> Self {
+        let color = std::env::var_os("NO_COLOR").is_none();
+        Self {
+            ansi: (
+                color && std::io::stdout().is_terminal(),
+                color && std::io::stderr().is_terminal(),
+            ),
+        }
+    }
+
+    fn paint(&self, text: &str, color: Color, stderr: bool) -> String {
+        let ansi = if stderr { self.ansi.1 } else { self.ansi.0 };
+        if ansi {
+            text.with(color).bold().to_string()
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ConsoleReporter {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+
+        let level = *event.metadata().level();
+        let stderr = matches!(level, Level::ERROR | Level::WARN);
+        let color = match level {
+            Level::ERROR => Color::Red,
+            Level::WARN => Color::Yellow,
+            Level::INFO => Color::Green,
+            _ => Color::DarkGrey,
+        };
+
+        let prefix = match (fields.status, level) {
+            (Some(status), _) => {
+                format!(
+                    "{} ",
+                    self.paint(&format!("{:>STATUS_WIDTH$}", status), color, stderr)
+                )
+            }
+            (None, Level::ERROR) => format!("{} ", self.paint("error:", color, stderr)),
+            (None, Level::WARN) => format!("{} ", self.paint("warning:", color, stderr)),
+            (None, Level::INFO) => String::new(),
+            (None, _) => format!(
+                "{} ",
+                self.paint(&format!("{}:", event.metadata().target()), color, stderr)
+            ),
+        };
+
+        let line = format!("{}{}{}", prefix, fields.message, fields.rest);
+        progress::suspend(|| {
+            let _ = if stderr {
+                writeln!(std::io::stderr().lock(), "{}", line)
+            } else {
+                writeln!(std::io::stdout().lock(), "{}", line)
+            };
+        });
+    }
+}
+
+/// Fields of an event as shown on the console
+#[derive(Default)]
+struct EventFields {
+    message: String,
+    status: Option<String>,
+    /// Any other fields as ` name=value`
+    rest: String,
+}
+
+impl Visit for EventFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            "status" => self.status = Some(value.to_string()),
+            name => {
+                let _ = write!(self.rest, " {}={}", name, value);
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{:?}", value),
+            "status" => self.status = Some(format!("{:?}", value)),
+            name => {
+                let _ = write!(self.rest, " {}={:?}", name, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::downloader::Downloader;
+use crate::error::{AppError, Result};
+use crate::ffmpeg::FFmpeg;
+use crate::library::Library;
+use crate::soundcloud::model::{Resource, Track};
+use crate::util;
+
+/// How a file was matched to its track
+#[derive(Clone, Debug)]
+pub enum TrackSource {
+    Id {
+        id: u64,
+        secret_token: Option<String>,
+    },
+    Url(String),
+}
+
+/// Matches audio files to the tracks they were downloaded from, through the library,
+/// an `.info.json` sidecar or a SoundCloud URL in their tags
+pub struct TrackMatcher {
+    /// Library entries by canonical path
+    library_paths: HashMap<PathBuf, TrackSource>,
+}
+
+impl TrackMatcher {
+    pub fn new(library: &Library) -> Self {
+        let library_paths = library
+            .entries()
+            .into_iter()
+            .filter_map(|(id, entry)| {
+                let path = fs::canonicalize(&entry.path).ok()?;
+                let secret_token = util::secret_token(&entry.permalink_url);
+                Some((path, TrackSource::Id { id, secret_token }))
+            })
+            .collect();
+
+        Self { library_paths }
+    }
+
+    /// Finds the track of a file, `None` if nothing matches
+    ///
+    /// # Arguments
+    /// * `path` - The audio file
+    /// * `ffmpeg` - Used to read the tags of files other than MP3
+    pub async fn find(&self, path: &Path, ffmpeg: Option<&FFmpeg<PathBuf>>) -> Option<TrackSource> {
+        if let Some(source) = fs::canonicalize(path)
+            .ok()
+            .and_then(|path| self.library_paths.get(&path))
+        {
+            return Some(source.clone());
+        }
+
+        if let Some(source) = sidecar_source(path) {
+            return Some(source);
+        }
+
+        let values = match util::audio_ext(path) {
+            Some("mp3") => id3::Tag::read_from_path(path)
+                .map(|tag| {
+                    tag.frames()
+                        .filter_map(|frame| match frame.content() {
+                            id3::Content::Comment(comment) => Some(comment.text.clone()),
+                            id3::Content::Link(link) => Some(link.clone()),
+                            id3::Content::ExtendedLink(link) => Some(link.link.clone()),
+                            content => content.text().map(str::to_string),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            _ => match ffmpeg {
+                Some(ffmpeg) => ffmpeg
+                    .probe(path)
+                    .await
+                    .map(|probe| probe.tags.into_values().collect())
+                    .unwrap_or_default(),
+                None => Vec::new(),
+            },
+        };
+
+        values
+            .iter()
+            .flat_map(|value| value.split_whitespace())
+            .find(|word| is_track_url(word))
+            .map(|url| TrackSource::Url(url.to_string()))
+    }
+}
+
+impl Downloader {
+    /// Fetches the full metadata of a matched track
+    pub async fn fetch_source(&self, source: TrackSource) -> Result<Track> {
+        match source {
+            TrackSource::Id { id, secret_token } => {
+                self.fetch_track(id, secret_token.as_deref()).await
+            }
+            TrackSource::Url(url) => match self.client.resolve(&url).await? {
+                Resource::Track(track) => {
+                    self.fetch_track(track.id, track.secret_token.as_deref())
+                        .await
+                }
+                _ => Err(AppError::Configuration(format!(
+                    "{} is not a track URL",
+                    url
+                ))),
+            },
+        }
+    }
+}
+
+/// Reads the track ID from the `<name>.info.json` sidecar next to a file
+fn sidecar_source(path: &Path) -> Option<TrackSource> {
+    let sidecar = path.with_extension("info.json");
+    let info: Value = serde_json::from_str(&fs::read_to_string(sidecar).ok()?).ok()?;
+
+    let id = match &info["id"] {
+        Value::Number(id) => id.as_u64()?,
+        Value::String(id) => id.parse().ok()?,
+        _ => return None,
+    };
+    let secret_token = ["webpage_url", "permalink_url"]
+        .iter()
+        .filter_map(|key| info[key].as_str())
+        .find_map(util::secret_token);
+
+    Some(TrackSource::Id { id, secret_token })
+}
+
+/// Whether a word is a link to a SoundCloud track page rather than e.g. a profile
+fn is_track_url(word: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(word) else {
+        return false;
+    };
+
+    url.host_str()
+        .is_some_and(|host| host.trim_start_matches("www.") == "soundcloud.com")
+        && url
+            .path_segments()
+            .is_some_and(|segments| segments.count() >= 2)
+}
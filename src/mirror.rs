@@ -0,0 +1,462 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::downloader::{BatchSummary, DownloadOptions, DownloadRequest, Downloader};
+use crate::error::{Recovery, Result};
+use crate::ffmpeg::FFmpeg;
+use crate::library::Library;
+use crate::soundcloud::model::{Playlist, Track, User, WebProfile};
+use crate::soundcloud::SoundcloudClient;
+use crate::util;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const PROFILE_FILE: &str = "profile.json";
+const LIKES_CHUNK_SIZE: u32 = 50;
+
+/// Summary of a mirrored profile, written to `manifest.json` in the mirror root
+#[derive(Serialize)]
+struct Manifest {
+    user: ManifestUser,
+    mirrored_at: DateTime<Utc>,
+    uploads: Vec<ManifestTrack>,
+    likes: Vec<ManifestTrack>,
+    reposts: Vec<ManifestTrack>,
+    playlists: Vec<ManifestPlaylist>,
+    reposted_playlists: Vec<ManifestPlaylist>,
+}
+
+#[derive(Serialize)]
+struct ManifestUser {
+    id: u64,
+    username: String,
+    permalink: String,
+}
+
+/// Profile page of a mirrored user, written to `profile.json` in the mirror root
+#[derive(Serialize)]
+struct Profile {
+    id: u64,
+    username: String,
+    full_name: Option<String>,
+    permalink_url: Option<String>,
+    description: Option<String>,
+    city: Option<String>,
+    country_code: Option<String>,
+    followers_count: Option<u64>,
+    followings_count: Option<u64>,
+    track_count: Option<u64>,
+    playlist_count: Option<u64>,
+    verified: bool,
+    created_at: Option<DateTime<Utc>>,
+    links: Vec<WebProfile>,
+    /// Location of the avatar relative to the mirror root, `None` if there is none
+    avatar: Option<PathBuf>,
+    saved_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct ManifestTrack {
+    id: u64,
+    title: String,
+    artist: String,
+    permalink_url: String,
+    /// Location of the file relative to the mirror root, `None` if it was not downloaded
+    path: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct ManifestPlaylist {
+    id: u64,
+    title: String,
+    permalink_url: String,
+    directory: PathBuf,
+    tracks: Vec<ManifestTrack>,
+}
+
+/// Downloads a user's uploads, likes, reposts and playlists into one directory tree
+pub struct Mirror {
+    client: SoundcloudClient,
+    ffmpeg: Option<FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+    options: DownloadOptions,
+    user: User,
+    root: PathBuf,
+    limit: u32,
+    /// Counts of every collection mirrored so far
+    summary: Mutex<BatchSummary>,
+    /// Tracks saved by this run, linked rather than downloaded again when another
+    /// collection lists them
+    saved: Mutex<HashSet<u64>>,
+}
+
+impl Mirror {
+    /// Creates a mirror of `user` rooted at `<output>/<username>`
+    ///
+    /// # Arguments
+    /// * `limit` - Maximum number of items fetched from each collection
+    pub fn new(
+        client: SoundcloudClient,
+        ffmpeg: Option<FFmpeg<PathBuf>>,
+        library: Arc<Library>,
+        options: DownloadOptions,
+        output: &Path,
+        user: User,
+        limit: u32,
+    ) -> Self {
+        let name = if util::is_empty(&user.username) {
+            user.permalink.clone()
+        } else {
+            user.username.clone()
+        };
+
+        Self {
+            client,
+            ffmpeg,
+            library,
+            options,
+            root: output.join(util::sanitize(&name)),
+            user,
+            limit,
+            summary: Mutex::default(),
+            saved: Mutex::default(),
+        }
+    }
+
+    /// Downloads every collection and writes the manifest
+    ///
+    /// A failure in one collection is logged and the remaining ones are still mirrored.
+    ///
+    /// # Returns
+    /// Result containing the download counts across all collections
+    pub async fn run(&self) -> Result<BatchSummary> {
+        let user = &self.user;
+        tracing::info!("Mirroring {} into {}", user.username, self.root.display());
+
+        if let Err(e) = self.save_profile().await {
+            tracing::error!("Failed to save profile: {}", e);
+        }
+
+        let uploads = self.recover("uploads", self.mirror_uploads(user).await)?;
+        let likes = self.recover("likes", self.mirror_likes(user).await)?;
+        let (reposts, reposted_playlists) =
+            self.recover("reposts", self.mirror_reposts(user).await)?;
+
+        let playlists = async {
+            let playlists = self.client.get_user_playlists(user.id, self.limit).await?;
+            self.mirror_playlists(&self.root.join("playlists"), playlists)
+                .await
+        }
+        .await;
+        let playlists = self.recover("playlists", playlists)?;
+
+        let manifest = Manifest {
+            user: ManifestUser {
+                id: user.id,
+                username: user.username.clone(),
+                permalink: user.permalink.clone(),
+            },
+            mirrored_at: Utc::now(),
+            uploads,
+            likes,
+            reposts,
+            playlists,
+            reposted_playlists,
+        };
+
+        fs::create_dir_all(&self.root)?;
+        let path = self.root.join(MANIFEST_FILE);
+        fs::write(&path, serde_json::to_string_pretty(&manifest)?)?;
+        tracing::info!("Wrote manifest to {}", path.display());
+
+        Ok(self.summary.lock().unwrap().clone())
+    }
+
+    /// Writes the user's profile page to `profile.json`, with their avatar next to it
+    async fn save_profile(&self) -> Result<()> {
+        tracing::info!("Saving profile of {}", self.user.username);
+        let user = self.client.fetch_user(self.user.id).await?;
+        let links = self
+            .client
+            .get_web_profiles(user.id)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Could not fetch profile links: {}", e);
+                Vec::new()
+            });
+
+        fs::create_dir_all(&self.root)?;
+        let avatar = self.save_avatar(&user).await.unwrap_or_else(|e| {
+            tracing::warn!("Could not download avatar: {}", e);
+            None
+        });
+
+        let profile = Profile {
+            id: user.id,
+            username: user.username,
+            full_name: user.full_name.filter(|name| !util::is_empty(name)),
+            permalink_url: user.permalink_url,
+            description: user.description.filter(|bio| !util::is_empty(bio)),
+            city: user.city.filter(|city| !util::is_empty(city)),
+            country_code: user.country_code,
+            followers_count: user.followers_count,
+            followings_count: user.followings_count,
+            track_count: user.track_count,
+            playlist_count: user.playlist_count,
+            verified: user.verified,
+            created_at: user.created_at,
+            links,
+            avatar,
+            saved_at: Utc::now(),
+        };
+        fs::write(
+            self.root.join(PROFILE_FILE),
+            serde_json::to_string_pretty(&profile)?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Downloads the user's avatar in its original size into the mirror root
+    ///
+    /// # Returns
+    /// Result containing the avatar's path relative to the root, `None` if the user
+    /// kept the default avatar
+    async fn save_avatar(&self, user: &User) -> Result<Option<PathBuf>> {
+        let Some(url) = user
+            .avatar_url
+            .as_ref()
+            .filter(|url| !url.contains("default_avatar"))
+        else {
+            return Ok(None);
+        };
+
+        let avatar = match self
+            .client
+            .download_bytes(&url.replace("-large", "-original"))
+            .await
+        {
+            Ok(avatar) => avatar,
+            Err(_) => self.client.download_bytes(url).await?,
+        };
+
+        let path = PathBuf::from(format!("avatar.{}", avatar.file_ext));
+        fs::write(self.root.join(&path), &avatar.data)?;
+
+        Ok(Some(path))
+    }
+
+    async fn mirror_uploads(&self, user: &User) -> Result<Vec<ManifestTrack>> {
+        tracing::info!("Fetching uploads for user: {}", user.username);
+        let downloader = self.downloader(&self.root.join("uploads"))?;
+        let tracks = self.client.get_user_tracks(user.id, self.limit).await?;
+
+        self.download(&downloader, downloader.select_items(tracks).await)
+            .await
+    }
+
+    async fn mirror_likes(&self, user: &User) -> Result<Vec<ManifestTrack>> {
+        let downloader = self.downloader(&self.root.join("likes"))?;
+        let tracks = downloader
+            .fetch(&DownloadRequest::Likes {
+                user: Box::new(user.clone()),
+                skip: 0,
+                limit: self.limit,
+                chunk_size: LIKES_CHUNK_SIZE,
+            })
+            .await?;
+
+        self.download(&downloader, tracks).await
+    }
+
+    async fn mirror_reposts(
+        &self,
+        user: &User,
+    ) -> Result<(Vec<ManifestTrack>, Vec<ManifestPlaylist>)> {
+        tracing::info!("Fetching reposts for user: {}", user.username);
+        let dir = self.root.join("reposts");
+        let downloader = self.downloader(&dir)?;
+
+        let (tracks, playlists): (Vec<_>, Vec<_>) = self
+            .client
+            .get_user_reposts(user.id, self.limit)
+            .await?
+            .into_iter()
+            .partition(|repost| repost.track.is_some());
+
+        let tracks = tracks
+            .into_iter()
+            .filter_map(|repost| repost.track)
+            .collect::<Vec<_>>();
+        let tracks = self
+            .download(&downloader, downloader.select_items(tracks).await)
+            .await?;
+
+        let playlists = playlists
+            .into_iter()
+            .filter_map(|repost| repost.playlist)
+            .collect::<Vec<_>>();
+        let playlists = self.mirror_playlists(&dir, playlists).await?;
+
+        Ok((tracks, playlists))
+    }
+
+    /// Logs a collection that failed to mirror and counts it as failed, so the other
+    /// collections are still mirrored
+    ///
+    /// # Returns
+    /// Result containing what was mirrored, nothing for a failed collection, or the
+    /// error if it stops the whole mirror
+    fn recover<T: Default>(&self, collection: &str, result: Result<T>) -> Result<T> {
+        match result {
+            Ok(mirrored) => Ok(mirrored),
+            Err(e) if e.recovery() == Recovery::Abort => Err(e),
+            Err(e) => {
+                tracing::error!("Failed to mirror {}: {}", collection, e);
+                let mut summary = self.summary.lock().unwrap();
+                summary.total += 1;
+                summary.failed += 1;
+                Ok(T::default())
+            }
+        }
+    }
+
+    /// Downloads each playlist into its own subdirectory of `dir`, logging and counting
+    /// failures
+    ///
+    /// # Returns
+    /// Result containing the mirrored playlists, or the error that stops the mirror
+    async fn mirror_playlists(
+        &self,
+        dir: &Path,
+        playlists: Vec<Playlist>,
+    ) -> Result<Vec<ManifestPlaylist>> {
+        let mut mirrored = Vec::with_capacity(playlists.len());
+
+        let directories = util::playlist_dirs(dir, &playlists);
+        for (playlist, directory) in playlists.into_iter().zip(directories) {
+            let tracks = match self.mirror_playlist(&directory, &playlist).await {
+                Ok(tracks) => tracks,
+                Err(e) if e.recovery() == Recovery::Abort => return Err(e),
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to mirror playlist {}: {}",
+                        playlist.permalink_url,
+                        e
+                    );
+                    let mut summary = self.summary.lock().unwrap();
+                    summary.total += 1;
+                    summary.failed += 1;
+                    continue;
+                }
+            };
+
+            mirrored.push(ManifestPlaylist {
+                id: playlist.id,
+                title: playlist.title,
+                permalink_url: playlist.permalink_url,
+                directory: self.relative(&directory),
+                tracks,
+            });
+        }
+
+        Ok(mirrored)
+    }
+
+    async fn mirror_playlist(
+        &self,
+        directory: &Path,
+        playlist: &Playlist,
+    ) -> Result<Vec<ManifestTrack>> {
+        let downloader = self.downloader(directory)?;
+        let tracks = downloader
+            .fetch(&DownloadRequest::PlaylistId {
+                id: playlist.id,
+                secret_token: playlist.secret_token.clone(),
+            })
+            .await?;
+
+        self.download(&downloader, tracks).await
+    }
+
+    /// Downloads the tracks and describes them for the manifest
+    ///
+    /// Tracks another collection of the mirror already saved are hard linked from
+    /// where the library recorded them.
+    async fn download(
+        &self,
+        downloader: &Downloader,
+        tracks: Vec<Track>,
+    ) -> Result<Vec<ManifestTrack>> {
+        let (saved, new): (Vec<_>, Vec<_>) = {
+            let saved = self.saved.lock().unwrap();
+            tracks
+                .iter()
+                .cloned()
+                .partition(|track| saved.contains(&track.id))
+        };
+
+        let mut paths = HashMap::new();
+        for track in &saved {
+            let Some(entry) = self.library.get(track.id) else {
+                continue;
+            };
+            let path = downloader
+                .link_track(track, &entry.path)
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Failed to link {}: {}", entry.path.display(), e);
+                    entry.path
+                });
+            paths.insert(track.id, path);
+        }
+        if !saved.is_empty() {
+            tracing::info!(
+                "Linked {} track(s) saved for another collection",
+                saved.len()
+            );
+        }
+
+        let summary = downloader.download_tracks(new.clone()).await?;
+        self.summary.lock().unwrap().merge(&summary);
+        self.saved.lock().unwrap().extend(
+            new.iter()
+                .filter(|track| {
+                    self.library
+                        .get(track.id)
+                        .is_some_and(|entry| entry.path.exists())
+                })
+                .map(|track| track.id),
+        );
+
+        Ok(tracks
+            .into_iter()
+            .map(|track| ManifestTrack {
+                path: paths
+                    .remove(&track.id)
+                    .or_else(|| self.library.get(track.id).map(|entry| entry.path))
+                    .map(|path| self.relative(&path)),
+                id: track.id,
+                title: track.title,
+                artist: track.user.username,
+                permalink_url: track.permalink_url,
+            })
+            .collect())
+    }
+
+    fn downloader(&self, dir: &Path) -> Result<Downloader> {
+        Downloader::new(
+            self.client.clone(),
+            dir,
+            self.ffmpeg.clone(),
+            self.library.clone(),
+            self.options.clone(),
+        )
+    }
+
+    fn relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.root).unwrap_or(path).to_path_buf()
+    }
+}
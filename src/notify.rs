@@ -0,0 +1,35 @@
+use notify_rust::Notification;
+
+use crate::downloader::BatchSummary;
+
+const APP_NAME: &str = "soundcloud-dl";
+
+/// Shows a desktop notification with the counts of a finished batch
+///
+/// Notifications are best effort, a missing notification daemon is only logged.
+pub fn batch_complete(summary: &BatchSummary) {
+    let skipped = summary.skipped();
+
+    let mut body = format!(
+        "Downloaded {} of {} track(s), {} failed",
+        summary.downloaded, summary.total, summary.failed
+    );
+    if skipped > 0 {
+        body.push_str(&format!(", {} skipped", skipped));
+    }
+
+    show("Download finished", &body);
+}
+
+/// Shows a desktop notification, only logging a failure
+pub fn show(summary: &str, body: &str) {
+    let result = Notification::new()
+        .appname(APP_NAME)
+        .summary(summary)
+        .body(body)
+        .show();
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to show notification: {}", e);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cue;
+use crate::downloader::Downloader;
+use crate::error::Result;
+use crate::matcher::TrackMatcher;
+use crate::soundcloud::model::Track;
+use crate::util;
+
+/// Files next to a track that are named after it and move along with it
+const SIDECAR_EXTENSIONS: &[&str] = &[
+    "cue",
+    "comments.json",
+    "waveform.json",
+    "waveform.png",
+    "info.json",
+];
+
+/// Outcome counts of an `organize` run
+#[derive(Clone, Debug, Default)]
+pub struct OrganizeSummary {
+    pub total: usize,
+    /// Files moved, or that would be moved in a dry run
+    pub moved: usize,
+    /// Files already named as the template says
+    pub unchanged: usize,
+    /// Files that could not be matched to a track
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Downloader {
+    /// Renames and moves the audio files in a directory to match the filename template
+    ///
+    /// Files are matched to their track like in `retag`, sidecars such as cue sheets
+    /// move along with them and the library is updated with the new paths. Files are
+    /// never overwritten, a track whose new name is taken gets its ID appended.
+    ///
+    /// # Arguments
+    /// * `dir` - Directory to organize, the template is relative to it
+    /// * `dry_run` - Only report what would be moved
+    ///
+    /// # Returns
+    /// Result containing the outcome counts
+    pub async fn organize(&self, dir: &Path, dry_run: bool) -> Result<OrganizeSummary> {
+        let files = util::audio_files(dir)?;
+        let matcher = TrackMatcher::new(&self.library);
+        // Names given out in this run, which may not exist yet in a dry run
+        let mut claimed = HashSet::new();
+
+        let mut summary = OrganizeSummary {
+            total: files.len(),
+            ..OrganizeSummary::default()
+        };
+
+        for path in files {
+            let Some(source) = matcher.find(&path, self.ffmpeg.as_ref()).await else {
+                summary.skipped += 1;
+                tracing::warn!(
+                    status = "Skipped",
+                    "{} does not match any track",
+                    path.display()
+                );
+                continue;
+            };
+
+            let track = match self.fetch_source(source).await {
+                Ok(track) => track,
+                Err(e) => {
+                    summary.failed += 1;
+                    tracing::error!(status = "Failed", "{}: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            let target = self.organized_path(dir, &track, &path, &claimed);
+            claimed.insert(target.clone());
+            if target == path {
+                summary.unchanged += 1;
+                tracing::debug!("{} is already organized", path.display());
+                continue;
+            }
+
+            if dry_run {
+                summary.moved += 1;
+                tracing::info!(
+                    status = "Would move",
+                    "{} to {}",
+                    path.display(),
+                    target.display()
+                );
+                continue;
+            }
+
+            match self.move_track(&track, &path, &target).await {
+                Ok(()) => {
+                    summary.moved += 1;
+                    tracing::info!(
+                        status = "Moved",
+                        "{} to {}",
+                        path.display(),
+                        target.display()
+                    );
+                    remove_empty_dirs(dir, &path);
+                }
+                Err(e) => {
+                    summary.failed += 1;
+                    tracing::error!(status = "Failed", "{}: {}", path.display(), e);
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Returns where a track's file belongs, adding the track ID when another file
+    /// already has that name
+    fn organized_path(
+        &self,
+        dir: &Path,
+        track: &Track,
+        current: &Path,
+        claimed: &HashSet<PathBuf>,
+    ) -> PathBuf {
+        let template = &self.options.filename_template;
+        let ext = util::audio_ext(current).unwrap_or_default();
+        let with_id = || template.file_path(dir, track, None, &format!(" [{}]", track.id), ext);
+
+        if self.options.add_id {
+            return with_id();
+        }
+
+        let path = template.file_path(dir, track, None, "", ext);
+        let taken = claimed.contains(&path) || (path != current && path.exists());
+        if taken {
+            with_id()
+        } else {
+            path
+        }
+    }
+
+    /// Moves a track's file and its sidecars, then records the new path in the library
+    async fn move_track(&self, track: &Track, from: &Path, to: &Path) -> Result<()> {
+        if to.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            )
+            .into());
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(from, to)?;
+
+        for ext in SIDECAR_EXTENSIONS {
+            let sidecar = from.with_extension(ext);
+            if !sidecar.is_file() {
+                continue;
+            }
+
+            let moved = to.with_extension(ext);
+            if let Err(e) = fs::rename(&sidecar, &moved) {
+                tracing::warn!("Failed to move {}: {}", sidecar.display(), e);
+            } else if *ext == "cue" {
+                if let Err(e) = cue::retarget_cue(&moved, to) {
+                    tracing::warn!("Failed to update {}: {}", moved.display(), e);
+                }
+            }
+        }
+
+        // Songs cut from a mix are kept in a directory named after it
+        let split_dir = from.with_extension("");
+        if split_dir.is_dir() {
+            if let Err(e) = fs::rename(&split_dir, to.with_extension("")) {
+                tracing::warn!("Failed to move {}: {}", split_dir.display(), e);
+            }
+        }
+
+        if self.library.contains(track.id) {
+            self.library.relocate(track.id, to).await?;
+        } else {
+            self.library.record(track, to, None).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the directories a moved file left empty, from its old parent up to `root`
+///
+/// # Arguments
+/// * `root` - Organized directory, which is kept even when empty
+/// * `moved` - Old path of the moved file
+fn remove_empty_dirs(root: &Path, moved: &Path) {
+    for dir in moved.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) {
+            break;
+        }
+        // Fails on directories that still hold files, which ends the walk
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+        tracing::debug!("Removed empty directory {}", dir.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_emptied_directories_up_to_root() {
+        let root = tempfile::tempdir().unwrap();
+        let artist = root.path().join("Artist");
+        fs::create_dir_all(artist.join("Album")).unwrap();
+        fs::create_dir_all(artist.join("Other")).unwrap();
+
+        remove_empty_dirs(root.path(), &artist.join("Album").join("track.mp3"));
+        assert!(!artist.join("Album").exists());
+        assert!(artist.is_dir());
+
+        fs::remove_dir(artist.join("Other")).unwrap();
+        remove_empty_dirs(root.path(), &artist.join("track.mp3"));
+        assert!(!artist.exists());
+        assert!(root.path().is_dir());
+    }
+}
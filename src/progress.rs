@@ -0,0 +1,114 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+
+use crate::error::AppError;
+use crate::soundcloud::model::Track;
+
+const PROGRESS_TEMPLATE: &str = "{msg:30!} {bar:30} {bytes}/{total_bytes} ({bytes_per_sec})";
+
+/// Bars of every [`ProgressBars`], shared with the console log so its lines are printed
+/// above the bars instead of through them
+static BARS: LazyLock<MultiProgress> =
+    LazyLock::new(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr()));
+
+/// Runs `print` with the progress bars cleared, redrawing them afterwards, for writing
+/// to the terminal while downloads are shown
+pub fn suspend<R>(print: impl FnOnce() -> R) -> R {
+    BARS.suspend(print)
+}
+
+/// Receives the progress of track downloads, e.g. to draw progress bars or update a GUI
+///
+/// Events of concurrent downloads arrive interleaved, told apart by the track ID. A
+/// track that is retried starts again after its error.
+pub trait ProgressReporter: fmt::Debug + Send + Sync {
+    /// A track started downloading
+    fn on_track_start(&self, _track: &Track) {}
+
+    /// Audio of a track was received
+    ///
+    /// # Arguments
+    /// * `track_id` - ID of the track
+    /// * `received` - Bytes received so far
+    /// * `total` - Size of the audio, if the server announced it
+    fn on_bytes(&self, _track_id: u64, _received: u64, _total: Option<u64>) {}
+
+    /// A track was saved to `path`
+    fn on_track_done(&self, _track: &Track, _path: &Path) {}
+
+    /// A track failed or was skipped
+    fn on_error(&self, _track: &Track, _error: &AppError) {}
+}
+
+/// Reports nothing, used when no reporter is configured
+#[derive(Debug)]
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {}
+
+/// Draws a progress bar for each track being downloaded, hidden when stderr is not a
+/// terminal
+#[derive(Debug)]
+pub struct ProgressBars {
+    bars: MultiProgress,
+    tracks: Mutex<HashMap<u64, ProgressBar>>,
+}
+
+impl ProgressBars {
+    pub fn new() -> Self {
+        Self {
+            bars: BARS.clone(),
+            tracks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn finish(&self, track: &Track) {
+        if let Some(bar) = self.tracks.lock().unwrap().remove(&track.id) {
+            bar.finish_and_clear();
+            self.bars.remove(&bar);
+        }
+    }
+}
+
+impl Default for ProgressBars {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressReporter for ProgressBars {
+    fn on_track_start(&self, track: &Track) {
+        let bar = self.bars.add(
+            ProgressBar::no_length()
+                .with_style(
+                    ProgressStyle::with_template(PROGRESS_TEMPLATE)
+                        .unwrap_or_else(|_| ProgressStyle::default_bar()),
+                )
+                .with_message(track.title.clone()),
+        );
+        if let Some(old) = self.tracks.lock().unwrap().insert(track.id, bar) {
+            old.finish_and_clear();
+            self.bars.remove(&old);
+        }
+    }
+
+    fn on_bytes(&self, track_id: u64, received: u64, total: Option<u64>) {
+        if let Some(bar) = self.tracks.lock().unwrap().get(&track_id) {
+            if let Some(total) = total {
+                bar.set_length(total);
+            }
+            bar.set_position(received);
+        }
+    }
+
+    fn on_track_done(&self, track: &Track, _path: &Path) {
+        self.finish(track);
+    }
+
+    fn on_error(&self, track: &Track, _error: &AppError) {
+        self.finish(track);
+    }
+}
@@ -0,0 +1,572 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::config;
+use crate::error::Result;
+use crate::soundcloud::ResolvedStream;
+
+const QUEUE_FILE: &str = "queue.sqlite";
+/// Attempts before a job is given up on
+const MAX_ATTEMPTS: u32 = 4;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30 * 60);
+/// How often a process marks the jobs it is running as still alive
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Time without a heartbeat after which a running job is taken to belong to a process
+/// that was killed
+const STALE_AFTER: Duration = Duration::from_secs(3 * 60);
+/// How long a write waits for another process holding the database lock
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    track_id        INTEGER NOT NULL,
+    secret_token    TEXT,
+    output_dir      TEXT NOT NULL,
+    priority        INTEGER NOT NULL,
+    state           TEXT NOT NULL DEFAULT 'pending',
+    attempts        INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    last_error      TEXT,
+    created_at      INTEGER NOT NULL,
+    owner           TEXT,
+    heartbeat_at    INTEGER,
+    UNIQUE (track_id, output_dir)
+);
+CREATE INDEX IF NOT EXISTS jobs_ready ON jobs (state, priority DESC, next_attempt_at, id);
+CREATE TABLE IF NOT EXISTS streams (
+    track_id        INTEGER PRIMARY KEY,
+    transcoding_url TEXT NOT NULL,
+    url             TEXT NOT NULL,
+    expires_at      INTEGER NOT NULL
+);
+";
+
+/// How urgently a job should run, higher priorities are claimed first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Part of a bulk download such as likes or a playlist
+    Bulk = 0,
+    /// A single track requested on its own
+    Single = 10,
+}
+
+/// A queued track download
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub id: i64,
+    pub track_id: u64,
+    pub secret_token: Option<String>,
+    pub attempts: u32,
+}
+
+/// A queued job as shown by `queue list`
+#[derive(Clone, Debug)]
+pub struct JobStatus {
+    pub track_id: u64,
+    pub output_dir: PathBuf,
+    pub state: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+/// On-disk queue of track downloads that survives restarts
+///
+/// Jobs are claimed by priority, failed jobs are retried with exponential backoff.
+/// Several processes can share the queue: each marks the jobs it claimed as its own
+/// and keeps them alive with a heartbeat, so others only take over jobs of a process
+/// that stopped.
+#[derive(Debug)]
+pub struct JobQueue {
+    conn: Mutex<Connection>,
+    /// Marks the jobs claimed by this process
+    owner: String,
+    /// Stops the heartbeat thread when the queue is dropped
+    _heartbeat: Sender<()>,
+}
+
+impl JobQueue {
+    /// Opens the queue at the given path, or at the default location in the
+    /// application's data directory
+    ///
+    /// Jobs left running by a process that was killed are returned to the queue, those
+    /// of processes still running are left to them.
+    pub fn open(path: Option<PathBuf>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                let data_dir = config::project_dirs()?.data_dir().to_path_buf();
+                fs::create_dir_all(&data_dir)?;
+                data_dir.join(QUEUE_FILE)
+            }
+        };
+
+        let conn = connect(&path)?;
+        let owner = format!("{}-{:08x}", std::process::id(), rand::random::<u32>());
+
+        let (heartbeat, stop) = mpsc::channel();
+        let (heartbeat_path, heartbeat_owner) = (path.clone(), owner.clone());
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(HEARTBEAT_INTERVAL) {
+                if let Err(e) = beat(&heartbeat_path, &heartbeat_owner) {
+                    tracing::warn!("Failed to update the download queue: {}", e);
+                }
+            }
+        });
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            owner,
+            _heartbeat: heartbeat,
+        })
+    }
+
+    /// Adds a track to the queue, raising the priority of a job that is already queued
+    ///
+    /// A track that was given up on is queued again, one that was already downloaded
+    /// into the same directory is not.
+    ///
+    /// # Returns
+    /// Result containing whether the track is queued, `false` if its job is done
+    pub fn enqueue(
+        &self,
+        track_id: u64,
+        secret_token: Option<&str>,
+        output_dir: &Path,
+        priority: Priority,
+    ) -> Result<bool> {
+        self.insert(track_id, secret_token, output_dir, priority, false)
+    }
+
+    /// Adds a track to the queue like [`enqueue`](Self::enqueue), downloading it again
+    /// even if its job is done, e.g. to repair a broken file
+    pub fn requeue(
+        &self,
+        track_id: u64,
+        secret_token: Option<&str>,
+        output_dir: &Path,
+        priority: Priority,
+    ) -> Result<()> {
+        self.insert(track_id, secret_token, output_dir, priority, true)?;
+
+        Ok(())
+    }
+
+    fn insert(
+        &self,
+        track_id: u64,
+        secret_token: Option<&str>,
+        output_dir: &Path,
+        priority: Priority,
+        redo: bool,
+    ) -> Result<bool> {
+        let now = Utc::now().timestamp();
+
+        let changed = self.conn.lock().unwrap().execute(
+            "INSERT INTO jobs (track_id, secret_token, output_dir, priority, next_attempt_at, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+             ON CONFLICT (track_id, output_dir) DO UPDATE SET
+                 priority = MAX(priority, excluded.priority),
+                 secret_token = excluded.secret_token,
+                 state = CASE WHEN state = 'running' THEN state ELSE 'pending' END,
+                 attempts = CASE WHEN state IN ('done', 'failed') THEN 0 ELSE attempts END,
+                 next_attempt_at = CASE WHEN state = 'running' THEN next_attempt_at ELSE excluded.next_attempt_at END
+             WHERE state != 'done' OR ?6",
+            params![
+                track_id as i64,
+                secret_token,
+                output_dir.to_string_lossy(),
+                priority as i64,
+                now,
+                redo
+            ],
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    /// Claims the most urgent job that is ready to run, optionally only for one directory
+    /// or one track
+    ///
+    /// Jobs of processes that stopped sending heartbeats are claimed again.
+    pub fn claim(&self, output_dir: Option<&Path>, track_id: Option<u64>) -> Result<Option<Job>> {
+        let mut conn = self.conn.lock().unwrap();
+        let output_dir = output_dir.map(|dir| dir.to_string_lossy().into_owned());
+
+        // Taking the write lock up front keeps other processes from claiming the same job
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        reclaim_stale(&tx)?;
+
+        let job = tx
+            .query_row(
+                "SELECT id, track_id, secret_token, attempts FROM jobs
+                 WHERE state = 'pending' AND next_attempt_at <= ?1
+                   AND (?2 IS NULL OR output_dir = ?2)
+                   AND (?3 IS NULL OR track_id = ?3)
+                 ORDER BY priority DESC, next_attempt_at, id
+                 LIMIT 1",
+                params![
+                    Utc::now().timestamp(),
+                    output_dir,
+                    track_id.map(|id| id as i64)
+                ],
+                |row| {
+                    Ok(Job {
+                        id: row.get(0)?,
+                        track_id: row.get::<_, i64>(1)? as u64,
+                        secret_token: row.get(2)?,
+                        attempts: row.get(3)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        if let Some(job) = &job {
+            tx.execute(
+                "UPDATE jobs SET state = 'running', owner = ?2, heartbeat_at = ?3 WHERE id = ?1",
+                params![job.id, self.owner, Utc::now().timestamp()],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(job)
+    }
+
+    pub fn complete(&self, job: &Job) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET state = 'done', last_error = NULL, owner = NULL WHERE id = ?1",
+            params![job.id],
+        )?;
+        conn.execute(
+            "DELETE FROM streams WHERE track_id = ?1",
+            params![job.track_id as i64],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns a claimed job to the queue without counting an attempt, e.g. when the
+    /// run was cancelled
+    pub fn release(&self, job: &Job) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE jobs SET state = 'pending', owner = NULL WHERE id = ?1",
+            params![job.id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns every job this process claimed and has not finished to the queue, e.g.
+    /// when a run stops while other downloads are in progress
+    pub fn release_claimed(&self) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE jobs SET state = 'pending', owner = NULL
+             WHERE state = 'running' AND owner = ?1",
+            params![self.owner],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the stream a track was resolved to by an earlier attempt, if it has not
+    /// expired, so resuming does not resolve it again
+    pub fn stream(&self, track_id: u64) -> Result<Option<ResolvedStream>> {
+        let stream = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT transcoding_url, url, expires_at FROM streams WHERE track_id = ?1",
+                params![track_id as i64],
+                |row| {
+                    Ok(ResolvedStream {
+                        transcoding_url: row.get(0)?,
+                        url: row.get(1)?,
+                        expires_at: DateTime::from_timestamp(row.get(2)?, 0).unwrap_or_default(),
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(stream.filter(|stream| !stream.is_expired()))
+    }
+
+    /// Records the stream a track was resolved to, until its job is done
+    pub fn save_stream(&self, track_id: u64, stream: &ResolvedStream) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO streams (track_id, transcoding_url, url, expires_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                track_id as i64,
+                stream.transcoding_url,
+                stream.url,
+                stream.expires_at.timestamp()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt
+    ///
+    /// # Arguments
+    /// * `job` - The failed job
+    /// * `error` - Why it failed
+    /// * `retry` - Whether the failure is worth retrying
+    ///
+    /// # Returns
+    /// Result containing whether the job will be retried
+    pub fn fail(&self, job: &Job, error: &str, retry: bool) -> Result<bool> {
+        let attempts = job.attempts + 1;
+        let retry = retry && attempts < MAX_ATTEMPTS;
+        let delay = INITIAL_RETRY_DELAY
+            .saturating_mul(2u32.saturating_pow(attempts - 1))
+            .min(MAX_RETRY_DELAY);
+
+        self.conn.lock().unwrap().execute(
+            "UPDATE jobs SET state = ?2, attempts = ?3, last_error = ?4, next_attempt_at = ?5,
+                 owner = NULL
+             WHERE id = ?1",
+            params![
+                job.id,
+                if retry { "pending" } else { "failed" },
+                attempts,
+                error,
+                Utc::now().timestamp() + delay.as_secs() as i64
+            ],
+        )?;
+
+        Ok(retry)
+    }
+
+    /// Returns how long until the next pending job becomes ready, `None` if nothing is
+    /// pending, optionally only for one directory or one track
+    pub fn next_ready_in(
+        &self,
+        output_dir: Option<&Path>,
+        track_id: Option<u64>,
+    ) -> Result<Option<Duration>> {
+        let output_dir = output_dir.map(|dir| dir.to_string_lossy().into_owned());
+
+        let next: Option<i64> = self.conn.lock().unwrap().query_row(
+            "SELECT MIN(next_attempt_at) FROM jobs
+             WHERE state = 'pending' AND (?1 IS NULL OR output_dir = ?1)
+               AND (?2 IS NULL OR track_id = ?2)",
+            params![output_dir, track_id.map(|id| id as i64)],
+            |row| row.get(0),
+        )?;
+
+        Ok(next.map(|at| Duration::from_secs((at - Utc::now().timestamp()).max(0) as u64)))
+    }
+
+    /// Returns the directories that have pending jobs
+    pub fn pending_dirs(&self) -> Result<Vec<PathBuf>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT DISTINCT output_dir FROM jobs WHERE state = 'pending'")?;
+        let dirs = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|dir| dir.map(PathBuf::from))
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(dirs)
+    }
+
+    /// Lists jobs that are not done, most urgent first
+    pub fn list(&self) -> Result<Vec<JobStatus>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT track_id, output_dir, state, attempts, last_error FROM jobs
+             WHERE state != 'done'
+             ORDER BY state, priority DESC, next_attempt_at, id",
+        )?;
+        let jobs = stmt
+            .query_map([], |row| {
+                Ok(JobStatus {
+                    track_id: row.get::<_, i64>(0)? as u64,
+                    output_dir: PathBuf::from(row.get::<_, String>(1)?),
+                    state: row.get(2)?,
+                    attempts: row.get(3)?,
+                    last_error: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(jobs)
+    }
+
+    /// Removes finished jobs, and with `all` every job
+    ///
+    /// # Returns
+    /// Result containing the number of removed jobs
+    pub fn clear(&self, all: bool) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            if all {
+                "DELETE FROM jobs"
+            } else {
+                "DELETE FROM jobs WHERE state IN ('done', 'failed')"
+            },
+            [],
+        )?;
+        conn.execute(
+            "DELETE FROM streams WHERE track_id NOT IN (SELECT track_id FROM jobs)",
+            [],
+        )?;
+
+        Ok(removed)
+    }
+}
+
+/// Opens the queue database, creating or upgrading its tables
+fn connect(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.execute_batch(SCHEMA)?;
+
+    // Queues created before jobs had owners
+    let has_owner = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('jobs') WHERE name = 'owner'",
+        [],
+        |row| row.get::<_, i64>(0),
+    )? > 0;
+    if !has_owner {
+        conn.execute_batch(
+            "ALTER TABLE jobs ADD COLUMN owner TEXT;
+             ALTER TABLE jobs ADD COLUMN heartbeat_at INTEGER;",
+        )?;
+    }
+
+    reclaim_stale(&conn)?;
+
+    Ok(conn)
+}
+
+/// Returns running jobs whose process stopped sending heartbeats to the queue
+fn reclaim_stale(conn: &Connection) -> Result<()> {
+    let stale_before = Utc::now().timestamp() - STALE_AFTER.as_secs() as i64;
+    conn.execute(
+        "UPDATE jobs SET state = 'pending', owner = NULL
+         WHERE state = 'running' AND (heartbeat_at IS NULL OR heartbeat_at < ?1)",
+        params![stale_before],
+    )?;
+
+    Ok(())
+}
+
+/// Marks the jobs an owner is running as alive
+fn beat(path: &Path, owner: &str) -> Result<()> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.execute(
+        "UPDATE jobs SET heartbeat_at = ?2 WHERE state = 'running' AND owner = ?1",
+        params![owner, Utc::now().timestamp()],
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue() -> (tempfile::TempDir, JobQueue) {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = JobQueue::open(Some(dir.path().join(QUEUE_FILE))).unwrap();
+        (dir, queue)
+    }
+
+    #[test]
+    fn enqueue_skips_done_jobs() {
+        let (_dir, queue) = queue();
+        let out = Path::new("out");
+
+        assert!(queue.enqueue(1, None, out, Priority::Bulk).unwrap());
+        let job = queue.claim(Some(out), None).unwrap().unwrap();
+        queue.complete(&job).unwrap();
+
+        assert!(!queue.enqueue(1, None, out, Priority::Single).unwrap());
+        assert!(queue.claim(Some(out), None).unwrap().is_none());
+
+        queue.requeue(1, None, out, Priority::Bulk).unwrap();
+        assert_eq!(queue.claim(Some(out), None).unwrap().unwrap().track_id, 1);
+    }
+
+    #[test]
+    fn enqueue_retries_failed_jobs() {
+        let (_dir, queue) = queue();
+        let out = Path::new("out");
+
+        queue.enqueue(1, None, out, Priority::Bulk).unwrap();
+        let job = queue.claim(Some(out), None).unwrap().unwrap();
+        assert!(!queue.fail(&job, "gone", false).unwrap());
+
+        assert!(queue.enqueue(1, None, out, Priority::Bulk).unwrap());
+        let job = queue.claim(Some(out), None).unwrap().unwrap();
+        assert_eq!(job.attempts, 0);
+    }
+
+    #[test]
+    fn claims_only_the_given_track() {
+        let (_dir, queue) = queue();
+        let out = Path::new("out");
+
+        queue.enqueue(1, None, out, Priority::Single).unwrap();
+        queue.enqueue(2, None, out, Priority::Bulk).unwrap();
+
+        let job = queue.claim(Some(out), Some(2)).unwrap().unwrap();
+        assert_eq!(job.track_id, 2);
+        assert!(queue.claim(Some(out), Some(2)).unwrap().is_none());
+        assert_eq!(queue.next_ready_in(Some(out), Some(2)).unwrap(), None);
+        assert!(queue.next_ready_in(Some(out), None).unwrap().is_some());
+    }
+
+    #[test]
+    fn second_instance_leaves_running_jobs_alone() {
+        let (dir, first) = queue();
+        let out = Path::new("out");
+        first.enqueue(1, None, out, Priority::Bulk).unwrap();
+        first.enqueue(2, None, out, Priority::Bulk).unwrap();
+        let job = first.claim(Some(out), Some(1)).unwrap().unwrap();
+
+        let second = JobQueue::open(Some(dir.path().join(QUEUE_FILE))).unwrap();
+        assert_eq!(second.claim(Some(out), None).unwrap().unwrap().track_id, 2);
+        assert!(second.claim(Some(out), None).unwrap().is_none());
+
+        // A job whose process stopped sending heartbeats is taken over
+        first
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE jobs SET heartbeat_at = 0 WHERE id = ?1",
+                params![job.id],
+            )
+            .unwrap();
+        assert_eq!(second.claim(Some(out), None).unwrap().unwrap().track_id, 1);
+    }
+
+    #[test]
+    fn releases_only_own_jobs() {
+        let (dir, first) = queue();
+        let out = Path::new("out");
+        first.enqueue(1, None, out, Priority::Bulk).unwrap();
+        first.enqueue(2, None, out, Priority::Bulk).unwrap();
+        first.claim(Some(out), Some(1)).unwrap().unwrap();
+
+        let second = JobQueue::open(Some(dir.path().join(QUEUE_FILE))).unwrap();
+        second.claim(Some(out), Some(2)).unwrap().unwrap();
+        second.release_claimed().unwrap();
+
+        assert!(first.claim(Some(out), Some(1)).unwrap().is_none());
+        assert_eq!(first.claim(Some(out), None).unwrap().unwrap().track_id, 2);
+    }
+}
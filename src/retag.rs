@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::Path;
+
+use crate::downloader::Downloader;
+use crate::error::Result;
+use crate::matcher::{TrackMatcher, TrackSource};
+use crate::soundcloud::model::Track;
+use crate::util;
+
+/// Outcome counts of a `retag` run
+#[derive(Clone, Debug, Default)]
+pub struct RetagSummary {
+    pub total: usize,
+    pub retagged: usize,
+    /// Files that could not be matched to a track or cannot be tagged
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Downloader {
+    /// Refetches the metadata of every audio file in a directory and rewrites its tags
+    /// and artwork in place
+    ///
+    /// Files are matched to their track through the library, an `.info.json` sidecar
+    /// or a SoundCloud URL in their tags, files that match nothing are skipped.
+    ///
+    /// # Arguments
+    /// * `dir` - Directory to retag, including its subdirectories
+    ///
+    /// # Returns
+    /// Result containing the outcome counts
+    pub async fn retag(&self, dir: &Path) -> Result<RetagSummary> {
+        let files = util::audio_files(dir)?;
+        let matcher = TrackMatcher::new(&self.library);
+
+        let mut summary = RetagSummary {
+            total: files.len(),
+            ..RetagSummary::default()
+        };
+
+        for path in files {
+            let Some(source) = matcher.find(&path, self.ffmpeg.as_ref()).await else {
+                summary.skipped += 1;
+                tracing::warn!(
+                    status = "Skipped",
+                    "{} does not match any track",
+                    path.display()
+                );
+                continue;
+            };
+
+            match self.retag_file(&path, source).await {
+                Ok(Some(track)) => {
+                    summary.retagged += 1;
+                    tracing::info!(
+                        status = "Retagged",
+                        "{} from {}",
+                        path.display(),
+                        track.permalink_url
+                    );
+                }
+                Ok(None) => {
+                    summary.skipped += 1;
+                    tracing::warn!(
+                        status = "Skipped",
+                        "{} is in a format that cannot be tagged",
+                        path.display()
+                    );
+                }
+                Err(e) => {
+                    summary.failed += 1;
+                    tracing::error!(status = "Failed", "{}: {}", path.display(), e);
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Fetches the track of a file and rewrites its tags
+    ///
+    /// # Returns
+    /// Result containing the track, or `None` for files that cannot be tagged
+    async fn retag_file(&self, path: &Path, source: TrackSource) -> Result<Option<Track>> {
+        let ext = util::audio_ext(path).unwrap_or_default().to_string();
+        if ext != "mp3" && ext != "m4a" {
+            return Ok(None);
+        }
+
+        let track = self.fetch_source(source).await?;
+        let thumbnail = self.artwork(&track).await?;
+        let tags = self.tags(&track);
+
+        if ext == "mp3" {
+            let tag = match id3::Tag::read_from_path(path) {
+                Ok(tag) => tag,
+                Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => id3::Tag::new(),
+                Err(e) => return Err(e.into()),
+            };
+            self.write_id3(path, tag, thumbnail, &tags)?;
+        } else {
+            // FFmpeg cannot write to the file it reads, the tagged copy replaces it
+            let audio = fs::read(path)?;
+            let part = util::part_path(path);
+            let result = self
+                .require_ffmpeg("M4A tagging")?
+                .reformat_m4a(audio.into(), thumbnail, &tags, None, part.clone())
+                .await;
+            if let Err(e) = result {
+                let _ = fs::remove_file(&part);
+                return Err(e);
+            }
+            fs::rename(&part, path)?;
+        }
+
+        self.set_file_attributes(&track, path);
+        if !self.library.contains(track.id) {
+            self.library.record(&track, path, None).await?;
+        }
+        // Rewriting the tags changed the checksum
+        self.library
+            .set_file_info(track.id, self.file_info(path).await?)
+            .await?;
+
+        Ok(Some(track))
+    }
+}
@@ -0,0 +1,82 @@
+use crate::soundcloud::model::{Playlist, Track, User};
+use crate::util;
+
+/// Prints numbered track search results
+pub fn print_tracks(tracks: &[Track]) {
+    for (i, track) in tracks.iter().enumerate() {
+        println!(
+            "{:>3}. {} - {} [{}] {}",
+            i + 1,
+            track.user.username,
+            track.title,
+            util::format_duration(track.full_duration.max(track.duration)),
+            track.permalink_url
+        );
+    }
+}
+
+/// Prints numbered playlist search results
+pub fn print_playlists(playlists: &[Playlist]) {
+    for (i, playlist) in playlists.iter().enumerate() {
+        println!(
+            "{:>3}. {} ({} tracks) {}",
+            i + 1,
+            playlist.title,
+            playlist.track_count,
+            playlist.permalink_url
+        );
+    }
+}
+
+/// Prints numbered user search results
+pub fn print_users(users: &[User]) {
+    for (i, user) in users.iter().enumerate() {
+        println!(
+            "{:>3}. {} (https://soundcloud.com/{})",
+            i + 1,
+            user.username,
+            user.permalink
+        );
+    }
+}
+
+/// Picks which of `count` results to download, returning zero-based indices
+///
+/// Uses `--download-all` or the `--download` numbers when given, otherwise
+/// asks the user to pick results interactively.
+pub fn select(count: usize, download: &[usize], download_all: bool) -> Vec<usize> {
+    if count == 0 {
+        return Vec::new();
+    }
+
+    if download_all {
+        return (0..count).collect();
+    }
+
+    let numbers = if download.is_empty() {
+        util::read_line("Enter result numbers to download (e.g. 1,3), or leave empty to skip")
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| match s.parse::<usize>() {
+                Ok(n) => Some(n),
+                Err(_) => {
+                    tracing::warn!("Ignoring invalid result number: {}", s);
+                    None
+                }
+            })
+            .collect()
+    } else {
+        download.to_vec()
+    };
+
+    let mut selected = Vec::new();
+    for n in numbers {
+        if n == 0 || n > count {
+            tracing::warn!("Ignoring result number {} (1-{} available)", n, count);
+        } else if !selected.contains(&(n - 1)) {
+            selected.push(n - 1);
+        }
+    }
+
+    selected
+}
@@ -0,0 +1,328 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::error::Result;
+use crate::lock;
+use crate::soundcloud::model::Playlist;
+use crate::soundcloud::SoundcloudClient;
+
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// Track list of a playlist at one point in time
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub taken_at: DateTime<Utc>,
+    pub playlist_id: u64,
+    pub title: String,
+    pub permalink_url: String,
+    pub tracks: Vec<SnapshotTrack>,
+}
+
+/// A track of a playlist snapshot, with enough metadata to identify it once it is
+/// deleted from SoundCloud
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SnapshotTrack {
+    pub id: u64,
+    /// `None` for tracks SoundCloud no longer returns metadata for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permalink_url: Option<String>,
+}
+
+impl SnapshotTrack {
+    /// Returns a readable name for the track, its ID if its title is unknown
+    pub fn name(&self) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => format!("track {}", self.id),
+        }
+    }
+}
+
+/// Changes to a playlist's track list between two snapshots
+#[derive(Debug, Default)]
+pub struct SnapshotDiff {
+    pub added: Vec<SnapshotTrack>,
+    pub removed: Vec<SnapshotTrack>,
+    /// Tracks kept in the playlist that moved relative to the others, with their old
+    /// and new one-based positions
+    pub reordered: Vec<(SnapshotTrack, usize, usize)>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.reordered.is_empty()
+    }
+
+    /// Prints the changes, one track per line
+    pub fn print(&self) {
+        if self.is_empty() {
+            println!("No changes");
+            return;
+        }
+
+        for track in &self.removed {
+            println!("- {}", track.name());
+        }
+        for track in &self.added {
+            println!("+ {}", track.name());
+        }
+        for (track, from, to) in &self.reordered {
+            println!("~ {} (#{} -> #{})", track.name(), from, to);
+        }
+        println!(
+            "\n{} added, {} removed, {} reordered",
+            self.added.len(),
+            self.removed.len(),
+            self.reordered.len()
+        );
+    }
+}
+
+impl Snapshot {
+    /// Takes a snapshot of a playlist's current track list
+    ///
+    /// Tracks the playlist only lists as stubs are looked up in bulk for their titles.
+    ///
+    /// # Arguments
+    /// * `client` - Client to look up tracks with
+    /// * `playlist` - The playlist as fetched from SoundCloud
+    ///
+    /// # Returns
+    /// Result containing the snapshot
+    pub async fn take(client: &SoundcloudClient, playlist: &Playlist) -> Result<Self> {
+        let stub_ids = playlist
+            .tracks
+            .iter()
+            .filter(|track| track.title.is_none())
+            .map(|track| track.id)
+            .collect::<Vec<_>>();
+
+        let mut hydrated = if stub_ids.is_empty() {
+            HashMap::new()
+        } else {
+            tracing::info!("Fetching metadata for {} tracks", stub_ids.len());
+            client
+                .fetch_tracks(&stub_ids, Some(playlist))
+                .await?
+                .into_iter()
+                .map(|track| (track.id, track))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let tracks = playlist
+            .tracks
+            .iter()
+            .map(|track| match hydrated.remove(&track.id) {
+                Some(full) => SnapshotTrack {
+                    id: full.id,
+                    title: Some(full.title),
+                    artist: Some(full.user.username),
+                    permalink_url: Some(full.permalink_url),
+                },
+                None => SnapshotTrack {
+                    id: track.id,
+                    title: track.title.clone(),
+                    artist: track.user.as_ref().map(|user| user.username.clone()),
+                    permalink_url: track.permalink_url.clone(),
+                },
+            })
+            .collect();
+
+        Ok(Self {
+            taken_at: Utc::now(),
+            playlist_id: playlist.id,
+            title: playlist.title.clone(),
+            permalink_url: playlist.permalink_url.clone(),
+            tracks,
+        })
+    }
+
+    /// Compares the track list with that of an earlier snapshot
+    ///
+    /// A track only counts as reordered if it moved relative to the other tracks both
+    /// snapshots share, so adding or removing a track doesn't report every track after it.
+    /// The tracks still in their old order are the longest run of shared tracks whose old
+    /// positions increase, the others are the ones that moved.
+    ///
+    /// # Arguments
+    /// * `previous` - The earlier snapshot
+    ///
+    /// # Returns
+    /// The tracks added, removed and reordered since
+    pub fn diff(&self, previous: &Snapshot) -> SnapshotDiff {
+        let old_ids = previous.tracks.iter().map(|t| t.id).collect::<HashSet<_>>();
+        let new_ids = self.tracks.iter().map(|t| t.id).collect::<HashSet<_>>();
+
+        let added = self
+            .tracks
+            .iter()
+            .filter(|t| !old_ids.contains(&t.id))
+            .cloned()
+            .collect();
+        let removed = previous
+            .tracks
+            .iter()
+            .filter(|t| !new_ids.contains(&t.id))
+            .cloned()
+            .collect();
+
+        let old_indices = previous
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.id, i))
+            .collect::<HashMap<_, _>>();
+
+        let kept = self
+            .tracks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| Some((i, t, *old_indices.get(&t.id)?)))
+            .collect::<Vec<_>>();
+        let in_order = longest_increasing(&kept.iter().map(|(_, _, old)| *old).collect::<Vec<_>>());
+
+        let reordered = kept
+            .into_iter()
+            .enumerate()
+            .filter(|(k, _)| !in_order.contains(k))
+            .map(|(_, (i, t, old))| (t.clone(), old + 1, i + 1))
+            .collect();
+
+        SnapshotDiff {
+            added,
+            removed,
+            reordered,
+        }
+    }
+}
+
+/// Returns the indices of a longest strictly increasing subsequence of `values`
+fn longest_increasing(values: &[usize]) -> HashSet<usize> {
+    // `tails[len]` is the index of the smallest value ending an increasing run of
+    // `len + 1` values, `previous` links each value to the one before it in its run
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; values.len()];
+
+    for (i, value) in values.iter().enumerate() {
+        let len = tails.partition_point(|&tail| values[tail] < *value);
+        previous[i] = len.checked_sub(1).map(|before| tails[before]);
+        match tails.get_mut(len) {
+            Some(tail) => *tail = i,
+            None => tails.push(i),
+        }
+    }
+
+    std::iter::successors(tails.last().copied(), |&i| previous[i]).collect()
+}
+
+/// Snapshots of one playlist, oldest first, kept in the application's data directory
+pub struct SnapshotStore {
+    path: PathBuf,
+}
+
+impl SnapshotStore {
+    /// Opens the snapshots of a playlist
+    pub fn open(playlist_id: u64) -> Result<Self> {
+        let dir = config::project_dirs()?.data_dir().join(SNAPSHOTS_DIR);
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            path: dir.join(format!("{}.json", playlist_id)),
+        })
+    }
+
+    /// Returns every snapshot taken, oldest first
+    pub fn load(&self) -> Result<Vec<Snapshot>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(&self.path)?)?)
+    }
+
+    /// Returns the most recent snapshot, `None` if none was taken
+    pub fn latest(&self) -> Result<Option<Snapshot>> {
+        Ok(self.load()?.pop())
+    }
+
+    /// Appends a snapshot, writing to a temporary file first so a crash never leaves
+    /// the history truncated
+    pub async fn push(&self, snapshot: Snapshot) -> Result<()> {
+        let _lock = lock::lock(&self.path).await?;
+        let mut snapshots = self.load()?;
+        snapshots.push(snapshot);
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&snapshots)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(ids: &[u64]) -> Snapshot {
+        Snapshot {
+            taken_at: Utc::now(),
+            playlist_id: 1,
+            title: "Playlist".to_string(),
+            permalink_url: "https://soundcloud.com/user/sets/playlist".to_string(),
+            tracks: ids
+                .iter()
+                .map(|&id| SnapshotTrack {
+                    id,
+                    title: None,
+                    artist: None,
+                    permalink_url: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn reordered(before: &[u64], after: &[u64]) -> Vec<(u64, usize, usize)> {
+        snapshot(after)
+            .diff(&snapshot(before))
+            .reordered
+            .into_iter()
+            .map(|(track, from, to)| (track.id, from, to))
+            .collect()
+    }
+
+    #[test]
+    fn moving_one_track_reports_only_that_track() {
+        assert_eq!(reordered(&[1, 2, 3, 4], &[2, 3, 4, 1]), [(1, 1, 4)]);
+        assert_eq!(reordered(&[1, 2, 3, 4], &[4, 1, 2, 3]), [(4, 4, 1)]);
+    }
+
+    #[test]
+    fn adding_and_removing_tracks_reorders_nothing() {
+        let diff = snapshot(&[5, 1, 3, 4]).diff(&snapshot(&[1, 2, 3, 4]));
+
+        assert!(diff.reordered.is_empty());
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed.len(), 1);
+    }
+
+    #[test]
+    fn swapping_tracks_reports_one_of_them() {
+        assert_eq!(reordered(&[1, 2, 3], &[1, 3, 2]).len(), 1);
+    }
+
+    #[test]
+    fn longest_increasing_finds_tracks_in_order() {
+        assert_eq!(longest_increasing(&[]), HashSet::new());
+        assert_eq!(longest_increasing(&[1, 2, 3, 0]), HashSet::from([0, 1, 2]));
+        assert_eq!(longest_increasing(&[3, 0, 1, 2]), HashSet::from([1, 2, 3]));
+    }
+}
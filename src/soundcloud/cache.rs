@@ -0,0 +1,372 @@
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config;
+use crate::error::Result;
+use crate::util;
+
+const CACHE_DIR: &str = "metadata";
+const URLS_DIR: &str = "urls";
+const FILES_DIR: &str = "files";
+
+/// Downloaded files beyond this total size are evicted, least recently used first
+const MAX_FILES_SIZE: u64 = 512 * 1024 * 1024;
+/// Downloaded files not used for this long are evicted
+const MAX_FILE_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Deserialize, Serialize)]
+struct CachedObject {
+    fetched_at: DateTime<Utc>,
+    data: Value,
+}
+
+/// Where a resolved URL points to
+#[derive(Deserialize, Serialize)]
+struct CachedUrl {
+    kind: String,
+    id: u64,
+    #[serde(default)]
+    secret_token: Option<String>,
+}
+
+/// Validators a file was served with, sent back to revalidate the cached copy
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// What is stored along with a cached file, to revalidate and verify it
+#[derive(Deserialize, Serialize)]
+struct CachedFileInfo {
+    #[serde(flatten)]
+    validators: Validators,
+    size: u64,
+    /// Lowercase hex SHA-256 of the file, checked before the file is used
+    sha256: String,
+}
+
+/// A downloaded file kept to answer conditional requests
+pub struct CachedFile {
+    pub validators: Validators,
+    pub data: Bytes,
+}
+
+/// On-disk cache of track, playlist and user JSON returned by the API, keyed by ID and
+/// the secret token the object was requested with
+///
+/// Objects older than the TTL are fetched again, but are still used when SoundCloud
+/// cannot be reached. Downloaded artwork is kept along with its validators, so it is
+/// only transferred again when it changed, up to a total size and for as long as it is
+/// used.
+#[derive(Debug)]
+pub struct MetadataCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl MetadataCache {
+    /// Opens the cache at the given directory, or at the default location in the
+    /// application's cache directory
+    ///
+    /// # Arguments
+    /// * `dir` - Cache directory, created on the first write
+    /// * `ttl` - How long cached objects are used without asking the API
+    pub fn open(dir: Option<PathBuf>, ttl: Duration) -> Result<Self> {
+        let dir = match dir {
+            Some(dir) => dir,
+            None => config::project_dirs()?.cache_dir().join(CACHE_DIR),
+        };
+
+        Ok(Self { dir, ttl })
+    }
+
+    /// Returns a cached object
+    ///
+    /// # Arguments
+    /// * `kind` - SoundCloud `kind` of the object, e.g. `track`
+    /// * `id` - ID of the object
+    /// * `secret_token` - Secret token of a private object, objects cached with another
+    ///   token or without one are not returned
+    /// * `stale` - Also return objects older than the TTL
+    pub fn get(
+        &self,
+        kind: &str,
+        id: u64,
+        secret_token: Option<&str>,
+        stale: bool,
+    ) -> Option<Value> {
+        let cached = read::<CachedObject>(&self.object_path(kind, id, secret_token))?;
+
+        let age = Utc::now().signed_duration_since(cached.fetched_at);
+        let fresh = age.to_std().is_ok_and(|age| age < self.ttl);
+
+        (fresh || stale).then_some(cached.data)
+    }
+
+    /// Caches an object returned by the API, keyed by its `kind` and `id` and the secret
+    /// token it was requested with
+    pub fn put(&self, data: &Value, secret_token: Option<&str>) {
+        let (Some(kind), Some(id)) = (data["kind"].as_str(), data["id"].as_u64()) else {
+            return;
+        };
+
+        let cached = CachedObject {
+            fetched_at: Utc::now(),
+            data: data.clone(),
+        };
+        write(&self.object_path(kind, id, secret_token), &cached);
+    }
+
+    /// Returns the cached object a URL was resolved to
+    pub fn get_url(&self, url: &str, stale: bool) -> Option<Value> {
+        let cached = read::<CachedUrl>(&self.url_path(url))?;
+        self.get(
+            &cached.kind,
+            cached.id,
+            cached.secret_token.as_deref(),
+            stale,
+        )
+    }
+
+    /// Caches the object a URL was resolved to
+    ///
+    /// The object is keyed by its own secret token, as the URL of a private object is
+    /// what grants access to it.
+    pub fn put_url(&self, url: &str, data: &Value) {
+        let (Some(kind), Some(id)) = (data["kind"].as_str(), data["id"].as_u64()) else {
+            return;
+        };
+        let secret_token = data["secret_token"].as_str();
+
+        self.put(data, secret_token);
+        write(
+            &self.url_path(url),
+            &CachedUrl {
+                kind: kind.to_string(),
+                id,
+                secret_token: secret_token.map(str::to_string),
+            },
+        );
+    }
+
+    /// Returns a cached file download along with its validators
+    ///
+    /// A file that no longer matches the checksum it was cached with is removed
+    /// instead of returned.
+    pub fn get_file(&self, url: &str) -> Option<CachedFile> {
+        let path = self.file_path(url);
+        let info = read::<CachedFileInfo>(&path.with_extension("json"))?;
+        let data = fs::read(&path).ok()?;
+
+        if data.len() as u64 != info.size || util::sha256_hex(&data) != info.sha256 {
+            tracing::warn!("Cached copy of {} is corrupt, downloading it again", url);
+            remove_file(&path);
+            return None;
+        }
+
+        // Recently used files are the last to be evicted
+        let _ = File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+
+        Some(CachedFile {
+            validators: info.validators,
+            data: data.into(),
+        })
+    }
+
+    /// Caches a file download, files served without validators are not cached
+    pub fn put_file(&self, url: &str, validators: &Validators, data: &[u8]) {
+        if validators.is_empty() {
+            return;
+        }
+
+        let path = self.file_path(url);
+        let result =
+            fs::create_dir_all(self.dir.join(FILES_DIR)).and_then(|_| fs::write(&path, data));
+        match result {
+            Ok(()) => {
+                let info = CachedFileInfo {
+                    validators: validators.clone(),
+                    size: data.len() as u64,
+                    sha256: util::sha256_hex(data),
+                };
+                write(&path.with_extension("json"), &info);
+            }
+            Err(e) => tracing::debug!("Could not write {} to the cache: {}", path.display(), e),
+        }
+
+        self.evict_files();
+    }
+
+    /// Removes cached files not used for [`MAX_FILE_AGE`], then the least recently used
+    /// ones until they take up no more than [`MAX_FILES_SIZE`]
+    fn evict_files(&self) {
+        let Ok(entries) = fs::read_dir(self.dir.join(FILES_DIR)) else {
+            return;
+        };
+
+        let now = SystemTime::now();
+        let mut files = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "bin"))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((entry.path(), metadata.len(), metadata.modified().ok()?))
+            })
+            .collect::<Vec<_>>();
+        files.sort_by_key(|(_, _, used)| *used);
+
+        let mut total = files.iter().map(|(_, size, _)| size).sum::<u64>();
+        for (path, size, used) in files {
+            let stale = now.duration_since(used).is_ok_and(|age| age > MAX_FILE_AGE);
+            if !stale && total <= MAX_FILES_SIZE {
+                break;
+            }
+            tracing::debug!("Evicting {} from the cache", path.display());
+            remove_file(&path);
+            total -= size;
+        }
+    }
+
+    /// Removes every cached object and file
+    ///
+    /// # Returns
+    /// Result containing the number of removed objects and files
+    pub fn clear(&self) -> Result<usize> {
+        if !self.dir.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.is_dir() && path.file_name().is_some_and(|name| name != URLS_DIR) {
+                removed += fs::read_dir(&path)?
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+                    .count();
+            }
+        }
+        fs::remove_dir_all(&self.dir)?;
+
+        Ok(removed)
+    }
+
+    fn object_path(&self, kind: &str, id: u64, secret_token: Option<&str>) -> PathBuf {
+        let name = match secret_token {
+            Some(token) => format!("{}-{}.json", id, util::sha256_hex(token.as_bytes())),
+            None => format!("{}.json", id),
+        };
+        self.dir.join(util::sanitize(kind)).join(name)
+    }
+
+    fn file_path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(FILES_DIR)
+            .join(format!("{}.bin", util::sha256_hex(url.as_bytes())))
+    }
+
+    fn url_path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(URLS_DIR)
+            .join(format!("{}.json", util::sha256_hex(url.as_bytes())))
+    }
+}
+
+/// Removes a cached file along with its validators
+fn remove_file(path: &Path) {
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(path.with_extension("json"));
+}
+
+fn read<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    let contents = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Writes a cache file, a cache that cannot be written only costs requests
+fn write<T: Serialize>(path: &Path, value: &T) {
+    let result = (|| -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let part = path.with_extension("json.part");
+        fs::write(&part, serde_json::to_vec(value)?)?;
+        fs::rename(&part, path)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        tracing::debug!("Could not write {} to the cache: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://i1.sndcdn.com/artworks-000-original.jpg";
+
+    fn cache() -> (tempfile::TempDir, MetadataCache) {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = MetadataCache::open(Some(dir.path().to_path_buf()), Duration::ZERO).unwrap();
+        (dir, cache)
+    }
+
+    fn validators() -> Validators {
+        Validators {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+        }
+    }
+
+    #[test]
+    fn returns_cached_files_with_their_validators() {
+        let (_dir, cache) = cache();
+        cache.put_file(URL, &validators(), b"artwork");
+
+        let cached = cache.get_file(URL).unwrap();
+        assert_eq!(&cached.data[..], b"artwork");
+        assert_eq!(cached.validators.etag.as_deref(), Some("\"abc\""));
+    }
+
+    #[test]
+    fn removes_corrupt_files() {
+        let (_dir, cache) = cache();
+        cache.put_file(URL, &validators(), b"artwork");
+        fs::write(cache.file_path(URL), b"artwurk").unwrap();
+
+        assert!(cache.get_file(URL).is_none());
+        assert!(!cache.file_path(URL).exists());
+    }
+
+    #[test]
+    fn evicts_files_unused_for_too_long() {
+        let (_dir, cache) = cache();
+        cache.put_file(URL, &validators(), b"artwork");
+        File::options()
+            .write(true)
+            .open(cache.file_path(URL))
+            .unwrap()
+            .set_modified(SystemTime::now() - MAX_FILE_AGE - Duration::from_secs(60))
+            .unwrap();
+
+        cache.put_file("https://i1.sndcdn.com/other.jpg", &validators(), b"other");
+        assert!(cache.get_file(URL).is_none());
+        assert!(cache.get_file("https://i1.sndcdn.com/other.jpg").is_some());
+    }
+}
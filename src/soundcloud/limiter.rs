@@ -0,0 +1,88 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How long requests must succeed without being rate limited before
+/// another concurrent download slot is handed back
+const RAMP_UP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Concurrency limiter that shrinks when SoundCloud starts rate limiting
+/// and slowly grows back to its maximum after a period of success
+#[derive(Debug)]
+pub struct AdaptiveLimiter {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+    /// What is limited, for log messages
+    name: &'static str,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    current: usize,
+    last_change: Instant,
+}
+
+impl AdaptiveLimiter {
+    pub fn new(max: usize, name: &'static str) -> Self {
+        let max = max.max(1);
+
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            max,
+            name,
+            state: Mutex::new(LimiterState {
+                current: max,
+                last_change: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits for a free slot, which is held until the returned permit is dropped
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("limiter semaphore is never closed")
+    }
+
+    /// Removes one slot, down to a minimum of one
+    pub fn on_rate_limited(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.last_change = Instant::now();
+
+        if state.current <= 1 {
+            return;
+        }
+
+        state.current -= 1;
+        tracing::warn!(
+            "Rate limited, reducing concurrent {} to {}",
+            self.name,
+            state.current
+        );
+
+        // Slots may all be in use, so take one as soon as it is released
+        let semaphore = self.semaphore.clone();
+        tokio::spawn(async move {
+            if let Ok(permit) = semaphore.acquire_owned().await {
+                permit.forget();
+            }
+        });
+    }
+
+    /// Hands back a slot if nothing has been rate limited for a while
+    pub fn on_success(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.current >= self.max || state.last_change.elapsed() < RAMP_UP_INTERVAL {
+            return;
+        }
+
+        state.current += 1;
+        state.last_change = Instant::now();
+        self.semaphore.add_permits(1);
+        tracing::info!("Increasing concurrent {} to {}", self.name, state.current);
+    }
+}
@@ -1,13 +1,118 @@
+mod cache;
+mod limiter;
 pub mod model;
 mod rest;
+mod selector;
+mod stats;
+mod tokens;
 
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use cache::{MetadataCache, Validators};
+pub use limiter::AdaptiveLimiter;
+pub use selector::{TranscodingSelector, DEFAULT_TRANSCODING_ORDER};
+pub use stats::RateLimitStats;
+pub use tokens::TokenPool;
+
+/// Streams expiring within this time are resolved again before downloading
+const STREAM_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Client for the SoundCloud API and the CDN serving its media
 #[derive(Debug, Clone)]
 pub struct SoundcloudClient {
     http_client: reqwest::Client,
     oauth: String,
+    /// Endpoint paths are joined onto this, ends with `/`
+    api_base: reqwest::Url,
+    /// Calls to api-v2, which take a slot of their own limiter per request
+    api: RequestPolicy,
+    /// Files fetched from the CDN and other hosts, sent without credentials
+    media: RequestPolicy,
+    cache: Option<Arc<MetadataCache>>,
+    http_trace: HttpTrace,
+    rate_limits: Arc<RateLimitStats>,
+    /// Tokens API calls rotate between when rate limited, `None` unless configured
+    tokens: Option<Arc<TokenPool>>,
+    /// `client_id`s API calls rotate between when rate limited, `None` unless configured
+    client_ids: Option<Arc<TokenPool>>,
+    /// Identifies the app to the API, set when configured or looked up on first use
+    /// by calls made without a token
+    client_id: Arc<tokio::sync::OnceCell<String>>,
+}
+
+/// What is logged about each HTTP request, at debug level
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HttpTrace {
+    #[default]
+    Off,
+    /// Method, URL, status and timing
+    Requests,
+    /// Also the start of API response bodies
+    Bodies,
 }
 
+/// How requests to one kind of host are limited and retried
+#[derive(Debug, Clone)]
+struct RequestPolicy {
+    /// Shrinks while the host rate limits requests
+    limiter: Arc<AdaptiveLimiter>,
+    /// Whether every request waits for a slot, instead of the callers holding one
+    per_request: bool,
+    max_retries: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+    /// Statuses retried besides 429 Too Many Requests
+    retry_statuses: &'static [StatusCode],
+}
+
+/// Builder for [`SoundcloudClient`] with configurable network behaviour
+pub struct SoundcloudClientBuilder {
+    oauth: String,
+    timeout: Duration,
+    max_retries: u32,
+    max_concurrency: usize,
+    max_api_concurrency: usize,
+    api_base: reqwest::Url,
+    cache: Option<Arc<MetadataCache>>,
+    http_trace: HttpTrace,
+    rotation_tokens: Vec<String>,
+    client_id: Option<String>,
+    rotation_client_ids: Vec<String>,
+}
+
+#[derive(Clone)]
 pub struct DownloadedFile {
     pub data: bytes::Bytes,
     pub file_ext: String,
 }
+
+impl DownloadedFile {
+    /// Whether the file is an HLS playlist with encrypted segments, which FFmpeg cannot
+    /// remux without the key
+    pub fn is_encrypted_playlist(&self) -> bool {
+        String::from_utf8_lossy(&self.data).lines().any(|line| {
+            (line.starts_with("#EXT-X-KEY:") || line.starts_with("#EXT-X-SESSION-KEY:"))
+                && !line.contains("METHOD=NONE")
+        })
+    }
+}
+
+/// Media URL a transcoding was resolved to, which the CDN only accepts until its
+/// signature expires
+#[derive(Clone, Debug)]
+pub struct ResolvedStream {
+    /// API URL of the transcoding, identifying which one was chosen
+    pub transcoding_url: String,
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ResolvedStream {
+    /// Whether the URL expired or is about to
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now() + STREAM_EXPIRY_MARGIN
+    }
+}
@@ -1,8 +1,20 @@
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{LazyLock, Mutex};
+
+/// Unknown fields already logged by [`log_unknown_fields`], as `kind.field`
+static LOGGED_FIELDS: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Like {
     pub track: Track,
+    /// When the track was liked
+    pub created_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -10,20 +22,108 @@ pub struct Playlist {
     pub id: u64,
     pub permalink: String,
     pub permalink_url: String,
+    #[serde(default, deserialize_with = "lenient")]
     pub title: String,
+    #[serde(default, deserialize_with = "lenient_vec")]
     pub tracks: Vec<PlaylistTrack>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub track_count: u64,
+    /// Secret token of a private playlist shared by link
+    #[serde(default, deserialize_with = "lenient")]
+    pub secret_token: Option<String>,
+    /// Fields this version does not know about, kept for downstream consumers
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A playlist liked by a user
+#[derive(Clone, Debug, Deserialize)]
+pub struct PlaylistLike {
+    /// `None` for liked system playlists such as stations, which have a different shape
+    pub playlist: Option<Playlist>,
+    /// When the playlist was liked
+    #[serde(default, deserialize_with = "lenient")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// A comment on a track
+#[derive(Clone, Debug, Deserialize)]
+pub struct Comment {
+    pub body: String,
+    /// Position in the track the comment was left at, in milliseconds
+    pub timestamp: Option<u64>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub user: User,
+}
+
+/// A ranked track in a `/charts` listing
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChartEntry {
+    pub track: Track,
+}
+
+/// An entry of the activity stream or a user's reposts, either a track or a playlist
+#[derive(Clone, Debug, Deserialize)]
+pub struct StreamItem {
+    pub track: Option<Track>,
+    pub playlist: Option<Playlist>,
+    /// When the item was posted or reposted
+    pub created_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct PlaylistTrack {
     pub id: u64,
 
+    #[serde(default, deserialize_with = "lenient")]
     pub artwork_url: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
     pub permalink: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
     pub permalink_url: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
     pub title: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
     pub media: Option<Media>,
+    #[serde(default, deserialize_with = "lenient")]
     pub user: Option<User>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub policy: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub monetization_model: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub duration: Option<u64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub full_duration: Option<u64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub secret_token: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub display_date: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub license: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub waveform_url: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub description: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub genre: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub label_name: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub release_date: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub tag_list: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub playback_count: Option<u64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub likes_count: Option<u64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub downloadable: Option<bool>,
+    /// Fields this version does not know about, kept for downstream consumers
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl PlaylistTrack {
@@ -36,7 +136,24 @@ impl PlaylistTrack {
             title,
             media,
             user,
-            ..
+            policy,
+            monetization_model,
+            duration,
+            full_duration,
+            secret_token,
+            created_at,
+            display_date,
+            license,
+            waveform_url,
+            description,
+            genre,
+            label_name,
+            release_date,
+            tag_list,
+            playback_count,
+            likes_count,
+            downloadable,
+            extra,
         } = self;
 
         let media = media?;
@@ -50,6 +167,24 @@ impl PlaylistTrack {
             title: title?,
             media,
             user,
+            policy,
+            monetization_model,
+            duration: duration.unwrap_or_default(),
+            full_duration: full_duration.unwrap_or_default(),
+            secret_token,
+            created_at,
+            display_date,
+            license,
+            waveform_url,
+            description,
+            genre,
+            label_name,
+            release_date,
+            tag_list,
+            playback_count,
+            likes_count,
+            downloadable: downloadable.unwrap_or_default(),
+            extra,
         })
     }
 }
@@ -57,16 +192,114 @@ impl PlaylistTrack {
 #[derive(Clone, Debug, Deserialize)]
 pub struct Track {
     pub id: u64,
+    #[serde(default, deserialize_with = "lenient")]
     pub artwork_url: Option<String>,
     pub permalink: String,
     pub permalink_url: String,
+    #[serde(default, deserialize_with = "lenient")]
     pub title: String,
+    #[serde(default, deserialize_with = "lenient")]
     pub media: Media,
     pub user: User,
+    #[serde(default, deserialize_with = "lenient")]
+    pub policy: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub monetization_model: Option<String>,
+    /// Duration of the streamable audio in milliseconds
+    #[serde(default, deserialize_with = "lenient")]
+    pub duration: u64,
+    /// Duration of the full track in milliseconds
+    #[serde(default, deserialize_with = "lenient")]
+    pub full_duration: u64,
+    /// Secret token of a private track shared by link
+    #[serde(default, deserialize_with = "lenient")]
+    pub secret_token: Option<String>,
+    /// When the track was uploaded
+    #[serde(default, deserialize_with = "lenient")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the track is shown as released, which differs from `created_at` for
+    /// scheduled and back-dated releases
+    #[serde(default, deserialize_with = "lenient")]
+    pub display_date: Option<DateTime<Utc>>,
+    /// License code such as `all-rights-reserved` or `cc-by-sa`
+    #[serde(default, deserialize_with = "lenient")]
+    pub license: Option<String>,
+    /// Waveform peaks as JSON or a PNG rendering, depending on the API
+    #[serde(default, deserialize_with = "lenient")]
+    pub waveform_url: Option<String>,
+    /// Free text description, which for mixes often holds a tracklist
+    #[serde(default, deserialize_with = "lenient")]
+    pub description: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub genre: Option<String>,
+    /// Record label as entered by the uploader
+    #[serde(default, deserialize_with = "lenient")]
+    pub label_name: Option<String>,
+    /// Release date set by the uploader in the track's metadata
+    #[serde(default, deserialize_with = "lenient")]
+    pub release_date: Option<DateTime<Utc>>,
+    /// Space separated tags, with tags containing spaces in double quotes
+    #[serde(default, deserialize_with = "lenient")]
+    pub tag_list: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub playback_count: Option<u64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub likes_count: Option<u64>,
+    /// Whether the uploader offers the original file for download
+    #[serde(default, deserialize_with = "lenient")]
+    pub downloadable: bool,
+    /// Fields this version does not know about, kept for downstream consumers
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+impl Track {
+    /// Release date of the track, preferring the date set by the uploader, then the
+    /// displayed date and finally the upload time
+    pub fn release_date(&self) -> Option<DateTime<Utc>> {
+        self.release_date.or(self.display_date).or(self.created_at)
+    }
+
+    /// Tags of the track, without the quotes around tags containing spaces
+    pub fn tags(&self) -> Vec<String> {
+        let Some(tag_list) = &self.tag_list else {
+            return Vec::new();
+        };
+
+        tag_list
+            .split('"')
+            .enumerate()
+            .flat_map(|(i, part)| match i % 2 {
+                // Every other part is inside quotes
+                1 => vec![part.trim()],
+                _ => part.split_whitespace().collect(),
+            })
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Whether SoundCloud blocks this track in the requesting region
+    pub fn is_geo_blocked(&self) -> bool {
+        self.policy.as_deref() == Some("BLOCK")
+    }
+
+    /// Whether this track is part of the Go+ premium catalogue
+    pub fn is_go_plus(&self) -> bool {
+        self.monetization_model.as_deref() == Some("SUB_HIGH_TIER")
+    }
+
+    /// Whether only a preview snippet of this track can be streamed,
+    /// which is the case for Go+ tracks without a subscription
+    pub fn is_preview(&self) -> bool {
+        self.policy.as_deref() == Some("SNIP")
+            || (self.duration > 0 && self.duration < self.full_duration)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct Media {
+    #[serde(default, deserialize_with = "lenient_vec")]
     pub transcodings: Vec<Transcoding>,
 }
 
@@ -74,29 +307,242 @@ pub struct Media {
 pub struct Transcoding {
     pub url: String,
     pub format: Format,
-    pub quality: String,
+    pub quality: Quality,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Format {
-    pub protocol: String,
+    pub protocol: Protocol,
     pub mime_type: String,
 }
 
+/// How the audio of a transcoding is delivered
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(from = "String")]
+pub enum Protocol {
+    /// A single file
+    Progressive,
+    /// An HLS playlist of segments, remuxed with FFmpeg
+    Hls,
+    /// DRM protected HLS, e.g. `ctr-encrypted-hls`
+    Encrypted(String),
+    /// A protocol this version does not know
+    Other(String),
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Progressive => "progressive",
+            Self::Hls => "hls",
+            Self::Encrypted(name) | Self::Other(name) => name,
+        }
+    }
+
+    /// Whether streams of the protocol can be downloaded, encrypted streams need
+    /// DRM keys SoundCloud only hands to its own players
+    pub fn is_supported(&self) -> bool {
+        matches!(self, Self::Progressive | Self::Hls)
+    }
+
+    /// Whether downloading the protocol needs FFmpeg
+    pub fn needs_ffmpeg(&self) -> bool {
+        *self != Self::Progressive
+    }
+}
+
+impl From<String> for Protocol {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "progressive" => Self::Progressive,
+            "hls" => Self::Hls,
+            _ if name.contains("encrypted") => Self::Encrypted(name),
+            _ => Self::Other(name),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Quality tier of a transcoding
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(from = "String")]
+pub enum Quality {
+    /// High quality, 256 kbps AAC for Go+ subscribers
+    Hq,
+    /// Standard quality, 128 kbps MP3 or 64 kbps Opus
+    Sq,
+    Other(String),
+}
+
+impl Quality {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Hq => "hq",
+            Self::Sq => "sq",
+            Self::Other(name) => name,
+        }
+    }
+}
+
+impl From<String> for Quality {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "hq" => Self::Hq,
+            "sq" => Self::Sq,
+            _ => Self::Other(name),
+        }
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct User {
     pub id: u64,
+    #[serde(default, deserialize_with = "lenient")]
     pub username: String,
     pub permalink: String,
+    #[serde(default, deserialize_with = "lenient")]
+    pub avatar_url: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub permalink_url: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub full_name: Option<String>,
+    /// Bio shown on the profile page
+    #[serde(default, deserialize_with = "lenient")]
+    pub description: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub city: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub country_code: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub followers_count: Option<u64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub followings_count: Option<u64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub track_count: Option<u64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub playlist_count: Option<u64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub verified: bool,
+    /// When the account was created
+    #[serde(default, deserialize_with = "lenient")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Fields this version does not know about, kept for downstream consumers
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A link on a user's profile page, e.g. to their website or Instagram
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebProfile {
+    pub url: String,
+    /// Service the link points to, e.g. `instagram` or `personal`
+    #[serde(default, deserialize_with = "lenient")]
+    pub network: String,
+    #[serde(default, deserialize_with = "lenient")]
+    pub title: Option<String>,
+    /// Name of the account on the linked service
+    #[serde(default, deserialize_with = "lenient")]
+    pub username: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct GetLikesResponse {
+    #[serde(deserialize_with = "lenient_vec")]
     pub collection: Vec<Like>,
     pub next_href: Option<String>,
 }
 
+/// A page of results from a paginated endpoint such as `/search/tracks`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(bound(deserialize = "T: DeserializeOwned"))]
+pub struct Collection<T> {
+    #[serde(deserialize_with = "lenient_vec")]
+    pub collection: Vec<T>,
+    pub next_href: Option<String>,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct AudioResponse {
     pub url: String, // url to audio to be downloaded
 }
+
+/// A SoundCloud object returned by the `/resolve` endpoint
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Resource {
+    Track(Box<Track>),
+    Playlist(Playlist),
+    User(User),
+}
+
+impl Resource {
+    /// Returns the SoundCloud `kind` name of the resource
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Track(_) => "track",
+            Self::Playlist(_) => "playlist",
+            Self::User(_) => "user",
+        }
+    }
+}
+
+/// Logs fields of an API object that are not part of the model, once per field and
+/// run, to spot API changes
+///
+/// # Arguments
+/// * `kind` - SoundCloud `kind` of the object, e.g. `track`
+/// * `extra` - The unknown fields captured while deserializing it
+pub fn log_unknown_fields(kind: &str, extra: &Map<String, Value>) {
+    let mut logged = LOGGED_FIELDS.lock().unwrap();
+    for name in extra.keys().filter(|name| *name != "kind") {
+        if logged.insert(format!("{}.{}", kind, name)) {
+            tracing::debug!(
+                "The API returned {} field '{}' which is not modelled",
+                kind,
+                name
+            );
+        }
+    }
+}
+
+/// Deserializes a field the API may leave out, send as `null` or change the type of,
+/// falling back to the default instead of failing the whole object
+fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(T::deserialize(value).unwrap_or_default())
+}
+
+/// Deserializes a list, skipping items in an unexpected shape so one malformed track
+/// does not fail a whole playlist or page
+fn lenient_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let items = Option::<Vec<Value>>::deserialize(deserializer)?.unwrap_or_default();
+
+    Ok(items
+        .into_iter()
+        .filter_map(|item| {
+            let id = item["id"].clone();
+            T::deserialize(item)
+                .inspect_err(|e| tracing::warn!("Skipping unreadable item {}: {}", id, e))
+                .ok()
+        })
+        .collect())
+}
@@ -1,74 +1,686 @@
 use crate::error::{AppError, Result};
+use crate::progress::ProgressReporter;
 use crate::soundcloud::model::{AudioResponse, GetLikesResponse, Like, Track, User};
-use reqwest::{Client, Response, StatusCode};
-use std::time::Duration;
+use crate::util;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
+use indicatif::ProgressBar;
+use regex::Regex;
+use reqwest::header::{
+    HeaderValue, AUTHORIZATION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+    RETRY_AFTER,
+};
+use reqwest::{Client, Request, RequestBuilder, Response, ResponseBuilderExt, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::time::sleep;
 
-use super::model::{Playlist, Transcoding};
-use super::{DownloadedFile, SoundcloudClient};
+use super::model::{
+    log_unknown_fields, ChartEntry, Collection, Comment, Playlist, PlaylistLike, PlaylistTrack,
+    Resource, StreamItem, Transcoding, WebProfile,
+};
+use super::{
+    AdaptiveLimiter, DownloadedFile, HttpTrace, MetadataCache, RateLimitStats, RequestPolicy,
+    ResolvedStream, SoundcloudClient, SoundcloudClientBuilder, TokenPool, Validators,
+};
 
-const API_BASE: &str = "https://api-v2.soundcloud.com/";
-const ME_URL: &str = "https://api-v2.soundcloud.com/me";
-const MAX_RETRIES: u32 = 5;
+const DEFAULT_API_BASE: &str = "https://api-v2.soundcloud.com/";
+/// Page whose scripts contain the `client_id` of the web player
+const WEB_PLAYER_URL: &str = "https://soundcloud.com/";
+/// Hosts the OAuth token is sent to, requests to any other host go without it
+const AUTH_HOSTS: &[&str] = &["api-v2.soundcloud.com", "api.soundcloud.com"];
+/// API paths answered for the account of the token, which always get the account's own
+/// token instead of one rotated in from another account
+const ACCOUNT_PATHS: &[&str] = &["me", "stream"];
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_CONCURRENCY: usize = 3;
+const DEFAULT_MAX_API_CONCURRENCY: usize = 4;
 const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(30);
 const MAX_RETRY_DELAY: Duration = Duration::from_secs(500);
+/// The CDN recovers from errors quickly and rarely rate limits
+const MEDIA_INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+const MEDIA_MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+const API_RETRY_STATUSES: &[StatusCode] = &[StatusCode::SERVICE_UNAVAILABLE];
+const MEDIA_RETRY_STATUSES: &[StatusCode] = &[
+    StatusCode::INTERNAL_SERVER_ERROR,
+    StatusCode::BAD_GATEWAY,
+    StatusCode::SERVICE_UNAVAILABLE,
+    StatusCode::GATEWAY_TIMEOUT,
+];
+const TRACKS_BATCH_SIZE: usize = 50;
+const PAGE_SIZE: u32 = 200;
+const RESUME_DELAY: Duration = Duration::from_secs(2);
+/// Hydration data kinds of a page and the SoundCloud `kind` of the object they hold
+const HYDRATABLE_KINDS: &[(&str, &str)] = &[
+    ("sound", "track"),
+    ("playlist", "playlist"),
+    ("user", "user"),
+];
+/// Hydration data kinds of a page that hold no track, playlist or user
+const IGNORED_HYDRATABLES: &[&str] = &[
+    "anonymousId",
+    "features",
+    "experiments",
+    "geoip",
+    "privacySettings",
+    "trackingBrowserTabId",
+    "meUser",
+];
+/// How long a media URL is assumed to be valid when its expiry cannot be read
+const DEFAULT_STREAM_LIFETIME: Duration = Duration::from_secs(5 * 60);
+/// Characters of a response body written to the log with [`HttpTrace::Bodies`]
+const TRACED_BODY_CHARS: usize = 2000;
+/// Largest buffer allocated up front for a download of the announced size, larger
+/// files grow it as they arrive
+const MAX_PREALLOCATION: u64 = 64 * 1024 * 1024;
+
+impl SoundcloudClientBuilder {
+    /// Sets the connect and read timeout applied to every request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times a rate limited or unavailable request is retried, for API
+    /// calls and media downloads alike
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the maximum number of concurrent downloads, which is lowered
+    /// automatically while the CDN rate limits them
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency;
+        self
+    }
+
+    /// Sets the maximum number of concurrent API calls, which is lowered
+    /// automatically while the API rate limits them
+    pub fn max_api_concurrency(mut self, max_api_concurrency: usize) -> Self {
+        self.max_api_concurrency = max_api_concurrency;
+        self
+    }
+
+    /// Sends API calls to another base URL than api-v2, e.g. a local proxy or test server
+    ///
+    /// The OAuth token is sent to this host as well, but only over HTTPS unless the
+    /// host is the local machine.
+    pub fn api_base(mut self, mut api_base: reqwest::Url) -> Self {
+        // Endpoint paths are joined onto the base
+        if !api_base.path().ends_with('/') {
+            api_base.set_path(&format!("{}/", api_base.path()));
+        }
+        if api_base.scheme() != "https" && !is_loopback(&api_base) {
+            tracing::warn!(
+                "Not sending the OAuth token to {} since it does not use HTTPS",
+                api_base
+            );
+        }
+        self.api_base = api_base;
+        self
+    }
+
+    /// Caches fetched tracks, playlists and users on disk
+    pub fn cache(mut self, cache: Arc<MetadataCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Logs every request at debug level, with the OAuth token redacted
+    pub fn http_trace(mut self, http_trace: HttpTrace) -> Self {
+        self.http_trace = http_trace;
+        self
+    }
+
+    /// Adds OAuth tokens of other accounts that API calls switch to while the token in
+    /// use is rate limited, see [`TokenPool`]
+    pub fn rotation_tokens(mut self, tokens: Vec<String>) -> Self {
+        self.rotation_tokens = tokens;
+        self
+    }
+
+    /// Sets the `client_id` sent with API calls, which is looked up from the web player
+    /// for calls made without an OAuth token if not set
+    pub fn client_id(mut self, client_id: Option<String>) -> Self {
+        self.client_id = client_id.filter(|id| !id.is_empty());
+        self
+    }
+
+    /// Adds `client_id`s API calls switch to while the one in use is rate limited, after
+    /// the one set with [`client_id`](Self::client_id)
+    pub fn rotation_client_ids(mut self, client_ids: Vec<String>) -> Self {
+        self.rotation_client_ids = client_ids;
+        self
+    }
+
+    /// Builds the [`SoundcloudClient`]
+    ///
+    /// # Returns
+    /// Result containing the client or an error if the HTTP client could not be created
+    pub fn build(self) -> Result<SoundcloudClient> {
+        // A total timeout would cut off large media downloads, so only
+        // connecting and individual reads are bounded
+        let http_client = Client::builder()
+            .connect_timeout(self.timeout)
+            .read_timeout(self.timeout)
+            .build()?;
+
+        let tokens = (!self.oauth.is_empty() && !self.rotation_tokens.is_empty()).then(|| {
+            let first = Some(self.oauth.clone());
+            Arc::new(TokenPool::new(
+                "OAuth token",
+                pool(first, self.rotation_tokens),
+            ))
+        });
+        let client_ids = (!self.rotation_client_ids.is_empty()).then(|| {
+            let first = self.client_id.clone();
+            Arc::new(TokenPool::new(
+                "client_id",
+                pool(first, self.rotation_client_ids),
+            ))
+        });
+
+        Ok(SoundcloudClient {
+            http_client,
+            oauth: self.oauth,
+            api_base: self.api_base,
+            api: RequestPolicy {
+                limiter: Arc::new(AdaptiveLimiter::new(self.max_api_concurrency, "API calls")),
+                per_request: true,
+                max_retries: self.max_retries,
+                initial_delay: INITIAL_RETRY_DELAY,
+                max_delay: MAX_RETRY_DELAY,
+                retry_statuses: API_RETRY_STATUSES,
+            },
+            // Download workers hold a slot while processing a track
+            media: RequestPolicy {
+                limiter: Arc::new(AdaptiveLimiter::new(self.max_concurrency, "downloads")),
+                per_request: false,
+                max_retries: self.max_retries,
+                initial_delay: MEDIA_INITIAL_RETRY_DELAY,
+                max_delay: MEDIA_MAX_RETRY_DELAY,
+                retry_statuses: MEDIA_RETRY_STATUSES,
+            },
+            cache: self.cache,
+            http_trace: self.http_trace,
+            rate_limits: Arc::default(),
+            tokens,
+            client_ids,
+            client_id: Arc::new(tokio::sync::OnceCell::new_with(self.client_id)),
+        })
+    }
+}
+
+/// Whether a URL points at this machine, where plain HTTP does not expose the token
+fn is_loopback(url: &reqwest::Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Lists the tokens of a pool, the one in use first and without duplicates or empty ones
+fn pool(first: Option<String>, rotation: Vec<String>) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for token in first.into_iter().chain(rotation) {
+        if !token.is_empty() && !tokens.contains(&token) {
+            tokens.push(token);
+        }
+    }
+    tokens
+}
 
 impl SoundcloudClient {
-    /// Creates a new SoundCloud client instance
+    /// Creates a [`SoundcloudClientBuilder`] for configuring timeouts and retries
+    ///
+    /// # Arguments
+    /// * `oauth` - OAuth token for authentication
+    pub fn builder(oauth: String) -> SoundcloudClientBuilder {
+        SoundcloudClientBuilder {
+            oauth,
+            timeout: DEFAULT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+            max_api_concurrency: DEFAULT_MAX_API_CONCURRENCY,
+            api_base: reqwest::Url::parse(DEFAULT_API_BASE).expect("default API base is valid"),
+            cache: None,
+            http_trace: HttpTrace::Off,
+            rotation_tokens: Vec::new(),
+            client_id: None,
+            rotation_client_ids: Vec::new(),
+        }
+    }
+
+    /// Returns the rate limit waits of this client and its clones
+    pub fn rate_limits(&self) -> Arc<RateLimitStats> {
+        self.rate_limits.clone()
+    }
+
+    /// Returns the limiter of concurrent downloads, a slot is held by each download worker
+    pub fn limiter(&self) -> Arc<AdaptiveLimiter> {
+        self.media.limiter.clone()
+    }
+
+    /// Returns an object from the metadata cache, fetching and caching it when it is
+    /// missing or expired
+    ///
+    /// Expired objects are still returned when SoundCloud cannot be reached.
+    ///
+    /// # Arguments
+    /// * `kind` - SoundCloud `kind` of the object
+    /// * `id` - ID of the object
+    /// * `secret_token` - Secret token the object is requested with
+    /// * `fresh` - Ask the API even if a cached object has not expired yet
+    /// * `fetch` - Requests the object from the API
+    async fn cached(
+        &self,
+        kind: &str,
+        id: u64,
+        secret_token: Option<&str>,
+        fresh: bool,
+        fetch: impl Future<Output = Result<Value>>,
+    ) -> Result<Value> {
+        let Some(cache) = &self.cache else {
+            return fetch.await;
+        };
+
+        if let Some(data) = cache.get(kind, id, secret_token, false).filter(|_| !fresh) {
+            return Ok(data);
+        }
+
+        match fetch.await {
+            Ok(data) => {
+                cache.put(&data, secret_token);
+                Ok(data)
+            }
+            Err(e) if is_offline(&e) => match cache.get(kind, id, secret_token, true) {
+                Some(data) => {
+                    tracing::warn!("SoundCloud is unreachable, using cached {} {}", kind, id);
+                    Ok(data)
+                }
+                None => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Makes an API call with the OAuth token, rate limiting and retries
+    ///
+    /// Rate limited (429) and unavailable (503) responses are retried, waiting
+    /// for the duration given by the `Retry-After` header when present and
+    /// falling back to exponential backoff otherwise.
+    ///
+    /// The token is only attached to requests to the SoundCloud API over HTTPS, see
+    /// [`AUTH_HOSTS`], an `Authorization` header set for any other URL is removed.
+    /// Without a token, the API only answers calls carrying a `client_id`, which is
+    /// added to them instead.
+    ///
+    /// Calls for the account itself, see [`ACCOUNT_PATHS`], always carry the account's
+    /// own token, even while another account's token is rotated in.
     ///
     /// # Arguments
-    /// * `oauth` - Optional OAuth token for authentication
+    /// * `req` - A reqwest request builder, given the token unless it sets its own
+    ///   `Authorization` header
     ///
     /// # Returns
-    /// Some([`SoundcloudClient`]) if OAuth token is provided, None otherwise
-    pub fn new(oauth: String) -> Self {
-        Self {
-            oauth,
-            http_client: Client::new(),
+    /// Result containing the response or an error
+    async fn api_request(&self, req: RequestBuilder) -> Result<Response> {
+        let mut req = req.build()?;
+
+        if !self.is_auth_host(req.url()) {
+            tracing::debug!("Not sending the OAuth token to {}", req.url());
+            req.headers_mut().remove(AUTHORIZATION);
+        } else if !req.headers().contains_key(AUTHORIZATION) && !self.oauth().is_empty() {
+            let token = match self.is_account_scoped(req.url()) {
+                true => &self.oauth,
+                false => self.oauth(),
+            };
+            req.headers_mut()
+                .insert(AUTHORIZATION, authorization(token)?);
+        }
+
+        if self.is_auth_host(req.url())
+            && !req.url().query_pairs().any(|(key, _)| key == "client_id")
+        {
+            let client_id = match (&self.client_ids, self.oauth().is_empty()) {
+                (Some(pool), _) => Some(pool.current()),
+                (None, true) => Some(self.client_id().await?),
+                (None, false) => self.client_id.get().map(String::as_str),
+            };
+            if let Some(client_id) = client_id {
+                req.url_mut()
+                    .query_pairs_mut()
+                    .append_pair("client_id", client_id);
+            }
+        }
+
+        let resp = self.send(req, &self.api).await?;
+        let resp = match self.http_trace {
+            HttpTrace::Bodies => self.trace_body(resp).await?,
+            _ => resp,
+        };
+
+        check_api_status(resp).await
+    }
+
+    /// Logs the start of a response body, returning a response with the same body
+    async fn trace_body(&self, resp: Response) -> Result<Response> {
+        let mut builder = http::Response::builder()
+            .status(resp.status())
+            .version(resp.version())
+            .url(resp.url().clone());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = resp.headers().clone();
+        }
+        let url = resp.url().clone();
+        let body = resp.bytes().await?;
+
+        tracing::debug!(
+            "HTTP body of {}: {}",
+            self.redact(url.as_str()),
+            self.redact(&truncate(
+                &String::from_utf8_lossy(&body),
+                TRACED_BODY_CHARS
+            ))
+        );
+
+        let resp = builder
+            .body(body)
+            .expect("parts of a received response are valid");
+        Ok(Response::from(resp))
+    }
+
+    /// Replaces the OAuth tokens in text written to the log
+    fn redact(&self, text: &str) -> String {
+        let tokens = match &self.tokens {
+            Some(pool) => pool.tokens(),
+            None => std::slice::from_ref(&self.oauth),
+        };
+
+        tokens.iter().fold(text.to_string(), |text, token| {
+            let token = token.trim_start_matches("OAuth ").trim();
+            match token.is_empty() {
+                true => text,
+                false => text.replace(token, "<redacted>"),
+            }
+        })
+    }
+
+    /// Returns the configured `client_id`, looking up the one of the web player if none
+    /// is configured
+    async fn client_id(&self) -> Result<&str> {
+        let client_id = self
+            .client_id
+            .get_or_try_init(|| self.fetch_client_id())
+            .await?;
+        Ok(client_id)
+    }
+
+    /// Finds the `client_id` of the web player in the scripts of soundcloud.com
+    ///
+    /// # Returns
+    /// Result containing the `client_id`, or an error asking for one to be configured
+    async fn fetch_client_id(&self) -> Result<String> {
+        tracing::debug!("Looking up the client_id of the web player");
+        let html = self
+            .http_client
+            .get(WEB_PLAYER_URL)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        // The app script holding it is one of the last ones on the page
+        for script in asset_scripts(&html).iter().rev() {
+            let js = self
+                .http_client
+                .get(script)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            if let Some(client_id) = find_client_id(&js) {
+                return Ok(client_id.to_string());
+            }
+        }
+
+        Err(AppError::Auth(
+            "No OAuth token and the client_id of the web player could not be found, \
+             pass one with --client-id"
+                .into(),
+        ))
+    }
+
+    /// Returns the OAuth token API calls are sent with, which changes while tokens
+    /// rotate
+    fn oauth(&self) -> &str {
+        match &self.tokens {
+            Some(pool) => pool.current(),
+            None => &self.oauth,
+        }
+    }
+
+    /// Whether an API call is answered for the account of its token, see
+    /// [`ACCOUNT_PATHS`]
+    fn is_account_scoped(&self, url: &reqwest::Url) -> bool {
+        url.path()
+            .strip_prefix(self.api_base.path())
+            .is_some_and(|path| {
+                ACCOUNT_PATHS.iter().any(|account_path| {
+                    path.strip_prefix(account_path)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+                })
+            })
+    }
+
+    /// Returns the OAuth tokens of other accounts API calls rotate to
+    pub fn rotation_tokens(&self) -> &[String] {
+        match &self.tokens {
+            Some(pool) => &pool.tokens()[1..],
+            None => &[],
+        }
+    }
+
+    /// Switches a rate limited API call to the next token or `client_id` of its pool
+    ///
+    /// # Returns
+    /// Whether the request was given another token or `client_id` to retry with right away
+    fn rotate_credentials(&self, req: &mut Request, cooldown: Duration) -> bool {
+        (!self.is_account_scoped(req.url()) && self.rotate_token(req, cooldown))
+            || self.rotate_client_id(req, cooldown)
+    }
+
+    /// Switches a rate limited API call to the next `client_id` of the pool
+    fn rotate_client_id(&self, req: &mut Request, cooldown: Duration) -> bool {
+        let Some(pool) = &self.client_ids else {
+            return false;
+        };
+        let Some((_, client_id)) = req.url().query_pairs().find(|(key, _)| key == "client_id")
+        else {
+            return false;
+        };
+        let Some(next) = pool.rotate(&client_id, cooldown) else {
+            return false;
+        };
+
+        let pairs = req
+            .url()
+            .query_pairs()
+            .map(|(key, value)| match key == "client_id" {
+                true => (key.into_owned(), next.to_string()),
+                false => (key.into_owned(), value.into_owned()),
+            })
+            .collect::<Vec<_>>();
+        req.url_mut().query_pairs_mut().clear().extend_pairs(pairs);
+        true
+    }
+
+    /// Switches a rate limited API call to the next token of the pool
+    fn rotate_token(&self, req: &mut Request, cooldown: Duration) -> bool {
+        let Some(pool) = &self.tokens else {
+            return false;
+        };
+        let Some(header) = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+        else {
+            return false;
+        };
+        let Some(token) = pool.tokens().iter().find(|t| header.ends_with(t.as_str())) else {
+            return false;
+        };
+        let Some(next) = pool.rotate(token, cooldown) else {
+            return false;
+        };
+
+        match HeaderValue::from_str(&header.replace(token.as_str(), next)) {
+            Ok(value) => {
+                req.headers_mut().insert(AUTHORIZATION, value);
+                true
+            }
+            Err(_) => false,
         }
     }
 
-    /// Makes an HTTP request with rate limiting and retries
+    /// Returns the base URL of API calls
+    pub fn api_base(&self) -> &reqwest::Url {
+        &self.api_base
+    }
+
+    /// Returns the URL of an API endpoint, e.g. `tracks/123`
+    fn api_url(&self, path: &str) -> String {
+        format!("{}{}", self.api_base, path)
+    }
+
+    /// Whether the OAuth token may be sent along with a request to `url`, which is
+    /// only the case for the SoundCloud API and the configured API base over HTTPS
+    ///
+    /// A local API base, e.g. a test server, may use plain HTTP.
+    fn is_auth_host(&self, url: &reqwest::Url) -> bool {
+        let is_api_base = (url.scheme() == "https" || is_loopback(url))
+            && url.scheme() == self.api_base.scheme()
+            && url.host_str() == self.api_base.host_str()
+            && url.port_or_known_default() == self.api_base.port_or_known_default();
+        let is_soundcloud = url.scheme() == "https"
+            && url
+                .host_str()
+                .is_some_and(|host| AUTH_HOSTS.contains(&host));
+
+        is_api_base || is_soundcloud
+    }
+
+    /// Fetches a file from the CDN or another host, without credentials
+    ///
+    /// Rate limited and failing (5xx) responses are retried with a shorter backoff
+    /// than API calls.
     ///
     /// # Arguments
     /// * `req` - A reqwest request builder
     ///
     /// # Returns
     /// Result containing the response or an error
-    async fn make_request(&self, req: reqwest::RequestBuilder) -> Result<Response> {
+    async fn media_request(&self, req: RequestBuilder) -> Result<Response> {
+        let mut req = req.build()?;
+        req.headers_mut().remove(AUTHORIZATION);
+
+        classify(self.send(req, &self.media).await?)
+    }
+
+    async fn send(&self, mut req: Request, policy: &RequestPolicy) -> Result<Response> {
         let mut retries = 0;
-        let mut delay = INITIAL_RETRY_DELAY;
+        let mut delay = policy.initial_delay;
 
         loop {
-            match req
-                .try_clone()
-                .expect("request should be cloneable")
-                .send()
-                .await
-            {
-                Ok(resp) => {
-                    match resp.status() {
-                        StatusCode::TOO_MANY_REQUESTS => {
-                            if retries >= MAX_RETRIES {
-                                return Err(AppError::RateLimited);
-                            }
-
-                            tracing::warn!("Rate limited, waiting {:?} before retry", delay);
-                            sleep(delay).await;
-
-                            // Exponential backoff with jitter
-                            delay = std::cmp::min(
-                                delay * 2 + Duration::from_millis(rand::random::<u64>() % 1000),
-                                MAX_RETRY_DELAY,
-                            );
-                            retries += 1;
-                            continue;
-                        }
-                        _ => return Ok(resp),
-                    }
+            let permit = match policy.per_request {
+                true => Some(policy.limiter.acquire().await),
+                false => None,
+            };
+            let started = Instant::now();
+            let resp = self
+                .http_client
+                .execute(req.try_clone().expect("request should be cloneable"))
+                .await;
+            drop(permit);
+
+            if self.http_trace != HttpTrace::Off {
+                let outcome = match &resp {
+                    Ok(resp) => resp.status().to_string(),
+                    Err(e) => format!("failed: {}", e),
+                };
+                tracing::debug!(
+                    "HTTP {} {} -> {} in {:?}",
+                    req.method(),
+                    self.redact(req.url().as_str()),
+                    self.redact(&outcome),
+                    started.elapsed()
+                );
+            }
+            let resp = resp?;
+
+            let status = resp.status();
+            if status != StatusCode::TOO_MANY_REQUESTS && !policy.retry_statuses.contains(&status) {
+                policy.limiter.on_success();
+                return Ok(resp);
+            }
+
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                policy.limiter.on_rate_limited();
+
+                // Another token goes on right away, this one waits out at least a backoff
+                let cooldown = retry_after(&resp, policy.max_delay)
+                    .unwrap_or(delay)
+                    .max(policy.initial_delay);
+                if self.rotate_credentials(&mut req, cooldown) {
+                    continue;
                 }
-                Err(e) => return Err(AppError::Network(e)),
             }
+
+            if retries >= policy.max_retries {
+                return match status {
+                    StatusCode::TOO_MANY_REQUESTS => Err(AppError::RateLimited),
+                    _ => Ok(resp),
+                };
+            }
+
+            let wait = retry_after(&resp, policy.max_delay).unwrap_or(delay);
+            tracing::warn!(
+                "Received {} from {}, waiting {:?} before retry",
+                status,
+                req.url().host_str().unwrap_or_default(),
+                wait
+            );
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                self.rate_limits.record(wait);
+            }
+            sleep(wait).await;
+
+            // Exponential backoff with jitter
+            delay = std::cmp::min(
+                delay * 2 + Duration::from_millis(rand::random::<u64>() % 1000),
+                policy.max_delay,
+            );
+            retries += 1;
         }
     }
 
@@ -78,16 +690,72 @@ impl SoundcloudClient {
     /// Result containing [`User`] data or an error
     pub async fn get_me(&self) -> Result<User> {
         let resp = self
-            .make_request(
+            .api_request(self.http_client.get(self.api_url("me")))
+            .await?;
+
+        let user = resp.json::<User>().await?;
+        log_unknown_fields("user", &user.extra);
+
+        Ok(user)
+    }
+
+    /// Fetches the profile of the account an OAuth token belongs to, e.g. to check one
+    /// of the [rotation tokens](Self::rotation_tokens)
+    ///
+    /// # Returns
+    /// Result containing [`User`] data or an error
+    pub async fn get_me_as(&self, token: &str) -> Result<User> {
+        let resp = self
+            .api_request(
                 self.http_client
-                    .get(ME_URL)
-                    .header("Authorization", &self.oauth),
+                    .get(self.api_url("me"))
+                    .header(AUTHORIZATION, authorization(token)?),
             )
             .await?;
 
         Ok(resp.json::<User>().await?)
     }
 
+    /// Fetches a user's full profile, including the bio and follower counts that
+    /// embedded user objects leave out
+    ///
+    /// # Arguments
+    /// * `user_id` - The ID of the user
+    ///
+    /// # Returns
+    /// Result containing [`User`] data or an error
+    pub async fn fetch_user(&self, user_id: u64) -> Result<User> {
+        let resp = self
+            .api_request(
+                self.http_client
+                    .get(self.api_url(&format!("users/{}", user_id))),
+            )
+            .await?;
+
+        let user = resp.json::<User>().await?;
+        log_unknown_fields("user", &user.extra);
+
+        Ok(user)
+    }
+
+    /// Fetches the links shown on a user's profile page
+    ///
+    /// # Arguments
+    /// * `user_id` - The ID of the user
+    ///
+    /// # Returns
+    /// Result containing a vector of [`WebProfile`]s or an error
+    pub async fn get_web_profiles(&self, user_id: u64) -> Result<Vec<WebProfile>> {
+        let resp = self
+            .api_request(
+                self.http_client
+                    .get(self.api_url(&format!("users/{}/web-profiles", user_id))),
+            )
+            .await?;
+
+        Ok(resp.json::<Vec<WebProfile>>().await?)
+    }
+
     /// Fetches a user's liked tracks
     ///
     /// # Arguments
@@ -98,102 +766,150 @@ impl SoundcloudClient {
     /// # Returns
     /// Result containing a vector of [`Like`]s or an error
     pub async fn get_likes(&self, user_id: u64, limit: u32, chunk_size: u32) -> Result<Vec<Like>> {
+        self.get_likes_until(user_id, limit, chunk_size, |_| false)
+            .await
+    }
+
+    /// Fetches a user's liked tracks, newest first, until a like matches `stop`
+    ///
+    /// # Arguments
+    /// * `user_id` - The ID of the user
+    /// * `limit` - Maximum number of [`Like`]s to fetch
+    /// * `chunk_size` - Number of [`Like`]s to fetch per request
+    /// * `stop` - Predicate ending pagination at the first matching [`Like`],
+    ///   which is not included in the result
+    ///
+    /// # Returns
+    /// Result containing a vector of [`Like`]s or an error
+    pub async fn get_likes_until(
+        &self,
+        user_id: u64,
+        limit: u32,
+        chunk_size: u32,
+        stop: impl Fn(&Like) -> bool,
+    ) -> Result<Vec<Like>> {
         let mut likes = Vec::new();
-        let mut next_href = Some(format!(
-            "{}users/{}/track_likes?limit={}",
-            API_BASE, user_id, limit
-        ));
+        let mut next_href = Some(self.api_url(&format!(
+            "users/{}/track_likes?limit={}",
+            user_id,
+            chunk_size.min(limit)
+        )));
 
         while let Some(url) = next_href {
             let res = self
-                .make_request(
-                    self.http_client
-                        .get(&url)
-                        .header("Authorization", &self.oauth),
-                )
+                .api_request(self.http_client.get(&url))
                 .await?
                 .json::<GetLikesResponse>()
                 .await?;
-            likes.extend(res.collection);
 
-            next_href = res.next_href;
-
-            if likes.len() >= limit as usize {
-                likes.truncate(limit as usize);
-                break;
-            }
+            for like in res.collection {
+                if stop(&like) {
+                    return Ok(likes);
+                }
 
-            if next_href.is_some() {
-                let remaining = limit as usize - likes.len();
-                if remaining < chunk_size as usize {
-                    next_href = Some(format!(
-                        "{}users/{}/track_likes?limit={}",
-                        API_BASE, user_id, remaining
-                    ));
+                likes.push(like);
+                if likes.len() >= limit as usize {
+                    return Ok(likes);
                 }
             }
+
+            next_href = res.next_href;
         }
 
         Ok(likes)
     }
 
-    /// Fetches track metadata from a SoundCloud URL
+    /// Resolves a SoundCloud URL into the object it points to
+    ///
+    /// Uses the `/resolve` API endpoint, falling back to scraping the page's
+    /// hydration data if the API request fails.
     ///
     /// # Arguments
-    /// * `url` - A SoundCloud track URL
+    /// * `url` - A SoundCloud track, playlist or user URL
     ///
     /// # Returns
-    /// Result containing [`Track`] metadata or an error. Errors can occur if:
-    /// * The URL is invalid or inaccessible
-    /// * The page doesn't contain valid hydration data
-    /// * The track data cannot be parsed
-    pub async fn track_from_url(&self, url: &str) -> Result<Track> {
-        let resp = self
-            .make_request(self.http_client.get(url))
-            .await?
-            .text()
-            .await?;
+    /// Result containing the resolved [`Resource`] or an error
+    pub async fn resolve(&self, url: &str) -> Result<Resource> {
+        let url = &self.normalize_url(url).await?;
 
-        let hydration_data = resp
-            .split("window.__sc_hydration = ")
-            .nth(1)
-            .and_then(|s| s.split(";</script>").next())
-            .ok_or_else(|| {
-                AppError::Io(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Could not find hydration data",
-                ))
-            })?;
+        if let Some(data) = self.cache.as_ref().and_then(|c| c.get_url(url, false)) {
+            return Ok(serde_json::from_value(data)?);
+        }
 
-        let hydration: serde_json::Value = serde_json::from_str(hydration_data)?;
+        let data = match self.resolve_from_api(url).await {
+            Ok(data) => Ok(data),
+            Err(e) => {
+                tracing::debug!(
+                    "Resolve API failed for {}: {}, falling back to HTML",
+                    url,
+                    e
+                );
+                self.resolve_from_html(url).await
+            }
+        };
 
-        if let Some(track_data) = hydration
-            .as_array()
-            .and_then(|arr| arr.iter().find(|item| item["hydratable"] == "sound"))
-            .and_then(|item| item.get("data"))
-        {
-            Ok(serde_json::from_value(track_data.clone())?)
-        } else {
-            Err(AppError::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Could not find track data",
-            )))
-        }
+        let data = match (data, &self.cache) {
+            (Ok(data), Some(cache)) => {
+                cache.put_url(url, &data);
+                data
+            }
+            (Err(e), Some(cache)) if is_offline(&e) => match cache.get_url(url, true) {
+                Some(data) => {
+                    tracing::warn!("SoundCloud is unreachable, using cached {}", url);
+                    data
+                }
+                None => return Err(e),
+            },
+            (data, _) => data?,
+        };
+
+        Ok(serde_json::from_value(data)?)
     }
 
-    /// Fetches playlist metadata from a SoundCloud URL
+    /// Turns a share link into the canonical SoundCloud URL
+    ///
+    /// Shortened links (`on.soundcloud.com`, `goo.gl`) are followed to their
+    /// destination, mobile hosts are rewritten and tracking parameters are
+    /// removed.
     ///
     /// # Arguments
-    /// * `url` - A SoundCloud playlist URL
+    /// * `url` - A SoundCloud URL as shared by a user
     ///
     /// # Returns
-    /// Result containing [`Playlist`] metadata or an error. Errors can occur if:
-    /// * The URL is invalid or inaccessible
-    /// * The page doesn't contain valid hydration data
-    /// * The playlist data cannot be parsed
-    pub async fn playlist_from_url(&self, url: &str) -> Result<Playlist> {
+    /// Result containing the canonical URL or an error if a redirect could not be followed
+    pub async fn normalize_url(&self, url: &str) -> Result<String> {
+        let mut parsed = match reqwest::Url::parse(url.trim()) {
+            Ok(parsed) => parsed,
+            Err(_) => return Ok(url.to_string()),
+        };
+
+        if util::is_short_link(&parsed) {
+            tracing::debug!("Following short link: {}", parsed);
+            parsed = self
+                .media_request(self.http_client.get(parsed))
+                .await?
+                .url()
+                .clone();
+        }
+
+        Ok(util::canonicalize_url(parsed).to_string())
+    }
+
+    async fn resolve_from_api(&self, url: &str) -> Result<Value> {
+        let resp = self
+            .api_request(
+                self.http_client
+                    .get(self.api_url("resolve"))
+                    .query(&[("url", url)]),
+            )
+            .await?;
+
+        Ok(resp.json::<Value>().await?)
+    }
+
+    async fn resolve_from_html(&self, url: &str) -> Result<Value> {
         let resp = self
-            .make_request(self.http_client.get(url))
+            .media_request(self.http_client.get(url))
             .await?
             .text()
             .await?;
@@ -202,187 +918,1125 @@ impl SoundcloudClient {
             .split("window.__sc_hydration = ")
             .nth(1)
             .and_then(|s| s.split(";</script>").next())
-            .ok_or_else(|| {
-                AppError::Io(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Could not find hydration data",
-                ))
-            })?;
+            .ok_or_else(|| invalid_data("Could not find hydration data"))?;
 
         let hydration: serde_json::Value = serde_json::from_str(hydration_data)?;
-
-        if let Some(playlist_data) = hydration
+        let items = hydration
             .as_array()
-            .and_then(|arr| arr.iter().find(|item| item["hydratable"] == "playlist"))
-            .and_then(|item| item.get("data"))
-        {
-            Ok(serde_json::from_value(playlist_data.clone())?)
-        } else {
-            Err(AppError::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Could not find playlist data",
-            )))
+            .ok_or_else(|| invalid_data("Could not find hydration data"))?;
+
+        for hydratable in items.iter().filter_map(|item| item["hydratable"].as_str()) {
+            let known = HYDRATABLE_KINDS
+                .iter()
+                .any(|(known, _)| *known == hydratable)
+                || IGNORED_HYDRATABLES.contains(&hydratable);
+            if !known {
+                tracing::debug!("Unknown hydration data kind '{}' on {}", hydratable, url);
+            }
         }
-    }
 
-    pub async fn fetch_track(&self, id: u64) -> Result<Track> {
-        let url = format!("{}tracks/{}", API_BASE, id);
-        let resp = self
-            .make_request(
-                self.http_client
-                    .get(&url)
-                    .header("Authorization", &self.oauth),
-            )
-            .await?;
+        let find = |kind: &str| {
+            items
+                .iter()
+                .find(|item| item["hydratable"] == kind)
+                .and_then(|item| item.get("data"))
+                .cloned()
+        };
+
+        let (kind, mut data) = HYDRATABLE_KINDS
+            .iter()
+            .find_map(|(hydratable, kind)| find(hydratable).map(|data| (*kind, data)))
+            .ok_or_else(|| invalid_data("Could not find track, playlist or user data"))?;
+
+        if let Some(object) = data.as_object_mut() {
+            object.insert("kind".into(), kind.into());
+        }
 
-        Ok(resp.json::<Track>().await?)
+        Ok(data)
     }
 
-    pub async fn fetch_playlist(&self, id: u64) -> Result<Playlist> {
-        let url = format!("{}playlists/{}", API_BASE, id);
-        let resp = self
-            .make_request(
-                self.http_client
-                    .get(&url)
-                    .header("Authorization", &self.oauth),
-            )
-            .await?;
+    /// Searches SoundCloud for tracks
+    ///
+    /// # Arguments
+    /// * `query` - Free text search query
+    /// * `limit` - Maximum number of results
+    ///
+    /// # Returns
+    /// Result containing the matching [`Track`]s or an error
+    pub async fn search_tracks(&self, query: &str, limit: u32) -> Result<Vec<Track>> {
+        self.search("tracks", query, limit).await
+    }
 
-        Ok(resp.json::<Playlist>().await?)
+    /// Searches SoundCloud for playlists and albums
+    ///
+    /// # Arguments
+    /// * `query` - Free text search query
+    /// * `limit` - Maximum number of results
+    ///
+    /// # Returns
+    /// Result containing the matching [`Playlist`]s or an error
+    pub async fn search_playlists(&self, query: &str, limit: u32) -> Result<Vec<Playlist>> {
+        self.search("playlists", query, limit).await
     }
 
-    /// Downloads a track's audio file
+    /// Searches SoundCloud for users
     ///
     /// # Arguments
-    /// * `track` - [`Track`] metadata containing download information
+    /// * `query` - Free text search query
+    /// * `limit` - Maximum number of results
     ///
     /// # Returns
-    /// Result containing a tuple of (audio bytes, file extension) or an error
-    pub async fn download_track<'t>(
+    /// Result containing the matching [`User`]s or an error
+    pub async fn search_users(&self, query: &str, limit: u32) -> Result<Vec<User>> {
+        self.search("users", query, limit).await
+    }
+
+    async fn search<T: DeserializeOwned>(
         &self,
-        track: &'t Track,
-    ) -> Result<(&'t Transcoding, DownloadedFile)> {
-        let transcoding = track
-            .media
-            .transcodings
-            .iter()
-            .find(|t| t.format.protocol == "progressive" && t.quality == "hq")
-            .or_else(|| {
-                track
-                    .media
-                    .transcodings
-                    .iter()
-                    .find(|t| t.format.protocol == "hls" && t.quality == "hq")
-            })
-            .or_else(|| {
-                track
-                    .media
-                    .transcodings
-                    .iter()
-                    .find(|t| t.format.protocol == "progressive" && t.quality == "sq")
-            })
-            .or_else(|| {
-                track
-                    .media
-                    .transcodings
-                    .iter()
-                    .find(|t| t.format.protocol == "hls" && t.quality == "sq")
+        kind: &str,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<T>> {
+        let req = self
+            .http_client
+            .get(self.api_url(&format!("search/{}", kind)))
+            .query(&[("q", query), ("limit", &limit.to_string())]);
+
+        self.paginate(req, limit).await
+    }
+
+    /// Fetches a user's uploaded tracks, newest first
+    ///
+    /// # Arguments
+    /// * `user_id` - The ID of the user
+    /// * `limit` - Maximum number of [`Track`]s to fetch
+    ///
+    /// # Returns
+    /// Result containing a vector of [`Track`]s or an error
+    pub async fn get_user_tracks(&self, user_id: u64, limit: u32) -> Result<Vec<Track>> {
+        self.get_user_collection(user_id, "tracks", limit).await
+    }
+
+    /// Fetches the playlists and albums created by a user
+    ///
+    /// # Arguments
+    /// * `user_id` - The ID of the user
+    /// * `limit` - Maximum number of [`Playlist`]s to fetch
+    ///
+    /// # Returns
+    /// Result containing a vector of [`Playlist`]s or an error
+    pub async fn get_user_playlists(&self, user_id: u64, limit: u32) -> Result<Vec<Playlist>> {
+        self.get_user_collection(user_id, "playlists", limit).await
+    }
+
+    /// Fetches the playlists and albums liked by a user, newest first
+    ///
+    /// # Arguments
+    /// * `user_id` - The ID of the user
+    /// * `limit` - Maximum number of [`Playlist`]s to fetch
+    /// * `since` - Leave out playlists liked before this time
+    ///
+    /// # Returns
+    /// Result containing a vector of [`Playlist`]s or an error
+    pub async fn get_playlist_likes(
+        &self,
+        user_id: u64,
+        limit: u32,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Playlist>> {
+        let likes: Vec<PlaylistLike> = self
+            .get_user_collection(user_id, "playlist_likes", limit)
+            .await?;
+
+        Ok(likes
+            .into_iter()
+            .filter(|like| match (since, like.created_at) {
+                (Some(since), Some(liked)) => liked >= since,
+                _ => true,
             })
-            .ok_or_else(|| AppError::Audio("No suitable transcodings found".to_string()))?;
+            .filter_map(|like| like.playlist)
+            .collect())
+    }
 
+    /// Fetches the users a user follows, most recently followed first
+    ///
+    /// # Arguments
+    /// * `user_id` - The ID of the user
+    /// * `limit` - Maximum number of [`User`]s to fetch
+    ///
+    /// # Returns
+    /// Result containing a vector of [`User`]s or an error
+    pub async fn get_followings(&self, user_id: u64, limit: u32) -> Result<Vec<User>> {
+        self.get_user_collection(user_id, "followings", limit).await
+    }
+
+    /// Fetches the tracks and playlists reposted by a user, newest first
+    ///
+    /// # Arguments
+    /// * `user_id` - The ID of the user
+    /// * `limit` - Maximum number of [`StreamItem`]s to fetch
+    ///
+    /// # Returns
+    /// Result containing a vector of [`StreamItem`]s or an error
+    pub async fn get_user_reposts(&self, user_id: u64, limit: u32) -> Result<Vec<StreamItem>> {
+        let req = self
+            .http_client
+            .get(self.api_url(&format!("stream/users/{}/reposts", user_id)))
+            .query(&[("limit", PAGE_SIZE.min(limit))]);
+
+        self.paginate(req, limit).await
+    }
+
+    /// Fetches the logged in user's activity stream of uploads and reposts by followed
+    /// users, newest first
+    ///
+    /// # Arguments
+    /// * `limit` - Maximum number of [`StreamItem`]s to fetch
+    /// * `since` - Stop at the first item posted before this time
+    ///
+    /// # Returns
+    /// Result containing a vector of [`StreamItem`]s or an error
+    pub async fn get_stream(
+        &self,
+        limit: u32,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<StreamItem>> {
+        let req = self
+            .http_client
+            .get(self.api_url("stream"))
+            .query(&[("limit", PAGE_SIZE.min(limit))]);
+
+        self.paginate_until(req, limit, |item: &StreamItem| {
+            since.is_some_and(|since| item.created_at.is_some_and(|posted| posted < since))
+        })
+        .await
+    }
+
+    /// Fetches every comment on a track, including replies
+    ///
+    /// # Arguments
+    /// * `track_id` - The ID of the track
+    ///
+    /// # Returns
+    /// Result containing a vector of [`Comment`]s or an error
+    pub async fn get_comments(&self, track_id: u64) -> Result<Vec<Comment>> {
+        let req = self
+            .http_client
+            .get(self.api_url(&format!("tracks/{}/comments", track_id)))
+            .query(&[
+                ("threaded", "0"),
+                ("filter_replies", "0"),
+                ("limit", &PAGE_SIZE.to_string()),
+            ]);
+
+        self.paginate(req, u32::MAX).await
+    }
+
+    /// Fetches the tracks SoundCloud recommends alongside a track, as played by its station
+    ///
+    /// # Arguments
+    /// * `track_id` - The ID of the seed track
+    /// * `limit` - Maximum number of [`Track`]s to fetch
+    ///
+    /// # Returns
+    /// Result containing a vector of [`Track`]s or an error
+    pub async fn get_related_tracks(&self, track_id: u64, limit: u32) -> Result<Vec<Track>> {
+        let req = self
+            .http_client
+            .get(self.api_url(&format!("tracks/{}/related", track_id)))
+            .query(&[("limit", PAGE_SIZE.min(limit))]);
+
+        self.paginate(req, limit).await
+    }
+
+    /// Fetches the tracks of a SoundCloud chart, highest ranked first
+    ///
+    /// # Arguments
+    /// * `kind` - Chart kind, `top` or `trending`
+    /// * `genre` - Genre name such as `electronic`, or `all-music`
+    /// * `limit` - Maximum number of [`Track`]s to fetch
+    ///
+    /// # Returns
+    /// Result containing a vector of [`Track`]s or an error
+    pub async fn get_charts(&self, kind: &str, genre: &str, limit: u32) -> Result<Vec<Track>> {
+        let req = self.http_client.get(self.api_url("charts")).query(&[
+            ("kind", kind.to_string()),
+            ("genre", format!("soundcloud:genres:{}", genre)),
+            ("limit", PAGE_SIZE.min(limit).to_string()),
+        ]);
+
+        let entries: Vec<ChartEntry> = self.paginate(req, limit).await?;
+
+        Ok(entries.into_iter().map(|entry| entry.track).collect())
+    }
+
+    async fn get_user_collection<T: DeserializeOwned>(
+        &self,
+        user_id: u64,
+        collection: &str,
+        limit: u32,
+    ) -> Result<Vec<T>> {
+        let req = self
+            .http_client
+            .get(self.api_url(&format!("users/{}/{}", user_id, collection)))
+            .query(&[("limit", PAGE_SIZE.min(limit))]);
+
+        self.paginate(req, limit).await
+    }
+
+    /// Follows `next_href` links of a paginated endpoint until `limit` items are collected
+    async fn paginate<T: DeserializeOwned>(
+        &self,
+        req: reqwest::RequestBuilder,
+        limit: u32,
+    ) -> Result<Vec<T>> {
+        self.paginate_until(req, limit, |_| false).await
+    }
+
+    /// Like [`Self::paginate`], but also stops at the first item matching `stop`,
+    /// which is not included in the result
+    async fn paginate_until<T: DeserializeOwned>(
+        &self,
+        mut req: reqwest::RequestBuilder,
+        limit: u32,
+        stop: impl Fn(&T) -> bool,
+    ) -> Result<Vec<T>> {
+        let mut results = Vec::new();
+
+        loop {
+            let page = self.api_request(req).await?.json::<Collection<T>>().await?;
+
+            let empty = page.collection.is_empty();
+            let len = results.len();
+            results.extend(page.collection);
+
+            if let Some(i) = results[len..].iter().position(&stop) {
+                results.truncate(len + i);
+                break;
+            }
+
+            match page.next_href {
+                Some(next) if !empty && results.len() < limit as usize => {
+                    req = self.http_client.get(next);
+                }
+                _ => break,
+            }
+        }
+
+        results.truncate(limit as usize);
+
+        Ok(results)
+    }
+
+    /// Fetches track metadata from a SoundCloud URL
+    ///
+    /// # Arguments
+    /// * `url` - A SoundCloud track URL
+    ///
+    /// # Returns
+    /// Result containing [`Track`] metadata or an error if the URL cannot be
+    /// resolved or does not point to a track
+    pub async fn track_from_url(&self, url: &str) -> Result<Track> {
+        let url = &self.normalize_url(url).await?;
+
+        match self.resolve(url).await? {
+            Resource::Track(mut track) => {
+                if track.secret_token.is_none() {
+                    track.secret_token = util::secret_token(url);
+                }
+                Ok(*track)
+            }
+            other => Err(unexpected_kind("track", &other)),
+        }
+    }
+
+    /// Fetches playlist metadata from a SoundCloud URL
+    ///
+    /// # Arguments
+    /// * `url` - A SoundCloud playlist URL
+    ///
+    /// # Returns
+    /// Result containing [`Playlist`] metadata or an error if the URL cannot be
+    /// resolved or does not point to a playlist
+    pub async fn playlist_from_url(&self, url: &str) -> Result<Playlist> {
+        let url = &self.normalize_url(url).await?;
+
+        match self.resolve(url).await? {
+            Resource::Playlist(mut playlist) => {
+                if playlist.secret_token.is_none() {
+                    playlist.secret_token = util::secret_token(url);
+                }
+                Ok(playlist)
+            }
+            other => Err(unexpected_kind("playlist", &other)),
+        }
+    }
+
+    /// Fetches track metadata by ID
+    ///
+    /// # Arguments
+    /// * `id` - The ID of the track
+    /// * `secret_token` - Secret token required to access a private track
+    ///
+    /// # Returns
+    /// Result containing [`Track`] metadata or an error
+    pub async fn fetch_track(&self, id: u64, secret_token: Option<&str>) -> Result<Track> {
+        self.fetch_track_with(id, secret_token, false).await
+    }
+
+    /// Fetches track metadata by ID from the API even if it is cached, to find out
+    /// whether the track is still available
+    ///
+    /// The cached copy is only used when SoundCloud cannot be reached.
+    ///
+    /// # Returns
+    /// Result containing [`Track`] metadata or an error
+    pub async fn fetch_track_fresh(&self, id: u64, secret_token: Option<&str>) -> Result<Track> {
+        self.fetch_track_with(id, secret_token, true).await
+    }
+
+    async fn fetch_track_with(
+        &self,
+        id: u64,
+        secret_token: Option<&str>,
+        fresh: bool,
+    ) -> Result<Track> {
+        let url = self.api_url(&format!("tracks/{}", id));
+        let fetch = async {
+            let resp = self
+                .api_request(
+                    self.http_client
+                        .get(&url)
+                        .query(&secret_token.map(|token| [("secret_token", token)])),
+                )
+                .await
+                .map_err(|e| match e {
+                    // Deleted tracks and private tracks without a valid secret token
+                    AppError::NotFound(_) => AppError::NotFound(format!("track {}", id)),
+                    e => e,
+                })?;
+
+            Ok(resp.json::<Value>().await?)
+        };
+
+        let data = self.cached("track", id, secret_token, fresh, fetch).await?;
+        let mut track = serde_json::from_value::<Track>(data)?;
+        log_unknown_fields("track", &track.extra);
+        if track.secret_token.is_none() {
+            track.secret_token = secret_token.map(str::to_string);
+        }
+
+        Ok(track)
+    }
+
+    /// Fetches metadata for several tracks at once using the batch tracks endpoint
+    ///
+    /// # Arguments
+    /// * `ids` - IDs of the tracks to fetch, requested in chunks of 50
+    /// * `playlist` - Playlist the tracks belong to, required to access the
+    ///   tracks of a private playlist
+    ///
+    /// # Returns
+    /// Result containing the [`Track`]s that could be fetched or an error.
+    /// Tracks the API omits or returns without media are left out.
+    pub async fn fetch_tracks(
+        &self,
+        ids: &[u64],
+        playlist: Option<&Playlist>,
+    ) -> Result<Vec<Track>> {
+        let url = self.api_url("tracks");
+        let mut tracks = Vec::with_capacity(ids.len());
+
+        let mut missing = Vec::new();
+        for &id in ids {
+            let cached = self
+                .cache
+                .as_ref()
+                .and_then(|cache| cache.get("track", id, None, false))
+                .and_then(|data| serde_json::from_value::<Track>(data).ok());
+            match cached {
+                Some(track) => tracks.push(track),
+                None => missing.push(id),
+            }
+        }
+
+        for chunk in missing.chunks(TRACKS_BATCH_SIZE) {
+            let ids = chunk
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join(",");
+
+            let resp = self
+                .api_request(self.http_client.get(&url).query(&[("ids", ids)]).query(
+                    &playlist.and_then(|p| {
+                        p.secret_token.as_ref().map(|token| {
+                            [
+                                ("playlistId", p.id.to_string()),
+                                ("playlistSecretToken", token.clone()),
+                            ]
+                        })
+                    }),
+                ))
+                .await?;
+
+            for data in resp.json::<Vec<Value>>().await? {
+                let Some(track) = serde_json::from_value::<PlaylistTrack>(data.clone())
+                    .ok()
+                    .and_then(PlaylistTrack::into_track)
+                else {
+                    continue;
+                };
+
+                // Private tracks are keyed by their own token, the playlist's only grants
+                // access through this playlist
+                if let Some(cache) = &self.cache {
+                    cache.put(&data, track.secret_token.as_deref());
+                }
+                tracks.push(track);
+            }
+        }
+
+        Ok(tracks)
+    }
+
+    /// Fetches playlist metadata by ID
+    ///
+    /// # Arguments
+    /// * `id` - The ID of the playlist
+    /// * `secret_token` - Secret token required to access a private playlist
+    ///
+    /// # Returns
+    /// Result containing [`Playlist`] metadata or an error
+    pub async fn fetch_playlist(&self, id: u64, secret_token: Option<&str>) -> Result<Playlist> {
+        let url = self.api_url(&format!("playlists/{}", id));
+        let fetch = async {
+            let resp = self
+                .api_request(
+                    self.http_client
+                        .get(&url)
+                        .query(&secret_token.map(|token| [("secret_token", token)])),
+                )
+                .await?;
+
+            Ok(resp.json::<Value>().await?)
+        };
+
+        let mut playlist = serde_json::from_value::<Playlist>(
+            self.cached("playlist", id, secret_token, false, fetch)
+                .await?,
+        )?;
+        log_unknown_fields("playlist", &playlist.extra);
+        if playlist.secret_token.is_none() {
+            playlist.secret_token = secret_token.map(str::to_string);
+        }
+
+        Ok(playlist)
+    }
+
+    /// Resolves a transcoding of a track to the signed URL of its media
+    ///
+    /// # Arguments
+    /// * `track` - [`Track`] the transcoding belongs to
+    /// * `transcoding` - One of the track's transcodings
+    ///
+    /// # Returns
+    /// Result containing the media URL and when its signature expires
+    pub async fn resolve_stream(
+        &self,
+        track: &Track,
+        transcoding: &Transcoding,
+    ) -> Result<ResolvedStream> {
         let resp = self
-            .make_request(
-                self.http_client
-                    .get(&transcoding.url)
-                    .header("Authorization", format!("OAuth {}", self.oauth)),
+            .api_request(
+                self.http_client.get(&transcoding.url).query(
+                    &track
+                        .secret_token
+                        .as_ref()
+                        .map(|token| [("secret_token", token)]),
+                ),
             )
             .await?
             .json::<AudioResponse>()
             .await?;
 
-        Ok((transcoding, self.download_bytes(&resp.url).await?))
+        let expires_at =
+            media_url_expiry(&resp.url).unwrap_or_else(|| Utc::now() + DEFAULT_STREAM_LIFETIME);
+
+        Ok(ResolvedStream {
+            transcoding_url: transcoding.url.clone(),
+            url: resp.url,
+            expires_at,
+        })
+    }
+
+    /// Downloads the media of a resolved stream, an HLS playlist for HLS transcodings
+    ///
+    /// # Arguments
+    /// * `track` - [`Track`] the stream belongs to
+    /// * `stream` - Stream resolved by [`Self::resolve_stream`]
+    /// * `max_size` - Maximum file size in bytes, checked against the
+    ///   `Content-Length` of progressive downloads before fetching the body
+    /// * `progress` - Told about the bytes received
+    pub async fn fetch_stream(
+        &self,
+        track: &Track,
+        stream: &ResolvedStream,
+        max_size: Option<u64>,
+        progress: &dyn ProgressReporter,
+    ) -> Result<DownloadedFile> {
+        self.read_media(&stream.url, max_size, |received, total| {
+            progress.on_bytes(track.id, received, total)
+        })
+        .await
     }
 
     /// Downloads a track's cover artwork
     ///
+    /// Tries the original artwork, then the smaller renditions SoundCloud always
+    /// generates, then the uploader's avatar for tracks without artwork. A failed
+    /// candidate only moves on to the next one.
+    ///
     /// # Arguments
     /// * `track` - [`Track`] metadata containing artwork information
     ///
     /// # Returns
     /// Result containing an optional DownloadedFile, None if no cover exists
     pub async fn download_cover(&self, track: &Track) -> Result<Option<DownloadedFile>> {
-        match &track.artwork_url {
-            Some(cover_url) => {
-                let cover_url = cover_url.replace("-large", "-original");
+        let avatar = track
+            .user
+            .avatar_url
+            .as_ref()
+            .filter(|url| !url.contains("default_avatar"));
+
+        let candidates = [track.artwork_url.as_ref(), avatar]
+            .into_iter()
+            .flatten()
+            .flat_map(|url| {
+                [
+                    url.replace("-large", "-original"),
+                    url.replace("-large", "-t500x500"),
+                    url.clone(),
+                ]
+            });
 
-                self.download_bytes(&cover_url).await.map(|file| Some(file))
+        let mut tried = HashSet::new();
+        for url in candidates {
+            if !tried.insert(url.clone()) {
+                continue;
             }
+
+            match self.fetch_revalidated(&url).await {
+                Ok(cover) => return Ok(Some(cover)),
+                Err(e @ AppError::RateLimited) => return Err(e),
+                Err(e) => tracing::debug!("Artwork {} is unavailable: {}", url, e),
+            }
+        }
+
+        if !tried.is_empty() {
+            tracing::warn!("No artwork could be downloaded for {}", track.permalink_url);
+        }
+
+        Ok(None)
+    }
+
+    /// Downloads the waveform data of a track, if it has any
+    pub async fn download_waveform(&self, track: &Track) -> Result<Option<DownloadedFile>> {
+        match &track.waveform_url {
+            Some(url) => self.download_bytes(url).await.map(Some),
             None => Ok(None),
         }
     }
 
     pub async fn download_bytes(&self, url: &str) -> Result<DownloadedFile> {
-        let file_ext = url
-            .rsplit('/')
-            .next()
-            .and_then(|s| s.split('.').last())
-            .and_then(|s| s.split('?').next())
-            .unwrap_or("")
-            .to_string();
-
-        let bytes = self
-            .make_request(
-                self.http_client
-                    .get(url)
-                    .header("Authorization", &self.oauth),
-            )
-            .await?
-            .bytes()
-            .await?;
+        self.fetch_bytes(url, None).await
+    }
+
+    async fn fetch_bytes(&self, url: &str, max_size: Option<u64>) -> Result<DownloadedFile> {
+        self.read_media(url, max_size, |_, _| {}).await
+    }
+
+    /// Downloads a file into memory, calling `on_bytes` with the bytes received so far
+    /// and the announced size after each chunk
+    async fn read_media(
+        &self,
+        url: &str,
+        max_size: Option<u64>,
+        on_bytes: impl Fn(u64, Option<u64>),
+    ) -> Result<DownloadedFile> {
+        let file_ext = file_ext(url);
+
+        let resp = self.media_request(self.http_client.get(url)).await?;
+
+        let mut resp = resp.error_for_status()?;
+
+        let total = resp.content_length();
+        if let (Some(max), Some(size)) = (max_size, total) {
+            if size > max {
+                return Err(AppError::TooLarge { size, max });
+            }
+        }
+
+        // The announced size is not trusted with more than a bounded allocation
+        let mut data = BytesMut::with_capacity(total.unwrap_or(0).min(MAX_PREALLOCATION) as usize);
+        while let Some(chunk) = resp.chunk().await? {
+            data.extend_from_slice(&chunk);
+            if let Some(max) = max_size.filter(|&max| data.len() as u64 > max) {
+                return Err(AppError::TooLarge {
+                    size: data.len() as u64,
+                    max,
+                });
+            }
+            on_bytes(data.len() as u64, total);
+        }
 
         Ok(DownloadedFile {
-            data: bytes,
+            data: data.freeze(),
             file_ext,
         })
     }
 
+    /// Downloads a file, revalidating a copy cached by an earlier run
+    ///
+    /// The cached copy's `ETag` and `Last-Modified` are sent back as `If-None-Match`
+    /// and `If-Modified-Since`, so an unchanged file is not transferred again.
+    async fn fetch_revalidated(&self, url: &str) -> Result<DownloadedFile> {
+        let Some(cache) = &self.cache else {
+            return self.fetch_bytes(url, None).await;
+        };
+
+        let cached = cache.get_file(url);
+
+        let mut req = self.http_client.get(url);
+        if let Some(validators) = cached.as_ref().map(|cached| &cached.validators) {
+            if let Some(etag) = &validators.etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let resp = self.media_request(req).await?;
+        if let (StatusCode::NOT_MODIFIED, Some(cached)) = (resp.status(), cached) {
+            tracing::debug!("{} is unchanged, using the cached copy", url);
+            return Ok(DownloadedFile {
+                data: cached.data,
+                file_ext: file_ext(url),
+            });
+        }
+
+        let resp = resp.error_for_status()?;
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+
+        let data = resp.bytes().await?;
+        cache.put_file(url, &validators, &data);
+
+        Ok(DownloadedFile {
+            data,
+            file_ext: file_ext(url),
+        })
+    }
+
+    /// Downloads a public file to disk, resuming a partial file left at `path`
+    ///
+    /// Interrupted transfers are retried up to the client's retry limit, each time
+    /// continuing from the bytes already written. No credentials are sent.
+    ///
+    /// # Arguments
+    /// * `url` - URL of the file
+    /// * `path` - Destination, appended to if it already exists and the server supports ranges
+    /// * `progress` - Progress bar advanced as the file is written
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    pub async fn download_file(
+        &self,
+        url: &str,
+        path: &Path,
+        progress: &ProgressBar,
+    ) -> Result<()> {
+        let mut retries = 0;
+
+        loop {
+            match self.download_file_once(url, path, progress).await {
+                Err(AppError::Network(e)) if retries < self.media.max_retries => {
+                    tracing::warn!("Download of {} interrupted ({}), resuming", url, e);
+                    sleep(RESUME_DELAY).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn download_file_once(
+        &self,
+        url: &str,
+        path: &Path,
+        progress: &ProgressBar,
+    ) -> Result<()> {
+        let offset = std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+
+        let mut req = self.http_client.get(url);
+        if offset > 0 {
+            req = req.header(RANGE, format!("bytes={}-", offset));
+        }
+        let mut resp = self.media_request(req).await?;
+
+        // The partial file already holds everything
+        if offset > 0 && resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(());
+        }
+        resp.error_for_status_ref()?;
+
+        // Servers ignoring the range send the whole file again
+        let resumed = resp.status() == StatusCode::PARTIAL_CONTENT;
+        let start = if resumed { offset } else { 0 };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(path)?;
+
+        if let Some(length) = resp.content_length() {
+            progress.set_length(start + length);
+        }
+        progress.set_position(start);
+
+        while let Some(chunk) = resp.chunk().await? {
+            file.write_all(&chunk)?;
+            progress.inc(chunk.len() as u64);
+        }
+        file.flush()?;
+
+        Ok(())
+    }
+
+    /// Checks that a host can be reached
+    ///
+    /// # Returns
+    /// Result containing the status of a `HEAD` request to `url`, whatever it is
+    pub async fn ping(&self, url: &str) -> Result<StatusCode> {
+        Ok(self.http_client.head(url).send().await?.status())
+    }
+
     pub async fn resolve_user(&self, username: Option<String>) -> Result<User> {
-        if username.is_none() {
+        let Some(username) = username else {
             return self.get_me().await;
+        };
+
+        let url = format!("https://soundcloud.com/{}", username);
+
+        match self.resolve(&url).await? {
+            Resource::User(user) => Ok(user),
+            other => Err(unexpected_kind("user", &other)),
         }
+    }
+}
 
-        let url = format!("https://soundcloud.com/{}", username.unwrap());
+/// Builds the `Authorization` header of an OAuth token, which is stored as copied from
+/// the browser, `OAuth <token>`, or as the bare token
+fn authorization(token: &str) -> Result<HeaderValue> {
+    let token = match token.starts_with("OAuth ") {
+        true => token.to_string(),
+        false => format!("OAuth {}", token),
+    };
+    HeaderValue::from_str(&token)
+        .map_err(|_| AppError::Configuration("OAuth token contains invalid characters".into()))
+}
 
-        let resp = self
-            .make_request(self.http_client.get(&url))
-            .await?
-            .text()
-            .await?;
+/// Parses the `Retry-After` header, which is either a number of seconds or an HTTP date,
+/// capped at `max`
+fn retry_after(resp: &Response, max: Duration) -> Option<Duration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
 
-        let hydration_data = resp
-            .split("window.__sc_hydration = ")
-            .nth(1)
-            .and_then(|s| s.split(";</script>").next())
-            .ok_or_else(|| {
-                AppError::Io(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Could not find hydration data",
-                ))
-            })?;
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs).min(max));
+    }
 
-        let hydration: serde_json::Value = serde_json::from_str(hydration_data)?;
+    httpdate::parse_http_date(value)
+        .ok()?
+        .duration_since(SystemTime::now())
+        .ok()
+        .map(|wait| wait.min(max))
+}
 
-        if let Some(user_data) = hydration
-            .as_array()
-            .and_then(|arr| arr.iter().find(|item| item["hydratable"] == "user"))
-            .and_then(|item| item.get("data"))
-        {
-            Ok(serde_json::from_value(user_data.clone())?)
-        } else {
-            Err(AppError::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Could not find user data",
-            )))
+/// Turns an unsuccessful API response into an error naming the endpoint, with the
+/// start of the response body
+///
+/// Rejected tokens are reported as such instead of failing to parse the error body.
+async fn check_api_status(resp: Response) -> Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+
+    let host = resp.url().host_str().unwrap_or_default().to_string();
+    let endpoint = format!("{}{}", host, resp.url().path());
+    let body = body_snippet(&resp.text().await.unwrap_or_default());
+
+    Err(match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => AppError::Unauthorized(format!(
+            "{} returned {}{}. The token may have expired, pass a new one with --auth <TOKEN> --save-token",
+            endpoint, status, body
+        )),
+        StatusCode::NOT_FOUND => AppError::NotFound(endpoint),
+        status if status.is_server_error() => AppError::ServerError { status, host },
+        status => AppError::Api {
+            endpoint,
+            status,
+            body,
+        },
+    })
+}
+
+/// Returns the start of a response body on one line, prefixed with `: ` when not empty
+fn body_snippet(body: &str) -> String {
+    const MAX_CHARS: usize = 200;
+
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    if body.is_empty() {
+        return String::new();
+    }
+
+    format!(": {}", truncate(&body, MAX_CHARS))
+}
+
+/// Reads when a signed CDN URL expires, from its `Expires` parameter or the
+/// `AWS:EpochTime` of its CloudFront policy
+fn media_url_expiry(url: &str) -> Option<DateTime<Utc>> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    let expires = match param("Expires") {
+        Some(expires) => expires.parse().ok()?,
+        None => {
+            // CloudFront swaps the characters of base64 that are not URL safe
+            let policy = param("Policy")?
+                .replace('-', "+")
+                .replace('_', "=")
+                .replace('~', "/");
+            let policy = serde_json::from_slice::<Value>(&BASE64.decode(policy).ok()?).ok()?;
+            policy["Statement"][0]["Condition"]["DateLessThan"]["AWS:EpochTime"].as_i64()?
+        }
+    };
+
+    DateTime::from_timestamp(expires, 0)
+}
+
+/// Shortens text to at most `max_chars` characters, marking cut off text with `...`
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Turns media responses whose status leaves no doubt about the outcome into typed errors
+///
+/// Other error statuses are left to the caller, which may expect them, e.g. 416 when
+/// resuming a complete file.
+fn classify(resp: Response) -> Result<Response> {
+    let status = resp.status();
+    let url = resp.url();
+    let host = url.host_str().unwrap_or_default().to_string();
+
+    match status {
+        StatusCode::NOT_FOUND => Err(AppError::NotFound(format!("{}{}", host, url.path()))),
+        status if status.is_server_error() => Err(AppError::ServerError { status, host }),
+        _ => Ok(resp),
+    }
+}
+
+/// Returns the file extension at the end of a URL's path
+fn file_ext(url: &str) -> String {
+    url.rsplit('/')
+        .next()
+        .and_then(|s| s.split('.').next_back())
+        .and_then(|s| s.split('?').next())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Returns the URLs of the scripts the web player loads from its asset host
+fn asset_scripts(html: &str) -> Vec<String> {
+    static SCRIPT: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r#"<script[^>]+src="(https://a-v2\.sndcdn\.com/assets/[^"]+\.js)""#)
+            .expect("valid regex")
+    });
+
+    SCRIPT
+        .captures_iter(html)
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+/// Finds the `client_id` in a script of the web player
+fn find_client_id(js: &str) -> Option<&str> {
+    static CLIENT_ID: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r#"client_id\s*[:=]\s*"?([a-zA-Z0-9]{32})\b"#).expect("valid regex")
+    });
+
+    CLIENT_ID
+        .captures(js)
+        .and_then(|captures| captures.get(1))
+        .map(|id| id.as_str())
+}
+
+/// Whether SoundCloud could not be reached at all, as opposed to refusing a request
+fn is_offline(e: &AppError) -> bool {
+    matches!(e, AppError::Network(e) if e.is_connect() || e.is_timeout())
+}
+
+fn invalid_data(msg: &str) -> AppError {
+    AppError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        msg.to_string(),
+    ))
+}
+
+fn unexpected_kind(expected: &str, resource: &Resource) -> AppError {
+    invalid_data(&format!(
+        "Expected a {} but the URL points to a {}",
+        expected,
+        resource.kind()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves a user on every path of a local port, returning its base URL and the
+    /// headers of the requests received, lowercased
+    async fn serve() -> (reqwest::Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request).to_lowercase());
+
+                let body = r#"{"id":1,"permalink":"user"}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        (reqwest::Url::parse(&base).unwrap(), requests)
+    }
+
+    fn client(api_base: reqwest::Url) -> SoundcloudClient {
+        SoundcloudClient::builder("secret".into())
+            .api_base(api_base)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_token_to_local_api_base() {
+        let (base, requests) = serve().await;
+        let client = client(base);
+
+        assert_eq!(client.get_me().await.unwrap().id, 1);
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("get /me"));
+        assert!(requests[0].contains("authorization: oauth secret"));
+    }
+
+    #[tokio::test]
+    async fn keeps_token_from_other_hosts() {
+        let (base, _) = serve().await;
+        let (other, requests) = serve().await;
+        let client = client(base);
+
+        let url = other.join("me").unwrap();
+        client
+            .api_request(client.http_client.get(url.clone()))
+            .await
+            .unwrap();
+        client
+            .media_request(
+                client
+                    .http_client
+                    .get(url)
+                    .header(AUTHORIZATION, "OAuth secret"),
+            )
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|r| !r.contains("authorization")));
+    }
+
+    #[test]
+    fn requires_https_for_remote_hosts() {
+        let url = |url: &str| reqwest::Url::parse(url).unwrap();
+        let plain = client(url("http://example.com/"));
+        assert!(!plain.is_auth_host(&url("http://example.com/me")));
+        assert!(!plain.is_auth_host(&url("http://api-v2.soundcloud.com/me")));
+        assert!(plain.is_auth_host(&url("https://api-v2.soundcloud.com/me")));
+
+        let https = client(url("https://proxy.example.com/"));
+        assert!(https.is_auth_host(&url("https://proxy.example.com/me")));
+        assert!(!https.is_auth_host(&url("https://other.example.com/me")));
+    }
+
+    #[test]
+    fn recognizes_loopback_hosts() {
+        for base in [
+            "http://localhost:8080/",
+            "http://127.0.0.1/",
+            "http://[::1]:80/",
+        ] {
+            assert!(is_loopback(&reqwest::Url::parse(base).unwrap()), "{}", base);
+        }
+        for base in [
+            "http://example.com/",
+            "http://10.0.0.1/",
+            "http://localhost.example/",
+        ] {
+            assert!(
+                !is_loopback(&reqwest::Url::parse(base).unwrap()),
+                "{}",
+                base
+            );
         }
     }
 }
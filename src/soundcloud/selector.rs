@@ -0,0 +1,172 @@
+use crate::error::{AppError, Result};
+
+use super::model::{Protocol, Quality, Transcoding};
+
+/// Order transcodings are tried in when none is configured
+pub const DEFAULT_TRANSCODING_ORDER: &str = "progressive:hq,hls:hq,progressive:sq,hls:sq";
+
+/// Picks the transcoding of a track to download from an ordered list of protocol and
+/// quality preferences, e.g. `progressive:hq,hls:hq`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TranscodingSelector {
+    preferences: Vec<(Protocol, Quality)>,
+}
+
+impl TranscodingSelector {
+    /// Parses a comma separated list of `protocol:quality` pairs, most preferred first
+    pub fn parse(order: &str) -> Result<Self> {
+        let preferences = order
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (protocol, quality) = pair.split_once(':').ok_or_else(|| {
+                    AppError::Configuration(format!(
+                        "Invalid transcoding preference '{}', expected protocol:quality, e.g. hls:hq",
+                        pair
+                    ))
+                })?;
+                let protocol = Protocol::from(protocol.trim().to_lowercase());
+                if !protocol.is_supported() {
+                    return Err(AppError::Configuration(format!(
+                        "Cannot download {} streams, expected progressive or hls",
+                        protocol
+                    )));
+                }
+                Ok((protocol, Quality::from(quality.trim().to_lowercase())))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if preferences.is_empty() {
+            return Err(AppError::Configuration(
+                "The transcoding order must name at least one protocol:quality pair".into(),
+            ));
+        }
+
+        Ok(Self { preferences })
+    }
+
+    /// Returns the selector without the preferences that need FFmpeg
+    pub fn without_ffmpeg(&self) -> Self {
+        Self {
+            preferences: self
+                .preferences
+                .iter()
+                .filter(|(protocol, _)| !protocol.needs_ffmpeg())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Returns the most preferred of a track's transcodings, `None` if no preference
+    /// matches any of them
+    pub fn select<'t>(&self, transcodings: &'t [Transcoding]) -> Option<&'t Transcoding> {
+        self.candidates(transcodings).next()
+    }
+
+    /// Returns a track's transcodings matching a preference, most preferred first, so
+    /// the next one can be tried when a stream turns out to be unplayable
+    ///
+    /// Encrypted and unknown protocols are never returned.
+    pub fn candidates<'s, 't: 's>(
+        &'s self,
+        transcodings: &'t [Transcoding],
+    ) -> impl Iterator<Item = &'t Transcoding> + 's {
+        self.preferences
+            .iter()
+            .flat_map(move |(protocol, quality)| {
+                transcodings.iter().filter(move |t| {
+                    t.format.protocol.is_supported()
+                        && t.format.protocol == *protocol
+                        && t.quality == *quality
+                })
+            })
+    }
+}
+
+impl Default for TranscodingSelector {
+    fn default() -> Self {
+        Self::parse(DEFAULT_TRANSCODING_ORDER).expect("default transcoding order is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::soundcloud::model::Format;
+
+    fn transcoding(protocol: &str, quality: &str) -> Transcoding {
+        Transcoding {
+            url: format!("https://example.com/{}/{}", protocol, quality),
+            format: Format {
+                protocol: Protocol::from(protocol.to_string()),
+                mime_type: "audio/mpeg".into(),
+            },
+            quality: Quality::from(quality.to_string()),
+        }
+    }
+
+    fn order(selected: Vec<&Transcoding>) -> Vec<String> {
+        selected
+            .into_iter()
+            .map(|t| format!("{}:{}", t.format.protocol, t.quality))
+            .collect()
+    }
+
+    #[test]
+    fn rejects_invalid_orders() {
+        for order in ["", " , ", "hls", "ctr-encrypted-hls:hq", "dash:sq"] {
+            assert!(
+                TranscodingSelector::parse(order).is_err(),
+                "{:?} should be rejected",
+                order
+            );
+        }
+    }
+
+    #[test]
+    fn parses_case_and_whitespace_insensitively() {
+        assert_eq!(
+            TranscodingSelector::parse(" HLS : HQ ,progressive:sq,").unwrap(),
+            TranscodingSelector {
+                preferences: vec![
+                    (Protocol::Hls, Quality::Hq),
+                    (Protocol::Progressive, Quality::Sq)
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn default_order_prefers_quality_then_progressive() {
+        let transcodings = [
+            transcoding("hls", "sq"),
+            transcoding("ctr-encrypted-hls", "hq"),
+            transcoding("progressive", "sq"),
+            transcoding("hls", "hq"),
+            transcoding("progressive", "hq"),
+        ];
+        let selector = TranscodingSelector::default();
+
+        assert_eq!(
+            order(selector.candidates(&transcodings).collect()),
+            ["progressive:hq", "hls:hq", "progressive:sq", "hls:sq"]
+        );
+        assert_eq!(
+            selector.select(&transcodings).map(|t| t.url.as_str()),
+            Some("https://example.com/progressive/hq")
+        );
+    }
+
+    #[test]
+    fn without_ffmpeg_drops_hls() {
+        let transcodings = [transcoding("hls", "hq"), transcoding("progressive", "sq")];
+        let selector = TranscodingSelector::default().without_ffmpeg();
+
+        assert_eq!(
+            order(selector.candidates(&transcodings).collect()),
+            ["progressive:sq"]
+        );
+        assert!(selector.select(&transcodings[..1]).is_none());
+    }
+}
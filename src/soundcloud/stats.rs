@@ -0,0 +1,28 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Waits for rate limits, counted across all clones of a client
+#[derive(Debug, Default)]
+pub struct RateLimitStats {
+    waits: AtomicU64,
+    waited_ms: AtomicU64,
+}
+
+impl RateLimitStats {
+    /// Records a wait before retrying a rate limited request
+    pub fn record(&self, wait: Duration) {
+        self.waits.fetch_add(1, Ordering::Relaxed);
+        self.waited_ms
+            .fetch_add(wait.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Number of waits so far
+    pub fn waits(&self) -> u64 {
+        self.waits.load(Ordering::Relaxed)
+    }
+
+    /// Total time spent waiting so far
+    pub fn waited(&self) -> Duration {
+        Duration::from_millis(self.waited_ms.load(Ordering::Relaxed))
+    }
+}
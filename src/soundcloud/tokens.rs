@@ -0,0 +1,146 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// OAuth tokens or `client_id`s API calls rotate between when one is rate limited, for
+/// archival jobs too large for the rate limit of a single account or app
+///
+/// The token in use is kept until SoundCloud rate limits it, after which it cools down
+/// for as long as the server asked and the next available token takes over.
+#[derive(Debug)]
+pub struct TokenPool {
+    /// What the tokens are, e.g. `OAuth token`, for the log
+    kind: &'static str,
+    tokens: Vec<String>,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Debug)]
+struct PoolState {
+    /// Index of the token in use
+    current: usize,
+    /// When each token may be used again, `None` if it was never rate limited
+    cooldowns: Vec<Option<Instant>>,
+}
+
+impl TokenPool {
+    /// Creates a pool starting with the first token
+    pub fn new(kind: &'static str, tokens: Vec<String>) -> Self {
+        let cooldowns = vec![None; tokens.len()];
+        Self {
+            kind,
+            tokens,
+            state: Mutex::new(PoolState {
+                current: 0,
+                cooldowns,
+            }),
+        }
+    }
+
+    /// Returns the token in use
+    pub fn current(&self) -> &str {
+        &self.tokens[self.state.lock().unwrap().current]
+    }
+
+    pub fn tokens(&self) -> &[String] {
+        &self.tokens
+    }
+
+    /// Puts a rate limited token on cooldown and switches to the next token that is not
+    /// cooling down
+    ///
+    /// # Arguments
+    /// * `token` - The rate limited token
+    /// * `cooldown` - How long it is not used, e.g. the `Retry-After` of the response
+    ///
+    /// # Returns
+    /// The token to retry with, `None` if every other token is cooling down as well
+    pub fn rotate(&self, token: &str, cooldown: Duration) -> Option<&str> {
+        let index = self.tokens.iter().position(|t| t == token)?;
+        let now = Instant::now();
+
+        let mut state = self.state.lock().unwrap();
+        state.cooldowns[index] = Some(now + cooldown);
+
+        let next = (1..self.tokens.len())
+            .map(|offset| (index + offset) % self.tokens.len())
+            .find(|&i| state.cooldowns[i].is_none_or(|until| until <= now))?;
+        state.current = next;
+        tracing::info!(
+            "{} {} of {} is rate limited for {:?}, switching to {} {}",
+            self.kind,
+            index + 1,
+            self.tokens.len(),
+            cooldown,
+            self.kind,
+            next + 1
+        );
+
+        Some(&self.tokens[next])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_secs(60);
+
+    fn pool(tokens: &[&str]) -> TokenPool {
+        TokenPool::new("token", tokens.iter().map(|t| t.to_string()).collect())
+    }
+
+    #[test]
+    fn starts_with_the_first_token() {
+        assert_eq!(pool(&["a", "b"]).current(), "a");
+    }
+
+    #[test]
+    fn rotates_in_order_and_wraps_around() {
+        let pool = pool(&["a", "b", "c"]);
+
+        assert_eq!(pool.rotate("a", Duration::ZERO), Some("b"));
+        assert_eq!(pool.current(), "b");
+        assert_eq!(pool.rotate("b", Duration::ZERO), Some("c"));
+        assert_eq!(pool.rotate("c", Duration::ZERO), Some("a"));
+        assert_eq!(pool.current(), "a");
+    }
+
+    #[test]
+    fn skips_tokens_cooling_down() {
+        let pool = pool(&["a", "b", "c"]);
+
+        assert_eq!(pool.rotate("a", COOLDOWN), Some("b"));
+        assert_eq!(pool.rotate("b", COOLDOWN), Some("c"));
+        assert_eq!(pool.rotate("c", Duration::ZERO), None);
+    }
+
+    #[test]
+    fn keeps_the_current_token_when_every_token_is_cooling_down() {
+        let pool = pool(&["a", "b"]);
+
+        assert_eq!(pool.rotate("a", COOLDOWN), Some("b"));
+        assert_eq!(pool.rotate("b", COOLDOWN), None);
+        assert_eq!(pool.current(), "b");
+    }
+
+    #[test]
+    fn reuses_tokens_once_their_cooldown_ends() {
+        let pool = pool(&["a", "b"]);
+
+        assert_eq!(pool.rotate("a", Duration::ZERO), Some("b"));
+        assert_eq!(pool.rotate("b", COOLDOWN), Some("a"));
+    }
+
+    #[test]
+    fn ignores_unknown_tokens() {
+        let pool = pool(&["a", "b"]);
+
+        assert_eq!(pool.rotate("x", COOLDOWN), None);
+        assert_eq!(pool.current(), "a");
+    }
+
+    #[test]
+    fn single_token_never_rotates() {
+        assert_eq!(pool(&["a"]).rotate("a", Duration::ZERO), None);
+    }
+}
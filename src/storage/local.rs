@@ -0,0 +1,42 @@
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::StorageBackend;
+use crate::error::Result;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LocalConfig {
+    /// Directory files are copied to, e.g. a mounted NAS share
+    pub path: PathBuf,
+}
+
+/// Copies downloads into another local directory
+#[derive(Debug)]
+pub struct LocalFs {
+    root: PathBuf,
+}
+
+impl LocalFs {
+    pub fn new(config: &LocalConfig) -> Self {
+        Self {
+            root: config.path.clone(),
+        }
+    }
+}
+
+impl StorageBackend for LocalFs {
+    fn put<'a>(&'a self, key: &'a str, local: &'a Path) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let target = self.root.join(key);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            fs::copy(local, &target)?;
+
+            Ok(target.display().to_string())
+        })
+    }
+}
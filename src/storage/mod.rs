@@ -0,0 +1,124 @@
+mod local;
+mod s3;
+mod webdav;
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::{AppError, Result};
+
+pub use local::LocalFs;
+pub use s3::S3;
+pub use webdav::WebDav;
+
+/// Somewhere finished downloads are stored
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Stores the file at `local` under `key`, a `/` separated path relative to the
+    /// output directory
+    ///
+    /// # Returns
+    /// Result containing the location of the stored file, e.g. its URL
+    fn put<'a>(&'a self, key: &'a str, local: &'a Path) -> BoxFuture<'a, Result<String>>;
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Keep files in the output directory only
+    #[default]
+    Local,
+    S3,
+    WebDav,
+}
+
+/// The `[storage]` section of the config file
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: Backend,
+    /// Keep the downloaded file in the output directory after storing it
+    #[serde(default)]
+    pub keep_local: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<local::LocalConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s3: Option<s3::S3Config>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webdav: Option<webdav::WebDavConfig>,
+}
+
+/// Stores finished downloads in the configured backend
+#[derive(Clone, Debug)]
+pub struct Storage {
+    backend: Arc<dyn StorageBackend>,
+    /// Output directory keys are relative to
+    root: PathBuf,
+    keep_local: bool,
+}
+
+impl Storage {
+    /// Creates the backend described by the config
+    ///
+    /// # Arguments
+    /// * `config` - The `[storage]` section
+    /// * `root` - Output directory, files are stored under their path relative to it
+    ///
+    /// # Returns
+    /// Result containing the storage, `None` for the plain local backend
+    pub fn from_config(config: &StorageConfig, root: &Path) -> Result<Option<Self>> {
+        let missing = |section: &str| {
+            AppError::Configuration(format!(
+                "storage backend is {:?} but [storage.{}] is missing",
+                config.backend, section
+            ))
+        };
+
+        let backend: Arc<dyn StorageBackend> = match config.backend {
+            Backend::Local => match &config.local {
+                Some(local) => Arc::new(LocalFs::new(local)),
+                None => return Ok(None),
+            },
+            Backend::S3 => Arc::new(S3::new(config.s3.as_ref().ok_or_else(|| missing("s3"))?)?),
+            Backend::WebDav => Arc::new(WebDav::new(
+                config.webdav.as_ref().ok_or_else(|| missing("webdav"))?,
+            )?),
+        };
+
+        Ok(Some(Self {
+            backend,
+            root: root.to_path_buf(),
+            keep_local: config.keep_local,
+        }))
+    }
+
+    /// Whether files should stay in the output directory after they are stored
+    pub fn keep_local(&self) -> bool {
+        self.keep_local
+    }
+
+    /// Stores a finished download or one of its sidecars
+    ///
+    /// # Returns
+    /// Result containing the location of the stored file, e.g. its URL
+    pub async fn store(&self, local: &Path) -> Result<String> {
+        let key = self.key(local);
+        tracing::debug!("Storing {} as {}", local.display(), key);
+        self.backend.put(&key, local).await
+    }
+
+    fn key(&self, local: &Path) -> String {
+        let relative = local.strip_prefix(&self.root).unwrap_or(local);
+
+        relative
+            .components()
+            .filter_map(|component| match component {
+                std::path::Component::Normal(part) => Some(part.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
@@ -0,0 +1,102 @@
+use futures::future::BoxFuture;
+use reqwest::{Client, Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::StorageBackend;
+use crate::error::{AppError, Result};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebDavConfig {
+    /// Collection files are uploaded into, e.g. `https://nas.local/remote.php/dav/files/me/Music/`
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// Uploads downloads to a WebDAV server
+#[derive(Debug)]
+pub struct WebDav {
+    http_client: Client,
+    base: Url,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl WebDav {
+    pub fn new(config: &WebDavConfig) -> Result<Self> {
+        let mut base = Url::parse(&config.url)
+            .map_err(|e| AppError::Configuration(format!("Invalid WebDAV URL: {}", e)))?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        Ok(Self {
+            http_client: Client::new(),
+            base,
+            username: config.username.clone(),
+            password: config.password.clone(),
+        })
+    }
+
+    fn request(&self, method: Method, url: Url) -> reqwest::RequestBuilder {
+        let req = self.http_client.request(method, url);
+        match &self.username {
+            Some(username) => req.basic_auth(username, self.password.as_ref()),
+            None => req,
+        }
+    }
+
+    /// Creates the collections above `key` that do not exist yet
+    async fn create_parents(&self, key: &str) -> Result<()> {
+        let mut url = self.base.clone();
+        let parents = key.split('/').collect::<Vec<_>>();
+
+        for parent in &parents[..parents.len().saturating_sub(1)] {
+            url.path_segments_mut()
+                .map_err(|_| AppError::Configuration("Invalid WebDAV URL".into()))?
+                .pop_if_empty()
+                .push(parent)
+                .push("");
+
+            let resp = self
+                .request(
+                    Method::from_bytes(b"MKCOL").expect("valid method"),
+                    url.clone(),
+                )
+                .send()
+                .await?;
+
+            // 405 means the collection already exists
+            if !resp.status().is_success() && resp.status() != StatusCode::METHOD_NOT_ALLOWED {
+                resp.error_for_status()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl StorageBackend for WebDav {
+    fn put<'a>(&'a self, key: &'a str, local: &'a Path) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            self.create_parents(key).await?;
+
+            let mut url = self.base.clone();
+            url.path_segments_mut()
+                .map_err(|_| AppError::Configuration("Invalid WebDAV URL".into()))?
+                .pop_if_empty()
+                .extend(key.split('/'));
+
+            self.request(Method::PUT, url.clone())
+                .body(std::fs::read(local)?)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            Ok(url.to_string())
+        })
+    }
+}
@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config;
+use crate::downloader::{BatchSummary, DownloadOptions, DownloadRequest, Downloader};
+use crate::error::{AppError, Result};
+use crate::ffmpeg::FFmpeg;
+use crate::library::Library;
+use crate::soundcloud::model::Resource;
+use crate::soundcloud::{SoundcloudClient, TranscodingSelector};
+use crate::storage::{Storage, StorageConfig};
+use crate::template::{self, FilenameTemplate};
+use crate::util;
+
+/// Source naming the likes of the logged in user
+const LIKES_SOURCE: &str = "likes";
+const LIKES_CHUNK_SIZE: u32 = 50;
+
+/// A `[[sync]]` entry of the config file, a source kept downloaded into a directory
+///
+/// Filters and formats left out fall back to the options given on the command line.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SyncConfig {
+    /// Name selecting the definition with `sync <name>` (default: the source)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// `likes` for your likes, or the URL of a track, a playlist, a profile for its
+    /// uploads, or a profile's likes page, e.g. `https://soundcloud.com/user/likes`
+    pub source: String,
+
+    /// Output directory, `@music` is supported (default: a folder named after the
+    /// definition in the output directory)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+
+    /// Maximum number of likes or uploads to fetch [default: all]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+
+    /// Stop fetching likes at the first one already in the library
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_only: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_previews: Option<bool>,
+
+    /// Only download tracks uploaded on or after this date (YYYY-MM-DD)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploaded_after: Option<String>,
+
+    /// Only download tracks uploaded before this date (YYYY-MM-DD)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploaded_before: Option<String>,
+
+    /// Only download tracks at least this long, e.g. `30m`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_duration: Option<String>,
+
+    /// Only download tracks at most this long, e.g. `2h`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_duration: Option<String>,
+
+    /// Skip tracks whose audio file is larger than this, e.g. `200M`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_filesize: Option<String>,
+
+    /// Only download tracks whose title or artist matches this regex
+    #[serde(rename = "match", skip_serializing_if = "Option::is_none")]
+    pub match_filter: Option<String>,
+
+    /// Skip tracks whose title or artist matches this regex
+    #[serde(rename = "reject", skip_serializing_if = "Option::is_none")]
+    pub reject_filter: Option<String>,
+
+    /// Only download tracks with one of these licenses
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub licenses: Vec<String>,
+
+    /// File names of downloaded tracks, like `--filename-template`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename_template: Option<String>,
+
+    /// Order the streams of a track are tried in, like `--transcoding-order`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcoding_order: Option<String>,
+}
+
+impl SyncConfig {
+    /// Returns the name of the definition, its source if it has none
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.source)
+    }
+
+    /// Applies the filters and formats of the definition to the options of the run
+    ///
+    /// # Returns
+    /// Result containing the options, or an error naming an invalid value
+    pub fn options(&self, base: &DownloadOptions) -> Result<DownloadOptions> {
+        let mut options = base.clone();
+        if let Some(new_only) = self.new_only {
+            options.new_only = new_only;
+        }
+        if let Some(skip_previews) = self.skip_previews {
+            options.skip_previews = skip_previews;
+        }
+        if let Some(date) = &self.uploaded_after {
+            options.uploaded_after = Some(util::parse_date(date)?);
+        }
+        if let Some(date) = &self.uploaded_before {
+            options.uploaded_before = Some(util::parse_date(date)?);
+        }
+        if let Some(duration) = &self.min_duration {
+            options.min_duration = Some(util::parse_duration(duration)?);
+        }
+        if let Some(duration) = &self.max_duration {
+            options.max_duration = Some(util::parse_duration(duration)?);
+        }
+        if let Some(size) = &self.max_filesize {
+            options.max_filesize = Some(util::parse_size(size)?);
+        }
+        if let Some(pattern) = &self.match_filter {
+            options.match_filter = Some(util::parse_regex(pattern)?);
+        }
+        if let Some(pattern) = &self.reject_filter {
+            options.reject_filter = Some(util::parse_regex(pattern)?);
+        }
+        if !self.licenses.is_empty() {
+            options.licenses = self.licenses.clone();
+        }
+        if let Some(template) = &self.filename_template {
+            options.filename_template = FilenameTemplate::parse(template)?;
+        }
+        if let Some(order) = &self.transcoding_order {
+            options.transcodings = TranscodingSelector::parse(order)?;
+        }
+
+        Ok(options)
+    }
+}
+
+/// Runs `[[sync]]` definitions one after another, going on with the others when one
+/// fails
+pub struct Syncer {
+    client: SoundcloudClient,
+    ffmpeg: Option<FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+    options: DownloadOptions,
+    /// Directory definitions without an output directory get a folder in
+    output: PathBuf,
+    storage: Option<StorageConfig>,
+}
+
+impl Syncer {
+    /// Creates a syncer applying definitions on top of the options of the run
+    ///
+    /// # Arguments
+    /// * `output` - Directory definitions without an output directory get a folder in
+    /// * `storage` - Storage backend of the config file, set up for each output directory
+    pub fn new(
+        client: SoundcloudClient,
+        ffmpeg: Option<FFmpeg<PathBuf>>,
+        library: Arc<Library>,
+        options: DownloadOptions,
+        output: &Path,
+        storage: Option<StorageConfig>,
+    ) -> Self {
+        Self {
+            client,
+            ffmpeg,
+            library,
+            options,
+            output: output.to_path_buf(),
+            storage,
+        }
+    }
+
+    /// Runs each definition in turn
+    ///
+    /// # Returns
+    /// Result containing the counts across all definitions, or the last failure
+    pub async fn run(&self, definitions: &[SyncConfig]) -> Result<BatchSummary> {
+        let mut summary = BatchSummary::default();
+        let mut failure = None;
+
+        for definition in definitions {
+            tracing::info!("Syncing {}", definition.name());
+            match self.sync(definition).await {
+                Ok(sync_summary) => summary.merge(&sync_summary),
+                Err(AppError::Cancelled) => return Err(AppError::Cancelled),
+                Err(e) => {
+                    tracing::error!("Sync {} failed: {}", definition.name(), e);
+                    failure = Some(e);
+                }
+            }
+        }
+
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(summary),
+        }
+    }
+
+    async fn sync(&self, definition: &SyncConfig) -> Result<BatchSummary> {
+        let output = match &definition.output {
+            Some(output) => config::expand_output(output)?,
+            None => self.output.join(util::sanitize(definition.name())),
+        };
+
+        // Placeholders in the output directory are filled in per track, like the file name
+        let (output, output_template) = template::split_output(&output);
+        let mut options = definition.options(&self.options)?;
+        if let Some(dirs) = &output_template {
+            options.filename_template = options.filename_template.nested_in(dirs)?;
+        }
+        if let Some(storage) = &self.storage {
+            options.storage = Storage::from_config(storage, &output)?;
+        }
+
+        let limit = definition.limit.unwrap_or(u32::MAX);
+        let request = match likes_user(&definition.source) {
+            Some(username) => DownloadRequest::Likes {
+                user: Box::new(self.client.resolve_user(username).await?),
+                skip: 0,
+                limit,
+                chunk_size: LIKES_CHUNK_SIZE,
+            },
+            None => match self.client.resolve(&definition.source).await? {
+                Resource::Track(track) => DownloadRequest::TrackId {
+                    id: track.id,
+                    secret_token: track.secret_token,
+                },
+                Resource::Playlist(playlist) => DownloadRequest::Playlist(Box::new(playlist)),
+                Resource::User(user) => DownloadRequest::Uploads {
+                    user_id: user.id,
+                    limit,
+                },
+            },
+        };
+
+        let downloader = Downloader::new(
+            self.client.clone(),
+            &output,
+            self.ffmpeg.clone(),
+            self.library.clone(),
+            options,
+        )?;
+        if let Some(name) = &definition.name {
+            downloader.set_collection(name);
+        }
+        downloader.download(request).await
+    }
+}
+
+/// Returns whose likes a source names, `Some(None)` for the logged in user and `None`
+/// if it is not a likes source
+fn likes_user(source: &str) -> Option<Option<String>> {
+    let source = source.trim();
+    if source.eq_ignore_ascii_case(LIKES_SOURCE) {
+        return Some(None);
+    }
+
+    let url = reqwest::Url::parse(source).ok()?;
+    if !matches!(
+        url.host_str()?,
+        "soundcloud.com" | "www.soundcloud.com" | "m.soundcloud.com"
+    ) {
+        return None;
+    }
+    match url.path_segments()?.collect::<Vec<_>>().as_slice() {
+        [user, "likes"] | [user, "likes", ""] => Some(Some(user.to_string())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_likes_sources() {
+        assert_eq!(likes_user(" likes "), Some(None));
+        assert_eq!(
+            likes_user("https://soundcloud.com/user/likes"),
+            Some(Some("user".into()))
+        );
+        assert_eq!(
+            likes_user("https://m.soundcloud.com/user/likes/"),
+            Some(Some("user".into()))
+        );
+        assert_eq!(likes_user("https://soundcloud.com/user"), None);
+        assert_eq!(likes_user("https://evilsoundcloud.com/user/likes"), None);
+        assert_eq!(
+            likes_user("https://soundcloud.com.example/user/likes"),
+            None
+        );
+    }
+
+    #[test]
+    fn new_only_falls_back_to_the_command_line() {
+        let base = DownloadOptions {
+            new_only: true,
+            ..DownloadOptions::default()
+        };
+        let definition = |new_only| SyncConfig {
+            new_only,
+            ..SyncConfig::default()
+        };
+
+        assert!(definition(None).options(&base).unwrap().new_only);
+        assert!(!definition(Some(false)).options(&base).unwrap().new_only);
+    }
+}
@@ -0,0 +1,260 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, Result};
+use crate::soundcloud::model::Track;
+
+/// Track fields that can be mapped to tags in the config
+const MAPPABLE_FIELDS: &[&str] = &[
+    "id",
+    "title",
+    "artist",
+    "user_permalink",
+    "permalink",
+    "permalink_url",
+    "description",
+    "genre",
+    "tag_list",
+    "label_name",
+    "license",
+    "artwork_url",
+    "created_at",
+    "release_date",
+    "playback_count",
+    "likes_count",
+    "downloadable",
+    "playlist",
+];
+
+/// A text tag written to downloaded files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    TrackNumber,
+    Copyright,
+    /// Free text comment, written as a `COMM` frame rather than a text frame in ID3
+    Comment,
+    /// Release date as `YYYY-MM-DD`, split into `TYER` and `TDAT` for ID3v2.3
+    Date,
+}
+
+impl TagField {
+    /// ID3v2 text frame the field is written to
+    pub fn id3_frame(self) -> &'static str {
+        match self {
+            Self::Title => "TIT2",
+            Self::Artist => "TPE1",
+            Self::Album => "TALB",
+            Self::Genre => "TCON",
+            Self::TrackNumber => "TRCK",
+            Self::Copyright => "TCOP",
+            Self::Comment => "COMM",
+            Self::Date => "TDRC",
+        }
+    }
+
+    /// FFmpeg `-metadata` key the field is written to
+    pub fn ffmpeg_key(self) -> &'static str {
+        match self {
+            Self::Title => "title",
+            Self::Artist => "artist",
+            Self::Album => "album",
+            Self::Genre => "genre",
+            Self::TrackNumber => "track",
+            Self::Copyright => "copyright",
+            Self::Comment => "comment",
+            Self::Date => "date",
+        }
+    }
+}
+
+/// A track field written to a tag of the user's choosing, from `[[tags.mapping]]` in
+/// the config
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TagMapping {
+    /// Track field, e.g. `permalink_url`, `label_name` or `playlist`
+    pub field: String,
+    /// ID3v2 frame of MP3 files, e.g. `WOAS`, `TPUB` or `TIT1`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id3: Option<String>,
+    /// FFmpeg metadata key of M4A and Ogg files, e.g. `grouping`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ffmpeg: Option<String>,
+}
+
+/// `[tags]` section of the config file
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TagConfig {
+    #[serde(default)]
+    pub mapping: Vec<TagMapping>,
+}
+
+impl TagMapping {
+    /// Checks that the field exists and the ID3 frame is a text or URL frame
+    pub fn validate(&self) -> Result<()> {
+        if !MAPPABLE_FIELDS.contains(&self.field.as_str()) {
+            return Err(AppError::Configuration(format!(
+                "Unknown tag mapping field \"{}\", expected one of: {}",
+                self.field,
+                MAPPABLE_FIELDS.join(", ")
+            )));
+        }
+
+        if let Some(frame) = &self.id3 {
+            let valid = frame.len() == 4
+                && (frame.starts_with('T') || frame.starts_with('W'))
+                && frame
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+            if !valid {
+                return Err(AppError::Configuration(format!(
+                    "Tag mapping of \"{}\" must use an ID3 text (T...) or URL (W...) frame, got \"{}\"",
+                    self.field, frame
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value of the mapped field for a track, if it has one
+    ///
+    /// # Arguments
+    /// * `track` - The track being tagged
+    /// * `playlist` - Title of the playlist the track is downloaded from
+    pub fn value(&self, track: &Track, playlist: Option<&str>) -> Option<String> {
+        let date = |date: Option<chrono::DateTime<chrono::Utc>>| {
+            date.map(|date| date.format("%Y-%m-%d").to_string())
+        };
+
+        match self.field.as_str() {
+            "id" => Some(track.id.to_string()),
+            "title" => Some(track.title.clone()),
+            "artist" => Some(track.user.username.clone()),
+            "user_permalink" => Some(track.user.permalink.clone()),
+            "permalink" => Some(track.permalink.clone()),
+            "permalink_url" => Some(track.permalink_url.clone()),
+            "description" => track.description.clone(),
+            "genre" => track.genre.clone(),
+            "tag_list" => Some(track.tags().join(", ")),
+            "label_name" => track.label_name.clone(),
+            "license" => track.license.clone(),
+            "artwork_url" => track.artwork_url.clone(),
+            "created_at" => date(track.created_at),
+            "release_date" => date(track.release_date()),
+            "playback_count" => track.playback_count.map(|count| count.to_string()),
+            "likes_count" => track.likes_count.map(|count| count.to_string()),
+            "downloadable" => Some(track.downloadable.to_string()),
+            "playlist" => playlist.map(str::to_string),
+            _ => None,
+        }
+        .filter(|value| !value.is_empty())
+    }
+}
+
+/// Text tags for a track, shared by the ID3 and FFmpeg tagging paths
+#[derive(Clone, Debug, Default)]
+pub struct Tags {
+    fields: Vec<(TagField, String)>,
+    /// Values of configured tag mappings
+    custom: Vec<(TagMapping, String)>,
+}
+
+impl Tags {
+    pub fn from_track(track: &Track) -> Self {
+        let mut tags = Self::default();
+        tags.push(TagField::Title, track.title.clone());
+        tags.push(TagField::Artist, track.user.username.clone());
+
+        if let Some(genre) = track.genre.clone().filter(|genre| !genre.is_empty()) {
+            tags.push(TagField::Genre, genre);
+        }
+
+        if let Some(license) = &track.license {
+            tags.push(TagField::Copyright, license.clone());
+        }
+
+        if let Some(date) = track.release_date() {
+            tags.push(TagField::Date, date.format("%Y-%m-%d").to_string());
+        }
+
+        tags
+    }
+
+    /// Sets a field, replacing the value it already has
+    pub fn push(&mut self, field: TagField, value: String) {
+        match self.fields.iter_mut().find(|(f, _)| *f == field) {
+            Some((_, old)) => *old = value,
+            None => self.fields.push((field, value)),
+        }
+    }
+
+    /// Adds the values of the configured tag mappings
+    ///
+    /// # Arguments
+    /// * `mappings` - Configured tag mappings
+    /// * `track` - The track being tagged
+    /// * `playlist` - Title of the playlist the track is downloaded from
+    pub fn push_mapped(&mut self, mappings: &[TagMapping], track: &Track, playlist: Option<&str>) {
+        for mapping in mappings {
+            if let Some(value) = mapping.value(track, playlist) {
+                self.custom.push((mapping.clone(), value));
+            }
+        }
+    }
+
+    /// Returns the first value of a field
+    pub fn get(&self, field: TagField) -> Option<&str> {
+        self.iter()
+            .find(|(f, _)| *f == field)
+            .map(|(_, value)| value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TagField, &str)> {
+        self.fields
+            .iter()
+            .map(|(field, value)| (*field, value.as_str()))
+    }
+
+    /// Returns the mapped ID3 frames and their values
+    pub fn id3_custom(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.custom.iter().filter_map(|(mapping, value)| {
+            mapping.id3.as_deref().map(|frame| (frame, value.as_str()))
+        })
+    }
+
+    /// Returns every FFmpeg metadata key and value, standard fields followed by
+    /// mapped ones
+    pub fn ffmpeg_metadata(&self) -> impl Iterator<Item = (&str, &str)> {
+        let custom = self.custom.iter().filter_map(|(mapping, value)| {
+            mapping.ffmpeg.as_deref().map(|key| (key, value.as_str()))
+        });
+
+        self.iter()
+            .map(|(field, value)| (field.ffmpeg_key(), value))
+            .chain(custom)
+    }
+}
+
+/// ID3v2 version MP3 tags are written with
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Id3Version {
+    /// Understood by older players and car stereos
+    #[value(name = "2.3")]
+    V23,
+    #[default]
+    #[value(name = "2.4")]
+    V24,
+}
+
+impl From<Id3Version> for id3::Version {
+    fn from(version: Id3Version) -> Self {
+        match version {
+            Id3Version::V23 => Self::Id3v23,
+            Id3Version::V24 => Self::Id3v24,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use crate::error::Result;
+
+/// Prefix of the temporary directories of a run
+const RUN_DIR_PREFIX: &str = "soundcloud-dl-";
+
+/// Directories of runs that were killed are removed once they are this old
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Directory holding the temporary files of this run, set by [`init`]
+static RUN_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Removes the temporary directory of the run when dropped
+#[must_use]
+#[derive(Debug)]
+pub struct TempDirGuard(PathBuf);
+
+impl Drop for TempDirGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Creates a directory of its own for the temporary files of this run, so parallel
+/// runs and other tools never share names with it
+///
+/// Files in it are removed by their own guards as soon as they are no longer needed,
+/// and the directory itself when the guard is dropped. Directories left behind by runs
+/// that were killed are cleaned up first.
+///
+/// # Arguments
+/// * `base` - Directory to create it in, e.g. on the same drive as the output
+///   (default: the system temp directory)
+///
+/// # Returns
+/// The guard removing the directory, to be kept until the run ends
+pub fn init(base: Option<&Path>) -> Result<TempDirGuard> {
+    let base = base.map_or_else(std::env::temp_dir, Path::to_path_buf);
+    fs::create_dir_all(&base)?;
+    remove_stale(&base);
+
+    let dir = tempfile::Builder::new()
+        .prefix(RUN_DIR_PREFIX)
+        .tempdir_in(&base)?
+        .into_path();
+    let _ = RUN_DIR.set(dir.clone());
+
+    tracing::debug!("Temporary files go to {}", dir.display());
+    Ok(TempDirGuard(dir))
+}
+
+/// Returns the directory for temporary files, the system temp directory if [`init`]
+/// was not called
+pub fn dir() -> PathBuf {
+    RUN_DIR.get().cloned().unwrap_or_else(std::env::temp_dir)
+}
+
+/// Removes the temporary directories of earlier runs that were not modified recently
+fn remove_stale(base: &Path) {
+    let Ok(entries) = fs::read_dir(base) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(RUN_DIR_PREFIX)
+        {
+            continue;
+        }
+        let stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > STALE_AFTER);
+        if stale && entry.path().is_dir() {
+            tracing::debug!("Removing stale temporary files {}", entry.path().display());
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{AppError, Result};
+use crate::soundcloud::model::Track;
+use crate::util;
+
+/// Template used when none is given, `Artist - Title.mp3`
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{artist} - {title}";
+/// `{collection}` of tracks downloaded on their own rather than from a playlist or likes
+const DEFAULT_COLLECTION: &str = "Tracks";
+
+/// A value of the track filled into a filename template
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Placeholder {
+    /// Display name of the uploader
+    Artist,
+    Title,
+    Id,
+    /// Profile URL name of the uploader
+    User,
+    Genre,
+    Label,
+    /// Release date as `YYYY-MM-DD`
+    Date,
+    Year,
+    /// Title of the playlist, or a name such as `Likes` for other collections
+    Collection,
+}
+
+impl Placeholder {
+    const ALL: &'static [(&'static str, Self)] = &[
+        ("artist", Self::Artist),
+        ("title", Self::Title),
+        ("id", Self::Id),
+        ("user", Self::User),
+        ("genre", Self::Genre),
+        ("label", Self::Label),
+        ("date", Self::Date),
+        ("year", Self::Year),
+        ("collection", Self::Collection),
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(placeholder, _)| *placeholder == name)
+            .map(|(_, placeholder)| *placeholder)
+    }
+
+    fn value(self, track: &Track, collection: Option<&str>) -> String {
+        match self {
+            Self::Artist if util::is_empty(&util::sanitize(&track.user.username)) => {
+                track.user.permalink.clone()
+            }
+            Self::Artist => track.user.username.clone(),
+            Self::Title if util::is_empty(&track.title) => track.permalink.clone(),
+            Self::Title => track.title.clone(),
+            Self::Id => track.id.to_string(),
+            Self::User => track.user.permalink.clone(),
+            Self::Genre => track.genre.clone().unwrap_or_default(),
+            Self::Label => track.label_name.clone().unwrap_or_default(),
+            Self::Date => track
+                .release_date()
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+            Self::Year => track
+                .release_date()
+                .map(|date| date.format("%Y").to_string())
+                .unwrap_or_default(),
+            Self::Collection => collection.unwrap_or(DEFAULT_COLLECTION).to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// File name of downloaded tracks with `{placeholder}`s filled in from the track,
+/// e.g. `{artist}/{date} {title}`
+///
+/// `/` separates subdirectories of the output directory, the extension is added
+/// automatically.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilenameTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl FilenameTemplate {
+    /// Parses a template, rejecting unknown placeholders and unbalanced braces
+    pub fn parse(template: &str) -> Result<Self> {
+        let invalid = |reason: String| {
+            AppError::Configuration(format!(
+                "Invalid filename template '{}': {}",
+                template, reason
+            ))
+        };
+
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err(invalid("unmatched '{'".into()));
+                    }
+
+                    let placeholder = Placeholder::from_name(&name).ok_or_else(|| {
+                        let names = Placeholder::ALL
+                            .iter()
+                            .map(|(name, _)| format!("{{{}}}", name))
+                            .collect::<Vec<_>>();
+                        invalid(format!(
+                            "unknown placeholder {{{}}}, expected one of {}",
+                            name,
+                            names.join(", ")
+                        ))
+                    })?;
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                }
+                '}' => return Err(invalid("unmatched '}'".into())),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        let components = template.split(['/', '\\']).collect::<Vec<_>>();
+        if components
+            .iter()
+            .any(|component| component.trim().is_empty())
+        {
+            return Err(invalid("path components must not be empty".into()));
+        }
+        if components.contains(&"..") {
+            return Err(invalid("'..' is not allowed".into()));
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+            segments,
+        })
+    }
+
+    /// Returns the template placed in subdirectories given as a template themselves,
+    /// e.g. the placeholder part of the output directory
+    pub fn nested_in(&self, dirs: &str) -> Result<Self> {
+        Self::parse(&format!(
+            "{}/{}",
+            dirs.trim_end_matches(['/', '\\']),
+            self.template
+        ))
+    }
+
+    /// Whether `{collection}` is used, so tracks of a playlist need no directory of
+    /// their own
+    pub fn has_collection(&self) -> bool {
+        self.segments
+            .contains(&Segment::Placeholder(Placeholder::Collection))
+    }
+
+    /// Number of subdirectories of the output directory the template places files in
+    pub fn depth(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.matches(['/', '\\']).count(),
+                Segment::Placeholder(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Whether this is the `{artist} - {title}` default
+    pub fn is_default(&self) -> bool {
+        self.template == DEFAULT_FILENAME_TEMPLATE
+    }
+
+    /// Returns the path of a track's file in `dir`
+    ///
+    /// # Arguments
+    /// * `dir` - Output directory the template is relative to
+    /// * `track` - Track to fill in the placeholders from
+    /// * `collection` - Playlist title or collection name filled in for `{collection}`
+    /// * `suffix` - Appended to the file name, e.g. ` [id]`
+    /// * `ext` - File extension
+    ///
+    /// # Returns
+    /// The file path, shortened to fit file system limits
+    pub fn file_path(
+        &self,
+        dir: &Path,
+        track: &Track,
+        collection: Option<&str>,
+        suffix: &str,
+        ext: &str,
+    ) -> PathBuf {
+        if self.is_default() {
+            // Keeps the artist recognisable when the title has to be shortened
+            return util::track_file_path(
+                dir,
+                &Placeholder::Artist.value(track, collection),
+                &Placeholder::Title.value(track, collection),
+                suffix,
+                ext,
+            );
+        }
+
+        util::template_file_path(dir, &self.render(track, collection), suffix, ext)
+    }
+
+    /// Fills in the placeholders, returning the path components of the file name
+    ///
+    /// Separators in track values are sanitized later, so only `/` in the template
+    /// itself starts a subdirectory.
+    fn render(&self, track: &Track, collection: Option<&str>) -> Vec<String> {
+        let mut components = vec![String::new()];
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => {
+                    let mut parts = literal.split(['/', '\\']);
+                    if let Some(first) = parts.next() {
+                        components.last_mut().unwrap().push_str(first);
+                    }
+                    components.extend(parts.map(str::to_string));
+                }
+                Segment::Placeholder(placeholder) => components
+                    .last_mut()
+                    .unwrap()
+                    .push_str(&placeholder.value(track, collection)),
+            }
+        }
+
+        components
+    }
+}
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_FILENAME_TEMPLATE).expect("default template is valid")
+    }
+}
+
+/// Splits an output directory into the directory before its first placeholder and the
+/// rest, e.g. `Music/{collection}/{artist}` into `Music` and `{collection}/{artist}`
+///
+/// # Returns
+/// The directory tracks are saved under, and the placeholder part if there is one
+pub fn split_output(output: &Path) -> (PathBuf, Option<String>) {
+    let mut base = PathBuf::new();
+    let mut components = output.components();
+
+    for component in components.by_ref() {
+        let name = component.as_os_str().to_string_lossy();
+        if name.contains('{') {
+            let rest = std::iter::once(name.into_owned())
+                .chain(components.map(|c| c.as_os_str().to_string_lossy().into_owned()))
+                .collect::<Vec<_>>();
+            if base.as_os_str().is_empty() {
+                base.push(".");
+            }
+            return (base, Some(rest.join("/")));
+        }
+        base.push(component);
+    }
+
+    (base, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_subdirectories() {
+        let depth = |template: &str| FilenameTemplate::parse(template).unwrap().depth();
+
+        assert_eq!(depth(DEFAULT_FILENAME_TEMPLATE), 0);
+        assert_eq!(depth("{artist}/{title}"), 1);
+        assert_eq!(depth("Music\\{artist}/{date} {title}"), 2);
+    }
+}
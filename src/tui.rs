@@ -0,0 +1,231 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::error::Result;
+use crate::soundcloud::model::Track;
+use crate::util;
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortOrder {
+    Original,
+    Title,
+    Artist,
+    Duration,
+}
+
+impl SortOrder {
+    fn next(self) -> Self {
+        match self {
+            Self::Original => Self::Title,
+            Self::Title => Self::Artist,
+            Self::Artist => Self::Duration,
+            Self::Duration => Self::Original,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Original => "original",
+            Self::Title => "title",
+            Self::Artist => "artist",
+            Self::Duration => "duration",
+        }
+    }
+}
+
+/// State of the track picker
+struct App {
+    tracks: Vec<Track>,
+    checked: Vec<bool>,
+    /// Indices into `tracks` matching the filter, in display order
+    visible: Vec<usize>,
+    filter: String,
+    editing_filter: bool,
+    sort: SortOrder,
+    list_state: ListState,
+}
+
+impl App {
+    fn new(tracks: Vec<Track>) -> Self {
+        let mut app = Self {
+            checked: vec![false; tracks.len()],
+            tracks,
+            visible: Vec::new(),
+            filter: String::new(),
+            editing_filter: false,
+            sort: SortOrder::Original,
+            list_state: ListState::default(),
+        };
+        app.refresh();
+        app
+    }
+
+    /// Recomputes the visible tracks after the filter or sort order changed
+    fn refresh(&mut self) {
+        let filter = self.filter.to_lowercase();
+
+        self.visible = (0..self.tracks.len())
+            .filter(|&i| {
+                let track = &self.tracks[i];
+                filter.is_empty()
+                    || track.title.to_lowercase().contains(&filter)
+                    || track.user.username.to_lowercase().contains(&filter)
+            })
+            .collect();
+
+        let tracks = &self.tracks;
+        match self.sort {
+            SortOrder::Original => {}
+            SortOrder::Title => self
+                .visible
+                .sort_by_key(|&i| tracks[i].title.to_lowercase()),
+            SortOrder::Artist => self
+                .visible
+                .sort_by_key(|&i| tracks[i].user.username.to_lowercase()),
+            SortOrder::Duration => self.visible.sort_by_key(|&i| tracks[i].full_duration),
+        }
+
+        let selected = self
+            .list_state
+            .selected()
+            .filter(|&i| i < self.visible.len())
+            .or(if self.visible.is_empty() {
+                None
+            } else {
+                Some(0)
+            });
+        self.list_state.select(selected);
+    }
+
+    fn toggle_current(&mut self) {
+        if let Some(&i) = self
+            .list_state
+            .selected()
+            .and_then(|selected| self.visible.get(selected))
+        {
+            self.checked[i] = !self.checked[i];
+        }
+    }
+
+    /// Checks every visible track, or unchecks them if they are all checked already
+    fn toggle_all(&mut self) {
+        let check = !self.visible.iter().all(|&i| self.checked[i]);
+        for &i in &self.visible {
+            self.checked[i] = check;
+        }
+    }
+
+    fn into_selected(self) -> Vec<Track> {
+        self.tracks
+            .into_iter()
+            .zip(self.checked)
+            .filter_map(|(track, checked)| checked.then_some(track))
+            .collect()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, status_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(3)]).areas(frame.area());
+
+        let items = self
+            .visible
+            .iter()
+            .map(|&i| {
+                let track = &self.tracks[i];
+                ListItem::new(format!(
+                    "[{}] {} - {} [{}]",
+                    if self.checked[i] { "x" } else { " " },
+                    track.user.username,
+                    track.title,
+                    util::format_duration(track.full_duration.max(track.duration)),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let checked = self.checked.iter().filter(|&&c| c).count();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                " {} of {} tracks selected | sort: {} ",
+                checked,
+                self.tracks.len(),
+                self.sort.name()
+            )))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        let status = if self.editing_filter {
+            Line::from(format!("Filter: {}_", self.filter))
+        } else {
+            Line::from(format!(
+                "space: toggle | a: toggle all | /: filter ({}) | s: sort | enter: download | q: quit",
+                if self.filter.is_empty() { "none" } else { &self.filter }
+            ))
+        };
+        frame.render_widget(
+            Paragraph::new(status).block(Block::default().borders(Borders::ALL)),
+            status_area,
+        );
+    }
+}
+
+/// Shows an interactive picker for the given tracks
+///
+/// # Arguments
+/// * `tracks` - Tracks to choose from
+///
+/// # Returns
+/// Result containing the tracks the user checked, empty if the picker was quit
+pub fn select_tracks(tracks: Vec<Track>) -> Result<Vec<Track>> {
+    let mut terminal = ratatui::try_init()?;
+    let result = run(&mut terminal, App::new(tracks));
+    ratatui::restore();
+    result
+}
+
+fn run(terminal: &mut DefaultTerminal, mut app: App) -> Result<Vec<Track>> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if app.editing_filter {
+            match key.code {
+                KeyCode::Enter | KeyCode::Esc => app.editing_filter = false,
+                KeyCode::Backspace => {
+                    app.filter.pop();
+                    app.refresh();
+                }
+                KeyCode::Char(c) => {
+                    app.filter.push(c);
+                    app.refresh();
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(Vec::new()),
+            KeyCode::Enter => return Ok(app.into_selected()),
+            KeyCode::Down | KeyCode::Char('j') => app.list_state.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => app.list_state.select_previous(),
+            KeyCode::Char(' ') => app.toggle_current(),
+            KeyCode::Char('a') => app.toggle_all(),
+            KeyCode::Char('/') => app.editing_filter = true,
+            KeyCode::Char('s') => {
+                app.sort = app.sort.next();
+                app.refresh();
+            }
+            _ => {}
+        }
+    }
+}
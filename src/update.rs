@@ -0,0 +1,203 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{AppError, Result};
+use crate::soundcloud::SoundcloudClient;
+use crate::util;
+
+const RELEASES_URL: &str = "https://api.github.com/repos/damaredayo/soundcloud-dl/releases/latest";
+const USER_AGENT: &str = concat!("soundcloud-dl/", env!("CARGO_PKG_VERSION"));
+/// Release asset listing the SHA-256 checksums of every binary
+const CHECKSUMS_ASSET: &str = "checksums.sha256";
+const PROGRESS_TEMPLATE: &str = "{bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})";
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|asset| asset.name == name)
+    }
+}
+
+/// Name of the release asset built for this platform, e.g. `soundcloud-dl-linux-x86_64`
+fn asset_name() -> String {
+    format!(
+        "soundcloud-dl-{}-{}{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+/// Parses `major.minor.patch` into comparable numbers, ignoring pre-release suffixes
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .split(['-', '+'])
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+async fn latest_release() -> Result<Release> {
+    let release = reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .build()?
+        .get(RELEASES_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Release>()
+        .await?;
+
+    Ok(release)
+}
+
+/// Updates the running executable to the latest GitHub release
+///
+/// # Arguments
+/// * `client` - Client used to download the release binary
+/// * `check_only` - Only report whether an update is available
+///
+/// # Returns
+/// Result indicating success or failure
+pub async fn self_update(client: &SoundcloudClient, check_only: bool) -> Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let exe = current_exe()?;
+    remove_stale(&exe);
+
+    let release = latest_release().await?;
+    if parse_version(release.version()) <= parse_version(current) {
+        tracing::info!("soundcloud-dl {} is up to date", current);
+        return Ok(());
+    }
+
+    if check_only {
+        tracing::info!(
+            "soundcloud-dl {} is available (installed: {})",
+            release.version(),
+            current
+        );
+        return Ok(());
+    }
+
+    let name = asset_name();
+    let asset = release.asset(&name).ok_or_else(|| {
+        AppError::Update(format!(
+            "Release {} has no binary for this platform ({})",
+            release.tag_name, name
+        ))
+    })?;
+    let checksums = release.asset(CHECKSUMS_ASSET).ok_or_else(|| {
+        AppError::Update(format!("Release {} has no checksums", release.tag_name))
+    })?;
+
+    let checksums = reqwest::get(&checksums.browser_download_url)
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let expected = util::find_checksum(&checksums, &name)
+        .ok_or_else(|| AppError::Update(format!("No checksum published for {}", name)))?;
+
+    tracing::info!("Downloading soundcloud-dl {}", release.version());
+    let new_exe = exe.with_extension("new");
+    let _ = fs::remove_file(&new_exe);
+    let progress = ProgressBar::no_length().with_style(
+        ProgressStyle::with_template(PROGRESS_TEMPLATE)
+            .unwrap_or_else(|_| ProgressStyle::default_bar()),
+    );
+    let result = client
+        .download_file(&asset.browser_download_url, &new_exe, &progress)
+        .await;
+    progress.finish_and_clear();
+    result?;
+
+    let actual = util::sha256_hex(&fs::read(&new_exe)?);
+    if !actual.eq_ignore_ascii_case(&expected) {
+        let _ = fs::remove_file(&new_exe);
+        return Err(AppError::Update(format!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            name, expected, actual
+        )));
+    }
+
+    replace_exe(&exe, &new_exe)?;
+    tracing::info!(
+        "Updated soundcloud-dl from {} to {}",
+        current,
+        release.version()
+    );
+
+    Ok(())
+}
+
+/// Returns the path of the running executable with symlinks resolved, so the real
+/// binary is replaced rather than a link to it
+fn current_exe() -> Result<PathBuf> {
+    let exe = std::env::current_exe()?;
+    Ok(fs::canonicalize(&exe).unwrap_or(exe))
+}
+
+/// Path the previous executable is moved to while it is still running on Windows
+fn old_exe(exe: &Path) -> PathBuf {
+    exe.with_extension("old")
+}
+
+/// Deletes the executable left behind by a previous update on Windows
+fn remove_stale(exe: &Path) {
+    let old = old_exe(exe);
+    if old.exists() {
+        if let Err(e) = fs::remove_file(&old) {
+            tracing::debug!("Failed to remove {}: {}", old.display(), e);
+        }
+    }
+}
+
+/// Moves `new_exe` over the running executable
+#[cfg(unix)]
+fn replace_exe(exe: &Path, new_exe: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(new_exe, fs::Permissions::from_mode(0o755))?;
+    // The running process keeps the old inode, so renaming over it is safe
+    fs::rename(new_exe, exe)?;
+
+    Ok(())
+}
+
+/// Moves `new_exe` over the running executable
+///
+/// A running executable cannot be overwritten on Windows but it can be renamed, so it is
+/// moved aside first and deleted by the next update.
+#[cfg(windows)]
+fn replace_exe(exe: &Path, new_exe: &Path) -> Result<()> {
+    let old = old_exe(exe);
+    let _ = fs::remove_file(&old);
+    fs::rename(exe, &old)?;
+
+    if let Err(e) = fs::rename(new_exe, exe) {
+        // Put the working executable back
+        fs::rename(&old, exe)?;
+        return Err(e.into());
+    }
+
+    Ok(())
+}
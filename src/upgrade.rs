@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::downloader::{BatchSummary, DownloadOptions, Downloader};
+use crate::error::{AppError, Result};
+use crate::ffmpeg::FFmpeg;
+use crate::library::{Library, LibraryEntry};
+use crate::soundcloud::model::{Quality, Track};
+use crate::soundcloud::SoundcloudClient;
+use crate::util;
+
+/// Difference in length below which the audio is considered unchanged
+const DURATION_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// A downloaded track with a better version on SoundCloud
+struct Upgrade {
+    track: Track,
+    path: PathBuf,
+    reason: String,
+}
+
+/// Downloads library tracks again whose audio was replaced or improved since they
+/// were downloaded
+///
+/// A track is upgraded when its length changed, when only a preview was downloaded
+/// and the full track is now available, or when it was downloaded in standard quality
+/// and a high quality stream is now offered.
+pub struct Upgrader {
+    client: SoundcloudClient,
+    ffmpeg: Option<FFmpeg<PathBuf>>,
+    library: Arc<Library>,
+    options: DownloadOptions,
+    /// Keep the replaced file as `<name>.bak`
+    keep_backup: bool,
+}
+
+impl Upgrader {
+    pub fn new(
+        client: SoundcloudClient,
+        ffmpeg: Option<FFmpeg<PathBuf>>,
+        library: Arc<Library>,
+        options: DownloadOptions,
+        keep_backup: bool,
+    ) -> Self {
+        Self {
+            client,
+            ffmpeg,
+            library,
+            options,
+            keep_backup,
+        }
+    }
+
+    /// Checks every track in the library and downloads the improved ones again
+    ///
+    /// # Returns
+    /// Result containing the download counts of the upgraded tracks
+    pub async fn run(&self) -> Result<BatchSummary> {
+        let upgrades = self.find_upgrades().await?;
+        tracing::info!("Found {} track(s) to upgrade", upgrades.len());
+
+        let mut summary = BatchSummary::default();
+        for upgrade in upgrades {
+            tracing::info!(
+                status = "Upgrading",
+                "{}, {}",
+                upgrade.track.permalink_url,
+                upgrade.reason
+            );
+            summary.merge(&self.upgrade(upgrade).await?);
+        }
+
+        Ok(summary)
+    }
+
+    /// Fetches the current metadata of every library track whose file still exists
+    /// and compares it with what was downloaded
+    async fn find_upgrades(&self) -> Result<Vec<Upgrade>> {
+        let entries = self
+            .library
+            .entries()
+            .into_iter()
+            .filter(|(_, entry)| entry.path.is_file())
+            .collect::<Vec<_>>();
+        tracing::info!(
+            "Checking {} downloaded track(s) for upgrades",
+            entries.len()
+        );
+
+        // Public tracks are fetched in batches, private ones need their secret token
+        let ids = entries
+            .iter()
+            .filter(|(_, entry)| util::secret_token(&entry.permalink_url).is_none())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let mut tracks = self
+            .client
+            .fetch_tracks(&ids, None)
+            .await?
+            .into_iter()
+            .map(|track| (track.id, track))
+            .collect::<HashMap<_, _>>();
+
+        // The batch endpoint silently leaves out tracks that are gone, ask for those
+        // one by one to tell them apart
+        for (id, entry) in &entries {
+            if tracks.contains_key(id) {
+                self.library.mark_available(*id).await?;
+                continue;
+            }
+
+            let secret_token = util::secret_token(&entry.permalink_url);
+            let result = self.client.fetch_track(*id, secret_token.as_deref()).await;
+            self.library.update_availability(*id, &result).await?;
+            match result {
+                Ok(track) => {
+                    tracks.insert(*id, track);
+                }
+                Err(AppError::NotFound(_)) => {}
+                Err(e) => tracing::warn!("Failed to fetch {}: {}", entry.permalink_url, e),
+            }
+        }
+
+        let mut upgrades = Vec::new();
+        for (id, entry) in entries {
+            let Some(track) = tracks.remove(&id) else {
+                continue;
+            };
+
+            if let Some(reason) = self.upgrade_reason(&entry, &track).await {
+                upgrades.push(Upgrade {
+                    track,
+                    path: entry.path,
+                    reason,
+                });
+            }
+        }
+
+        Ok(upgrades)
+    }
+
+    /// Describes why the current version of a track is better than the downloaded one,
+    /// `None` if it is not
+    async fn upgrade_reason(&self, entry: &LibraryEntry, track: &Track) -> Option<String> {
+        if track.is_geo_blocked() || (track.is_preview() && self.options.skip_previews) {
+            return None;
+        }
+
+        if entry.preview && !track.is_preview() {
+            return Some("the full track is now available".into());
+        }
+
+        // Tracks downloaded before lengths were recorded are measured instead
+        let downloaded = match (entry.duration, &self.ffmpeg) {
+            (Some(ms), _) => Some(Duration::from_millis(ms)),
+            (None, Some(ffmpeg)) => ffmpeg
+                .probe(&entry.path)
+                .await
+                .ok()
+                .and_then(|probe| probe.duration),
+            (None, None) => None,
+        };
+        let current = Duration::from_millis(track.duration);
+        if let Some(downloaded) = downloaded.filter(|_| !current.is_zero()) {
+            if downloaded.abs_diff(current) > DURATION_TOLERANCE {
+                return Some(format!(
+                    "the audio was replaced ({} -> {})",
+                    util::format_duration(downloaded.as_millis() as u64),
+                    util::format_duration(track.duration)
+                ));
+            }
+        }
+
+        let selector = match self.ffmpeg {
+            Some(_) => self.options.transcodings.clone(),
+            None => self.options.transcodings.without_ffmpeg(),
+        };
+        let hq_available = selector
+            .select(&track.media.transcodings)
+            .is_some_and(|transcoding| transcoding.quality == Quality::Hq);
+        if entry.quality.as_deref() == Some(Quality::Sq.as_str()) && hq_available {
+            return Some("a high quality stream is now available".into());
+        }
+
+        None
+    }
+
+    /// Downloads a track again, moving the old file aside until the new one is saved
+    ///
+    /// The old file is restored when the download fails.
+    async fn upgrade(&self, upgrade: Upgrade) -> Result<BatchSummary> {
+        let Upgrade { track, path, .. } = upgrade;
+        // The template's subdirectories are recreated below the output directory
+        let depth = self.options.filename_template.depth();
+        let dir = path
+            .ancestors()
+            .nth(depth + 1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let backup = backup_path(&path);
+
+        let downloader = Downloader::new(
+            self.client.clone(),
+            dir,
+            self.ffmpeg.clone(),
+            self.library.clone(),
+            self.options.clone(),
+        )?;
+
+        fs::rename(&path, &backup)?;
+        let result = downloader.download_tracks(vec![track]).await;
+
+        if matches!(&result, Ok(summary) if summary.downloaded == 1) {
+            if !self.keep_backup {
+                fs::remove_file(&backup)?;
+            }
+        } else if !path.exists() {
+            fs::rename(&backup, &path)?;
+        }
+
+        result
+    }
+}
+
+/// Returns the `<name>.bak` path an upgraded file is moved to
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
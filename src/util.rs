@@ -1,7 +1,226 @@
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use regex::{Regex, RegexBuilder};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use sysinfo::Disks;
+
+use crate::error::{AppError, Result};
+use crate::soundcloud::model::Playlist;
+
+/// Longest file name most file systems accept, in bytes
+const MAX_NAME_LEN: usize = 255;
+/// Longest path the regular Windows APIs accept
+const WINDOWS_MAX_PATH: usize = 260;
+/// Room for the `.<name>.part` and `.trim.<ext>` variants of a file name
+const TEMP_NAME_OVERHEAD: usize = 12;
+/// Shortest title kept when a file name is shortened to fit
+const MIN_TITLE_LEN: usize = 16;
+/// Extensions of the audio files this tool writes
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "ogg", "opus"];
+
+/// A set of 1-based item positions parsed from an expression like `1-10,15,20-`
+#[derive(Clone, Debug)]
+pub struct ItemRanges(Vec<(usize, Option<usize>)>);
+
+impl ItemRanges {
+    /// Parses a comma separated list of positions (`15`), closed ranges (`1-10`)
+    /// and open ended ranges (`20-`)
+    pub fn parse(expr: &str) -> Result<Self> {
+        let invalid = |part: &str| {
+            AppError::Configuration(format!("Invalid item range '{}' in '{}'", part, expr))
+        };
+
+        let mut ranges = Vec::new();
+        for part in expr.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let range = match part.split_once('-') {
+                Some((start, end)) => {
+                    let start = match start.trim() {
+                        "" => 1,
+                        start => start.parse().map_err(|_| invalid(part))?,
+                    };
+                    let end = match end.trim() {
+                        "" => None,
+                        end => Some(end.parse().map_err(|_| invalid(part))?),
+                    };
+                    (start, end)
+                }
+                None => {
+                    let pos = part.parse().map_err(|_| invalid(part))?;
+                    (pos, Some(pos))
+                }
+            };
+
+            if range.0 == 0 || range.1.is_some_and(|end| end < range.0) {
+                return Err(invalid(part));
+            }
+            ranges.push(range);
+        }
+
+        if ranges.is_empty() {
+            return Err(invalid(expr));
+        }
+
+        Ok(Self(ranges))
+    }
+
+    /// Whether the 1-based position is part of any range
+    pub fn contains(&self, pos: usize) -> bool {
+        self.0
+            .iter()
+            .any(|&(start, end)| pos >= start && end.is_none_or(|end| pos <= end))
+    }
+}
+
+/// Parses a date given on the command line, either as `YYYY-MM-DD` (midnight UTC)
+/// or as a full RFC 3339 timestamp
+pub fn parse_date(date: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|_| {
+            AppError::Configuration(format!(
+                "Invalid date '{}', expected YYYY-MM-DD or an RFC 3339 timestamp",
+                date
+            ))
+        })
+}
+
+/// Parses a duration given on the command line, either as `[h:]m:ss`, as a
+/// number with an `h`/`m`/`s` unit suffix (e.g. `1h30m`, `90s`) or as plain seconds
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let invalid = || {
+        AppError::Configuration(format!(
+            "Invalid duration '{}', expected e.g. 90, 1:30, 1h30m or 45s",
+            duration
+        ))
+    };
+
+    let duration = duration.trim();
+
+    if duration.contains(':') {
+        let mut secs = 0;
+        for part in duration.split(':') {
+            secs = secs * 60 + part.parse::<u64>().map_err(|_| invalid())?;
+        }
+        return Ok(Duration::from_secs(secs));
+    }
+
+    if let Ok(secs) = duration.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut secs = 0;
+    let mut number = String::new();
+    for c in duration.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        secs += number.parse::<u64>().map_err(|_| invalid())? * unit;
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parses a file size given on the command line as bytes or with a
+/// `K`/`M`/`G` suffix (binary multiples), e.g. `500K` or `1.5G`
+pub fn parse_size(size: &str) -> Result<u64> {
+    let invalid = || {
+        AppError::Configuration(format!(
+            "Invalid size '{}', expected e.g. 500K, 50M or 1.5G",
+            size
+        ))
+    };
+
+    let trimmed = size.trim().trim_end_matches(['B', 'b']);
+    let (number, multiplier) = match trimmed.chars().last() {
+        Some('K' | 'k') => (&trimmed[..trimmed.len() - 1], 1u64 << 10),
+        Some('M' | 'm') => (&trimmed[..trimmed.len() - 1], 1 << 20),
+        Some('G' | 'g') => (&trimmed[..trimmed.len() - 1], 1 << 30),
+        _ => (trimmed, 1),
+    };
+
+    let number = number.trim().parse::<f64>().map_err(|_| invalid())?;
+    if number < 0.0 {
+        return Err(invalid());
+    }
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Compiles a case-insensitive regex given on the command line
+pub fn parse_regex(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| AppError::Configuration(format!("Invalid regex '{}': {}", pattern, e)))
+}
+
 pub fn is_empty(s: &str) -> bool {
     s.replace('_', "").trim().is_empty()
 }
 
+/// Returns the directory a playlist's tracks are saved to, named after its title
+pub fn playlist_dir(output: &Path, playlist: &Playlist) -> PathBuf {
+    output.join(playlist_dir_name(playlist))
+}
+
+/// Returns the directories of playlists saved side by side, named after their titles
+///
+/// Titles shared by several playlists, compared case-insensitively for file systems
+/// that ignore case, get the playlist ID appended so each playlist keeps its own
+/// directory.
+///
+/// # Returns
+/// The directory of each playlist, in the same order
+pub fn playlist_dirs(output: &Path, playlists: &[Playlist]) -> Vec<PathBuf> {
+    let names = playlists.iter().map(playlist_dir_name).collect::<Vec<_>>();
+
+    let mut counts = HashMap::<String, usize>::new();
+    for name in &names {
+        *counts.entry(name.to_lowercase()).or_default() += 1;
+    }
+
+    names
+        .into_iter()
+        .zip(playlists)
+        .map(|(name, playlist)| {
+            if counts[&name.to_lowercase()] > 1 {
+                output.join(sanitize(&format!("{} [{}]", name, playlist.id)))
+            } else {
+                output.join(name)
+            }
+        })
+        .collect()
+}
+
+fn playlist_dir_name(playlist: &Playlist) -> String {
+    let playlist_title = if is_empty(&playlist.title) {
+        playlist.permalink.clone()
+    } else {
+        playlist.title.clone()
+    };
+
+    sanitize(&playlist_title)
+}
+
 pub fn sanitize(name: &str) -> String {
     const INVALID_CHARS: &[char] = &['\\', '/', ':', '*', '?', '"', '<', '>', '|'];
     let mut filename = name
@@ -21,13 +240,253 @@ pub fn sanitize(name: &str) -> String {
         }
     }
 
-    if filename.len() > 255 {
-        filename.truncate(255);
-    }
+    let len = truncate(&filename, MAX_NAME_LEN).len();
+    filename.truncate(len);
 
     filename
 }
 
+/// Cuts a string to at most `max_len` bytes without splitting a character
+pub fn truncate(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &s[..end]
+}
+
+/// Builds the path of an `<artist> - <title><suffix>.<ext>` file in `dir`
+///
+/// The title is shortened first so the artist stays recognisable, until the name fits
+/// the file name limit and, on Windows, the full path stays below `MAX_PATH`. Paths in
+/// directories too deep for that become extended-length `\\?\` paths.
+pub fn track_file_path(dir: &Path, artist: &str, title: &str, suffix: &str, ext: &str) -> PathBuf {
+    let artist = sanitize(artist);
+    let title = sanitize(title);
+    let suffix = sanitize(suffix);
+    let budget = name_budget(dir, suffix.len() + ext.len() + 1);
+
+    let title_budget = budget.saturating_sub(artist.len() + 3).max(MIN_TITLE_LEN);
+    let name = format!("{} - {}", artist, truncate(&title, title_budget));
+    // Windows drops trailing dots and spaces, which would make the name ambiguous
+    let stem = truncate(&name, budget).trim_end_matches(['.', ' ']);
+
+    long_path(dir.join(format!("{}{}.{}", stem, suffix, ext)))
+}
+
+/// Builds the path of a `<name><suffix>.<ext>` file in `dir`, with `name` given as a
+/// list of path components from a filename template
+///
+/// Every component is sanitized, the last one is shortened like in [`track_file_path`].
+pub fn template_file_path(dir: &Path, components: &[String], suffix: &str, ext: &str) -> PathBuf {
+    let (name, subdirs) = components.split_last().expect("templates are never empty");
+
+    let mut dir = dir.to_path_buf();
+    for subdir in subdirs {
+        let subdir = sanitize(subdir);
+        let subdir = truncate(&subdir, MAX_NAME_LEN).trim_end_matches(['.', ' ']);
+        dir.push(if is_empty(subdir) { "_" } else { subdir });
+    }
+
+    let name = sanitize(name);
+    let suffix = sanitize(suffix);
+    let budget = name_budget(&dir, suffix.len() + ext.len() + 1);
+    let stem = truncate(&name, budget).trim_end_matches(['.', ' ']);
+    let stem = if is_empty(stem) { "_" } else { stem };
+
+    long_path(dir.join(format!("{}{}.{}", stem, suffix, ext)))
+}
+
+/// Bytes left for the name of a file in `dir` once `suffix_len` bytes of suffix and
+/// extension are added, so the name fits the file name limit and, on Windows, the full
+/// path stays below `MAX_PATH`
+fn name_budget(dir: &Path, suffix_len: usize) -> usize {
+    let suffix_len = suffix_len + TEMP_NAME_OVERHEAD;
+
+    let mut budget = MAX_NAME_LEN - suffix_len;
+    if cfg!(windows) && !is_verbatim(dir) {
+        let dir_len =
+            std::path::absolute(dir).map_or(dir.as_os_str().len(), |dir| dir.as_os_str().len());
+        budget = budget.min(WINDOWS_MAX_PATH.saturating_sub(dir_len + 1 + suffix_len));
+    }
+
+    budget.max(MIN_TITLE_LEN)
+}
+
+/// Turns a path too long for the regular Windows APIs into an extended-length `\\?\`
+/// path, leaving other paths and other platforms as they are
+pub fn long_path(path: PathBuf) -> PathBuf {
+    if !cfg!(windows) || is_verbatim(&path) {
+        return path;
+    }
+
+    // Extended-length paths are used as is, so they must be absolute and normalized
+    let Ok(absolute) = std::path::absolute(&path) else {
+        return path;
+    };
+    if absolute.as_os_str().len() < WINDOWS_MAX_PATH - TEMP_NAME_OVERHEAD {
+        return path;
+    }
+
+    let absolute = absolute.to_string_lossy();
+    match absolute.strip_prefix(r"\\") {
+        Some(unc) => PathBuf::from(format!(r"\\?\UNC\{}", unc)),
+        None => PathBuf::from(format!(r"\\?\{}", absolute)),
+    }
+}
+
+/// Whether a path already has the `\\?\` extended-length prefix
+fn is_verbatim(path: &Path) -> bool {
+    path.as_os_str().to_string_lossy().starts_with(r"\\?\")
+}
+
+/// Returns the hidden `.<name>.part` file that is written in place of `path` and
+/// renamed to it once complete, so an interrupted write never leaves a truncated file
+/// under the final name
+pub fn part_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.part", name))
+}
+
+/// Returns the extension of a file, looking through the `.part` suffix of files that
+/// are still being written
+pub fn audio_ext(path: &Path) -> Option<&str> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("part") => Path::new(path.file_stem()?)
+            .extension()
+            .and_then(|ext| ext.to_str()),
+        ext => ext,
+    }
+}
+
+/// Lists the audio files in a directory and its subdirectories, sorted by path
+///
+/// Hidden files, including partial downloads, are left out.
+pub fn audio_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with('.'))
+            {
+                continue;
+            }
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext))
+            {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Returns the free space in bytes of the volume holding `path`, `None` if it cannot
+/// be determined
+///
+/// A directory that does not exist yet is on the volume of its nearest existing parent.
+pub fn available_space(path: &Path) -> Option<u64> {
+    // Mount points are listed without the `\\?\` prefix Windows canonical paths get
+    let path = std::path::absolute(path)
+        .ok()?
+        .ancestors()
+        .find_map(|dir| dunce::canonicalize(dir).ok())?;
+
+    Disks::new_with_refreshed_list()
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Extracts the secret token from a private share link, given either as a
+/// `secret_token` query parameter or as the trailing `s-XXXX` path segment
+pub fn secret_token(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+
+    if let Some((_, token)) = url.query_pairs().find(|(key, _)| key == "secret_token") {
+        return Some(token.into_owned());
+    }
+
+    url.path_segments()?
+        .rfind(|segment| !segment.is_empty())
+        .filter(|segment| segment.starts_with("s-"))
+        .map(str::to_string)
+}
+
+/// Whether the URL is a shortened share link that redirects to SoundCloud
+pub fn is_short_link(url: &reqwest::Url) -> bool {
+    const SHORT_HOSTS: &[&str] = &[
+        "on.soundcloud.com",
+        "snd.sc",
+        "goo.gl",
+        "soundcloud.app.goo.gl",
+    ];
+
+    url.host_str()
+        .is_some_and(|host| SHORT_HOSTS.contains(&host))
+}
+
+/// Rewrites mobile hosts to `soundcloud.com` and drops tracking parameters,
+/// keeping only the `secret_token` needed for private links
+pub fn canonicalize_url(mut url: reqwest::Url) -> reqwest::Url {
+    if matches!(
+        url.host_str(),
+        Some("m.soundcloud.com" | "www.soundcloud.com")
+    ) {
+        let _ = url.set_host(Some("soundcloud.com"));
+    }
+
+    let kept = url
+        .query_pairs()
+        .filter(|(key, _)| key == "secret_token")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+
+    if kept.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(kept);
+    }
+
+    url.set_fragment(None);
+    url
+}
+
+/// Formats a duration in milliseconds as `m:ss` or `h:mm:ss`
+pub fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
 pub fn prompt(msg: &str) -> bool {
     use std::io::{self, Write};
 
@@ -39,3 +498,113 @@ pub fn prompt(msg: &str) -> bool {
 
     input.trim().to_lowercase() == "y" || input.trim().is_empty()
 }
+
+pub fn read_line(msg: &str) -> String {
+    use std::io::{self, Write};
+
+    print!("{}: ", msg);
+    io::stdout().flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+
+    input.trim().to_string()
+}
+
+/// Finds the SHA-256 checksum of `file_name` in `sha256sum` output, or takes the
+/// checksum of a `.sha256` file holding nothing but the digest
+///
+/// # Returns
+/// The lowercase hex digest, `None` if the file is not listed or its checksum is not
+/// a SHA-256 digest
+pub fn find_checksum(checksums: &str, file_name: &str) -> Option<String> {
+    let lines = checksums
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let hash = fields.next()?;
+            // `*` marks binary mode, listed paths may include directories
+            let name = fields.next().map(|path| {
+                let path = path.trim_start_matches('*');
+                path.rsplit_once(['/', '\\']).map_or(path, |(_, name)| name)
+            });
+            Some((hash, name))
+        })
+        .collect::<Vec<_>>();
+
+    let hash = match &lines[..] {
+        [(hash, None)] => hash,
+        _ => &lines.iter().find(|(_, name)| *name == Some(file_name))?.0,
+    };
+
+    let is_sha256 = hash.len() == 64 && hash.bytes().all(|byte| byte.is_ascii_hexdigit());
+    is_sha256.then(|| hash.to_ascii_lowercase())
+}
+
+/// Returns the lowercase hex SHA-256 digest of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// Returns the lowercase hex SHA-256 digest of a file, read in chunks
+pub fn file_sha256(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    #[test]
+    fn finds_listed_checksum() {
+        let other = "0".repeat(64);
+        let listing = format!(
+            "{}  other.tar.xz\n{} *dist/app.tar.xz\n",
+            other,
+            DIGEST.to_uppercase()
+        );
+
+        assert_eq!(
+            find_checksum(&listing, "app.tar.xz").as_deref(),
+            Some(DIGEST)
+        );
+        assert_eq!(find_checksum(&listing, "missing.zip"), None);
+    }
+
+    #[test]
+    fn takes_a_bare_digest() {
+        assert_eq!(
+            find_checksum(&format!("{}\n", DIGEST), "app.zip").as_deref(),
+            Some(DIGEST)
+        );
+    }
+
+    #[test]
+    fn rejects_other_files_and_non_digests() {
+        // A single listed file must still be the one asked for
+        assert_eq!(
+            find_checksum(&format!("{}  other.zip", DIGEST), "app.zip"),
+            None
+        );
+        assert_eq!(find_checksum("<html>", "app.zip"), None);
+        assert_eq!(find_checksum("not-a-digest  app.zip", "app.zip"), None);
+        assert_eq!(find_checksum(&DIGEST[..40], "app.zip"), None);
+    }
+}